@@ -1,20 +1,99 @@
+#[path = "src/nes20db.rs"]
+mod nes20db;
+
 fn main() {
+	generate_layout();
+	generate_rom_db();
+
+	println!("cargo:rustc-check-cfg=cfg(recompiled)");
+	println!("cargo:rerun-if-env-changed=NES_RECOMPILED");
+
+	// Without the C instructions there's nothing to build
+	#[cfg(feature = "c-core")]
+	{
+		build_c_core();
+		if let Ok(path) = std::env::var("NES_RECOMPILED") {
+			build_recompiled(&path);
+		}
+	}
+}
+
+/// Turns the `#define`s in `inc/layout.h` into Rust constants, so Rust can check its structs
+/// against the same numbers the C does.
+fn generate_layout() {
+	println!("cargo:rerun-if-changed=inc/layout.h");
+
+	let header = std::fs::read_to_string("inc/layout.h").expect("Couldn't read inc/layout.h");
+	let mut constants = String::new();
+	for line in header.lines() {
+		let Some(define) = line.strip_prefix("#define ") else {
+			continue;
+		};
+		let Some((name, value)) = define.split_once(' ') else {
+			panic!("layout.h: {line} has no value");
+		};
+		let value: usize = value
+			.trim()
+			.parse()
+			.unwrap_or_else(|_| panic!("layout.h: {line} isn't a plain number"));
+		constants += &format!("pub const {name}: usize = {value};\n");
+	}
+
+	let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("layout.rs");
+	std::fs::write(out, constants).expect("Couldn't write layout.rs");
+}
+
+/// The ROM database `rom_db` embeds: `data/romdb.txt`, then the NES 2.0 XML database that
+/// `NES20DB` points at, if it does. Lookups take the first match, so the hand written entries win.
+fn generate_rom_db() {
+	println!("cargo:rerun-if-changed=data/romdb.txt");
+	println!("cargo:rerun-if-env-changed=NES20DB");
+
+	let mut db = std::fs::read_to_string("data/romdb.txt").expect("Couldn't read data/romdb.txt");
+	if let Ok(path) = std::env::var("NES20DB") {
+		println!("cargo:rerun-if-changed={path}");
+		let xml = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{path}: {e}"));
+		db += &nes20db::import_nes20db(&xml).unwrap_or_else(|e| panic!("{path}: {e:#}"));
+	}
+
+	let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("romdb.txt");
+	std::fs::write(out, db).expect("Couldn't write romdb.txt");
+}
+
+#[cfg(feature = "c-core")]
+fn build_c_core() {
 	// Tell cargo to invalidate the built crate whenever the header changes
 	println!("cargo:rerun-if-changed=inc/interface.h");
+	println!("cargo:rerun-if-changed=inc/layout.h");
 	println!("cargo:rerun-if-changed=src/evaluate_instruction_1.c");
 	println!("cargo:rerun-if-changed=src/evaluate_instruction_2.c");
 	println!("cargo:rerun-if-changed=src/evaluate_instruction_3.c");
 	println!("cargo:rerun-if-changed=src/evaluate_instruction_4.c");
 	println!("cargo:rerun-if-changed=src/evaluate_instruction_5.c");
+	println!("cargo:rerun-if-changed=src/evaluate_instruction_6.c");
 
-	let mut build = cc::Build::new();
-	build
+	c_build()
 		.file("src/evaluate_instruction_1.c")
 		.file("src/evaluate_instruction_2.c")
 		.file("src/evaluate_instruction_3.c")
 		.file("src/evaluate_instruction_4.c")
-		.file("src/evaluate_instruction_5.c");
+		.file("src/evaluate_instruction_5.c")
+		.file("src/evaluate_instruction_6.c")
+		.compile("evaluate_instruction");
+}
+
+/// The output of `--recompile` for one game, which `recompile::compiled_pages` finds through
+/// the `recompiled` cfg.
+#[cfg(feature = "c-core")]
+fn build_recompiled(path: &str) {
+	println!("cargo:rerun-if-changed={path}");
+	c_build().file(path).compile("recompiled");
+	println!("cargo:rustc-cfg=recompiled");
+}
 
+#[cfg(feature = "c-core")]
+fn c_build() -> cc::Build {
+	let mut build = cc::Build::new();
 	build.compiler("clang");
 
 	// build.flag("-w");
@@ -36,6 +115,5 @@ fn main() {
 			panic!("Unknown opt_level!");
 		}
 	}
-
-	build.compile("evaluate_instruction");
+	build
 }
@@ -1,5 +1,8 @@
+#include <stddef.h>
 #include <stdint.h>
 
+#include "layout.h"
+
 typedef union {
 	struct {
 		uint8_t C       : 1;
@@ -20,6 +23,7 @@ typedef struct {
 	uint8_t y;
 	uint8_t s;
 	P p;
+	bool jammed;
 	uint16_t pc;
 } Cpu;
 
@@ -29,24 +33,33 @@ typedef struct {
 	uint8_t status;
 	uint8_t oam_adr;
 	uint8_t oam_data;
-	struct {
-		uint8_t x;
-		uint8_t y;
-	} scroll;
-	struct {
-		uint8_t high;
-		uint8_t low;
-	} adr;
+	/* VramAdr */ uint8_t scroll[2];
+	/* VramAdr */ uint8_t adr[2];
 	uint8_t data;
 
 	uint16_t scanline;
 	uint16_t dot;
+	uint64_t frame;
+	uint64_t cycles;
 	uint8_t vram[2048];
 	uint8_t oam[256];
+	/* Palettes */ uint8_t palettes[32];
+	uint8_t next_tile;
+	uint8_t fine_x;
+	bool write_latch;
 } Ppu;
 
-typedef uint8_t Bitmap[240][256];
+typedef struct {
+	uint8_t red;
+	uint8_t green;
+	uint8_t blue;
+	uint8_t alpha;
+} Colour;
+
+typedef Colour Bitmap[240][256];
 
+// Only the start of the Rust struct, the fields after `cycles` are Rust's business. So a State
+// only ever lives on the Rust side and C gets pointers to it.
 typedef struct {
 	Cpu cpu;
 	Ppu ppu;
@@ -55,142 +68,246 @@ typedef struct {
 	uint8_t bus;
 	/* Arc<Mutex<Bitmap>> */ void *output_texture;
 	Bitmap current_texture;
+	uint64_t cycles;
 } State;
 
+#if UINTPTR_MAX == UINT64_MAX
+#define CHECK_OFFSET(type, field, offset)                                                         \
+	_Static_assert(offsetof(type, field) == (offset), #type "." #field " moved, see layout.h")
+#define CHECK_SIZE(type, size)                                                                    \
+	_Static_assert(sizeof(type) == (size), #type " changed size, see layout.h")
+
+CHECK_SIZE(Cpu, CPU_SIZE);
+CHECK_OFFSET(Cpu, p, CPU_P);
+CHECK_OFFSET(Cpu, jammed, CPU_JAMMED);
+CHECK_OFFSET(Cpu, pc, CPU_PC);
+
+CHECK_SIZE(Ppu, PPU_SIZE);
+CHECK_OFFSET(Ppu, ctrl, PPU_CTRL);
+CHECK_OFFSET(Ppu, mask, PPU_MASK);
+CHECK_OFFSET(Ppu, status, PPU_STATUS);
+CHECK_OFFSET(Ppu, oam_adr, PPU_OAM_ADR);
+CHECK_OFFSET(Ppu, oam_data, PPU_OAM_DATA);
+CHECK_OFFSET(Ppu, scroll, PPU_SCROLL);
+CHECK_OFFSET(Ppu, adr, PPU_ADR);
+CHECK_OFFSET(Ppu, data, PPU_DATA);
+CHECK_OFFSET(Ppu, scanline, PPU_SCANLINE);
+CHECK_OFFSET(Ppu, dot, PPU_DOT);
+CHECK_OFFSET(Ppu, frame, PPU_FRAME);
+CHECK_OFFSET(Ppu, cycles, PPU_CYCLES);
+CHECK_OFFSET(Ppu, vram, PPU_VRAM);
+CHECK_OFFSET(Ppu, oam, PPU_OAM);
+CHECK_OFFSET(Ppu, palettes, PPU_PALETTES);
+CHECK_OFFSET(Ppu, next_tile, PPU_NEXT_TILE);
+CHECK_OFFSET(Ppu, fine_x, PPU_FINE_X);
+CHECK_OFFSET(Ppu, write_latch, PPU_WRITE_LATCH);
+
+CHECK_SIZE(Colour, COLOUR_SIZE);
+CHECK_SIZE(Bitmap, BITMAP_SIZE);
+
+CHECK_OFFSET(State, cpu, STATE_CPU);
+CHECK_OFFSET(State, ppu, STATE_PPU);
+CHECK_OFFSET(State, rom, STATE_ROM);
+CHECK_OFFSET(State, ram, STATE_RAM);
+CHECK_OFFSET(State, bus, STATE_BUS);
+CHECK_OFFSET(State, output_texture, STATE_OUTPUT_TEXTURE);
+CHECK_OFFSET(State, current_texture, STATE_CURRENT_TEXTURE);
+CHECK_OFFSET(State, cycles, STATE_CYCLES);
+
+#undef CHECK_OFFSET
+#undef CHECK_SIZE
+#endif
+
 uint8_t state_get_mem(State *state, uint16_t adr);
 void state_set_mem(State *state, uint16_t adr, uint8_t val);
 void state_step_ppu(State *state);
 void state_step_ppu_many(State *state, uint32_t times);
 
+// One CPU cycle each, with the rest of the console caught up to the cycle before the access. By
+// the time an instruction function runs, its first two cycles have read the opcode and the byte
+// after it, every cycle after that goes through these.
+uint8_t state_read(State *state, uint16_t adr);
+void state_write(State *state, uint16_t adr, uint8_t val);
+
+// The values of `cpu::Variant`
+enum { RICOH_2A03, NMOS_6502, CMOS_65C02 };
+
+uint8_t state_variant(State *state);
+
+// Whether a recompiled block should stop before its next instruction, for an IRQ or because a
+// bank switch mapped other code in
+bool state_leave_block(State *state);
+
+// A basic block recompiled by `--recompile`, which runs from where it starts in PRG ROM, `offset`,
+// to the end of the block and returns where the CPU goes next
+typedef struct {
+	size_t offset;
+	uint16_t (*run)(State *state);
+} CompiledBlock;
+
+// The `count` blocks in 4K page `page` of PRG ROM, sorted by offset
+typedef struct {
+	size_t page;
+	const CompiledBlock *blocks;
+	size_t count;
+} CompiledPage;
+
+static inline void push(State *state, uint8_t val) {
+	state_write(state, (uint16_t) (0x100 + state->cpu.s), val);
+	state->cpu.s--;
+}
+
+// The cycle where the stack pointer gets incremented, before anything can be pulled
+static inline void peek_stack(State *state) {
+	state_read(state, (uint16_t) (0x100 + state->cpu.s));
+}
+
+static inline uint8_t pull(State *state) {
+	state->cpu.s++;
+	return state_read(state, (uint16_t) (0x100 + state->cpu.s));
+}
+
+// The cycle reading an absolute address's high byte, the low byte came with the opcode
+static inline void fetch_high(State *state) {
+	state_read(state, (uint16_t) (state->cpu.pc + 2));
+}
+
+// The pointers the indirect modes use wrap around within the zero page
+static inline uint16_t zero_page_pointer(State *state, uint8_t adr) {
+	uint8_t low = state_read(state, adr);
+	return (uint16_t) (low | state_read(state, (uint8_t) (adr + 1)) << 8);
+}
+
+// Indexing adds to the low byte first, and reads from that address while fixing up the high byte.
+// Reads that didn't carry can stop there, stores and read-modify-writes always take the extra
+// cycle.
+static inline uint16_t indexed(State *state, uint16_t base, uint8_t index, bool write) {
+	uint16_t adr     = (uint16_t) (base + index);
+	uint16_t unfixed = (base & 0xFF00) | (adr & 0x00FF);
+	if (unfixed != adr || write) {
+		state_read(state, unfixed);
+	}
+	return adr;
+}
+
+// Zero page indexing reads the unindexed address while adding, and wraps
+static inline uint16_t zero_page_indexed(State *state, uint8_t adr, uint8_t index) {
+	state_read(state, adr);
+	return (uint8_t) (adr + index);
+}
+
+static inline uint16_t absolute(State *state, uint16_t adr) {
+	fetch_high(state);
+	return adr;
+}
+
+static inline uint16_t absolute_indexed(State *state, uint16_t adr, uint8_t index, bool write) {
+	fetch_high(state);
+	return indexed(state, adr, index, write);
+}
+
+static inline uint16_t indirect_x(State *state, uint8_t adr) {
+	state_read(state, adr);
+	return zero_page_pointer(state, (uint8_t) (adr + state->cpu.x));
+}
+
+// Taken branches read the next opcode while adding the offset, and then from the wrong page if
+// that carried
+static inline void branch(State *state, int8_t offset, bool taken) {
+	uint16_t next = (uint16_t) (state->cpu.pc + 2);
+	state->cpu.pc = next;
+	if (taken) {
+		uint16_t target  = (uint16_t) (next + offset);
+		uint16_t unfixed = (next & 0xFF00) | (target & 0x00FF);
+		state_read(state, next);
+		if (unfixed != target) {
+			state_read(state, unfixed);
+		}
+		state->cpu.pc = target;
+	}
+}
+
+static inline uint16_t indirect_y(State *state, uint8_t adr, bool write) {
+	return indexed(state, zero_page_pointer(state, adr), state->cpu.y, write);
+}
+
 #define ACCUMULATOR(fn)                                                                          \
 	void fn##_accumulator(State *state) {                                                    \
 		fn##_impl(state, &state->cpu.a);                                                 \
 		state->cpu.pc += 1;                                                              \
-		state_step_ppu_many(state, 2);                                                   \
 	}
 
 #define IMMEDIATE(fn)                                                                            \
 	void fn##_immediate(State *state, uint8_t val) {                                         \
 		fn##_impl(state, val);                                                           \
 		state->cpu.pc += 2;                                                              \
-		state_step_ppu_many(state, 2);                                                   \
 	}
 
-#define ZERO_PAGE(fn)                                                                            \
-	void fn##_zero_page(State *state, uint8_t offset) {                                      \
-		uint8_t val = state_get_mem(state, (uint16_t) offset);                           \
-		fn##_impl(state, val);                                                           \
-		state->cpu.pc += 2;                                                              \
-		state_step_ppu_many(state, 3);                                                   \
+// Instructions that read their operand, `fn##_impl` gets the value
+#define READ_AT(fn, mode, operand_type, len, address)                                            \
+	void fn##_##mode(State *state, operand_type operand) {                                   \
+		fn##_impl(state, state_read(state, address));                                    \
+		state->cpu.pc += len;                                                            \
 	}
 
-#define ZERO_PAGE_RMW(fn)                                                                        \
-	void fn##_zero_page(State *state, uint8_t offset) {                                      \
-		uint8_t val = state_get_mem(state, (uint16_t) offset);                           \
-		fn##_impl(state, &val);                                                          \
-		state_set_mem(state, (uint16_t) offset, val);                                    \
-		state->cpu.pc += 2;                                                              \
+// Instructions that store a register, `fn##_impl` returns what to store
+#define STORE_AT(fn, mode, operand_type, len, address)                                           \
+	void fn##_##mode(State *state, operand_type operand) {                                   \
+		uint16_t adr = address;                                                          \
+		state_write(state, adr, fn##_impl(state));                                       \
+		state->cpu.pc += len;                                                            \
 	}
 
-#define ZERO_PAGE_X(fn)                                                                          \
-	void fn##_zero_page_x(State *state, uint8_t offset) {                                    \
-		uint8_t val =                                                                    \
-		    state_get_mem(state, ((uint16_t) state->cpu.x + (uint16_t) offset) & 0xFF);  \
-		fn##_impl(state, val);                                                           \
-		state->cpu.pc += 2;                                                              \
-		state_step_ppu_many(state, 4);                                                   \
-	}
-
-#define ZERO_PAGE_X_RMW(fn)                                                                      \
-	void fn##_zero_page_x(State *state, uint8_t offset) {                                    \
-		uint8_t val =                                                                    \
-		    state_get_mem(state, ((uint16_t) state->cpu.x + (uint16_t) offset) & 0xFF);  \
+// Read-modify-write instructions write the value back unchanged while they work on it
+#define RMW_AT(fn, mode, operand_type, len, address)                                             \
+	void fn##_##mode(State *state, operand_type operand) {                                   \
+		uint16_t adr = address;                                                          \
+		uint8_t val  = state_read(state, adr);                                           \
+		state_write(state, adr, val);                                                    \
 		fn##_impl(state, &val);                                                          \
-		state_set_mem(state, (uint16_t) offset, val);                                    \
-		state->cpu.pc += 2;                                                              \
-		state_step_ppu_many(state, 5);                                                   \
+		state_write(state, adr, val);                                                    \
+		state->cpu.pc += len;                                                            \
 	}
 
+#define ZERO_PAGE(fn) READ_AT(fn, zero_page, uint8_t, 2, operand)
+#define ZERO_PAGE_X(fn)                                                                          \
+	READ_AT(fn, zero_page_x, uint8_t, 2, zero_page_indexed(state, operand, state->cpu.x))
 #define ZERO_PAGE_Y(fn)                                                                          \
-	void fn##_zero_page_y(State *state, uint8_t offset) {                                    \
-		uint8_t val =                                                                    \
-		    state_get_mem(state, ((uint16_t) state->cpu.y + (uint16_t) offset) & 0xFF);  \
-		fn##_impl(state, val);                                                           \
-		state->cpu.pc += 2;                                                              \
-		state_step_ppu_many(state, 4);                                                   \
-	}
-
-#define ABSOLUTE(fn)                                                                             \
-	void fn##_absolute(State *state, uint16_t adr) {                                         \
-		uint8_t val = state_get_mem(state, adr);                                         \
-		fn##_impl(state, val);                                                           \
-		state->cpu.pc += 3;                                                              \
-		state_step_ppu_many(state, 4);                                                   \
-	}
-
-#define ABSOLUTE_RMW(fn)                                                                         \
-	void fn##_absolute(State *state, uint16_t adr) {                                         \
-		uint8_t val = state_get_mem(state, adr);                                         \
-		fn##_impl(state, &val);                                                          \
-		state_set_mem(state, adr, val);                                                  \
-		state->cpu.pc += 3;                                                              \
-		state_step_ppu_many(state, 6);                                                   \
-	}
-
+	READ_AT(fn, zero_page_y, uint8_t, 2, zero_page_indexed(state, operand, state->cpu.y))
+#define ABSOLUTE(fn) READ_AT(fn, absolute, uint16_t, 3, absolute(state, operand))
 #define ABSOLUTE_X(fn)                                                                           \
-	void fn##_absolute_x(State *state, uint16_t adr) {                                       \
-		uint8_t val = state_get_mem(state, (uint16_t) state->cpu.x + adr);               \
-		fn##_impl(state, val);                                                           \
-		state->cpu.pc += 3;                                                              \
-		state_step_ppu_many(state, 3);                                                   \
-	}
-
-#define ABSOLUTE_X_RMW(fn)                                                                       \
-	void fn##_absolute_x(State *state, uint16_t adr) {                                       \
-		uint8_t val = state_get_mem(state, (uint16_t) state->cpu.x + adr);               \
-		fn##_impl(state, &val);                                                          \
-		state_set_mem(state, (uint16_t) state->cpu.x + adr, val);                        \
-		state->cpu.pc += 3;                                                              \
-		state_step_ppu_many(state, 7);                                                   \
-	}
-
+	READ_AT(fn, absolute_x, uint16_t, 3, absolute_indexed(state, operand, state->cpu.x, false))
 #define ABSOLUTE_Y(fn)                                                                           \
-	void fn##_absolute_y(State *state, uint16_t adr) {                                       \
-		uint8_t val = state_get_mem(state, (uint16_t) state->cpu.y + adr);               \
-		fn##_impl(state, val);                                                           \
-		state->cpu.pc += 3;                                                              \
-		state_step_ppu_many(state, 3);                                                   \
-	}
+	READ_AT(fn, absolute_y, uint16_t, 3, absolute_indexed(state, operand, state->cpu.y, false))
+#define INDIRECT_X(fn) READ_AT(fn, indirect_x, uint8_t, 2, indirect_x(state, operand))
+#define INDIRECT_Y(fn) READ_AT(fn, indirect_y, uint8_t, 2, indirect_y(state, operand, false))
+#define ZERO_PAGE_INDIRECT(fn)                                                                   \
+	READ_AT(fn, zero_page_indirect, uint8_t, 2, zero_page_pointer(state, operand))
 
-#define ABSOLUTE_Y_RMW(fn)                                                                       \
-	void fn##_absolute_y(State *state, uint16_t adr) {                                       \
-		uint8_t val = state_get_mem(state, (uint16_t) state->cpu.y + adr);               \
-		fn##_impl(state, &val);                                                          \
-		state_set_mem(state, (uint16_t) state->cpu.y + adr, val);                        \
-		state->cpu.pc += 3;                                                              \
-		state_step_ppu_many(state, 7);                                                   \
-	}
+#define ZERO_PAGE_STORE(fn) STORE_AT(fn, zero_page, uint8_t, 2, operand)
+#define ZERO_PAGE_X_STORE(fn)                                                                    \
+	STORE_AT(fn, zero_page_x, uint8_t, 2, zero_page_indexed(state, operand, state->cpu.x))
+#define ZERO_PAGE_Y_STORE(fn)                                                                    \
+	STORE_AT(fn, zero_page_y, uint8_t, 2, zero_page_indexed(state, operand, state->cpu.y))
+#define ABSOLUTE_STORE(fn) STORE_AT(fn, absolute, uint16_t, 3, absolute(state, operand))
+#define ABSOLUTE_X_STORE(fn)                                                                     \
+	STORE_AT(fn, absolute_x, uint16_t, 3, absolute_indexed(state, operand, state->cpu.x, true))
+#define ABSOLUTE_Y_STORE(fn)                                                                     \
+	STORE_AT(fn, absolute_y, uint16_t, 3, absolute_indexed(state, operand, state->cpu.y, true))
+#define INDIRECT_X_STORE(fn) STORE_AT(fn, indirect_x, uint8_t, 2, indirect_x(state, operand))
+#define INDIRECT_Y_STORE(fn) STORE_AT(fn, indirect_y, uint8_t, 2, indirect_y(state, operand, true))
+#define ZERO_PAGE_INDIRECT_STORE(fn)                                                             \
+	STORE_AT(fn, zero_page_indirect, uint8_t, 2, zero_page_pointer(state, operand))
 
-#define INDIRECT_X(fn)                                                                           \
-	void fn##_indirect_x(State *state, uint8_t adr) {                                        \
-		uint8_t tmp = state_get_mem(state, (uint16_t) (state->cpu.x + adr) & 0xFF);      \
-		uint16_t adr2 =                                                                  \
-		    (uint16_t) (state_get_mem(state, (uint16_t) tmp)                             \
-		                | state_get_mem(state, (uint16_t) (tmp + 1) & 0xFF) << 8);       \
-		uint8_t val = state_get_mem(state, adr2);                                        \
-		fn##_impl(state, val);                                                           \
-		state->cpu.pc += 2;                                                              \
-		state_step_ppu_many(state, 2);                                                   \
-	}
-
-#define INDIRECT_Y(fn)                                                                           \
-	void fn##_indirect_y(State *state, uint8_t adr) {                                        \
-		uint8_t tmp = state_get_mem(state, (uint16_t) (state->cpu.y + adr) & 0xFF);      \
-		uint16_t adr2 =                                                                  \
-		    (uint16_t) (state_get_mem(state, (uint16_t) tmp)                             \
-		                | state_get_mem(state, (uint16_t) (tmp + 1) & 0xFF) << 8);       \
-		uint8_t val = state_get_mem(state, adr2);                                        \
-		fn##_impl(state, val);                                                           \
-		state->cpu.pc += 2;                                                              \
-		state_step_ppu_many(state, 2);                                                   \
-	}
+#define ZERO_PAGE_RMW(fn) RMW_AT(fn, zero_page, uint8_t, 2, operand)
+#define ZERO_PAGE_X_RMW(fn)                                                                      \
+	RMW_AT(fn, zero_page_x, uint8_t, 2, zero_page_indexed(state, operand, state->cpu.x))
+#define ZERO_PAGE_Y_RMW(fn)                                                                      \
+	RMW_AT(fn, zero_page_y, uint8_t, 2, zero_page_indexed(state, operand, state->cpu.y))
+#define ABSOLUTE_RMW(fn) RMW_AT(fn, absolute, uint16_t, 3, absolute(state, operand))
+#define ABSOLUTE_X_RMW(fn)                                                                       \
+	RMW_AT(fn, absolute_x, uint16_t, 3, absolute_indexed(state, operand, state->cpu.x, true))
+#define ABSOLUTE_Y_RMW(fn)                                                                       \
+	RMW_AT(fn, absolute_y, uint16_t, 3, absolute_indexed(state, operand, state->cpu.y, true))
+#define INDIRECT_X_RMW(fn) RMW_AT(fn, indirect_x, uint8_t, 2, indirect_x(state, operand))
+#define INDIRECT_Y_RMW(fn) RMW_AT(fn, indirect_y, uint8_t, 2, indirect_y(state, operand, true))
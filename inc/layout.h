@@ -0,0 +1,42 @@
+// Where everything C and Rust share lives, on 64 bit targets. Both languages check their own
+// layout against this when building, so changing a shared struct on one side only fails the
+// build instead of reading the wrong memory. build.rs turns these into constants for Rust, so
+// keep to plain `#define NAME number` lines.
+#pragma once
+
+#define CPU_SIZE 8
+#define CPU_P 4
+#define CPU_JAMMED 5
+#define CPU_PC 6
+
+#define PPU_SIZE 2376
+#define PPU_CTRL 0
+#define PPU_MASK 1
+#define PPU_STATUS 2
+#define PPU_OAM_ADR 3
+#define PPU_OAM_DATA 4
+#define PPU_SCROLL 5
+#define PPU_ADR 7
+#define PPU_DATA 9
+#define PPU_SCANLINE 10
+#define PPU_DOT 12
+#define PPU_FRAME 16
+#define PPU_CYCLES 24
+#define PPU_VRAM 32
+#define PPU_OAM 2080
+#define PPU_PALETTES 2336
+#define PPU_NEXT_TILE 2368
+#define PPU_FINE_X 2369
+#define PPU_WRITE_LATCH 2370
+
+#define COLOUR_SIZE 4
+#define BITMAP_SIZE 245760
+
+#define STATE_CPU 0
+#define STATE_PPU 8
+#define STATE_ROM 2384
+#define STATE_RAM 2392
+#define STATE_BUS 4440
+#define STATE_OUTPUT_TEXTURE 4448
+#define STATE_CURRENT_TEXTURE 4456
+#define STATE_CYCLES 250216
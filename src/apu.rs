@@ -0,0 +1,710 @@
+//! The 2A03's own sound: two pulses, a triangle, noise and the delta modulation channel, mixed
+//! the way the console's resistor network does it. The DMC's sample fetches don't stall the CPU.
+
+/// What a 2A03 pulse channel at full volume comes out of the mixer as. Expansion audio levels
+/// are given relative to this, as that's what the chips were measured against.
+pub const PULSE_LEVEL: f32 = pulse_mix(15);
+
+const LENGTHS: [u8; 32] = [
+	10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+	192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTIES: [[u8; 8]; 4] = [
+	[0, 1, 0, 0, 0, 0, 0, 0],
+	[0, 1, 1, 0, 0, 0, 0, 0],
+	[0, 1, 1, 1, 1, 0, 0, 0],
+	[1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// NTSC noise periods, in CPU cycles.
+const NOISE_PERIODS: [u16; 16] = [
+	4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// NTSC DMC periods, in CPU cycles.
+const DMC_PERIODS: [u16; 16] = [
+	428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// CPU cycles into the frame sequence of each quarter frame, and where the sequence starts over.
+const FOUR_STEP: [u32; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP: [u32; 4] = [7457, 14913, 22371, 37281];
+
+/// The pulse half of the mixer, for the sum of both pulses' volumes.
+const fn pulse_mix(sum: u8) -> f32 {
+	if sum == 0 {
+		0.0
+	} else {
+		95.88 / (8128.0 / sum as f32 + 100.0)
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Envelope {
+	start: bool,
+	/// Also halts the length counter.
+	looping: bool,
+	constant: bool,
+	/// The constant volume, or the envelope's period.
+	volume: u8,
+	divider: u8,
+	decay: u8,
+}
+
+impl Envelope {
+	fn write(&mut self, val: u8) {
+		self.looping = val & 0x20 != 0;
+		self.constant = val & 0x10 != 0;
+		self.volume = val & 0x0F;
+	}
+
+	fn quarter_frame(&mut self) {
+		if self.start {
+			self.start = false;
+			self.decay = 15;
+			self.divider = self.volume;
+		} else if self.divider > 0 {
+			self.divider -= 1;
+		} else {
+			self.divider = self.volume;
+			if self.decay > 0 {
+				self.decay -= 1;
+			} else if self.looping {
+				self.decay = 15;
+			}
+		}
+	}
+
+	fn output(&self) -> u8 {
+		if self.constant {
+			self.volume
+		} else {
+			self.decay
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Pulse {
+	/// Pulse 1 negates the sweep with one's complement, so it goes one lower than pulse 2.
+	first: bool,
+	envelope: Envelope,
+	duty: u8,
+	step: u8,
+	period: u16,
+	timer: u16,
+	length: u8,
+
+	sweep_enabled: bool,
+	sweep_period: u8,
+	sweep_negate: bool,
+	sweep_shift: u8,
+	sweep_divider: u8,
+	sweep_reload: bool,
+}
+
+impl Pulse {
+	fn write(&mut self, register: u16, val: u8, enabled: bool) {
+		match register & 0b11 {
+			0 => {
+				self.duty = val >> 6;
+				self.envelope.write(val);
+			}
+			1 => {
+				self.sweep_enabled = val & 0x80 != 0;
+				self.sweep_period = (val >> 4) & 0b111;
+				self.sweep_negate = val & 0x08 != 0;
+				self.sweep_shift = val & 0b111;
+				self.sweep_reload = true;
+			}
+			2 => self.period = (self.period & 0x700) | val as u16,
+			_ => {
+				self.period = (self.period & 0x0FF) | ((val & 0b111) as u16) << 8;
+				if enabled {
+					self.length = LENGTHS[val as usize >> 3];
+				}
+				self.step = 0;
+				self.envelope.start = true;
+			}
+		}
+	}
+
+	/// Every other CPU cycle.
+	fn tick(&mut self) {
+		if self.timer == 0 {
+			self.timer = self.period;
+			self.step = (self.step + 1) % 8;
+		} else {
+			self.timer -= 1;
+		}
+	}
+
+	fn sweep_target(&self) -> u16 {
+		let change = self.period >> self.sweep_shift;
+		if !self.sweep_negate {
+			self.period + change
+		} else if self.first {
+			self.period.saturating_sub(change + 1)
+		} else {
+			self.period.saturating_sub(change)
+		}
+	}
+
+	/// Too high or too low a period silences the channel, whether the sweep is on or not.
+	fn muted(&self) -> bool {
+		self.period < 8 || self.sweep_target() > 0x7FF
+	}
+
+	fn half_frame(&mut self) {
+		if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+			self.period = self.sweep_target();
+		}
+		if self.sweep_divider == 0 || self.sweep_reload {
+			self.sweep_divider = self.sweep_period;
+			self.sweep_reload = false;
+		} else {
+			self.sweep_divider -= 1;
+		}
+
+		if self.length > 0 && !self.envelope.looping {
+			self.length -= 1;
+		}
+	}
+
+	fn output(&self) -> u8 {
+		if self.length == 0 || self.muted() || DUTIES[self.duty as usize][self.step as usize] == 0 {
+			0
+		} else {
+			self.envelope.output()
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Triangle {
+	/// Also halts the length counter.
+	control: bool,
+	linear_reload: u8,
+	linear: u8,
+	reload: bool,
+	period: u16,
+	timer: u16,
+	step: u8,
+	length: u8,
+}
+
+impl Triangle {
+	fn write(&mut self, register: u16, val: u8, enabled: bool) {
+		match register & 0b11 {
+			0 => {
+				self.control = val & 0x80 != 0;
+				self.linear_reload = val & 0x7F;
+			}
+			1 => {}
+			2 => self.period = (self.period & 0x700) | val as u16,
+			_ => {
+				self.period = (self.period & 0x0FF) | ((val & 0b111) as u16) << 8;
+				if enabled {
+					self.length = LENGTHS[val as usize >> 3];
+				}
+				self.reload = true;
+			}
+		}
+	}
+
+	/// Every CPU cycle. The sequence stops where it is when either counter runs out, rather
+	/// than dropping to 0.
+	fn tick(&mut self) {
+		if self.timer == 0 {
+			self.timer = self.period;
+			if self.length > 0 && self.linear > 0 {
+				self.step = (self.step + 1) % 32;
+			}
+		} else {
+			self.timer -= 1;
+		}
+	}
+
+	fn quarter_frame(&mut self) {
+		if self.reload {
+			self.linear = self.linear_reload;
+		} else if self.linear > 0 {
+			self.linear -= 1;
+		}
+		if !self.control {
+			self.reload = false;
+		}
+	}
+
+	fn half_frame(&mut self) {
+		if self.length > 0 && !self.control {
+			self.length -= 1;
+		}
+	}
+
+	/// Silencing the triangle leaves it where it was, so this is never 0 for long.
+	fn output(&self) -> u8 {
+		if self.step < 16 {
+			15 - self.step
+		} else {
+			self.step - 16
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Noise {
+	envelope: Envelope,
+	/// Feeds back from bit 6 rather than bit 1, for the short 93 step sequence.
+	short: bool,
+	period: u16,
+	timer: u16,
+	lfsr: u16,
+	length: u8,
+}
+
+impl Default for Noise {
+	fn default() -> Self {
+		Self {
+			envelope: Envelope::default(),
+			short: false,
+			period: NOISE_PERIODS[0],
+			timer: 0,
+			lfsr: 1,
+			length: 0,
+		}
+	}
+}
+
+impl Noise {
+	fn write(&mut self, register: u16, val: u8, enabled: bool) {
+		match register & 0b11 {
+			0 => self.envelope.write(val),
+			1 => {}
+			2 => {
+				self.short = val & 0x80 != 0;
+				self.period = NOISE_PERIODS[val as usize & 0x0F];
+			}
+			_ => {
+				if enabled {
+					self.length = LENGTHS[val as usize >> 3];
+				}
+				self.envelope.start = true;
+			}
+		}
+	}
+
+	/// Every CPU cycle, the periods being in CPU cycles already.
+	fn tick(&mut self) {
+		if self.timer > 0 {
+			self.timer -= 1;
+			return;
+		}
+		self.timer = self.period - 1;
+		let tap = if self.short { 6 } else { 1 };
+		let feedback = (self.lfsr ^ (self.lfsr >> tap)) & 1;
+		self.lfsr = (self.lfsr >> 1) | feedback << 14;
+	}
+
+	fn half_frame(&mut self) {
+		if self.length > 0 && !self.envelope.looping {
+			self.length -= 1;
+		}
+	}
+
+	fn output(&self) -> u8 {
+		if self.length == 0 || self.lfsr & 1 != 0 {
+			0
+		} else {
+			self.envelope.output()
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Dmc {
+	irq_enabled: bool,
+	irq: bool,
+	looping: bool,
+	period: u16,
+	timer: u16,
+	level: u8,
+
+	sample_adr: u16,
+	sample_len: u16,
+	current_adr: u16,
+	remaining: u16,
+	buffer: Option<u8>,
+
+	shift: u8,
+	bits: u8,
+	silent: bool,
+}
+
+impl Default for Dmc {
+	fn default() -> Self {
+		Self {
+			irq_enabled: false,
+			irq: false,
+			looping: false,
+			period: DMC_PERIODS[0],
+			timer: 0,
+			level: 0,
+			sample_adr: 0xC000,
+			sample_len: 1,
+			current_adr: 0xC000,
+			remaining: 0,
+			buffer: None,
+			shift: 0,
+			bits: 8,
+			silent: true,
+		}
+	}
+}
+
+impl Dmc {
+	fn write(&mut self, register: u16, val: u8) {
+		match register & 0b11 {
+			0 => {
+				self.irq_enabled = val & 0x80 != 0;
+				if !self.irq_enabled {
+					self.irq = false;
+				}
+				self.looping = val & 0x40 != 0;
+				self.period = DMC_PERIODS[val as usize & 0x0F];
+			}
+			1 => self.level = val & 0x7F,
+			2 => self.sample_adr = 0xC000 | (val as u16) << 6,
+			_ => self.sample_len = (val as u16) << 4 | 1,
+		}
+	}
+
+	fn restart(&mut self) {
+		self.current_adr = self.sample_adr;
+		self.remaining = self.sample_len;
+	}
+
+	/// Where the next sample byte comes from, if the DMC wants one.
+	fn wants(&self) -> Option<u16> {
+		(self.buffer.is_none() && self.remaining > 0).then_some(self.current_adr)
+	}
+
+	fn fill(&mut self, val: u8) {
+		self.buffer = Some(val);
+		// Wraps around to $8000, not $0000
+		self.current_adr = self.current_adr.checked_add(1).unwrap_or(0x8000);
+		self.remaining -= 1;
+		if self.remaining == 0 {
+			if self.looping {
+				self.restart();
+			} else if self.irq_enabled {
+				self.irq = true;
+			}
+		}
+	}
+
+	fn tick(&mut self) {
+		if self.timer > 0 {
+			self.timer -= 1;
+			return;
+		}
+		self.timer = self.period - 1;
+
+		if !self.silent {
+			if self.shift & 1 != 0 {
+				if self.level <= 125 {
+					self.level += 2;
+				}
+			} else if self.level >= 2 {
+				self.level -= 2;
+			}
+		}
+		self.shift >>= 1;
+		self.bits -= 1;
+		if self.bits == 0 {
+			self.bits = 8;
+			match self.buffer.take() {
+				Some(byte) => {
+					self.shift = byte;
+					self.silent = false;
+				}
+				None => self.silent = true,
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Apu {
+	pulses: [Pulse; 2],
+	triangle: Triangle,
+	noise: Noise,
+	dmc: Dmc,
+	/// $4015 bits 0-3, whether each of the other channels' length counters can run.
+	enabled: [bool; 4],
+
+	five_step: bool,
+	irq_inhibit: bool,
+	frame_irq: bool,
+	/// CPU cycles into the frame sequence.
+	frame_cycle: u32,
+	odd_cycle: bool,
+}
+
+impl Apu {
+	pub fn new() -> Self {
+		let mut apu = Self::default();
+		apu.pulses[0].first = true;
+		apu
+	}
+
+	pub fn irq(&self) -> bool {
+		self.frame_irq || self.dmc.irq
+	}
+
+	/// $4015, without reading it clearing the frame IRQ.
+	pub fn status(&self) -> u8 {
+		let lengths = [
+			self.pulses[0].length,
+			self.pulses[1].length,
+			self.triangle.length,
+			self.noise.length,
+		];
+		let mut status = (self.frame_irq as u8) << 6 | (self.dmc.irq as u8) << 7;
+		status |= ((self.dmc.remaining > 0) as u8) << 4;
+		for (i, length) in lengths.into_iter().enumerate() {
+			status |= ((length > 0) as u8) << i;
+		}
+		status
+	}
+
+	pub fn read_status(&mut self) -> u8 {
+		let status = self.status();
+		self.frame_irq = false;
+		status
+	}
+
+	/// A write to $4000-$4013, $4015 or $4017.
+	pub fn write(&mut self, adr: u16, val: u8) {
+		match adr {
+			0x4000..=0x4007 => {
+				let i = (adr as usize - 0x4000) / 4;
+				self.pulses[i].write(adr, val, self.enabled[i]);
+			}
+			0x4008..=0x400B => self.triangle.write(adr, val, self.enabled[2]),
+			0x400C..=0x400F => self.noise.write(adr, val, self.enabled[3]),
+			0x4010..=0x4013 => self.dmc.write(adr, val),
+			0x4015 => {
+				for i in 0..4 {
+					self.enabled[i] = val & (1 << i) != 0;
+				}
+				let [pulse_1, pulse_2] = &mut self.pulses;
+				let lengths = [
+					&mut pulse_1.length,
+					&mut pulse_2.length,
+					&mut self.triangle.length,
+					&mut self.noise.length,
+				];
+				for (length, enabled) in lengths.into_iter().zip(self.enabled) {
+					if !enabled {
+						*length = 0;
+					}
+				}
+
+				self.dmc.irq = false;
+				if val & 0x10 == 0 {
+					self.dmc.remaining = 0;
+				} else if self.dmc.remaining == 0 {
+					self.dmc.restart();
+				}
+			}
+			0x4017 => {
+				self.five_step = val & 0x80 != 0;
+				self.irq_inhibit = val & 0x40 != 0;
+				if self.irq_inhibit {
+					self.frame_irq = false;
+				}
+				self.frame_cycle = 0;
+				if self.five_step {
+					self.quarter_frame();
+					self.half_frame();
+				}
+			}
+			_ => {}
+		}
+	}
+
+	/// Where the DMC wants its next sample byte read from, to be handed to `dmc_fill`.
+	pub fn dmc_wants(&self) -> Option<u16> {
+		self.dmc.wants()
+	}
+
+	pub fn dmc_fill(&mut self, val: u8) {
+		self.dmc.fill(val);
+	}
+
+	/// Advances everything by one CPU cycle.
+	pub fn tick(&mut self) {
+		self.odd_cycle = !self.odd_cycle;
+		if self.odd_cycle {
+			self.pulses.iter_mut().for_each(Pulse::tick);
+		}
+		self.triangle.tick();
+		self.noise.tick();
+		self.dmc.tick();
+		self.tick_frame();
+	}
+
+	fn tick_frame(&mut self) {
+		self.frame_cycle += 1;
+		let steps = if self.five_step { FIVE_STEP } else { FOUR_STEP };
+		let Some(step) = steps.iter().position(|&step| step == self.frame_cycle) else {
+			return;
+		};
+		self.quarter_frame();
+		if step % 2 == 1 {
+			self.half_frame();
+		}
+		if step == 3 {
+			if !self.five_step && !self.irq_inhibit {
+				self.frame_irq = true;
+			}
+			self.frame_cycle = 0;
+		}
+	}
+
+	fn quarter_frame(&mut self) {
+		for pulse in &mut self.pulses {
+			pulse.envelope.quarter_frame();
+		}
+		self.triangle.quarter_frame();
+		self.noise.envelope.quarter_frame();
+	}
+
+	fn half_frame(&mut self) {
+		self.pulses.iter_mut().for_each(Pulse::half_frame);
+		self.triangle.half_frame();
+		self.noise.half_frame();
+	}
+
+	/// The level coming out of the console's mixer, 0 to about 1.
+	pub fn output(&self) -> f32 {
+		let pulses = pulse_mix(self.pulses[0].output() + self.pulses[1].output());
+
+		let triangle = self.triangle.output() as f32;
+		let noise = self.noise.output() as f32;
+		let dmc = self.dmc.level as f32;
+		let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+		let tnd = if tnd == 0.0 {
+			0.0
+		} else {
+			159.79 / (1.0 / tnd + 100.0)
+		};
+
+		pulses + tnd
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn run(apu: &mut Apu, cycles: u32) {
+		for _ in 0..cycles {
+			apu.tick();
+		}
+	}
+
+	#[test]
+	fn length_counters_and_status() {
+		let mut apu = Apu::new();
+		// Disabled channels don't take a length
+		apu.write(0x4003, 0x08);
+		assert_eq!(apu.status(), 0);
+
+		apu.write(0x4015, 0x0F);
+		apu.write(0x4000, 0x00);
+		apu.write(0x4003, 0x18); // 2 half frames
+		apu.write(0x400C, 0x20); // Halted
+		apu.write(0x400F, 0x18);
+		assert_eq!(apu.status(), 0b1001);
+
+		run(&mut apu, FOUR_STEP[3]);
+		assert_eq!(apu.status() & 0x0F, 0b1000);
+
+		apu.write(0x4015, 0x00);
+		assert_eq!(apu.status() & 0x0F, 0);
+	}
+
+	#[test]
+	fn frame_irq() {
+		let mut apu = Apu::new();
+		run(&mut apu, FOUR_STEP[3] - 1);
+		assert!(!apu.irq());
+		apu.tick();
+		assert!(apu.irq());
+		assert_eq!(apu.read_status(), 0x40);
+		assert!(!apu.irq());
+
+		// Neither the five step sequence nor an inhibited four step one raise it
+		for val in [0x80, 0x40] {
+			apu.write(0x4017, val);
+			run(&mut apu, FIVE_STEP[3] * 2);
+			assert!(!apu.irq(), "{val:02X}");
+		}
+	}
+
+	#[test]
+	fn mixer_levels() {
+		let mut apu = Apu::new();
+		// The triangle rests at the top of its sequence
+		let resting = apu.output();
+		assert!((resting - 0.246).abs() < 0.001);
+
+		// A 50% pulse at constant volume 15 adds PULSE_LEVEL half the time
+		apu.write(0x4015, 0x01);
+		apu.write(0x4000, 0xBF);
+		apu.write(0x4002, 0x40);
+		apu.write(0x4003, 0x08);
+		let levels = (0..1000)
+			.map(|_| {
+				apu.tick();
+				apu.output() - resting
+			})
+			.collect::<Vec<_>>();
+		let near = |l: f32, level: f32| (l - level).abs() < 1e-6;
+		assert!(levels.iter().any(|&l| near(l, PULSE_LEVEL)));
+		assert!(levels.iter().all(|&l| near(l, 0.0) || near(l, PULSE_LEVEL)));
+		assert!((PULSE_LEVEL - 0.149).abs() < 0.001);
+
+		apu.write(0x4015, 0x00);
+		apu.write(0x4011, 0x7F);
+		assert!((apu.output() - 0.681).abs() < 0.001);
+	}
+
+	#[test]
+	fn dmc_fetches_and_irq() {
+		let mut apu = Apu::new();
+		apu.write(0x4010, 0x8F);
+		apu.write(0x4012, 0xFF); // $FFC0
+		apu.write(0x4013, 0x04); // 65 bytes, through $FFFF to $8000
+		apu.write(0x4015, 0x10);
+		assert_eq!(apu.status() & 0x10, 0x10);
+
+		let mut fetched = Vec::new();
+		while let Some(adr) = apu.dmc_wants() {
+			fetched.push(adr);
+			apu.dmc_fill(0xFF);
+			run(&mut apu, 8 * DMC_PERIODS[15] as u32);
+		}
+		assert_eq!(fetched.len(), 65);
+		assert_eq!(fetched[63], 0xFFFF);
+		assert_eq!(fetched[64], 0x8000);
+		assert!(apu.irq());
+		assert_eq!(apu.status() & 0x90, 0x80);
+
+		// All ones ramp the level up
+		assert!(apu.dmc.level > 100);
+	}
+}
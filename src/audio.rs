@@ -0,0 +1,108 @@
+use std::{
+	collections::VecDeque,
+	sync::{Arc, Mutex},
+};
+
+/// Output sample rate, SDL resamples if the device wants something else.
+pub const SAMPLE_RATE: u32 = 44_100;
+/// NTSC CPU clock, which everything on the cartridge runs off.
+pub const CPU_RATE: f64 = 1_789_773.0;
+
+/// Samples handed over at a time, so the queue's lock isn't taken every sample.
+const CHUNK: usize = 256;
+/// Drop samples past this much latency. Emulation isn't paced by audio, so if it runs ahead
+/// something has to give.
+const MAX_QUEUED: usize = SAMPLE_RATE as usize / 5;
+
+/// Samples on their way from the emulation thread to the audio device.
+pub type SampleQueue = Arc<Mutex<VecDeque<f32>>>;
+
+pub fn new_queue() -> SampleQueue {
+	Arc::new(Mutex::new(VecDeque::new()))
+}
+
+/// Downsamples the per-CPU-cycle output of the sound hardware to `SAMPLE_RATE`.
+#[derive(Debug)]
+pub struct Mixer {
+	queue: SampleQueue,
+	/// Sum of the levels over the current output sample.
+	sum: f32,
+	count: u32,
+	/// CPU cycles left until the next output sample, in fractions of a cycle.
+	until_sample: f64,
+	/// High pass filter state, as the 2A03 and expansion chips output a DC offset.
+	last_in: f32,
+	last_out: f32,
+	pending: Vec<f32>,
+}
+
+impl Mixer {
+	pub fn new(queue: SampleQueue) -> Self {
+		Self {
+			queue,
+			sum: 0.0,
+			count: 0,
+			until_sample: CPU_RATE / SAMPLE_RATE as f64,
+			last_in: 0.0,
+			last_out: 0.0,
+			pending: Vec::with_capacity(CHUNK),
+		}
+	}
+
+	/// Adds one CPU cycle's worth of output. `level` is roughly -1 to 1, the 2A03 on its own
+	/// being 0 to 1.
+	pub fn add(&mut self, level: f32) {
+		self.sum += level;
+		self.count += 1;
+		self.until_sample -= 1.0;
+		if self.until_sample > 0.0 {
+			return;
+		}
+		self.until_sample += CPU_RATE / SAMPLE_RATE as f64;
+
+		let sample = self.sum / self.count as f32;
+		self.sum = 0.0;
+		self.count = 0;
+
+		// One pole high pass at about 20Hz
+		let out = 0.997 * (self.last_out + sample - self.last_in);
+		self.last_in = sample;
+		self.last_out = out;
+
+		self.pending.push(out);
+		if self.pending.len() >= CHUNK {
+			self.flush();
+		}
+	}
+
+	fn flush(&mut self) {
+		let mut queue = self
+			.queue
+			.lock()
+			.expect("Mutex poisoned, not dealing with that");
+		queue.extend(self.pending.drain(..));
+		let excess = queue.len().saturating_sub(MAX_QUEUED);
+		queue.drain(..excess);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn downsamples_to_the_output_rate() {
+		let queue = new_queue();
+		let mut mixer = Mixer::new(queue.clone());
+		for _ in 0..CPU_RATE as usize / 10 {
+			mixer.add(0.5);
+		}
+
+		let queue = queue.lock().unwrap();
+		// A tenth of a second, less whatever's still pending
+		assert!((SAMPLE_RATE as usize / 10 - queue.len()) < CHUNK);
+		// The high pass filter takes the constant level away
+		assert!(queue[0] > 0.4);
+		assert!(queue.back().unwrap().abs() < 0.01);
+	}
+}
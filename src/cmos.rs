@@ -0,0 +1,230 @@
+//! The opcodes the 65C02 runs differently from the NMOS 6502. Everything it runs the same way goes
+//! through `Inst` as usual.
+
+#[cfg(feature = "c-core")]
+use crate::evaluate_instruction::*;
+use crate::{cpu::Backend, inst::UnalignedU16, interpret::State};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CmosInst {
+	AdcIndirect(u8),
+	AndIndirect(u8),
+	BitAbsoluteX(UnalignedU16),
+	BitImmediate(u8),
+	BitZeroPageX(u8),
+	Bra(i8),
+	/// Clears D as well
+	Brk,
+	CmpIndirect(u8),
+	DecAccumulator,
+	EorIndirect(u8),
+	IncAccumulator,
+	/// Without the NMOS one's wrapping within the page, and a cycle longer
+	JmpIndirect(UnalignedU16),
+	JmpIndirectX(UnalignedU16),
+	LdaIndirect(u8),
+	/// The one byte, one cycle NOPs that fill the columns the NMOS parts use for the
+	/// read-modify-write combinations
+	Nop,
+	NopImmediate(u8),
+	NopZeroPage(u8),
+	NopZeroPageX(u8),
+	NopAbsolute(UnalignedU16),
+	/// $5C, which spends eight cycles reading
+	NopAbsolute8(UnalignedU16),
+	OraIndirect(u8),
+	Phx,
+	Phy,
+	Plx,
+	Ply,
+	SbcIndirect(u8),
+	StaIndirect(u8),
+	StzAbsolute(UnalignedU16),
+	StzAbsoluteX(UnalignedU16),
+	StzZeroPage(u8),
+	StzZeroPageX(u8),
+	TrbAbsolute(UnalignedU16),
+	TrbZeroPage(u8),
+	TsbAbsolute(UnalignedU16),
+	TsbZeroPage(u8),
+}
+
+impl CmosInst {
+	/// Decodes the opcodes that the 65C02 changed, `None` meaning it's run as on the NMOS parts.
+	pub fn decode(code: [u8; 3]) -> Option<Self> {
+		let [op, lo, hi] = code;
+		let adr = UnalignedU16::from(u16::from_le_bytes([lo, hi]));
+		Some(match op {
+			0x00 => CmosInst::Brk,
+			0x04 => CmosInst::TsbZeroPage(lo),
+			0x0C => CmosInst::TsbAbsolute(adr),
+			0x12 => CmosInst::OraIndirect(lo),
+			0x14 => CmosInst::TrbZeroPage(lo),
+			0x1A => CmosInst::IncAccumulator,
+			0x1C => CmosInst::TrbAbsolute(adr),
+			0x32 => CmosInst::AndIndirect(lo),
+			0x34 => CmosInst::BitZeroPageX(lo),
+			0x3A => CmosInst::DecAccumulator,
+			0x3C => CmosInst::BitAbsoluteX(adr),
+			0x52 => CmosInst::EorIndirect(lo),
+			0x5A => CmosInst::Phy,
+			0x5C => CmosInst::NopAbsolute8(adr),
+			0x64 => CmosInst::StzZeroPage(lo),
+			0x6C => CmosInst::JmpIndirect(adr),
+			0x72 => CmosInst::AdcIndirect(lo),
+			0x74 => CmosInst::StzZeroPageX(lo),
+			0x7A => CmosInst::Ply,
+			0x7C => CmosInst::JmpIndirectX(adr),
+			0x80 => CmosInst::Bra(lo as i8),
+			0x89 => CmosInst::BitImmediate(lo),
+			0x92 => CmosInst::StaIndirect(lo),
+			0x9C => CmosInst::StzAbsolute(adr),
+			0x9E => CmosInst::StzAbsoluteX(adr),
+			0xB2 => CmosInst::LdaIndirect(lo),
+			0xD2 => CmosInst::CmpIndirect(lo),
+			0xDA => CmosInst::Phx,
+			0xF2 => CmosInst::SbcIndirect(lo),
+			0xFA => CmosInst::Plx,
+			0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xC2 | 0xE2 => CmosInst::NopImmediate(lo),
+			0x44 => CmosInst::NopZeroPage(lo),
+			0x54 | 0xD4 | 0xF4 => CmosInst::NopZeroPageX(lo),
+			0xDC | 0xFC => CmosInst::NopAbsolute(adr),
+			_ if op & 0x03 == 0x03 => CmosInst::Nop,
+			_ => return None,
+		})
+	}
+
+	/// The cores know their own lengths, this is for checking them.
+	#[cfg(test)]
+	pub fn len(&self) -> u8 {
+		match self {
+			CmosInst::Brk
+			| CmosInst::DecAccumulator
+			| CmosInst::IncAccumulator
+			| CmosInst::Nop
+			| CmosInst::Phx
+			| CmosInst::Phy
+			| CmosInst::Plx
+			| CmosInst::Ply => 1,
+			CmosInst::AdcIndirect(..)
+			| CmosInst::AndIndirect(..)
+			| CmosInst::BitImmediate(..)
+			| CmosInst::BitZeroPageX(..)
+			| CmosInst::Bra(..)
+			| CmosInst::CmpIndirect(..)
+			| CmosInst::EorIndirect(..)
+			| CmosInst::LdaIndirect(..)
+			| CmosInst::NopImmediate(..)
+			| CmosInst::NopZeroPage(..)
+			| CmosInst::NopZeroPageX(..)
+			| CmosInst::OraIndirect(..)
+			| CmosInst::SbcIndirect(..)
+			| CmosInst::StaIndirect(..)
+			| CmosInst::StzZeroPage(..)
+			| CmosInst::StzZeroPageX(..)
+			| CmosInst::TrbZeroPage(..)
+			| CmosInst::TsbZeroPage(..) => 2,
+			CmosInst::BitAbsoluteX(..)
+			| CmosInst::JmpIndirect(..)
+			| CmosInst::JmpIndirectX(..)
+			| CmosInst::NopAbsolute(..)
+			| CmosInst::NopAbsolute8(..)
+			| CmosInst::StzAbsolute(..)
+			| CmosInst::StzAbsoluteX(..)
+			| CmosInst::TrbAbsolute(..)
+			| CmosInst::TsbAbsolute(..) => 3,
+		}
+	}
+
+	/// Runs the instruction on whichever CPU the state asks for, like `Inst::evaluate`.
+	pub fn evaluate(&self, state: &mut State) {
+		// The single cycle NOPs are over once the opcode's read
+		state.read(state.cpu.pc);
+		if *self != CmosInst::Nop {
+			state.read(state.cpu.pc.wrapping_add(1));
+		}
+		let decimal = state.cpu.p.d()
+			&& matches!(self, CmosInst::AdcIndirect(..) | CmosInst::SbcIndirect(..));
+
+		match state.backend {
+			#[cfg(feature = "c-core")]
+			Backend::C => self.evaluate_c(state),
+			#[cfg(feature = "rust-core")]
+			Backend::Rust => crate::execute::execute_cmos(*self, state),
+		}
+
+		// Fixing up N and Z after decimal arithmetic takes another cycle
+		if decimal {
+			state.idle(1);
+		}
+	}
+
+	#[cfg(feature = "c-core")]
+	fn evaluate_c(&self, state: &mut State) {
+		match self {
+			CmosInst::AdcIndirect(x) => adc_zero_page_indirect(state, *x),
+			CmosInst::AndIndirect(x) => and_zero_page_indirect(state, *x),
+			CmosInst::BitAbsoluteX(a) => bit_absolute_x(state, a.into()),
+			CmosInst::BitImmediate(x) => bit_immediate(state, *x),
+			CmosInst::BitZeroPageX(x) => bit_zero_page_x(state, *x),
+			CmosInst::Bra(x) => bra(state, *x),
+			CmosInst::Brk => cmos_brk(state),
+			CmosInst::CmpIndirect(x) => cmp_zero_page_indirect(state, *x),
+			CmosInst::DecAccumulator => dec_accumulator(state),
+			CmosInst::EorIndirect(x) => eor_zero_page_indirect(state, *x),
+			CmosInst::IncAccumulator => inc_accumulator(state),
+			CmosInst::JmpIndirect(a) => cmos_jmp_indirect(state, a.into()),
+			CmosInst::JmpIndirectX(a) => jmp_indirect_x(state, a.into()),
+			CmosInst::LdaIndirect(x) => lda_zero_page_indirect(state, *x),
+			CmosInst::Nop => cmos_nop(state),
+			CmosInst::NopImmediate(x) => nop_immediate(state, *x),
+			CmosInst::NopZeroPage(x) => nop_zero_page(state, *x),
+			CmosInst::NopZeroPageX(x) => nop_zero_page_x(state, *x),
+			CmosInst::NopAbsolute(a) => nop_absolute(state, a.into()),
+			CmosInst::NopAbsolute8(a) => cmos_nop_absolute_8(state, a.into()),
+			CmosInst::OraIndirect(x) => ora_zero_page_indirect(state, *x),
+			CmosInst::Phx => phx(state),
+			CmosInst::Phy => phy(state),
+			CmosInst::Plx => plx(state),
+			CmosInst::Ply => ply(state),
+			CmosInst::SbcIndirect(x) => sbc_zero_page_indirect(state, *x),
+			CmosInst::StaIndirect(x) => sta_zero_page_indirect(state, *x),
+			CmosInst::StzAbsolute(a) => stz_absolute(state, a.into()),
+			CmosInst::StzAbsoluteX(a) => stz_absolute_x(state, a.into()),
+			CmosInst::StzZeroPage(x) => stz_zero_page(state, *x),
+			CmosInst::StzZeroPageX(x) => stz_zero_page_x(state, *x),
+			CmosInst::TrbAbsolute(a) => trb_absolute(state, a.into()),
+			CmosInst::TrbZeroPage(x) => trb_zero_page(state, *x),
+			CmosInst::TsbAbsolute(a) => tsb_absolute(state, a.into()),
+			CmosInst::TsbZeroPage(x) => tsb_zero_page(state, *x),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn decodes_the_changed_opcodes() {
+		let changed = (0..=0xFF)
+			.filter(|&op| CmosInst::decode([op, 0x34, 0x12]).is_some())
+			.count();
+		// Every opcode the NMOS parts leave undocumented, plus BRK and JMP indirect
+		assert_eq!(changed, 256 - 151 + 2);
+
+		assert_eq!(CmosInst::decode([0x80, 0xFE, 0]), Some(CmosInst::Bra(-2)));
+		assert_eq!(
+			CmosInst::decode([0x9E, 0x34, 0x12]),
+			Some(CmosInst::StzAbsoluteX(0x1234.into()))
+		);
+		assert_eq!(CmosInst::decode([0xFF, 0, 0]), Some(CmosInst::Nop));
+		assert_eq!(CmosInst::decode([0xA9, 0, 0]), None);
+
+		// The NOPs are as long as the addressing modes of their columns
+		let len = |op| CmosInst::decode([op, 0, 0]).unwrap().len();
+		assert_eq!([0x03, 0x0B, 0xDF].map(len), [1, 1, 1]);
+		assert_eq!([0x02, 0x44, 0xF4].map(len), [2, 2, 2]);
+		assert_eq!([0x5C, 0xDC, 0xFC].map(len), [3, 3, 3]);
+	}
+}
@@ -30,5 +30,78 @@ pub struct Cpu {
 	pub y: u8,
 	pub s: u8,
 	pub p: P,
+	/// Set by the JAM opcodes. Only a reset gets the CPU going again.
+	pub jammed: bool,
 	pub pc: u16,
 }
+
+#[cfg(not(any(feature = "c-core", feature = "rust-core")))]
+compile_error!("Enable at least one of the c-core and rust-core features to get a CPU");
+
+/// Which implementation of the instructions runs the CPU. Both behave the same, so with both
+/// built in they can be swapped to compare them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backend {
+	/// `evaluate_instruction_*.c`
+	#[cfg(feature = "c-core")]
+	C,
+	/// `execute.rs`
+	#[cfg(feature = "rust-core")]
+	Rust,
+}
+
+impl Default for Backend {
+	fn default() -> Self {
+		#[cfg(feature = "c-core")]
+		return Backend::C;
+		#[cfg(not(feature = "c-core"))]
+		return Backend::Rust;
+	}
+}
+
+impl std::str::FromStr for Backend {
+	type Err = anyhow::Error;
+
+	fn from_str(name: &str) -> anyhow::Result<Self> {
+		match name {
+			#[cfg(feature = "c-core")]
+			"c" => Ok(Backend::C),
+			#[cfg(feature = "rust-core")]
+			"rust" => Ok(Backend::Rust),
+			_ if ["c", "rust"].contains(&name) => {
+				anyhow::bail!("This build doesn't include the {name} CPU")
+			}
+			_ => anyhow::bail!("Unknown CPU {name}, expected c or rust"),
+		}
+	}
+}
+
+/// Which member of the 6502 family the CPU is. The NES only ever has the 2A03, the others are
+/// there for running the same cores in other machines.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Variant {
+	/// Ricoh's NES CPU, an NMOS 6502 with decimal mode cut out. D can be set but does nothing.
+	#[default]
+	Ricoh2A03,
+	/// A stock NMOS 6502, where D switches ADC and SBC to BCD.
+	Nmos6502,
+	/// The CMOS 65C02. Decimal mode sets N and Z properly, JMP indirect doesn't wrap within the
+	/// page, interrupts clear D, and the opcodes the NMOS parts leave undocumented are either new
+	/// instructions or NOPs. Its bus timing beyond the instruction lengths isn't emulated, the
+	/// dummy accesses are the NMOS ones.
+	Cmos65C02,
+}
+
+impl std::str::FromStr for Variant {
+	type Err = anyhow::Error;
+
+	fn from_str(name: &str) -> anyhow::Result<Self> {
+		match name.to_ascii_lowercase().as_str() {
+			"2a03" => Ok(Variant::Ricoh2A03),
+			"6502" => Ok(Variant::Nmos6502),
+			"65c02" => Ok(Variant::Cmos65C02),
+			_ => anyhow::bail!("Unknown CPU variant {name}, expected 2a03, 6502 or 65c02"),
+		}
+	}
+}
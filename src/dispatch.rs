@@ -0,0 +1,444 @@
+//! Runs the CPU a basic block at a time: from recompiled C when there's a block for the code at
+//! PC, or else from blocks decoded once and kept. Blocks are keyed by where PC is in PRG ROM and
+//! never leave their 4K page, so switching banks never runs the wrong code through a stale block,
+//! and finding the block for code that's just been switched in is one lookup. Code outside ROM is
+//! never kept, as a write can change it at any time, but where it ran and what was written over it
+//! is tracked to explain what the recompiler misses.
+
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+	cpu::Variant,
+	flow::{self, PAGE_SIZE},
+	inst::Inst,
+	interpret::State,
+	recompile::{CompiledBlock, CompiledPage},
+};
+
+/// Blocks get cut off here, so a long run of code that never jumps doesn't hold up the loop.
+const MAX_BLOCK_LEN: usize = 64;
+
+#[derive(Debug, Default)]
+pub struct BlockCache {
+	/// Recompiled blocks to run instead of interpreting, by page of PRG ROM.
+	compiled: Vec<&'static [CompiledBlock]>,
+	decoded: HashMap<usize, Rc<[Inst]>>,
+	/// Set when a write to the cartridge maps different PRG ROM in, so the block that did it
+	/// stops before running anything past the switch.
+	pub prg_switched: bool,
+	pub stats: BlockStats,
+	pub ram_code: RamCode,
+}
+
+/// Code run from RAM, PRG RAM or anywhere else that isn't ROM: routines copied there to run while
+/// the banks are switched, and code that patches itself.
+#[derive(Debug, Default)]
+pub struct RamCode {
+	/// Every byte an instruction was read from, with the RAM mirrors folded down to $0000-$07FF.
+	pub ran: AdrSet,
+	/// Those written to after they ran.
+	pub rewritten: AdrSet,
+}
+
+impl RamCode {
+	fn record_run(&mut self, pc: u16, len: u16) {
+		for i in 0..len {
+			self.ran.insert(fold_mirrors(pc.wrapping_add(i)));
+		}
+	}
+
+	/// Notes a write over code that's run, called for every write the CPU makes.
+	pub fn record_write(&mut self, adr: u16) {
+		let adr = fold_mirrors(adr);
+		if self.ran.contains(adr) {
+			self.rewritten.insert(adr);
+		}
+	}
+}
+
+fn fold_mirrors(adr: u16) -> u16 {
+	if adr < 0x2000 { adr % 0x0800 } else { adr }
+}
+
+impl fmt::Display for RamCode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.ran.is_empty() {
+			return write!(f, "No code ran from RAM");
+		}
+		write!(f, "Code ran from RAM at {}", Ranges(&self.ran))?;
+		if !self.rewritten.is_empty() {
+			write!(f, ", and was written over at {}", Ranges(&self.rewritten))?;
+		}
+		Ok(())
+	}
+}
+
+/// A set of CPU addresses kept as a bit each, so checking every write against it is one lookup.
+/// It covers the whole address space rather than just RAM and PRG RAM, as the FDS runs its games
+/// from RAM at $6000-$DFFF.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AdrSet(Box<[u64; 0x10000 / 64]>);
+
+impl AdrSet {
+	pub fn insert(&mut self, adr: u16) {
+		self.0[adr as usize / 64] |= 1 << (adr % 64);
+	}
+
+	pub fn contains(&self, adr: u16) -> bool {
+		self.0[adr as usize / 64] & 1 << (adr % 64) != 0
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.iter().all(|&word| word == 0)
+	}
+
+	/// The addresses in the set, lowest first.
+	pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+		(0..=u16::MAX).filter(|&adr| self.contains(adr))
+	}
+}
+
+impl Default for AdrSet {
+	fn default() -> Self {
+		Self(Box::new([0; _]))
+	}
+}
+
+impl fmt::Debug for AdrSet {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_set().entries(self.iter()).finish()
+	}
+}
+
+/// Addresses as runs like `$0300-$031F, $0400`.
+struct Ranges<'a>(&'a AdrSet);
+
+impl fmt::Display for Ranges<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut runs: Vec<(u16, u16)> = Vec::new();
+		for adr in self.0.iter() {
+			match runs.last_mut() {
+				Some((_, end)) if end.checked_add(1) == Some(adr) => *end = adr,
+				_ => runs.push((adr, adr)),
+			}
+		}
+		for (i, (start, end)) in runs.into_iter().enumerate() {
+			if i > 0 {
+				write!(f, ", ")?;
+			}
+			write!(f, "${start:04X}")?;
+			if end != start {
+				write!(f, "-${end:04X}")?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+	/// Blocks run from recompiled C.
+	pub compiled: u64,
+	/// Blocks run from the ones decoded before.
+	pub hits: u64,
+	/// Blocks that had to be decoded first.
+	pub misses: u64,
+	/// Instructions run from RAM, which are never kept as the code can change under them.
+	pub ram: u64,
+	/// CPU cycles spent in recompiled blocks, and everywhere else.
+	pub compiled_cycles: u64,
+	pub interpreted_cycles: u64,
+}
+
+impl fmt::Display for BlockStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let cycles = self.compiled_cycles + self.interpreted_cycles;
+		let percent = self.compiled_cycles as f64 * 100.0 / cycles.max(1) as f64;
+		write!(
+			f,
+			"{} compiled blocks, {} cache hits, {} misses, {} instructions from RAM. {percent:.1}% of cycles recompiled",
+			self.compiled, self.hits, self.misses, self.ram
+		)
+	}
+}
+
+impl BlockCache {
+	pub fn set_compiled(&mut self, pages: &[CompiledPage]) {
+		self.compiled.clear();
+		for page in pages {
+			if self.compiled.len() <= page.page {
+				self.compiled.resize(page.page + 1, &[]);
+			}
+			self.compiled[page.page] = page.blocks();
+		}
+	}
+}
+
+/// Whether a recompiled block should stop before its next instruction and let the dispatcher
+/// take over again.
+#[unsafe(no_mangle)]
+pub unsafe fn state_leave_block(ptr: *const State) -> bool {
+	let state = unsafe { &*ptr };
+	state.irq_pending() || state.blocks.prg_switched
+}
+
+impl State {
+	/// Runs up to the end of the block at PC, stopping early for IRQs and bank switches.
+	pub fn run_block(&mut self) {
+		let start_cycles = self.cycles;
+		let compiled = self.dispatch();
+		let cycles = self.cycles - start_cycles;
+		if compiled {
+			self.blocks.stats.compiled_cycles += cycles;
+		} else {
+			self.blocks.stats.interpreted_cycles += cycles;
+		}
+	}
+
+	/// Whether the block it ran was recompiled.
+	fn dispatch(&mut self) -> bool {
+		// Only the 2A03's instructions are in blocks, and interrupts and JAMs are `next`'s job
+		if self.variant != Variant::Ricoh2A03 || self.cpu.jammed || self.irq_pending() {
+			self.next();
+			return false;
+		}
+
+		let pc = self.cpu.pc;
+		let Some(offset) = self.rom.prg_rom_offset(pc) else {
+			self.blocks.stats.ram += 1;
+			let len = self.next_inst().len() as u16;
+			self.blocks.ram_code.record_run(pc, len);
+			self.next();
+			return false;
+		};
+		// Profiling needs to see every instruction
+		if self.profile.is_some() {
+			self.next();
+			return false;
+		}
+		self.blocks.prg_switched = false;
+
+		if let Some(block) = self.compiled_block(offset) {
+			// SAFETY: the recompiled C gets the same `State` the instruction functions do
+			unsafe { (block.run)(self) };
+			self.blocks.stats.compiled += 1;
+			return true;
+		}
+
+		let insts = match self.blocks.decoded.get(&offset) {
+			Some(insts) => {
+				self.blocks.stats.hits += 1;
+				insts.clone()
+			}
+			None => {
+				let insts = self.decode_block();
+				if insts.is_empty() {
+					// An instruction running on into the next page, which is whatever's mapped in
+					// there each time
+					self.next();
+					return false;
+				}
+				self.blocks.stats.misses += 1;
+				self.blocks.decoded.insert(offset, insts.clone());
+				insts
+			}
+		};
+		for (i, inst) in insts.iter().enumerate() {
+			if i > 0 && (self.irq_pending() || self.blocks.prg_switched) {
+				break;
+			}
+			inst.evaluate(self);
+		}
+		false
+	}
+
+	fn compiled_block(&self, offset: usize) -> Option<&'static CompiledBlock> {
+		let compiled = *self.blocks.compiled.get(offset / PAGE_SIZE)?;
+		let i = compiled
+			.binary_search_by_key(&offset, |block| block.offset)
+			.ok()?;
+		Some(&compiled[i])
+	}
+
+	/// The instructions from PC to the end of the block, as far as they stay in PC's page.
+	pub fn decode_block(&self) -> Rc<[Inst]> {
+		let mut insts = Vec::new();
+		let start = self.cpu.pc;
+		let mut pc = start;
+		while insts.len() < MAX_BLOCK_LEN && flow::same_page(start, pc) {
+			let inst = Inst::from([
+				self.mem_pure(pc),
+				self.mem_pure(pc.wrapping_add(1)),
+				self.mem_pure(pc.wrapping_add(2)),
+			]);
+			let next = pc.wrapping_add(inst.len() as u16);
+			if !flow::same_page(start, next.wrapping_sub(1)) {
+				break;
+			}
+			insts.push(inst);
+			if flow::ends_block(inst) {
+				break;
+			}
+			pc = next;
+		}
+		insts.into()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::collections::BTreeSet;
+
+	use crate::{audio, drawing, nes_file::Mapper, profile::Profile, tests::load, vrc, vrc6::Vrc6};
+
+	use super::*;
+
+	#[test]
+	fn runs_the_same_as_stepping() {
+		#[rustfmt::skip]
+		let program = [
+			0xA2, 0x00,       // LDX #0
+			0xA0, 0x10,       // LDY #$10
+			0xE8,             // loop: INX
+			0x8E, 0x00, 0x03, // STX $0300
+			0x88,             // DEY
+			0xD0, 0xF9,       // BNE loop
+			0xA9, 0x60,       // LDA #$60, RTS
+			0x8D, 0x00, 0x04, // STA $0400
+			0x20, 0x00, 0x04, // JSR $0400
+			0x02,             // JAM
+		];
+		let mut stepped = load(&program);
+		while !stepped.cpu.jammed {
+			stepped.next();
+		}
+		let mut blocks = load(&program);
+		while !blocks.cpu.jammed {
+			blocks.run_block();
+		}
+
+		assert_eq!(blocks.cpu, stepped.cpu);
+		assert_eq!(blocks.cycles, stepped.cycles);
+		assert_eq!(blocks.ram, stepped.ram);
+
+		let stats = blocks.blocks.stats;
+		// The first time round the loop is in the block from the start, the second decodes it
+		// from the top of the loop and the other 14 find it in the cache
+		assert_eq!(stats.hits, 14);
+		assert_eq!(stats.misses, 4);
+		// The RTS in RAM
+		assert_eq!(stats.ram, 1);
+		assert_eq!(stats.interpreted_cycles, blocks.cycles);
+	}
+
+	#[test]
+	fn profiles_returns_one_instruction_at_a_time() {
+		#[rustfmt::skip]
+		let program = [
+			0x20, 0x04, 0x80, // JSR $8004
+			0x02,             // JAM
+			0x60,             // $8004: RTS
+		];
+		let mut state = load(&program);
+		state.profile = Some(Profile::default());
+		while !state.cpu.jammed {
+			state.run_block();
+		}
+
+		let profile = state.profile.unwrap();
+		let mapping = state.rom.prg_mapping();
+		assert_eq!(profile.targets[&mapping], BTreeSet::from([0x8003]));
+		assert_eq!(state.blocks.stats.misses, 0);
+	}
+
+	#[test]
+	fn tracks_code_written_over_in_ram() {
+		#[rustfmt::skip]
+		let program = [
+			0xA9, 0xA9, 0x8D, 0x00, 0x03, // LDA #$A9, STA $0300
+			0xA9, 0x05, 0x8D, 0x01, 0x03, // LDA #5, STA $0301
+			0xA9, 0x60, 0x8D, 0x02, 0x03, // LDA #$60, STA $0302
+			0x20, 0x00, 0x03,             // JSR $0300, to LDA #5, RTS
+			0xAA,                         // TAX
+			0xA9, 0x07, 0x8D, 0x01, 0x0B, // LDA #7, STA $0B01, through a mirror
+			0x20, 0x00, 0x03,             // JSR $0300
+			0x02,                         // JAM
+		];
+		let mut state = load(&program);
+		while !state.cpu.jammed {
+			state.run_block();
+		}
+
+		assert_eq!((state.cpu.a, state.cpu.x), (7, 5));
+		let ram_code = &state.blocks.ram_code;
+		assert_eq!(
+			ram_code.ran.iter().collect::<Vec<_>>(),
+			[0x0300, 0x0301, 0x0302]
+		);
+		assert_eq!(ram_code.rewritten.iter().collect::<Vec<_>>(), [0x0301]);
+		assert_eq!(
+			ram_code.to_string(),
+			"Code ran from RAM at $0300-$0302, and was written over at $0301"
+		);
+	}
+
+	/// VRC6 with the same code in 16K banks 0 and 1: switching to bank 1, then `LDX #x` and a JAM,
+	/// with `x` being $11 in bank 0 and $22 in bank 1.
+	fn switching_banks() -> Box<State> {
+		let (mut prg, chr) = vrc::test::banked_rom();
+		// The same code in 16K banks 0 and 1, up to the write that swaps one for the other
+		let switch = [0xA9, 0x01, 0x8D, 0x00, 0x80]; // LDA #1, STA $8000
+		for (bank, x) in [(0, 0x11), (1, 0x22)] {
+			let code = &mut prg[bank * 0x4000..];
+			code[..5].copy_from_slice(&switch);
+			code[5..8].copy_from_slice(&[0xA2, x, 0x02]); // LDX #x, JAM
+		}
+		let len = prg.len();
+		prg[len - 4..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+		let game = Vrc6::new(&vrc::test::header(26, 0), &prg, &chr).unwrap();
+		Box::new(State::new(
+			Box::new(Mapper::VRC6(game)),
+			drawing::new_bitmap(),
+			audio::new_queue(),
+		))
+	}
+
+	#[test]
+	fn stops_blocks_at_bank_switches() {
+		let mut state = switching_banks();
+		while !state.cpu.jammed {
+			state.run_block();
+		}
+		assert_eq!(state.cpu.x, 0x22);
+		assert_eq!(state.blocks.stats.misses, 2);
+	}
+
+	unsafe extern "C" fn ldx_33(ptr: *mut State) -> u16 {
+		let state = unsafe { &mut *ptr };
+		state.cpu.x = 0x33;
+		state.cpu.jammed = true;
+		state.cpu.pc
+	}
+
+	static PAGE_4: [CompiledBlock; 1] = [CompiledBlock {
+		offset: 0x4005,
+		run: ldx_33,
+	}];
+	static PAGES: [CompiledPage; 1] = [CompiledPage {
+		page: 4,
+		blocks: &raw const PAGE_4 as *const CompiledBlock,
+		count: 1,
+	}];
+
+	#[test]
+	fn finds_compiled_blocks_by_offset() {
+		let mut state = switching_banks();
+		state.blocks.set_compiled(&PAGES);
+		while !state.cpu.jammed {
+			state.run_block();
+		}
+		// The code after the switch is at $8005 either way, but only bank 1's is recompiled
+		assert_eq!(state.cpu.x, 0x33);
+		assert_eq!(state.blocks.stats.compiled, 1);
+	}
+}
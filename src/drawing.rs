@@ -1,8 +1,17 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, mpsc::Sender};
 
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum, rect::Rect};
+use sdl2::{
+	audio::{AudioCallback, AudioSpecDesired},
+	event::Event,
+	keyboard::Keycode,
+	pixels::PixelFormatEnum,
+	rect::Rect,
+};
+
+use crate::audio::{self, SampleQueue};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[repr(C)]
 pub struct Colour {
 	pub red: u8,
 	pub green: u8,
@@ -10,6 +19,16 @@ pub struct Colour {
 	pub alpha: u8,
 }
 
+/// Things the window asks of the emulation thread.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+	/// Eject the disk and put the next side in.
+	NextDiskSide,
+	/// Change tracks when playing music.
+	NextTrack,
+	PreviousTrack,
+}
+
 pub const WIDTH: usize = 256;
 pub const HEIGHT: usize = 240;
 
@@ -23,10 +42,54 @@ pub fn new_bitmap() -> Arc<Mutex<Bitmap>> {
 	Arc::new(Mutex::new(empty_bitmap()))
 }
 
-pub fn sdl_thread(texture_ptr: Arc<Mutex<Bitmap>>) -> Result<(), String> {
+/// Feeds the audio device from the emulation thread's samples, with silence when it runs dry.
+struct Speaker {
+	samples: SampleQueue,
+}
+
+impl AudioCallback for Speaker {
+	type Channel = f32;
+
+	fn callback(&mut self, out: &mut [f32]) {
+		let mut samples = self
+			.samples
+			.lock()
+			.expect("Mutex poisoned, not dealing with that");
+		for dst in out {
+			*dst = samples.pop_front().unwrap_or(0.0);
+		}
+	}
+}
+
+pub fn sdl_thread(
+	texture_ptr: Arc<Mutex<Bitmap>>,
+	samples: SampleQueue,
+	commands: Sender<Command>,
+	caption: Arc<Mutex<String>>,
+) -> Result<(), String> {
 	let sdl_context = sdl2::init()?;
 	let video_subsystem = sdl_context.video()?;
 
+	// No sound isn't worth stopping for
+	let desired = AudioSpecDesired {
+		freq: Some(audio::SAMPLE_RATE as i32),
+		channels: Some(1),
+		samples: Some(1024),
+	};
+	let _speaker = match sdl_context
+		.audio()
+		.and_then(|audio| audio.open_playback(None, &desired, |_| Speaker { samples }))
+	{
+		Ok(device) => {
+			device.resume();
+			Some(device)
+		}
+		Err(e) => {
+			eprintln!("Couldn't open an audio device, carrying on without sound: {e}");
+			None
+		}
+	};
+
 	let window = video_subsystem
 		.window("Pixel Test", 800, 600)
 		.resizable()
@@ -46,6 +109,7 @@ pub fn sdl_thread(texture_ptr: Arc<Mutex<Bitmap>>) -> Result<(), String> {
 		.map_err(|e| e.to_string())?;
 
 	let mut event_pump = sdl_context.event_pump()?;
+	let mut shown_caption = String::new();
 
 	'running: loop {
 		for event in event_pump.poll_iter() {
@@ -55,10 +119,37 @@ pub fn sdl_thread(texture_ptr: Arc<Mutex<Bitmap>>) -> Result<(), String> {
 					keycode: Some(Keycode::Escape | Keycode::Q),
 					..
 				} => break 'running,
+				Event::KeyDown {
+					keycode: Some(keycode),
+					repeat: false,
+					..
+				} => {
+					let command = match keycode {
+						Keycode::S => Command::NextDiskSide,
+						Keycode::Right => Command::NextTrack,
+						Keycode::Left => Command::PreviousTrack,
+						_ => continue,
+					};
+					// The emulation thread only goes away when we do
+					let _ = commands.send(command);
+				}
 				_ => {}
 			}
 		}
 
+		{
+			let caption = caption
+				.lock()
+				.expect("Mutex poisoned, not dealing with that");
+			if *caption != shown_caption {
+				shown_caption.clone_from(&caption);
+				canvas
+					.window_mut()
+					.set_title(&shown_caption)
+					.map_err(|e| e.to_string())?;
+			}
+		}
+
 		let (win_w, win_h) = canvas.window().size();
 		let size = win_w.min(win_h);
 
@@ -153,6 +153,7 @@ unsafe extern "C" {
 	pub safe fn txa(state: &mut State);
 	pub safe fn txs(state: &mut State);
 	pub safe fn tya(state: &mut State);
+	pub safe fn lax_immediate(state: &mut State, val: u8);
 	pub safe fn lax_zero_page(state: &mut State, val: u8);
 	pub safe fn lax_zero_page_y(state: &mut State, val: u8);
 	pub safe fn lax_absolute(state: &mut State, val: u16);
@@ -205,14 +206,53 @@ unsafe extern "C" {
 	pub safe fn sre_absolute_y(state: &mut State, val: u16);
 	pub safe fn sre_indirect_x(state: &mut State, val: u8);
 	pub safe fn sre_indirect_y(state: &mut State, val: u8);
-	pub safe fn anc(state: &mut State, val: u8);
-	pub safe fn alr(state: &mut State, val: u8);
-	pub safe fn arr(state: &mut State, val: u8);
-	pub safe fn axs(state: &mut State, val: u8);
-	pub safe fn las(state: &mut State, val: u8);
-	pub safe fn tas(state: &mut State, val: u8);
-	pub safe fn shy(state: &mut State, val: u8);
-	pub safe fn shx(state: &mut State, val: u8);
+	pub safe fn anc_immediate(state: &mut State, val: u8);
+	pub safe fn alr_immediate(state: &mut State, val: u8);
+	pub safe fn arr_immediate(state: &mut State, val: u8);
+	pub safe fn axs_immediate(state: &mut State, val: u8);
+	pub safe fn xaa_immediate(state: &mut State, val: u8);
+	pub safe fn las_absolute_y(state: &mut State, val: u16);
+	pub safe fn nop_immediate(state: &mut State, val: u8);
+	pub safe fn nop_zero_page(state: &mut State, val: u8);
+	pub safe fn nop_zero_page_x(state: &mut State, val: u8);
+	pub safe fn nop_absolute(state: &mut State, val: u16);
+	pub safe fn nop_absolute_x(state: &mut State, val: u16);
+	pub safe fn shy_absolute_x(state: &mut State, val: u16);
+	pub safe fn shx_absolute_y(state: &mut State, val: u16);
 	pub safe fn ahx_absolute_y(state: &mut State, val: u16);
 	pub safe fn ahx_indirect_y(state: &mut State, val: u8);
+	pub safe fn tas_absolute_y(state: &mut State, val: u16);
+
+	// 65C02
+	pub safe fn adc_zero_page_indirect(state: &mut State, val: u8);
+	pub safe fn and_zero_page_indirect(state: &mut State, val: u8);
+	pub safe fn cmp_zero_page_indirect(state: &mut State, val: u8);
+	pub safe fn eor_zero_page_indirect(state: &mut State, val: u8);
+	pub safe fn lda_zero_page_indirect(state: &mut State, val: u8);
+	pub safe fn ora_zero_page_indirect(state: &mut State, val: u8);
+	pub safe fn sbc_zero_page_indirect(state: &mut State, val: u8);
+	pub safe fn sta_zero_page_indirect(state: &mut State, val: u8);
+	pub safe fn bit_zero_page_x(state: &mut State, val: u8);
+	pub safe fn bit_absolute_x(state: &mut State, val: u16);
+	pub safe fn bit_immediate(state: &mut State, val: u8);
+	pub safe fn inc_accumulator(state: &mut State);
+	pub safe fn dec_accumulator(state: &mut State);
+	pub safe fn bra(state: &mut State, val: i8);
+	pub safe fn cmos_brk(state: &mut State);
+	pub safe fn cmos_jmp_indirect(state: &mut State, adr: u16);
+	pub safe fn jmp_indirect_x(state: &mut State, adr: u16);
+	pub safe fn cmos_nop(state: &mut State);
+	pub safe fn cmos_nop_absolute_8(state: &mut State, adr: u16);
+	pub safe fn phx(state: &mut State);
+	pub safe fn phy(state: &mut State);
+	pub safe fn plx(state: &mut State);
+	pub safe fn ply(state: &mut State);
+	pub safe fn stz_zero_page(state: &mut State, val: u8);
+	pub safe fn stz_zero_page_x(state: &mut State, val: u8);
+	pub safe fn stz_absolute(state: &mut State, val: u16);
+	pub safe fn stz_absolute_x(state: &mut State, val: u16);
+	pub safe fn tsb_zero_page(state: &mut State, val: u8);
+	pub safe fn tsb_absolute(state: &mut State, val: u16);
+	pub safe fn trb_zero_page(state: &mut State, val: u8);
+	pub safe fn trb_absolute(state: &mut State, val: u16);
 }
@@ -3,7 +3,36 @@
 
 // C-implementations of NES instructions
 
+// With D set on chips that have decimal mode, ADC adds two BCD numbers. The NMOS parts take Z from
+// the binary sum and N and V from before the high digit is adjusted, the 65C02 sets N and Z from
+// the result.
+static void adc_decimal(State *state, uint8_t val) {
+	uint8_t a     = state->cpu.a;
+	uint8_t carry = state->cpu.p.C;
+	uint8_t low   = (uint8_t) ((a & 0x0F) + (val & 0x0F) + carry);
+	if (low > 0x09) {
+		low = (uint8_t) (((low + 0x06) & 0x0F) + 0x10);
+	}
+	uint16_t sum   = (uint16_t) ((a & 0xF0) + (val & 0xF0) + low);
+	state->cpu.p.Z = (uint8_t) (a + val + carry) == 0;
+	state->cpu.p.N = (sum & 0x80) != 0;
+	state->cpu.p.V = ((a ^ sum) & (val ^ sum) & 0x80) != 0;
+	if (sum > 0x9F) {
+		sum = (uint16_t) (sum + 0x60);
+	}
+	state->cpu.p.C = sum > 0xFF;
+	state->cpu.a   = (uint8_t) sum;
+	if (state_variant(state) == CMOS_65C02) {
+		state->cpu.p.Z = 0 == state->cpu.a;
+		state->cpu.p.N = (state->cpu.a & 0x80) != 0;
+	}
+}
+
 void adc_impl(State *state, uint8_t val) {
+	if (state->cpu.p.D && state_variant(state) != RICOH_2A03) {
+		adc_decimal(state, val);
+		return;
+	}
 	uint16_t res = (uint16_t) state->cpu.a + (uint16_t) state->cpu.p.C + (uint16_t) val;
 
 	state->cpu.p.C = res > 255;
@@ -52,33 +81,15 @@ ABSOLUTE_RMW(asl);
 ABSOLUTE_X_RMW(asl);
 
 void bcc(State *state, int8_t offset) {
-	uint16_t old_pc   = state->cpu.pc;
-	bool taken        = !state->cpu.p.C;
-	uint16_t new_pc   = old_pc + 2 + (taken ? (uint16_t) offset : 0);
-	bool page_crossed = ((old_pc + 2) & 0xFF00) != (new_pc & 0xFF00);
-	uint8_t cycles    = 2 + (taken ? 1 : 0) + (page_crossed ? 1 : 0);
-	state->cpu.pc     = new_pc;
-	state_step_ppu_many(state, cycles);
+	branch(state, offset, !state->cpu.p.C);
 }
 
 void bcs(State *state, int8_t offset) {
-	uint16_t old_pc   = state->cpu.pc;
-	bool taken        = state->cpu.p.C;
-	uint16_t new_pc   = old_pc + 2 + (taken ? (uint16_t) offset : 0);
-	bool page_crossed = ((old_pc + 2) & 0xFF00) != (new_pc & 0xFF00);
-	uint8_t cycles    = 2 + (taken ? 1 : 0) + (page_crossed ? 1 : 0);
-	state->cpu.pc     = new_pc;
-	state_step_ppu_many(state, cycles);
+	branch(state, offset, state->cpu.p.C);
 }
 
 void beq(State *state, int8_t offset) {
-	uint16_t old_pc   = state->cpu.pc;
-	bool taken        = state->cpu.p.Z;
-	uint16_t new_pc   = old_pc + 2 + (taken ? (uint16_t) offset : 0);
-	bool page_crossed = ((old_pc + 2) & 0xFF00) != (new_pc & 0xFF00);
-	uint8_t cycles    = 2 + (taken ? 1 : 0) + (page_crossed ? 1 : 0);
-	state->cpu.pc     = new_pc;
-	state_step_ppu_many(state, cycles);
+	branch(state, offset, state->cpu.p.Z);
 }
 
 void bit_impl(State *state, uint8_t val) {
@@ -91,64 +102,37 @@ ZERO_PAGE(bit);
 ABSOLUTE(bit);
 
 void bmi(State *state, int8_t offset) {
-	uint16_t old_pc   = state->cpu.pc;
-	bool taken        = state->cpu.p.N;
-	uint16_t new_pc   = old_pc + 2 + (taken ? (uint16_t) offset : 0);
-	bool page_crossed = ((old_pc + 2) & 0xFF00) != (new_pc & 0xFF00);
-	uint8_t cycles    = 2 + (taken ? 1 : 0) + (page_crossed ? 1 : 0);
-	state->cpu.pc     = new_pc;
-	state_step_ppu_many(state, cycles);
+	branch(state, offset, state->cpu.p.N);
 }
 
 void bne(State *state, int8_t offset) {
-	uint16_t old_pc   = state->cpu.pc;
-	bool taken        = !state->cpu.p.Z;
-	uint16_t new_pc   = old_pc + 2 + (taken ? (uint16_t) offset : 0);
-	bool page_crossed = ((old_pc + 2) & 0xFF00) != (new_pc & 0xFF00);
-	uint8_t cycles    = 2 + (taken ? 1 : 0) + (page_crossed ? 1 : 0);
-	state->cpu.pc     = new_pc;
-	state_step_ppu_many(state, cycles);
+	branch(state, offset, !state->cpu.p.Z);
 }
 
 void bpl(State *state, int8_t offset) {
-	uint16_t old_pc   = state->cpu.pc;
-	bool taken        = !state->cpu.p.N;
-	uint16_t new_pc   = old_pc + 2 + (taken ? (uint16_t) offset : 0);
-	bool page_crossed = ((old_pc + 2) & 0xFF00) != (new_pc & 0xFF00);
-	uint8_t cycles    = 2 + (taken ? 1 : 0) + (page_crossed ? 1 : 0);
-	state->cpu.pc     = new_pc;
-	state_step_ppu_many(state, cycles);
+	branch(state, offset, !state->cpu.p.N);
 }
 
 void brk(State *state) {
-	// BRK is a complex instruction that pushes PC+2 and status flags
-	// This is a simplified version for demonstration
-	state->cpu.pc++;
-	state_step_ppu_many(state, 2);
+	// The byte after BRK is skipped, it's there for the handler to look at
+	uint16_t ret = (uint16_t) (state->cpu.pc + 2);
+	push(state, (uint8_t) (ret >> 8));
+	push(state, (uint8_t) ret);
+	push(state, state->cpu.p.raw | 0x30);
+	state->cpu.p.I = 1;
+	uint8_t low    = state_read(state, 0xFFFE);
+	state->cpu.pc  = (uint16_t) (low | state_read(state, 0xFFFF) << 8);
 }
 
 void bvc(State *state, int8_t offset) {
-	uint16_t old_pc   = state->cpu.pc;
-	bool taken        = !state->cpu.p.V;
-	uint16_t new_pc   = old_pc + 2 + (taken ? (uint16_t) offset : 0);
-	bool page_crossed = ((old_pc + 2) & 0xFF00) != (new_pc & 0xFF00);
-	uint8_t cycles    = 2 + (taken ? 1 : 0) + (page_crossed ? 1 : 0);
-	state->cpu.pc     = new_pc;
-	state_step_ppu_many(state, cycles);
+	branch(state, offset, !state->cpu.p.V);
 }
 
 void bvs(State *state, int8_t offset) {
-	uint16_t old_pc   = state->cpu.pc;
-	bool taken        = state->cpu.p.V;
-	uint16_t new_pc   = old_pc + 2 + (taken ? (uint16_t) offset : 0);
-	bool page_crossed = ((old_pc + 2) & 0xFF00) != (new_pc & 0xFF00);
-	uint8_t cycles    = 2 + (taken ? 1 : 0) + (page_crossed ? 1 : 0);
-	state->cpu.pc     = new_pc;
-	state_step_ppu_many(state, cycles);
+	branch(state, offset, state->cpu.p.V);
 }
 
 void clc(State *state) {
 	state->cpu.p.C = 0;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
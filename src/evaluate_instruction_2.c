@@ -5,19 +5,16 @@
 void cld(State *state) {
 	state->cpu.p.D = 0;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void cli(State *state) {
 	state->cpu.p.I = 0;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void clv(State *state) {
 	state->cpu.p.V = 0;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void cmp_impl(State *state, uint8_t val) {
@@ -58,23 +55,22 @@ IMMEDIATE(cpy);
 ZERO_PAGE(cpy);
 ABSOLUTE(cpy);
 
-void dec_impl(State *state, uint8_t val) {
-	val--;
-	state->cpu.p.Z = 0 == val;
-	state->cpu.p.N = (val & 0x80) >> 7;
+void dec_impl(State *state, uint8_t *val) {
+	(*val)--;
+	state->cpu.p.Z = 0 == *val;
+	state->cpu.p.N = (*val & 0x80) >> 7;
 }
 
-ZERO_PAGE(dec);
-ZERO_PAGE_X(dec);
-ABSOLUTE(dec);
-ABSOLUTE_X(dec);
+ZERO_PAGE_RMW(dec);
+ZERO_PAGE_X_RMW(dec);
+ABSOLUTE_RMW(dec);
+ABSOLUTE_X_RMW(dec);
 
 void dex(State *state) {
 	state->cpu.x--;
 	state->cpu.p.Z = 0 == state->cpu.x;
 	state->cpu.p.N = (state->cpu.x & 0x80) >> 7;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void dey(State *state) {
@@ -82,7 +78,6 @@ void dey(State *state) {
 	state->cpu.p.Z = 0 == state->cpu.y;
 	state->cpu.p.N = (state->cpu.y & 0x80) >> 7;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void eor_impl(State *state, uint8_t val) {
@@ -100,23 +95,22 @@ ABSOLUTE_Y(eor);
 INDIRECT_X(eor);
 INDIRECT_Y(eor);
 
-void inc_impl(State *state, uint8_t val) {
-	val++;
-	state->cpu.p.Z = 0 == val;
-	state->cpu.p.N = (val & 0x80) >> 7;
+void inc_impl(State *state, uint8_t *val) {
+	(*val)++;
+	state->cpu.p.Z = 0 == *val;
+	state->cpu.p.N = (*val & 0x80) >> 7;
 }
 
-ZERO_PAGE(inc);
-ZERO_PAGE_X(inc);
-ABSOLUTE(inc);
-ABSOLUTE_X(inc);
+ZERO_PAGE_RMW(inc);
+ZERO_PAGE_X_RMW(inc);
+ABSOLUTE_RMW(inc);
+ABSOLUTE_X_RMW(inc);
 
 void inx(State *state) {
 	state->cpu.x++;
 	state->cpu.p.Z = 0 == state->cpu.x;
 	state->cpu.p.N = (state->cpu.x & 0x80) >> 7;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void iny(State *state) {
@@ -124,44 +118,28 @@ void iny(State *state) {
 	state->cpu.p.Z = 0 == state->cpu.y;
 	state->cpu.p.N = (state->cpu.y & 0x80) >> 7;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void jmp_absolute(State *state, uint16_t adr) {
+	fetch_high(state);
 	state->cpu.pc = adr;
-	state_step_ppu_many(state, 4);
 }
 
 void jmp_indirect(State *state, uint16_t adr) {
-	// Read the adress from memory at adr
-	uint16_t low_byte  = state_get_mem(state, adr);
-	uint16_t high_byte = state_get_mem(state, adr + 1);
-	state->cpu.pc      = (uint16_t) ((high_byte << 8) | low_byte);
-	state_step_ppu_many(state, 2);
+	// The pointer's high byte comes from the start of the same page when it straddles two
+	fetch_high(state);
+	uint16_t high_adr = (adr & 0xFF00) | ((adr + 1) & 0x00FF);
+	uint8_t low       = state_read(state, adr);
+	state->cpu.pc     = (uint16_t) (low | state_read(state, high_adr) << 8);
 }
 
 void jsr(State *state, uint16_t adr) {
-	// Push return adress (pc + 2) onto stack
-	uint16_t return_adr = state->cpu.pc + 2;
-
-	// Stack pointer is at cpu.s, but we need to adjust for the stack behaviour
-	// The stack grows downwards from 0x1FF to 0x100
-	uint8_t stack_ptr = state->cpu.s;
-
-	// Push high byte first (stack grows downward)
-	uint8_t high_byte = (return_adr >> 8) & 0xFF;
-	state_set_mem(state, 0x100 + stack_ptr, high_byte);
-	stack_ptr--;
-
-	// Push low byte
-	uint8_t low_byte = return_adr & 0xFF;
-	state_set_mem(state, 0x100 + stack_ptr, low_byte);
-	stack_ptr--;
-
-	// Update stack pointer (stack grows downward)
-	state->cpu.s = stack_ptr;
-
-	// Jump to subroutine
+	// Pushes the address of its own last byte, which RTS makes up for. That byte is only fetched
+	// after the pushes.
+	uint16_t return_adr = (uint16_t) (state->cpu.pc + 2);
+	peek_stack(state);
+	push(state, (uint8_t) (return_adr >> 8));
+	push(state, (uint8_t) return_adr);
+	fetch_high(state);
 	state->cpu.pc = adr;
-	state_step_ppu_many(state, 2);
 }
@@ -71,31 +71,28 @@ INDIRECT_X(ora);
 INDIRECT_Y(ora);
 
 void pha(State *state) {
-	state_set_mem(state, (uint16_t) (state->cpu.s + 0x100), state->cpu.a);
-	state->cpu.s -= 1;
-	state_step_ppu_many(state, 2);
+	push(state, state->cpu.a);
+	state->cpu.pc += 1;
 }
 
 void php(State *state) {
-	uint8_t val = state->cpu.p.raw | 0b00110000;
-	state_set_mem(state, (uint16_t) (state->cpu.s + 0x100), val);
-	state->cpu.s -= 1;
+	push(state, state->cpu.p.raw | 0b00110000);
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void pla(State *state) {
-	state->cpu.s += 1;
-	state->cpu.a = state_get_mem(state, (uint16_t) (state->cpu.s + 0x100));
+	peek_stack(state);
+	state->cpu.a   = pull(state);
+	state->cpu.p.Z = (uint8_t) (0 == state->cpu.a);
+	state->cpu.p.N = (uint8_t) ((state->cpu.a & 0x80) >> 7);
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
+// B and bit 5 only exist in the pushed copies of P
 void plp(State *state) {
-	state->cpu.s += 1;
-	state->cpu.p.raw = state_get_mem(state, (uint16_t) (state->cpu.s + 0x100));
+	peek_stack(state);
+	state->cpu.p.raw = pull(state) & 0b11001111;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void rol_impl(State *state, uint8_t *val) {
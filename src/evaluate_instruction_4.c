@@ -1,18 +1,51 @@
 #include "interface.h"
 #include <stdint.h>
-#include <stdio.h>
 
 // C-implementations of NES instructions
 
+// Decimal SBC leaves the flags as the binary one sets them, apart from the 65C02 setting N and Z
+// from the result
+static void sbc_decimal(State *state, uint8_t a, uint8_t val, int borrow) {
+	int low = (a & 0x0F) - (val & 0x0F) - borrow;
+	int res;
+	if (state_variant(state) == CMOS_65C02) {
+		res = a - val - borrow;
+		if (res < 0) {
+			res -= 0x60;
+		}
+		if (low < 0) {
+			res -= 0x06;
+		}
+		state->cpu.a   = (uint8_t) res;
+		state->cpu.p.Z = 0 == state->cpu.a;
+		state->cpu.p.N = (state->cpu.a & 0x80) != 0;
+	} else {
+		if (low < 0) {
+			low = ((low - 0x06) & 0x0F) - 0x10;
+		}
+		res = (a & 0xF0) - (val & 0xF0) + low;
+		if (res < 0) {
+			res -= 0x60;
+		}
+		state->cpu.a = (uint8_t) res;
+	}
+}
+
 void sbc_impl(State *state, uint8_t val) {
-	uint16_t res = (uint16_t) state->cpu.a - (uint16_t) val - (uint16_t) (1 - state->cpu.p.C);
+	uint8_t a    = state->cpu.a;
+	int borrow   = 1 - state->cpu.p.C;
+	uint16_t res = (uint16_t) state->cpu.a - (uint16_t) val - (uint16_t) borrow;
 
 	state->cpu.p.C = res < 256;
 	state->cpu.p.Z = 0 == (uint8_t) res;
-	state->cpu.p.V =
-	    ((res ^ (uint16_t) state->cpu.a) & (res ^ (uint16_t) val) & (uint16_t) 0x80) != 0;
+	// Overflow when the operands' signs differ and the result's sign isn't A's
+	state->cpu.p.V = ((a ^ val) & (a ^ res) & 0x80) != 0;
 	state->cpu.p.N = (res & 0x80) >> 7;
 	state->cpu.a   = (uint8_t) res;
+
+	if (state->cpu.p.D && state_variant(state) != RICOH_2A03) {
+		sbc_decimal(state, a, val, borrow);
+	}
 }
 
 IMMEDIATE(sbc);
@@ -27,93 +60,51 @@ INDIRECT_Y(sbc);
 void sec(State *state) {
 	state->cpu.p.C = 1;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void sed(State *state) {
 	state->cpu.p.D = 1;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void sei(State *state) {
 	state->cpu.p.I = 1;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
-}
-
-void sta_zero_page(State *state, uint8_t offset) {
-	state_set_mem(state, (uint16_t) offset, state->cpu.a);
-	state->cpu.pc += 2;
-	state_step_ppu_many(state, 3);
-};
-
-void sta_zero_page_x(State *state, uint8_t offset) {
-	state_set_mem(state, ((uint16_t) state->cpu.x + (uint16_t) offset) & 0xFF, state->cpu.a);
-	state->cpu.pc += 2;
-	state_step_ppu_many(state, 4);
-};
-
-void sta_absolute(State *state, uint16_t adr) {
-	state_set_mem(state, adr, state->cpu.a);
-	state->cpu.pc += 3;
-	state_step_ppu_many(state, 4);
-};
-
-void sta_absolute_x(State *state, uint16_t adr) {
-	state_set_mem(state, (uint16_t) state->cpu.x + adr, state->cpu.a);
-	state->cpu.pc += 3;
-	state_step_ppu_many(state, 3);
-};
-
-void sta_absolute_y(State *state, uint16_t adr) {
-	state_set_mem(state, (uint16_t) state->cpu.y + adr, state->cpu.a);
-	state->cpu.pc += 3;
-	state_step_ppu_many(state, 3);
-};
-
-void sta_indirect_x(State *state, uint8_t adr) {
-	uint8_t tmp   = state_get_mem(state, (uint16_t) (state->cpu.x + adr) & 0xFF);
-	uint16_t adr2 = (uint16_t) (state_get_mem(state, (uint16_t) tmp)
-				    | state_get_mem(state, (uint16_t) (tmp + 1) & 0xFF) << 8);
-	uint8_t val   = state_get_mem(state, adr2);
-	state_set_mem(state, (uint16_t) val, state->cpu.a);
-	state->cpu.pc += 2;
-	state_step_ppu_many(state, 2);
-};
-
-void sta_indirect_y(State *state, uint8_t adr) {
-	uint8_t tmp   = state_get_mem(state, (uint16_t) (state->cpu.y + adr) & 0xFF);
-	uint16_t adr2 = (uint16_t) (state_get_mem(state, (uint16_t) tmp)
-				    | state_get_mem(state, (uint16_t) (tmp + 1) & 0xFF) << 8);
-	uint8_t val   = state_get_mem(state, adr2);
-	state_set_mem(state, (uint16_t) val, state->cpu.a);
-	state->cpu.pc += 2;
-	state_step_ppu_many(state, 2);
-};
-
-void stx_impl(State *state, uint8_t val) {
-	puts("unimplemented");
-}
-
-ZERO_PAGE(stx);
-ZERO_PAGE_Y(stx);
-ABSOLUTE(stx);
-
-void sty_impl(State *state, uint8_t val) {
-	puts("unimplemented");
-}
-
-ZERO_PAGE(sty);
-ZERO_PAGE_X(sty);
-ABSOLUTE(sty);
+}
+
+uint8_t sta_impl(State *state) {
+	return state->cpu.a;
+}
+
+ZERO_PAGE_STORE(sta);
+ZERO_PAGE_X_STORE(sta);
+ABSOLUTE_STORE(sta);
+ABSOLUTE_X_STORE(sta);
+ABSOLUTE_Y_STORE(sta);
+INDIRECT_X_STORE(sta);
+INDIRECT_Y_STORE(sta);
+
+uint8_t stx_impl(State *state) {
+	return state->cpu.x;
+}
+
+ZERO_PAGE_STORE(stx);
+ZERO_PAGE_Y_STORE(stx);
+ABSOLUTE_STORE(stx);
+
+uint8_t sty_impl(State *state) {
+	return state->cpu.y;
+}
+
+ZERO_PAGE_STORE(sty);
+ZERO_PAGE_X_STORE(sty);
+ABSOLUTE_STORE(sty);
 
 void tax(State *state) {
 	state->cpu.x   = state->cpu.a;
 	state->cpu.p.Z = 0 == state->cpu.x;
 	state->cpu.p.N = (state->cpu.x & 0x80) >> 7;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void tay(State *state) {
@@ -121,7 +112,6 @@ void tay(State *state) {
 	state->cpu.p.Z = 0 == state->cpu.y;
 	state->cpu.p.N = (state->cpu.y & 0x80) >> 7;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void tsx(State *state) {
@@ -129,7 +119,6 @@ void tsx(State *state) {
 	state->cpu.p.Z = 0 == state->cpu.x;
 	state->cpu.p.N = (state->cpu.x & 0x80) >> 7;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void txa(State *state) {
@@ -137,13 +126,11 @@ void txa(State *state) {
 	state->cpu.p.Z = 0 == state->cpu.a;
 	state->cpu.p.N = (state->cpu.a & 0x80) >> 7;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void txs(State *state) {
 	state->cpu.s = state->cpu.x;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void tya(State *state) {
@@ -151,24 +138,25 @@ void tya(State *state) {
 	state->cpu.p.Z = 0 == state->cpu.a;
 	state->cpu.p.N = (state->cpu.y & 0x80) >> 7;
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
 
 void rti(State *state) {
-	state->cpu.s += 1;
-	state->cpu.p.raw = state_get_mem(state, (uint16_t) (state->cpu.s + 0x100));
-	state->cpu.s += 1;
-	state->cpu.pc = state_get_mem(state, (uint16_t) (state->cpu.s + 0x100));
-	state_step_ppu_many(state, 2);
+	peek_stack(state);
+	// B and bit 5 only exist in the pushed copies of P
+	state->cpu.p.raw = pull(state) & 0b11001111;
+	uint8_t low      = pull(state);
+	state->cpu.pc    = (uint16_t) (low | pull(state) << 8);
 }
 
 void rts(State *state) {
-	state->cpu.s += 1;
-	state->cpu.pc = state_get_mem(state, (uint16_t) (state->cpu.s + 0x100));
-	state_step_ppu_many(state, 2);
+	peek_stack(state);
+	uint8_t low  = pull(state);
+	uint16_t adr = (uint16_t) (low | pull(state) << 8);
+	// JSR pushes the address of its own last byte, which gets read while incrementing it
+	state_read(state, adr);
+	state->cpu.pc = (uint16_t) (adr + 1);
 }
 
 void nop([[maybe_unused]] State *state) {
 	state->cpu.pc += 1;
-	state_step_ppu_many(state, 2);
 }
@@ -1,874 +1,205 @@
 #include "interface.h"
+#include <stdint.h>
 
-// C-implementations of NES instructions
+// C-implementations of the unofficial NES instructions. Most of them are two official ones glued
+// together, so they reuse those.
 
-void lax_zero_page(State *state, uint8_t val) {
-	state->cpu.a = val;
-	state->cpu.x = val;
-	// Update flags
-	state->cpu.p.Z = (val == 0);
-	state->cpu.p.N = ((val & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void lax_zero_page_y(State *state, uint8_t val) {
-	state->cpu.a = val;
-	state->cpu.x = val;
-	// Update flags
-	state->cpu.p.Z = (val == 0);
-	state->cpu.p.N = ((val & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void lax_absolute(State *state, uint8_t val) {
-	state->cpu.a = val;
-	state->cpu.x = val;
-	// Update flags
-	state->cpu.p.Z = (val == 0);
-	state->cpu.p.N = ((val & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void lax_absolute_y(State *state, uint8_t val) {
-	state->cpu.a = val;
-	state->cpu.x = val;
-	// Update flags
-	state->cpu.p.Z = (val == 0);
-	state->cpu.p.N = ((val & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void lax_indirect_x(State *state, uint8_t val) {
-	state->cpu.a = val;
-	state->cpu.x = val;
-	// Update flags
-	state->cpu.p.Z = (val == 0);
-	state->cpu.p.N = ((val & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void lax_indirect_y(State *state, uint8_t val) {
-	state->cpu.a = val;
-	state->cpu.x = val;
-	// Update flags
-	state->cpu.p.Z = (val == 0);
-	state->cpu.p.N = ((val & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void sax_zero_page(State *state, uint8_t val) {
-	uint8_t result = state->cpu.a & state->cpu.x;
-	state_set_mem(state, val, result);
-	state->cpu.pc += 2;
-}
-
-void sax_zero_page_y(State *state, uint8_t val) {
-	uint8_t result = state->cpu.a & state->cpu.x;
-	state_set_mem(state, val, result);
-	state->cpu.pc += 2;
-}
-
-void sax_absolute(State *state, uint8_t val) {
-	uint8_t result = state->cpu.a & state->cpu.x;
-	state_set_mem(state, val, result);
-	state->cpu.pc += 2;
-}
-
-void sax_indirect_x(State *state, uint8_t val) {
-	uint8_t result = state->cpu.a & state->cpu.x;
-	state_set_mem(state, val, result);
-	state->cpu.pc += 2;
-}
-
-void dcp_zero_page(State *state, uint8_t val) {
-	uint8_t result = val - 1;
-	state_set_mem(state, val, result);
-
-	// Compare
-	uint8_t temp   = state->cpu.a - result;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void dcp_zero_page_x(State *state, uint8_t val) {
-	uint8_t result = val - 1;
-	state_set_mem(state, val, result);
-
-	// Compare
-	uint8_t temp   = state->cpu.a - result;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void dcp_absolute(State *state, uint8_t val) {
-	uint8_t result = val - 1;
-	state_set_mem(state, val, result);
-
-	// Compare
-	uint8_t temp   = state->cpu.a - result;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void dcp_absolute_x(State *state, uint8_t val) {
-	uint8_t result = val - 1;
-	state_set_mem(state, val, result);
-
-	// Compare
-	uint8_t temp   = state->cpu.a - result;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void dcp_absolute_y(State *state, uint8_t val) {
-	uint8_t result = val - 1;
-	state_set_mem(state, val, result);
-
-	// Compare
-	uint8_t temp   = state->cpu.a - result;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void dcp_indirect_x(State *state, uint8_t val) {
-	uint8_t result = val - 1;
-	state_set_mem(state, val, result);
-
-	// Compare
-	uint8_t temp   = state->cpu.a - result;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void dcp_indirect_y(State *state, uint8_t val) {
-	uint8_t result = val - 1;
-	state_set_mem(state, val, result);
-
-	// Compare
-	uint8_t temp   = state->cpu.a - result;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.pc += 2;
-}
-
-void isc_zero_page(State *state, uint8_t val) {
-	uint8_t result = val + 1;
-	state_set_mem(state, val, result);
-
-	// Subtract with borrow
-	uint8_t temp   = state->cpu.a - result - (1 - state->cpu.p.C);
-	state->cpu.p.C = (temp <= state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.a   = temp;
-	state->cpu.pc += 2;
-}
-
-void isc_zero_page_x(State *state, uint8_t val) {
-	uint8_t result = val + 1;
-	state_set_mem(state, val, result);
-
-	// Subtract with borrow
-	uint8_t temp   = state->cpu.a - result - (1 - state->cpu.p.C);
-	state->cpu.p.C = (temp <= state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.a   = temp;
-	state->cpu.pc += 2;
-}
-
-void isc_absolute(State *state, uint8_t val) {
-	uint8_t result = val + 1;
-	state_set_mem(state, val, result);
-
-	// Subtract with borrow
-	uint8_t temp   = state->cpu.a - result - (1 - state->cpu.p.C);
-	state->cpu.p.C = (temp <= state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.a   = temp;
-	state->cpu.pc += 2;
-}
-
-void isc_absolute_x(State *state, uint8_t val) {
-	uint8_t result = val + 1;
-	state_set_mem(state, val, result);
-
-	// Subtract with borrow
-	uint8_t temp   = state->cpu.a - result - (1 - state->cpu.p.C);
-	state->cpu.p.C = (temp <= state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.a   = temp;
-	state->cpu.pc += 2;
-}
-
-void isc_absolute_y(State *state, uint8_t val) {
-	uint8_t result = val + 1;
-	state_set_mem(state, val, result);
-
-	// Subtract with borrow
-	uint8_t temp   = state->cpu.a - result - (1 - state->cpu.p.C);
-	state->cpu.p.C = (temp <= state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.a   = temp;
-	state->cpu.pc += 2;
-}
-
-void isc_indirect_x(State *state, uint8_t val) {
-	uint8_t result = val + 1;
-	state_set_mem(state, val, result);
-
-	// Subtract with borrow
-	uint8_t temp   = state->cpu.a - result - (1 - state->cpu.p.C);
-	state->cpu.p.C = (temp <= state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.a   = temp;
-	state->cpu.pc += 2;
-}
-
-void isc_indirect_y(State *state, uint8_t val) {
-	uint8_t result = val + 1;
-	state_set_mem(state, val, result);
-
-	// Subtract with borrow
-	uint8_t temp   = state->cpu.a - result - (1 - state->cpu.p.C);
-	state->cpu.p.C = (temp <= state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-	state->cpu.a   = temp;
-	state->cpu.pc += 2;
-}
-
-void rla_zero_page(State *state, uint8_t val) {
-	// Rotate left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) ((val << 1) | (uint8_t) state->cpu.p.C);
-	state->cpu.p.C = carry;
-
-	// AND with accumulator
-	result &= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
-}
-
-void rla_zero_page_x(State *state, uint8_t val) {
-	// Rotate left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) ((val << 1) | (uint8_t) state->cpu.p.C);
-	state->cpu.p.C = carry;
-
-	// AND with accumulator
-	result &= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
-}
-
-void rla_absolute(State *state, uint8_t val) {
-	// Rotate left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) ((val << 1) | (uint8_t) state->cpu.p.C);
-	state->cpu.p.C = carry;
-
-	// AND with accumulator
-	result &= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
-}
-
-void rla_absolute_x(State *state, uint8_t val) {
-	// Rotate left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) ((val << 1) | (uint8_t) state->cpu.p.C);
-	state->cpu.p.C = carry;
-
-	// AND with accumulator
-	result &= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
-}
-
-void rla_absolute_y(State *state, uint8_t val) {
-	// Rotate left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) ((val << 1) | (uint8_t) state->cpu.p.C);
-	state->cpu.p.C = carry;
-
-	// AND with accumulator
-	result &= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
-}
-
-void rla_indirect_x(State *state, uint8_t val) {
-	// Rotate left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) ((val << 1) | (uint8_t) state->cpu.p.C);
-	state->cpu.p.C = carry;
-
-	// AND with accumulator
-	result &= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
-}
-
-void rla_indirect_y(State *state, uint8_t val) {
-	// Rotate left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) ((val << 1) | (uint8_t) state->cpu.p.C);
-	state->cpu.p.C = carry;
-
-	// AND with accumulator
-	result &= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
-}
-
-void rra_zero_page(State *state, uint8_t val) {
-	// Rotate right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = (uint8_t) ((val >> 1) | ((uint8_t) state->cpu.p.C << 7));
-	state->cpu.p.C = carry;
-
-	// Add with carry
-	uint8_t temp   = state->cpu.a + result + state->cpu.p.C;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = temp;
-	state->cpu.pc += 2;
-}
-
-void rra_zero_page_x(State *state, uint8_t val) {
-	// Rotate right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = (uint8_t) ((val >> 1) | ((uint8_t) state->cpu.p.C << 7));
-	state->cpu.p.C = carry;
-
-	// Add with carry
-	uint8_t temp   = state->cpu.a + result + state->cpu.p.C;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = temp;
-	state->cpu.pc += 2;
-}
-
-void rra_absolute(State *state, uint8_t val) {
-	// Rotate right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = (uint8_t) ((val >> 1) | ((uint8_t) state->cpu.p.C << 7));
-	state->cpu.p.C = carry;
-
-	// Add with carry
-	uint8_t temp   = state->cpu.a + result + state->cpu.p.C;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = temp;
-	state->cpu.pc += 2;
-}
-
-void rra_absolute_x(State *state, uint8_t val) {
-	// Rotate right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = (uint8_t) ((val >> 1) | ((uint8_t) state->cpu.p.C << 7));
-	state->cpu.p.C = carry;
-
-	// Add with carry
-	uint8_t temp   = state->cpu.a + result + state->cpu.p.C;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = temp;
-	state->cpu.pc += 2;
-}
-
-void rra_absolute_y(State *state, uint8_t val) {
-	// Rotate right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = (uint8_t) ((val >> 1) | ((uint8_t) state->cpu.p.C << 7));
-	state->cpu.p.C = carry;
-
-	// Add with carry
-	uint8_t temp   = state->cpu.a + result + state->cpu.p.C;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = temp;
-	state->cpu.pc += 2;
-}
-
-void rra_indirect_x(State *state, uint8_t val) {
-	// Rotate right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = (uint8_t) ((val >> 1) | ((uint8_t) state->cpu.p.C << 7));
-	state->cpu.p.C = carry;
-
-	// Add with carry
-	uint8_t temp   = state->cpu.a + result + state->cpu.p.C;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = temp;
-	state->cpu.pc += 2;
-}
-
-void rra_indirect_y(State *state, uint8_t val) {
-	// Rotate right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = (uint8_t) ((val >> 1) | ((uint8_t) state->cpu.p.C << 7));
-	state->cpu.p.C = carry;
-
-	// Add with carry
-	uint8_t temp   = state->cpu.a + result + state->cpu.p.C;
-	state->cpu.p.C = (temp < state->cpu.a);
-	state->cpu.p.Z = (temp == 0);
-	state->cpu.p.N = ((temp & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = temp;
-	state->cpu.pc += 2;
-}
-
-void slo_zero_page(State *state, uint8_t val) {
-	// Shift left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) (val << 1);
-	state->cpu.p.C = carry;
-
-	// OR with accumulator
-	result |= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
-}
-
-void slo_zero_page_x(State *state, uint8_t val) {
-	// Shift left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) (val << 1);
-	state->cpu.p.C = carry;
-
-	// OR with accumulator
-	result |= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
+void adc_impl(State *state, uint8_t val);
+void and_impl(State *state, uint8_t val);
+void cmp_impl(State *state, uint8_t val);
+void eor_impl(State *state, uint8_t val);
+void ora_impl(State *state, uint8_t val);
+void sbc_impl(State *state, uint8_t val);
+void asl_impl(State *state, uint8_t *val);
+void lsr_impl(State *state, uint8_t *val);
+void rol_impl(State *state, uint8_t *val);
+void ror_impl(State *state, uint8_t *val);
 
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
-}
-
-void slo_absolute(State *state, uint8_t val) {
-	// Shift left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) (val << 1);
-	state->cpu.p.C = carry;
-
-	// OR with accumulator
-	result |= state->cpu.a;
+// What the unstable immediate instructions OR A with first. It depends on the chip and even the
+// temperature, this is the usual value.
+#define MAGIC 0xEE
 
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+void lax_impl(State *state, uint8_t val) {
+	state->cpu.a   = val;
+	state->cpu.x   = val;
+	state->cpu.p.Z = 0 == val;
+	state->cpu.p.N = (val & 0x80) != 0;
 }
 
-void slo_absolute_x(State *state, uint8_t val) {
-	// Shift left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) (val << 1);
-	state->cpu.p.C = carry;
-
-	// OR with accumulator
-	result |= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
+ZERO_PAGE(lax);
+ZERO_PAGE_Y(lax);
+ABSOLUTE(lax);
+ABSOLUTE_Y(lax);
+INDIRECT_X(lax);
+INDIRECT_Y(lax);
 
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
+void lax_immediate(State *state, uint8_t val) {
+	lax_impl(state, (state->cpu.a | MAGIC) & val);
 	state->cpu.pc += 2;
 }
 
-void slo_absolute_y(State *state, uint8_t val) {
-	// Shift left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) (val << 1);
-	state->cpu.p.C = carry;
-
-	// OR with accumulator
-	result |= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+uint8_t sax_impl(State *state) {
+	return state->cpu.a & state->cpu.x;
 }
 
-void slo_indirect_x(State *state, uint8_t val) {
-	// Shift left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) (val << 1);
-	state->cpu.p.C = carry;
+ZERO_PAGE_STORE(sax);
+ZERO_PAGE_Y_STORE(sax);
+ABSOLUTE_STORE(sax);
+INDIRECT_X_STORE(sax);
 
-	// OR with accumulator
-	result |= state->cpu.a;
+#define UNOFFICIAL_RMW(fn)                                                                       \
+	ZERO_PAGE_RMW(fn);                                                                       \
+	ZERO_PAGE_X_RMW(fn);                                                                     \
+	ABSOLUTE_RMW(fn);                                                                        \
+	ABSOLUTE_X_RMW(fn);                                                                      \
+	ABSOLUTE_Y_RMW(fn);                                                                      \
+	INDIRECT_X_RMW(fn);                                                                      \
+	INDIRECT_Y_RMW(fn)
 
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+void dcp_impl(State *state, uint8_t *val) {
+	*val = (uint8_t) (*val - 1);
+	cmp_impl(state, *val);
 }
 
-void slo_indirect_y(State *state, uint8_t val) {
-	// Shift left
-	uint8_t carry  = (val & 0x80) ? 1 : 0;
-	uint8_t result = (uint8_t) (val << 1);
-	state->cpu.p.C = carry;
-
-	// OR with accumulator
-	result |= state->cpu.a;
+UNOFFICIAL_RMW(dcp);
 
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+void isc_impl(State *state, uint8_t *val) {
+	*val = (uint8_t) (*val + 1);
+	sbc_impl(state, *val);
 }
 
-void sre_zero_page(State *state, uint8_t val) {
-	// Shift right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = val >> 1;
-	state->cpu.p.C = carry;
-
-	// XOR with accumulator
-	result ^= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
+UNOFFICIAL_RMW(isc);
 
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+void rla_impl(State *state, uint8_t *val) {
+	rol_impl(state, val);
+	and_impl(state, *val);
 }
 
-void sre_zero_page_x(State *state, uint8_t val) {
-	// Shift right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = val >> 1;
-	state->cpu.p.C = carry;
-
-	// XOR with accumulator
-	result ^= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
+UNOFFICIAL_RMW(rla);
 
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+void rra_impl(State *state, uint8_t *val) {
+	ror_impl(state, val);
+	adc_impl(state, *val);
 }
 
-void sre_absolute(State *state, uint8_t val) {
-	// Shift right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = val >> 1;
-	state->cpu.p.C = carry;
-
-	// XOR with accumulator
-	result ^= state->cpu.a;
+UNOFFICIAL_RMW(rra);
 
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+void slo_impl(State *state, uint8_t *val) {
+	asl_impl(state, val);
+	ora_impl(state, *val);
 }
 
-void sre_absolute_x(State *state, uint8_t val) {
-	// Shift right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = val >> 1;
-	state->cpu.p.C = carry;
+UNOFFICIAL_RMW(slo);
 
-	// XOR with accumulator
-	result ^= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+void sre_impl(State *state, uint8_t *val) {
+	lsr_impl(state, val);
+	eor_impl(state, *val);
 }
 
-void sre_absolute_y(State *state, uint8_t val) {
-	// Shift right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = val >> 1;
-	state->cpu.p.C = carry;
+UNOFFICIAL_RMW(sre);
 
-	// XOR with accumulator
-	result ^= state->cpu.a;
-
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+void anc_impl(State *state, uint8_t val) {
+	and_impl(state, val);
+	state->cpu.p.C = state->cpu.p.N;
 }
 
-void sre_indirect_x(State *state, uint8_t val) {
-	// Shift right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = val >> 1;
-	state->cpu.p.C = carry;
-
-	// XOR with accumulator
-	result ^= state->cpu.a;
+IMMEDIATE(anc);
 
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+void alr_impl(State *state, uint8_t val) {
+	and_impl(state, val);
+	lsr_impl(state, &state->cpu.a);
 }
 
-void sre_indirect_y(State *state, uint8_t val) {
-	// Shift right
-	uint8_t carry  = (val & 0x01) ? 0x80 : 0;
-	uint8_t result = val >> 1;
-	state->cpu.p.C = carry;
-
-	// XOR with accumulator
-	result ^= state->cpu.a;
+IMMEDIATE(alr);
 
-	// Update flags
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
-
-	state_set_mem(state, val, result);
-	state->cpu.a = result;
-	state->cpu.pc += 2;
+// AND then ROR A, except C and V come from bits 6 and 5 of the result
+void arr_impl(State *state, uint8_t val) {
+	uint8_t res    = (uint8_t) (state->cpu.p.C << 7 | (state->cpu.a & val) >> 1);
+	state->cpu.a   = res;
+	state->cpu.p.Z = 0 == res;
+	state->cpu.p.N = (res & 0x80) != 0;
+	state->cpu.p.C = (res & 0x40) != 0;
+	state->cpu.p.V = ((res >> 6 ^ res >> 5) & 1) != 0;
 }
 
-void anc(State *state, uint8_t val) {
-	// AND with accumulator
-	state->cpu.a &= val;
+IMMEDIATE(arr);
 
-	// Update flags
-	state->cpu.p.Z = (state->cpu.a == 0);
-	state->cpu.p.N = ((state->cpu.a & 0x80) != 0);
-
-	// Set carry flag to bit 7 of accumulator
-	state->cpu.p.C = ((state->cpu.a & 0x80) != 0);
-	state->cpu.pc += 2;
+// X = A & X minus the operand, setting C like CMP does
+void axs_impl(State *state, uint8_t val) {
+	uint8_t ax     = state->cpu.a & state->cpu.x;
+	uint8_t res    = (uint8_t) (ax - val);
+	state->cpu.p.C = ax >= val;
+	state->cpu.p.Z = 0 == res;
+	state->cpu.p.N = (res & 0x80) != 0;
+	state->cpu.x   = res;
 }
 
-void alr(State *state, uint8_t val) {
-	// AND with accumulator
-	state->cpu.a &= val;
+IMMEDIATE(axs);
 
-	// Shift right
-	uint8_t carry = (state->cpu.a & 0x01) ? 0x80 : 0;
-	state->cpu.a >>= 1;
-	state->cpu.p.C = carry;
-
-	// Update flags
-	state->cpu.p.Z = (state->cpu.a == 0);
-	state->cpu.p.N = ((state->cpu.a & 0x80) != 0);
-	state->cpu.pc += 2;
+void xaa_impl(State *state, uint8_t val) {
+	uint8_t res    = (state->cpu.a | MAGIC) & state->cpu.x & val;
+	state->cpu.a   = res;
+	state->cpu.p.Z = 0 == res;
+	state->cpu.p.N = (res & 0x80) != 0;
 }
 
-void arr(State *state, uint8_t val) {
-	// AND with accumulator
-	state->cpu.a &= val;
-
-	// Shift right with carry
-	uint8_t carry = (state->cpu.a & 0x01) ? 0x80 : 0;
-	state->cpu.a >>= 1;
-	state->cpu.a |= ((uint8_t) state->cpu.p.C << 7);
-	state->cpu.p.C = carry;
+IMMEDIATE(xaa);
 
-	// Update flags
-	state->cpu.p.Z = (state->cpu.a == 0);
-	state->cpu.p.N = ((state->cpu.a & 0x80) != 0);
-	state->cpu.pc += 2;
+void las_impl(State *state, uint8_t val) {
+	state->cpu.s = val & state->cpu.s;
+	lax_impl(state, state->cpu.s);
 }
 
-void axs(State *state, uint8_t val) {
-	// AND accumulator with X register
-	uint8_t temp = state->cpu.a & state->cpu.x;
-
-	// Subtract with borrow
-	uint8_t result = temp - val - (1 - state->cpu.p.C);
-	state->cpu.p.C = (result <= temp);
-	state->cpu.p.Z = (result == 0);
-	state->cpu.p.N = ((result & 0x80) != 0);
+ABSOLUTE_Y(las);
 
-	// Store result in X register
-	state->cpu.x = result;
-	state->cpu.pc += 2;
-}
+// The unofficial NOPs still read their operands, which matters for the PPU and mappers
+void nop_impl([[maybe_unused]] State *state, [[maybe_unused]] uint8_t val) {}
 
-void las(State *state, uint8_t val) {
-	// AND with accumulator and store in A, X, and S
-	state->cpu.a &= val;
-	state->cpu.x = state->cpu.a;
-	state->cpu.s = state->cpu.a;
+IMMEDIATE(nop);
+ZERO_PAGE(nop);
+ZERO_PAGE_X(nop);
+ABSOLUTE(nop);
+ABSOLUTE_X(nop);
 
-	// Update flags
-	state->cpu.p.Z = (state->cpu.a == 0);
-	state->cpu.p.N = ((state->cpu.a & 0x80) != 0);
-	state->cpu.pc += 2;
+// SHA, SHX, SHY and TAS store `val` ANDed with one more than the high byte of the address before
+// indexing. When indexing crosses a page, that value also replaces the high byte of the address
+// written to.
+static void store_and_high(State *state, uint16_t base, uint8_t index, uint8_t val) {
+	uint16_t adr = indexed(state, base, index, true);
+	val &= (uint8_t) ((base >> 8) + 1);
+	if ((adr & 0xFF00) != (base & 0xFF00)) {
+		adr = (uint16_t) (val << 8 | (adr & 0xFF));
+	}
+	state_write(state, adr, val);
 }
 
-void tas(State *state, uint8_t val) {
-	// AND accumulator with X register and store in S
-	uint8_t temp = state->cpu.a & state->cpu.x;
-	state->cpu.s = temp;
-
-	// Store S in memory
-	state_set_mem(state, val, temp);
-	state->cpu.pc += 2;
+void shy_absolute_x(State *state, uint16_t adr) {
+	fetch_high(state);
+	store_and_high(state, adr, state->cpu.x, state->cpu.y);
+	state->cpu.pc += 3;
 }
 
-void shy(State *state, uint8_t val) {
-	// Store Y register with high byte of adress
-	uint8_t adr_low  = val;
-	uint8_t adr_high = (val + 1) & 0xFF;
-
-	// Store Y register in memory
-	state_set_mem(state, (uint16_t) (adr_low | (adr_high << 8)), state->cpu.y);
-	state->cpu.pc += 2;
+void shx_absolute_y(State *state, uint16_t adr) {
+	fetch_high(state);
+	store_and_high(state, adr, state->cpu.y, state->cpu.x);
+	state->cpu.pc += 3;
 }
 
-void shx(State *state, uint8_t val) {
-	// Store X register with high byte of adress
-	uint8_t adr_low  = val;
-	uint8_t adr_high = (val + 1) & 0xFF;
-
-	// Store X register in memory
-	state_set_mem(state, (uint16_t) (adr_low | (adr_high << 8)), state->cpu.x);
-	state->cpu.pc += 2;
+void ahx_absolute_y(State *state, uint16_t adr) {
+	fetch_high(state);
+	store_and_high(state, adr, state->cpu.y, state->cpu.a & state->cpu.x);
+	state->cpu.pc += 3;
 }
 
-void ahx_absolute_y(State *state, uint8_t val) {
-	// Store A and X registers with high byte of adress
-	uint8_t adr_low  = val;
-	uint8_t adr_high = (val + 1) & 0xFF;
-
-	// Store (A & X) in memory
-	state_set_mem(state, (uint16_t) (adr_low | (adr_high << 8)), state->cpu.a & state->cpu.x);
+void ahx_indirect_y(State *state, uint8_t adr) {
+	store_and_high(state, zero_page_pointer(state, adr), state->cpu.y,
+	               state->cpu.a & state->cpu.x);
 	state->cpu.pc += 2;
 }
 
-void ahx_indirect_y(State *state, uint8_t val) {
-	// Store A and X registers with high byte of adress
-	uint8_t adr_low  = val;
-	uint8_t adr_high = (val + 1) & 0xFF;
-
-	// Store (A & X) in memory
-	state_set_mem(state, (uint16_t) (adr_low | (adr_high << 8)), state->cpu.a & state->cpu.x);
-	state->cpu.pc += 2;
+void tas_absolute_y(State *state, uint16_t adr) {
+	fetch_high(state);
+	state->cpu.s = state->cpu.a & state->cpu.x;
+	store_and_high(state, adr, state->cpu.y, state->cpu.s);
+	state->cpu.pc += 3;
 }
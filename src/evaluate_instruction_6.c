@@ -0,0 +1,128 @@
+#include "interface.h"
+#include <stdint.h>
+
+// C-implementations of the instructions the 65C02 added or changed, see `cmos.rs`
+
+void adc_impl(State *state, uint8_t val);
+void and_impl(State *state, uint8_t val);
+void bit_impl(State *state, uint8_t val);
+void cmp_impl(State *state, uint8_t val);
+void eor_impl(State *state, uint8_t val);
+void lda_impl(State *state, uint8_t val);
+void ora_impl(State *state, uint8_t val);
+void sbc_impl(State *state, uint8_t val);
+void inc_impl(State *state, uint8_t *val);
+void dec_impl(State *state, uint8_t *val);
+uint8_t sta_impl(State *state);
+void brk(State *state);
+
+ZERO_PAGE_INDIRECT(adc);
+ZERO_PAGE_INDIRECT(and);
+ZERO_PAGE_INDIRECT(cmp);
+ZERO_PAGE_INDIRECT(eor);
+ZERO_PAGE_INDIRECT(lda);
+ZERO_PAGE_INDIRECT(ora);
+ZERO_PAGE_INDIRECT(sbc);
+ZERO_PAGE_INDIRECT_STORE(sta);
+
+ZERO_PAGE_X(bit);
+ABSOLUTE_X(bit);
+
+// Without an address there's nothing to copy N and V from
+void bit_immediate(State *state, uint8_t val) {
+	state->cpu.p.Z = 0 == (state->cpu.a & val);
+	state->cpu.pc += 2;
+}
+
+ACCUMULATOR(inc);
+ACCUMULATOR(dec);
+
+void bra(State *state, int8_t offset) {
+	branch(state, offset, true);
+}
+
+void cmos_brk(State *state) {
+	brk(state);
+	state->cpu.p.D = 0;
+}
+
+void cmos_jmp_indirect(State *state, uint16_t adr) {
+	// Reads the high byte of the pointer twice instead of getting it wrong
+	fetch_high(state);
+	fetch_high(state);
+	uint8_t low   = state_read(state, adr);
+	state->cpu.pc = (uint16_t) (low | state_read(state, (uint16_t) (adr + 1)) << 8);
+}
+
+void jmp_indirect_x(State *state, uint16_t adr) {
+	fetch_high(state);
+	fetch_high(state);
+	adr           = (uint16_t) (adr + state->cpu.x);
+	uint8_t low   = state_read(state, adr);
+	state->cpu.pc = (uint16_t) (low | state_read(state, (uint16_t) (adr + 1)) << 8);
+}
+
+void cmos_nop(State *state) {
+	state->cpu.pc += 1;
+}
+
+// Spends its cycles reading from the top page
+void cmos_nop_absolute_8(State *state, uint16_t adr) {
+	fetch_high(state);
+	for (int i = 0; i < 5; i++) {
+		state_read(state, 0xFF00 | (adr & 0x00FF));
+	}
+	state->cpu.pc += 3;
+}
+
+void phx(State *state) {
+	push(state, state->cpu.x);
+	state->cpu.pc += 1;
+}
+
+void phy(State *state) {
+	push(state, state->cpu.y);
+	state->cpu.pc += 1;
+}
+
+void plx(State *state) {
+	peek_stack(state);
+	state->cpu.x   = pull(state);
+	state->cpu.p.Z = 0 == state->cpu.x;
+	state->cpu.p.N = (state->cpu.x & 0x80) != 0;
+	state->cpu.pc += 1;
+}
+
+void ply(State *state) {
+	peek_stack(state);
+	state->cpu.y   = pull(state);
+	state->cpu.p.Z = 0 == state->cpu.y;
+	state->cpu.p.N = (state->cpu.y & 0x80) != 0;
+	state->cpu.pc += 1;
+}
+
+uint8_t stz_impl([[maybe_unused]] State *state) {
+	return 0;
+}
+
+ZERO_PAGE_STORE(stz);
+ZERO_PAGE_X_STORE(stz);
+ABSOLUTE_STORE(stz);
+ABSOLUTE_X_STORE(stz);
+
+// TSB and TRB set Z like BIT does, from A AND the old value
+void tsb_impl(State *state, uint8_t *val) {
+	state->cpu.p.Z = 0 == (state->cpu.a & *val);
+	*val |= state->cpu.a;
+}
+
+ZERO_PAGE_RMW(tsb);
+ABSOLUTE_RMW(tsb);
+
+void trb_impl(State *state, uint8_t *val) {
+	state->cpu.p.Z = 0 == (state->cpu.a & *val);
+	*val &= (uint8_t) ~state->cpu.a;
+}
+
+ZERO_PAGE_RMW(trb);
+ABSOLUTE_RMW(trb);
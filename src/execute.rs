@@ -0,0 +1,1311 @@
+//! The instructions in Rust, doing what `evaluate_instruction_*.c` does. Like the C, every cycle
+//! is a bus access of its own, dummy reads and writes included, so the rest of the system sees
+//! them in the same order as on hardware.
+
+use crate::{
+	cmos::CmosInst,
+	cpu::{Cpu, P, Variant},
+	inst::Inst,
+	interpret::{IRQ_VECTOR, State},
+};
+
+/// Where an instruction that goes to memory finds its operand.
+#[derive(Debug, Copy, Clone)]
+enum Mode {
+	Immediate(u8),
+	ZeroPage(u8),
+	ZeroPageX(u8),
+	ZeroPageY(u8),
+	Absolute(u16),
+	AbsoluteX(u16),
+	AbsoluteY(u16),
+	IndirectX(u8),
+	IndirectY(u8),
+	/// `(zp)`, which only the 65C02 has
+	ZeroPageIndirect(u8),
+}
+
+impl Mode {
+	fn len(self) -> u16 {
+		match self {
+			Mode::Absolute(_) | Mode::AbsoluteX(_) | Mode::AbsoluteY(_) => 3,
+			_ => 2,
+		}
+	}
+
+	/// The effective address, spending the cycles hardware does getting to it. Zero page
+	/// indexing wraps around within the zero page, and so do the pointers the indirect modes read
+	/// from it.
+	fn address(self, state: &mut State, access: Access) -> u16 {
+		let Cpu { x, y, .. } = state.cpu;
+		match self {
+			Mode::Immediate(_) => unreachable!("Immediate operands have no address"),
+			Mode::ZeroPage(adr) => adr as u16,
+			// Reads the unindexed address while adding
+			Mode::ZeroPageX(adr) => {
+				state.read(adr as u16);
+				adr.wrapping_add(x) as u16
+			}
+			Mode::ZeroPageY(adr) => {
+				state.read(adr as u16);
+				adr.wrapping_add(y) as u16
+			}
+			Mode::Absolute(adr) => {
+				fetch_high(state);
+				adr
+			}
+			Mode::AbsoluteX(adr) => {
+				fetch_high(state);
+				indexed(state, adr, x, access)
+			}
+			Mode::AbsoluteY(adr) => {
+				fetch_high(state);
+				indexed(state, adr, y, access)
+			}
+			Mode::IndirectX(adr) => {
+				state.read(adr as u16);
+				zero_page_pointer(state, adr.wrapping_add(x))
+			}
+			Mode::IndirectY(adr) => {
+				let base = zero_page_pointer(state, adr);
+				indexed(state, base, y, access)
+			}
+			Mode::ZeroPageIndirect(adr) => zero_page_pointer(state, adr),
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Access {
+	Read,
+	/// Stores and read-modify-writes
+	Write,
+}
+
+/// The cycle reading an absolute address's high byte. The low byte came with the opcode.
+fn fetch_high(state: &mut State) {
+	state.read(state.cpu.pc.wrapping_add(2));
+}
+
+/// Indexing adds to the low byte first, and reads from that address while fixing up the high
+/// byte. Reads that didn't carry can stop there, everything else takes the extra cycle.
+fn indexed(state: &mut State, base: u16, index: u8, access: Access) -> u16 {
+	let adr = base.wrapping_add(index as u16);
+	let unfixed = (base & 0xFF00) | (adr & 0x00FF);
+	if unfixed != adr || access == Access::Write {
+		state.read(unfixed);
+	}
+	adr
+}
+
+fn zero_page_pointer(state: &mut State, adr: u8) -> u16 {
+	u16::from_le_bytes([
+		state.read(adr as u16),
+		state.read(adr.wrapping_add(1) as u16),
+	])
+}
+
+/// Moves past an instruction of `len` bytes.
+fn finish(state: &mut State, len: u16) {
+	state.cpu.pc = state.cpu.pc.wrapping_add(len);
+}
+
+fn read(state: &mut State, mode: Mode, op: impl FnOnce(&mut Cpu, u8)) {
+	let val = match mode {
+		Mode::Immediate(val) => val,
+		_ => {
+			let adr = mode.address(state, Access::Read);
+			state.read(adr)
+		}
+	};
+	op(&mut state.cpu, val);
+	finish(state, mode.len());
+}
+
+fn store(state: &mut State, mode: Mode, val: u8) {
+	let adr = mode.address(state, Access::Write);
+	state.write(adr, val);
+	finish(state, mode.len());
+}
+
+/// Read-modify-write instructions write the value back unchanged while they work on it.
+fn modify(state: &mut State, mode: Mode, op: impl FnOnce(&mut Cpu, u8) -> u8) {
+	let adr = mode.address(state, Access::Write);
+	let val = state.read(adr);
+	state.write(adr, val);
+	let val = op(&mut state.cpu, val);
+	state.write(adr, val);
+	finish(state, mode.len());
+}
+
+fn accumulator(state: &mut State, op: fn(&mut Cpu, u8) -> u8) {
+	let a = state.cpu.a;
+	state.cpu.a = op(&mut state.cpu, a);
+	finish(state, 1);
+}
+
+fn implied(state: &mut State, op: fn(&mut Cpu)) {
+	op(&mut state.cpu);
+	finish(state, 1);
+}
+
+/// Taken branches read the next opcode while adding the offset, and then from the wrong page if
+/// that carried.
+fn branch(state: &mut State, offset: i8, taken: bool) {
+	let next = state.cpu.pc.wrapping_add(2);
+	let target = next.wrapping_add_signed(offset as i16);
+	state.cpu.pc = next;
+	if taken {
+		state.read(next);
+		let unfixed = (next & 0xFF00) | (target & 0x00FF);
+		if unfixed != target {
+			state.read(unfixed);
+		}
+		state.cpu.pc = target;
+	}
+}
+
+/// The cycle where the stack pointer gets incremented, before anything can be pulled.
+fn peek_stack(state: &mut State) {
+	state.read(0x100 + state.cpu.s as u16);
+}
+
+fn pull(state: &mut State) -> u8 {
+	state.cpu.s = state.cpu.s.wrapping_add(1);
+	state.read(0x100 + state.cpu.s as u16)
+}
+
+fn push_pc(state: &mut State, pc: u16) {
+	let [lo, hi] = pc.to_le_bytes();
+	state.push(hi);
+	state.push(lo);
+}
+
+fn pull_pc(state: &mut State) -> u16 {
+	let lo = pull(state);
+	let hi = pull(state);
+	u16::from_le_bytes([lo, hi])
+}
+
+/// B and bit 5 only exist in the pushed copies of P.
+fn pull_p(state: &mut State) {
+	state.cpu.p = P::from_bits(pull(state) & !0x30);
+}
+
+fn set_nz(cpu: &mut Cpu, val: u8) -> u8 {
+	cpu.p.set_z(val == 0);
+	cpu.p.set_n(val & 0x80 != 0);
+	val
+}
+
+/// Binary addition, which is all the 2A03 has.
+fn add(cpu: &mut Cpu, val: u8) {
+	let sum = cpu.a as u16 + val as u16 + cpu.p.c() as u16;
+	let res = sum as u8;
+	cpu.p.set_c(sum > 0xFF);
+	cpu.p.set_v((cpu.a ^ res) & (val ^ res) & 0x80 != 0);
+	cpu.a = set_nz(cpu, res);
+}
+
+/// With D set on chips that have decimal mode, this adds two BCD numbers. The NMOS parts take Z
+/// from the binary sum and N and V from before the high digit is adjusted, the 65C02 sets N and Z
+/// from the result.
+fn adc(cpu: &mut Cpu, variant: Variant, val: u8) {
+	if !(cpu.p.d() && variant != Variant::Ricoh2A03) {
+		return add(cpu, val);
+	}
+	let a = cpu.a;
+	let carry = cpu.p.c() as u8;
+	let binary = a.wrapping_add(val).wrapping_add(carry);
+
+	let mut low = (a & 0x0F) + (val & 0x0F) + carry;
+	if low > 0x09 {
+		low = ((low + 0x06) & 0x0F) + 0x10;
+	}
+	let mut sum = (a & 0xF0) as u16 + (val & 0xF0) as u16 + low as u16;
+	cpu.p.set_z(binary == 0);
+	cpu.p.set_n(sum & 0x80 != 0);
+	cpu.p.set_v((a ^ sum as u8) & (val ^ sum as u8) & 0x80 != 0);
+	if sum > 0x9F {
+		sum += 0x60;
+	}
+	cpu.p.set_c(sum > 0xFF);
+	cpu.a = sum as u8;
+	if variant == Variant::Cmos65C02 {
+		set_nz(cpu, cpu.a);
+	}
+}
+
+/// Decimal subtraction leaves the flags as the binary one sets them, apart from the 65C02 setting
+/// N and Z from the result.
+fn sbc(cpu: &mut Cpu, variant: Variant, val: u8) {
+	let a = cpu.a;
+	let borrow = !cpu.p.c() as i16;
+	add(cpu, !val);
+	if !(cpu.p.d() && variant != Variant::Ricoh2A03) {
+		return;
+	}
+
+	let low = (a & 0x0F) as i16 - (val & 0x0F) as i16 - borrow;
+	if variant == Variant::Cmos65C02 {
+		let mut res = a as i16 - val as i16 - borrow;
+		if res < 0 {
+			res -= 0x60;
+		}
+		if low < 0 {
+			res -= 0x06;
+		}
+		cpu.a = set_nz(cpu, res as u8);
+	} else {
+		let low = if low < 0 {
+			((low - 0x06) & 0x0F) - 0x10
+		} else {
+			low
+		};
+		let mut res = (a & 0xF0) as i16 - (val & 0xF0) as i16 + low;
+		if res < 0 {
+			res -= 0x60;
+		}
+		cpu.a = res as u8;
+	}
+}
+
+fn compare(cpu: &mut Cpu, reg: u8, val: u8) {
+	cpu.p.set_c(reg >= val);
+	set_nz(cpu, reg.wrapping_sub(val));
+}
+
+fn and(cpu: &mut Cpu, val: u8) {
+	cpu.a = set_nz(cpu, cpu.a & val);
+}
+
+fn ora(cpu: &mut Cpu, val: u8) {
+	cpu.a = set_nz(cpu, cpu.a | val);
+}
+
+fn eor(cpu: &mut Cpu, val: u8) {
+	cpu.a = set_nz(cpu, cpu.a ^ val);
+}
+
+fn bit(cpu: &mut Cpu, val: u8) {
+	cpu.p.set_z(cpu.a & val == 0);
+	cpu.p.set_v(val & 0x40 != 0);
+	cpu.p.set_n(val & 0x80 != 0);
+}
+
+fn asl(cpu: &mut Cpu, val: u8) -> u8 {
+	cpu.p.set_c(val & 0x80 != 0);
+	set_nz(cpu, val << 1)
+}
+
+fn lsr(cpu: &mut Cpu, val: u8) -> u8 {
+	cpu.p.set_c(val & 0x01 != 0);
+	set_nz(cpu, val >> 1)
+}
+
+fn rol(cpu: &mut Cpu, val: u8) -> u8 {
+	let carry = cpu.p.c() as u8;
+	cpu.p.set_c(val & 0x80 != 0);
+	set_nz(cpu, (val << 1) | carry)
+}
+
+fn ror(cpu: &mut Cpu, val: u8) -> u8 {
+	let carry = cpu.p.c() as u8;
+	cpu.p.set_c(val & 0x01 != 0);
+	set_nz(cpu, (carry << 7) | (val >> 1))
+}
+
+fn inc(cpu: &mut Cpu, val: u8) -> u8 {
+	set_nz(cpu, val.wrapping_add(1))
+}
+
+fn dec(cpu: &mut Cpu, val: u8) -> u8 {
+	set_nz(cpu, val.wrapping_sub(1))
+}
+
+/// DEC then CMP.
+fn dcp(cpu: &mut Cpu, val: u8) -> u8 {
+	let val = val.wrapping_sub(1);
+	compare(cpu, cpu.a, val);
+	val
+}
+
+/// INC then SBC.
+fn isc(cpu: &mut Cpu, variant: Variant, val: u8) -> u8 {
+	let val = val.wrapping_add(1);
+	sbc(cpu, variant, val);
+	val
+}
+
+/// ROL then AND.
+fn rla(cpu: &mut Cpu, val: u8) -> u8 {
+	let val = rol(cpu, val);
+	and(cpu, val);
+	val
+}
+
+/// ROR then ADC.
+fn rra(cpu: &mut Cpu, variant: Variant, val: u8) -> u8 {
+	let val = ror(cpu, val);
+	adc(cpu, variant, val);
+	val
+}
+
+/// ASL then ORA.
+fn slo(cpu: &mut Cpu, val: u8) -> u8 {
+	let val = asl(cpu, val);
+	ora(cpu, val);
+	val
+}
+
+/// LSR then EOR.
+fn sre(cpu: &mut Cpu, val: u8) -> u8 {
+	let val = lsr(cpu, val);
+	eor(cpu, val);
+	val
+}
+
+fn lax(cpu: &mut Cpu, val: u8) {
+	cpu.a = val;
+	cpu.x = set_nz(cpu, val);
+}
+
+/// What the unstable immediate instructions OR A with first. It depends on the chip and even the
+/// temperature, this is the usual value.
+const MAGIC: u8 = 0xEE;
+
+fn lax_immediate(cpu: &mut Cpu, val: u8) {
+	lax(cpu, (cpu.a | MAGIC) & val);
+}
+
+fn xaa(cpu: &mut Cpu, val: u8) {
+	cpu.a = set_nz(cpu, (cpu.a | MAGIC) & cpu.x & val);
+}
+
+/// AND, with N copied into C.
+fn anc(cpu: &mut Cpu, val: u8) {
+	and(cpu, val);
+	cpu.p.set_c(cpu.p.n());
+}
+
+/// AND then LSR A.
+fn alr(cpu: &mut Cpu, val: u8) {
+	and(cpu, val);
+	cpu.a = lsr(cpu, cpu.a);
+}
+
+/// AND then ROR A, except C and V come from bits 6 and 5 of the result.
+fn arr(cpu: &mut Cpu, val: u8) {
+	let res = ((cpu.p.c() as u8) << 7) | ((cpu.a & val) >> 1);
+	cpu.a = set_nz(cpu, res);
+	cpu.p.set_c(res & 0x40 != 0);
+	cpu.p.set_v(((res >> 6) ^ (res >> 5)) & 1 != 0);
+}
+
+/// X = A & X minus the operand, setting C like CMP does.
+fn axs(cpu: &mut Cpu, val: u8) {
+	let ax = cpu.a & cpu.x;
+	cpu.p.set_c(ax >= val);
+	cpu.x = set_nz(cpu, ax.wrapping_sub(val));
+}
+
+fn las(cpu: &mut Cpu, val: u8) {
+	let val = val & cpu.s;
+	cpu.s = val;
+	lax(cpu, val);
+}
+
+/// SHA, SHX, SHY and TAS store `val` ANDed with one more than the high byte of the address
+/// before indexing. When indexing crosses a page, that value also replaces the high byte of the
+/// address written to.
+fn store_and_high(state: &mut State, mode: Mode, val: u8) {
+	let (base, index) = match mode {
+		Mode::AbsoluteX(adr) => {
+			fetch_high(state);
+			(adr, state.cpu.x)
+		}
+		Mode::AbsoluteY(adr) => {
+			fetch_high(state);
+			(adr, state.cpu.y)
+		}
+		Mode::IndirectY(adr) => (zero_page_pointer(state, adr), state.cpu.y),
+		_ => unreachable!("Only indexed stores are unstable"),
+	};
+	let adr = indexed(state, base, index, Access::Write);
+	let val = val & ((base >> 8) as u8).wrapping_add(1);
+	let adr = if adr & 0xFF00 == base & 0xFF00 {
+		adr
+	} else {
+		((val as u16) << 8) | (adr & 0x00FF)
+	};
+	state.write(adr, val);
+	finish(state, mode.len());
+}
+
+/// Runs `inst`, which has to be the instruction at PC.
+pub fn execute(inst: Inst, state: &mut State) {
+	use Mode::*;
+
+	let a = state.cpu.a;
+	let x = state.cpu.x;
+	let y = state.cpu.y;
+	let p = state.cpu.p;
+	// Whether D does anything depends on the chip
+	let variant = state.variant;
+	let adc = |cpu: &mut Cpu, val| adc(cpu, variant, val);
+	let sbc = |cpu: &mut Cpu, val| sbc(cpu, variant, val);
+	let isc = |cpu: &mut Cpu, val| isc(cpu, variant, val);
+	let rra = |cpu: &mut Cpu, val| rra(cpu, variant, val);
+
+	match inst {
+		Inst::AdcImmediate(val) => read(state, Immediate(val), adc),
+		Inst::AdcZeroPage(adr) => read(state, ZeroPage(adr), adc),
+		Inst::AdcZeroPageX(adr) => read(state, ZeroPageX(adr), adc),
+		Inst::AdcAbsolute(adr) => read(state, Absolute(adr.into()), adc),
+		Inst::AdcAbsoluteX(adr) => read(state, AbsoluteX(adr.into()), adc),
+		Inst::AdcAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), adc),
+		Inst::AdcIndirectX(adr) => read(state, IndirectX(adr), adc),
+		Inst::AdcIndirectY(adr) => read(state, IndirectY(adr), adc),
+		Inst::AndImmediate(val) => read(state, Immediate(val), and),
+		Inst::AndZeroPage(adr) => read(state, ZeroPage(adr), and),
+		Inst::AndZeroPageX(adr) => read(state, ZeroPageX(adr), and),
+		Inst::AndAbsolute(adr) => read(state, Absolute(adr.into()), and),
+		Inst::AndAbsoluteX(adr) => read(state, AbsoluteX(adr.into()), and),
+		Inst::AndAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), and),
+		Inst::AndIndirectX(adr) => read(state, IndirectX(adr), and),
+		Inst::AndIndirectY(adr) => read(state, IndirectY(adr), and),
+		Inst::AslAccumulator => accumulator(state, asl),
+		Inst::AslZeroPage(adr) => modify(state, ZeroPage(adr), asl),
+		Inst::AslZeroPageX(adr) => modify(state, ZeroPageX(adr), asl),
+		Inst::AslAbsolute(adr) => modify(state, Absolute(adr.into()), asl),
+		Inst::AslAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), asl),
+		Inst::Bcc(offset) => branch(state, offset, !p.c()),
+		Inst::Bcs(offset) => branch(state, offset, p.c()),
+		Inst::Beq(offset) => branch(state, offset, p.z()),
+		Inst::BitZeroPage(adr) => read(state, ZeroPage(adr), bit),
+		Inst::BitAbsolute(adr) => read(state, Absolute(adr.into()), bit),
+		Inst::Bmi(offset) => branch(state, offset, p.n()),
+		Inst::Bne(offset) => branch(state, offset, !p.z()),
+		Inst::Bpl(offset) => branch(state, offset, !p.n()),
+		Inst::Brk => {
+			// The byte after BRK is skipped, it's there for the handler to look at
+			push_pc(state, state.cpu.pc.wrapping_add(2));
+			state.push(p.into_bits() | 0x30);
+			state.cpu.p.set_i(true);
+			state.cpu.pc = u16::from_le_bytes([state.read(IRQ_VECTOR), state.read(IRQ_VECTOR + 1)]);
+		}
+		Inst::Bvc(offset) => branch(state, offset, !p.v()),
+		Inst::Bvs(offset) => branch(state, offset, p.v()),
+		Inst::Clc => implied(state, |cpu| cpu.p.set_c(false)),
+		Inst::Cld => implied(state, |cpu| cpu.p.set_d(false)),
+		Inst::Cli => implied(state, |cpu| cpu.p.set_i(false)),
+		Inst::Clv => implied(state, |cpu| cpu.p.set_v(false)),
+		Inst::CmpImmediate(val) => read(state, Immediate(val), |cpu, val| compare(cpu, cpu.a, val)),
+		Inst::CmpZeroPage(adr) => read(state, ZeroPage(adr), |cpu, val| compare(cpu, cpu.a, val)),
+		Inst::CmpZeroPageX(adr) => read(state, ZeroPageX(adr), |cpu, val| compare(cpu, cpu.a, val)),
+		Inst::CmpAbsolute(adr) => read(state, Absolute(adr.into()), |cpu, val| {
+			compare(cpu, cpu.a, val)
+		}),
+		Inst::CmpAbsoluteX(adr) => read(state, AbsoluteX(adr.into()), |cpu, val| {
+			compare(cpu, cpu.a, val)
+		}),
+		Inst::CmpAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), |cpu, val| {
+			compare(cpu, cpu.a, val)
+		}),
+		Inst::CmpIndirectX(adr) => read(state, IndirectX(adr), |cpu, val| compare(cpu, cpu.a, val)),
+		Inst::CmpIndirectY(adr) => read(state, IndirectY(adr), |cpu, val| compare(cpu, cpu.a, val)),
+		Inst::CpxImmediate(val) => read(state, Immediate(val), |cpu, val| compare(cpu, cpu.x, val)),
+		Inst::CpxZeroPage(adr) => read(state, ZeroPage(adr), |cpu, val| compare(cpu, cpu.x, val)),
+		Inst::CpxAbsolute(adr) => read(state, Absolute(adr.into()), |cpu, val| {
+			compare(cpu, cpu.x, val)
+		}),
+		Inst::CpyImmediate(val) => read(state, Immediate(val), |cpu, val| compare(cpu, cpu.y, val)),
+		Inst::CpyZeroPage(adr) => read(state, ZeroPage(adr), |cpu, val| compare(cpu, cpu.y, val)),
+		Inst::CpyAbsolute(adr) => read(state, Absolute(adr.into()), |cpu, val| {
+			compare(cpu, cpu.y, val)
+		}),
+		Inst::DecZeroPage(adr) => modify(state, ZeroPage(adr), dec),
+		Inst::DecZeroPageX(adr) => modify(state, ZeroPageX(adr), dec),
+		Inst::DecAbsolute(adr) => modify(state, Absolute(adr.into()), dec),
+		Inst::DecAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), dec),
+		Inst::Dex => implied(state, |cpu| cpu.x = dec(cpu, cpu.x)),
+		Inst::Dey => implied(state, |cpu| cpu.y = dec(cpu, cpu.y)),
+		Inst::EorImmediate(val) => read(state, Immediate(val), eor),
+		Inst::EorZeroPage(adr) => read(state, ZeroPage(adr), eor),
+		Inst::EorZeroPageX(adr) => read(state, ZeroPageX(adr), eor),
+		Inst::EorAbsolute(adr) => read(state, Absolute(adr.into()), eor),
+		Inst::EorAbsoluteX(adr) => read(state, AbsoluteX(adr.into()), eor),
+		Inst::EorAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), eor),
+		Inst::EorIndirectX(adr) => read(state, IndirectX(adr), eor),
+		Inst::EorIndirectY(adr) => read(state, IndirectY(adr), eor),
+		Inst::IncZeroPage(adr) => modify(state, ZeroPage(adr), inc),
+		Inst::IncZeroPageX(adr) => modify(state, ZeroPageX(adr), inc),
+		Inst::IncAbsolute(adr) => modify(state, Absolute(adr.into()), inc),
+		Inst::IncAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), inc),
+		Inst::Inx => implied(state, |cpu| cpu.x = inc(cpu, cpu.x)),
+		Inst::Iny => implied(state, |cpu| cpu.y = inc(cpu, cpu.y)),
+		Inst::JmpAbsolute(adr) => {
+			fetch_high(state);
+			state.cpu.pc = adr.into();
+		}
+		Inst::JmpIndirect(adr) => {
+			// The pointer's high byte comes from the start of the same page when it straddles
+			// two
+			fetch_high(state);
+			let adr = u16::from(adr);
+			let hi_adr = (adr & 0xFF00) | (adr.wrapping_add(1) & 0x00FF);
+			state.cpu.pc = u16::from_le_bytes([state.read(adr), state.read(hi_adr)]);
+		}
+		Inst::Jsr(adr) => {
+			// Pushes the address of its own last byte, which RTS makes up for. That byte is only
+			// fetched after the pushes.
+			peek_stack(state);
+			push_pc(state, state.cpu.pc.wrapping_add(2));
+			fetch_high(state);
+			state.cpu.pc = adr.into();
+		}
+		Inst::LdaImmediate(val) => read(state, Immediate(val), |cpu, val| cpu.a = set_nz(cpu, val)),
+		Inst::LdaZeroPage(adr) => read(state, ZeroPage(adr), |cpu, val| cpu.a = set_nz(cpu, val)),
+		Inst::LdaZeroPageX(adr) => read(state, ZeroPageX(adr), |cpu, val| cpu.a = set_nz(cpu, val)),
+		Inst::LdaAbsolute(adr) => read(state, Absolute(adr.into()), |cpu, val| {
+			cpu.a = set_nz(cpu, val)
+		}),
+		Inst::LdaAbsoluteX(adr) => read(state, AbsoluteX(adr.into()), |cpu, val| {
+			cpu.a = set_nz(cpu, val)
+		}),
+		Inst::LdaAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), |cpu, val| {
+			cpu.a = set_nz(cpu, val)
+		}),
+		Inst::LdaIndirectX(adr) => read(state, IndirectX(adr), |cpu, val| cpu.a = set_nz(cpu, val)),
+		Inst::LdaIndirectY(adr) => read(state, IndirectY(adr), |cpu, val| cpu.a = set_nz(cpu, val)),
+		Inst::LdxImmediate(val) => read(state, Immediate(val), |cpu, val| cpu.x = set_nz(cpu, val)),
+		Inst::LdxZeroPage(adr) => read(state, ZeroPage(adr), |cpu, val| cpu.x = set_nz(cpu, val)),
+		Inst::LdxZeroPageY(adr) => read(state, ZeroPageY(adr), |cpu, val| cpu.x = set_nz(cpu, val)),
+		Inst::LdxAbsolute(adr) => read(state, Absolute(adr.into()), |cpu, val| {
+			cpu.x = set_nz(cpu, val)
+		}),
+		Inst::LdxAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), |cpu, val| {
+			cpu.x = set_nz(cpu, val)
+		}),
+		Inst::LdyImmediate(val) => read(state, Immediate(val), |cpu, val| cpu.y = set_nz(cpu, val)),
+		Inst::LdyZeroPage(adr) => read(state, ZeroPage(adr), |cpu, val| cpu.y = set_nz(cpu, val)),
+		Inst::LdyZeroPageX(adr) => read(state, ZeroPageX(adr), |cpu, val| cpu.y = set_nz(cpu, val)),
+		Inst::LdyAbsolute(adr) => read(state, Absolute(adr.into()), |cpu, val| {
+			cpu.y = set_nz(cpu, val)
+		}),
+		Inst::LdyAbsoluteX(adr) => read(state, AbsoluteX(adr.into()), |cpu, val| {
+			cpu.y = set_nz(cpu, val)
+		}),
+		Inst::LsrAccumulator => accumulator(state, lsr),
+		Inst::LsrZeroPage(adr) => modify(state, ZeroPage(adr), lsr),
+		Inst::LsrZeroPageX(adr) => modify(state, ZeroPageX(adr), lsr),
+		Inst::LsrAbsolute(adr) => modify(state, Absolute(adr.into()), lsr),
+		Inst::LsrAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), lsr),
+		Inst::Nop => implied(state, |_| {}),
+		Inst::Jam
+		| Inst::Jam2
+		| Inst::Jam3
+		| Inst::Jam4
+		| Inst::Jam5
+		| Inst::Jam6
+		| Inst::Jam7
+		| Inst::Jam8
+		| Inst::Jam9
+		| Inst::Jam10
+		| Inst::Jam11
+		| Inst::Jam12 => state.jam(),
+		Inst::OraImmediate(val) => read(state, Immediate(val), ora),
+		Inst::OraZeroPage(adr) => read(state, ZeroPage(adr), ora),
+		Inst::OraZeroPageX(adr) => read(state, ZeroPageX(adr), ora),
+		Inst::OraAbsolute(adr) => read(state, Absolute(adr.into()), ora),
+		Inst::OraAbsoluteX(adr) => read(state, AbsoluteX(adr.into()), ora),
+		Inst::OraAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), ora),
+		Inst::OraIndirectX(adr) => read(state, IndirectX(adr), ora),
+		Inst::OraIndirectY(adr) => read(state, IndirectY(adr), ora),
+		Inst::Pha => {
+			state.push(a);
+			finish(state, 1);
+		}
+		Inst::Php => {
+			state.push(p.into_bits() | 0x30);
+			finish(state, 1);
+		}
+		Inst::Pla => {
+			peek_stack(state);
+			let val = pull(state);
+			state.cpu.a = set_nz(&mut state.cpu, val);
+			finish(state, 1);
+		}
+		Inst::Plp => {
+			peek_stack(state);
+			pull_p(state);
+			finish(state, 1);
+		}
+		Inst::RolAccumulator => accumulator(state, rol),
+		Inst::RolZeroPage(adr) => modify(state, ZeroPage(adr), rol),
+		Inst::RolZeroPageX(adr) => modify(state, ZeroPageX(adr), rol),
+		Inst::RolAbsolute(adr) => modify(state, Absolute(adr.into()), rol),
+		Inst::RolAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), rol),
+		Inst::RorAccumulator => accumulator(state, ror),
+		Inst::RorZeroPage(adr) => modify(state, ZeroPage(adr), ror),
+		Inst::RorZeroPageX(adr) => modify(state, ZeroPageX(adr), ror),
+		Inst::RorAbsolute(adr) => modify(state, Absolute(adr.into()), ror),
+		Inst::RorAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), ror),
+		Inst::Rti => {
+			peek_stack(state);
+			pull_p(state);
+			state.cpu.pc = pull_pc(state);
+		}
+		Inst::Rts => {
+			peek_stack(state);
+			let adr = pull_pc(state);
+			// Reads the return address while incrementing it
+			state.read(adr);
+			state.cpu.pc = adr.wrapping_add(1);
+		}
+		Inst::SbcImmediate(val) => read(state, Immediate(val), sbc),
+		Inst::SbcZeroPage(adr) => read(state, ZeroPage(adr), sbc),
+		Inst::SbcZeroPageX(adr) => read(state, ZeroPageX(adr), sbc),
+		Inst::SbcAbsolute(adr) => read(state, Absolute(adr.into()), sbc),
+		Inst::SbcAbsoluteX(adr) => read(state, AbsoluteX(adr.into()), sbc),
+		Inst::SbcAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), sbc),
+		Inst::SbcIndirectX(adr) => read(state, IndirectX(adr), sbc),
+		Inst::SbcIndirectY(adr) => read(state, IndirectY(adr), sbc),
+		Inst::Sec => implied(state, |cpu| cpu.p.set_c(true)),
+		Inst::Sed => implied(state, |cpu| cpu.p.set_d(true)),
+		Inst::Sei => implied(state, |cpu| cpu.p.set_i(true)),
+		Inst::StaZeroPage(adr) => store(state, ZeroPage(adr), a),
+		Inst::StaZeroPageX(adr) => store(state, ZeroPageX(adr), a),
+		Inst::StaAbsolute(adr) => store(state, Absolute(adr.into()), a),
+		Inst::StaAbsoluteX(adr) => store(state, AbsoluteX(adr.into()), a),
+		Inst::StaAbsoluteY(adr) => store(state, AbsoluteY(adr.into()), a),
+		Inst::StaIndirectX(adr) => store(state, IndirectX(adr), a),
+		Inst::StaIndirectY(adr) => store(state, IndirectY(adr), a),
+		Inst::StxZeroPage(adr) => store(state, ZeroPage(adr), x),
+		Inst::StxZeroPageY(adr) => store(state, ZeroPageY(adr), x),
+		Inst::StxAbsolute(adr) => store(state, Absolute(adr.into()), x),
+		Inst::StyZeroPage(adr) => store(state, ZeroPage(adr), y),
+		Inst::StyZeroPageX(adr) => store(state, ZeroPageX(adr), y),
+		Inst::StyAbsolute(adr) => store(state, Absolute(adr.into()), y),
+		Inst::Tax => implied(state, |cpu| cpu.x = set_nz(cpu, cpu.a)),
+		Inst::Tay => implied(state, |cpu| cpu.y = set_nz(cpu, cpu.a)),
+		Inst::Tsx => implied(state, |cpu| cpu.x = set_nz(cpu, cpu.s)),
+		Inst::Txa => implied(state, |cpu| cpu.a = set_nz(cpu, cpu.x)),
+		Inst::Txs => implied(state, |cpu| cpu.s = cpu.x),
+		Inst::Tya => implied(state, |cpu| cpu.a = set_nz(cpu, cpu.y)),
+
+		// Unofficial instructions, mostly two official ones glued together
+		Inst::LAXZeroPage(adr) => read(state, ZeroPage(adr), lax),
+		Inst::LAXZeroPageY(adr) => read(state, ZeroPageY(adr), lax),
+		Inst::LAXAbsolute(adr) => read(state, Absolute(adr.into()), lax),
+		Inst::LAXAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), lax),
+		Inst::LAXIndirectX(adr) => read(state, IndirectX(adr), lax),
+		Inst::LAXIndirectY(adr) => read(state, IndirectY(adr), lax),
+		Inst::LAXImmediate(val) => read(state, Immediate(val), lax_immediate),
+		Inst::SAXZeroPage(adr) => store(state, ZeroPage(adr), a & x),
+		Inst::SAXZeroPageY(adr) => store(state, ZeroPageY(adr), a & x),
+		Inst::SAXAbsolute(adr) => store(state, Absolute(adr.into()), a & x),
+		Inst::SAXIndirectX(adr) => store(state, IndirectX(adr), a & x),
+		Inst::DCPZeroPage(adr) => modify(state, ZeroPage(adr), dcp),
+		Inst::DCPZeroPageX(adr) => modify(state, ZeroPageX(adr), dcp),
+		Inst::DCPAbsolute(adr) => modify(state, Absolute(adr.into()), dcp),
+		Inst::DCPAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), dcp),
+		Inst::DCPAbsoluteY(adr) => modify(state, AbsoluteY(adr.into()), dcp),
+		Inst::DCPIndirectX(adr) => modify(state, IndirectX(adr), dcp),
+		Inst::DCPIndirectY(adr) => modify(state, IndirectY(adr), dcp),
+		Inst::ISCZeroPage(adr) => modify(state, ZeroPage(adr), isc),
+		Inst::ISCZeroPageX(adr) => modify(state, ZeroPageX(adr), isc),
+		Inst::ISCAbsolute(adr) => modify(state, Absolute(adr.into()), isc),
+		Inst::ISCAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), isc),
+		Inst::ISCAbsoluteY(adr) => modify(state, AbsoluteY(adr.into()), isc),
+		Inst::ISCIndirectX(adr) => modify(state, IndirectX(adr), isc),
+		Inst::ISCIndirectY(adr) => modify(state, IndirectY(adr), isc),
+		Inst::RLAZeroPage(adr) => modify(state, ZeroPage(adr), rla),
+		Inst::RLAZeroPageX(adr) => modify(state, ZeroPageX(adr), rla),
+		Inst::RLAAbsolute(adr) => modify(state, Absolute(adr.into()), rla),
+		Inst::RLAAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), rla),
+		Inst::RLAAbsoluteY(adr) => modify(state, AbsoluteY(adr.into()), rla),
+		Inst::RLAIndirectX(adr) => modify(state, IndirectX(adr), rla),
+		Inst::RLAIndirectY(adr) => modify(state, IndirectY(adr), rla),
+		Inst::RRAZeroPage(adr) => modify(state, ZeroPage(adr), rra),
+		Inst::RRAZeroPageX(adr) => modify(state, ZeroPageX(adr), rra),
+		Inst::RRAAbsolute(adr) => modify(state, Absolute(adr.into()), rra),
+		Inst::RRAAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), rra),
+		Inst::RRAAbsoluteY(adr) => modify(state, AbsoluteY(adr.into()), rra),
+		Inst::RRAIndirectX(adr) => modify(state, IndirectX(adr), rra),
+		Inst::RRAIndirectY(adr) => modify(state, IndirectY(adr), rra),
+		Inst::SLOZeroPage(adr) => modify(state, ZeroPage(adr), slo),
+		Inst::SLOZeroPageX(adr) => modify(state, ZeroPageX(adr), slo),
+		Inst::SLOAbsolute(adr) => modify(state, Absolute(adr.into()), slo),
+		Inst::SLOAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), slo),
+		Inst::SLOAbsoluteY(adr) => modify(state, AbsoluteY(adr.into()), slo),
+		Inst::SLOIndirectX(adr) => modify(state, IndirectX(adr), slo),
+		Inst::SLOIndirectY(adr) => modify(state, IndirectY(adr), slo),
+		Inst::SREZeroPage(adr) => modify(state, ZeroPage(adr), sre),
+		Inst::SREZeroPageX(adr) => modify(state, ZeroPageX(adr), sre),
+		Inst::SREAbsolute(adr) => modify(state, Absolute(adr.into()), sre),
+		Inst::SREAbsoluteX(adr) => modify(state, AbsoluteX(adr.into()), sre),
+		Inst::SREAbsoluteY(adr) => modify(state, AbsoluteY(adr.into()), sre),
+		Inst::SREIndirectX(adr) => modify(state, IndirectX(adr), sre),
+		Inst::SREIndirectY(adr) => modify(state, IndirectY(adr), sre),
+		Inst::AncImmediate(val) | Inst::AncImmediate2(val) => read(state, Immediate(val), anc),
+		Inst::AlrImmediate(val) => read(state, Immediate(val), alr),
+		Inst::ArrImmediate(val) => read(state, Immediate(val), arr),
+		Inst::AxsImmediate(val) => read(state, Immediate(val), axs),
+		Inst::XAAImmediate(val) => read(state, Immediate(val), xaa),
+		Inst::SbcImmediate2(val) => read(state, Immediate(val), sbc),
+		Inst::LASAbsoluteY(adr) => read(state, AbsoluteY(adr.into()), las),
+		Inst::SHYAbsoluteX(adr) => store_and_high(state, AbsoluteX(adr.into()), y),
+		Inst::SHXAbsoluteY(adr) => store_and_high(state, AbsoluteY(adr.into()), x),
+		Inst::AhxAbsoluteY(adr) => store_and_high(state, AbsoluteY(adr.into()), a & x),
+		Inst::AhxIndirectY(adr) => store_and_high(state, IndirectY(adr), a & x),
+		Inst::TASAbsoluteY(adr) => {
+			state.cpu.s = a & x;
+			store_and_high(state, AbsoluteY(adr.into()), a & x);
+		}
+		Inst::NOP4 | Inst::NOP7 | Inst::NOP10 | Inst::NOP13 | Inst::NOP19 | Inst::NOP22 => {
+			implied(state, |_| {})
+		}
+		Inst::NOPImmediate(val)
+		| Inst::NOPImmediate2(val)
+		| Inst::NOPImmediate3(val)
+		| Inst::NOPImmediate4(val)
+		| Inst::NOPImmediate5(val) => read(state, Immediate(val), |_, _| {}),
+		Inst::NOPZeroPage(adr) | Inst::NOPZeroPage3(adr) | Inst::NOPZeroPage4(adr) => {
+			read(state, ZeroPage(adr), |_, _| {})
+		}
+		Inst::NOPZeroPageX(adr)
+		| Inst::NOPZeroPageX2(adr)
+		| Inst::NOPZeroPageX3(adr)
+		| Inst::NOPZeroPageX4(adr)
+		| Inst::NOPZeroPageX5(adr)
+		| Inst::NOPZeroPageX6(adr) => read(state, ZeroPageX(adr), |_, _| {}),
+		Inst::NOPAbsolute(adr) => read(state, Absolute(adr.into()), |_, _| {}),
+		Inst::NOPAbsoluteX(adr)
+		| Inst::NOPAbsoluteX2(adr)
+		| Inst::NOPAbsoluteX3(adr)
+		| Inst::NOPAbsoluteX4(adr)
+		| Inst::NOPAbsoluteX5(adr)
+		| Inst::NOPAbsoluteX6(adr) => read(state, AbsoluteX(adr.into()), |_, _| {}),
+	}
+}
+
+/// Runs `inst` on a 65C02, see `CmosInst`.
+pub fn execute_cmos(inst: CmosInst, state: &mut State) {
+	use Mode::*;
+
+	let Cpu { a, x, y, .. } = state.cpu;
+	let adc = |cpu: &mut Cpu, val| adc(cpu, Variant::Cmos65C02, val);
+	let sbc = |cpu: &mut Cpu, val| sbc(cpu, Variant::Cmos65C02, val);
+	let nop = |_: &mut Cpu, _| {};
+
+	match inst {
+		CmosInst::AdcIndirect(adr) => read(state, ZeroPageIndirect(adr), adc),
+		CmosInst::AndIndirect(adr) => read(state, ZeroPageIndirect(adr), and),
+		CmosInst::BitAbsoluteX(adr) => read(state, AbsoluteX(adr.into()), bit),
+		// Without an address there's nothing to copy N and V from
+		CmosInst::BitImmediate(val) => read(state, Immediate(val), |cpu, val| {
+			cpu.p.set_z(cpu.a & val == 0)
+		}),
+		CmosInst::BitZeroPageX(adr) => read(state, ZeroPageX(adr), bit),
+		CmosInst::Bra(offset) => branch(state, offset, true),
+		CmosInst::Brk => {
+			execute(Inst::Brk, state);
+			state.cpu.p.set_d(false);
+		}
+		CmosInst::CmpIndirect(adr) => read(state, ZeroPageIndirect(adr), |cpu, val| {
+			compare(cpu, cpu.a, val)
+		}),
+		CmosInst::DecAccumulator => accumulator(state, dec),
+		CmosInst::EorIndirect(adr) => read(state, ZeroPageIndirect(adr), eor),
+		CmosInst::IncAccumulator => accumulator(state, inc),
+		CmosInst::JmpIndirect(adr) => {
+			// Reads the high byte of the pointer twice instead of getting it wrong
+			fetch_high(state);
+			fetch_high(state);
+			let adr = u16::from(adr);
+			state.cpu.pc = u16::from_le_bytes([state.read(adr), state.read(adr.wrapping_add(1))]);
+		}
+		CmosInst::JmpIndirectX(adr) => {
+			fetch_high(state);
+			fetch_high(state);
+			let adr = u16::from(adr).wrapping_add(x as u16);
+			state.cpu.pc = u16::from_le_bytes([state.read(adr), state.read(adr.wrapping_add(1))]);
+		}
+		CmosInst::LdaIndirect(adr) => read(state, ZeroPageIndirect(adr), |cpu, val| {
+			cpu.a = set_nz(cpu, val)
+		}),
+		CmosInst::Nop => finish(state, 1),
+		CmosInst::NopImmediate(val) => read(state, Immediate(val), nop),
+		CmosInst::NopZeroPage(adr) => read(state, ZeroPage(adr), nop),
+		CmosInst::NopZeroPageX(adr) => read(state, ZeroPageX(adr), nop),
+		CmosInst::NopAbsolute(adr) => read(state, Absolute(adr.into()), nop),
+		CmosInst::NopAbsolute8(adr) => {
+			// Spends its cycles reading from the top page
+			fetch_high(state);
+			let [lo, _] = u16::from(adr).to_le_bytes();
+			for _ in 0..5 {
+				state.read(u16::from_le_bytes([lo, 0xFF]));
+			}
+			finish(state, 3);
+		}
+		CmosInst::OraIndirect(adr) => read(state, ZeroPageIndirect(adr), ora),
+		CmosInst::Phx => {
+			state.push(x);
+			finish(state, 1);
+		}
+		CmosInst::Phy => {
+			state.push(y);
+			finish(state, 1);
+		}
+		CmosInst::Plx => {
+			peek_stack(state);
+			let val = pull(state);
+			state.cpu.x = set_nz(&mut state.cpu, val);
+			finish(state, 1);
+		}
+		CmosInst::Ply => {
+			peek_stack(state);
+			let val = pull(state);
+			state.cpu.y = set_nz(&mut state.cpu, val);
+			finish(state, 1);
+		}
+		CmosInst::SbcIndirect(adr) => read(state, ZeroPageIndirect(adr), sbc),
+		CmosInst::StaIndirect(adr) => store(state, ZeroPageIndirect(adr), a),
+		CmosInst::StzAbsolute(adr) => store(state, Absolute(adr.into()), 0),
+		CmosInst::StzAbsoluteX(adr) => store(state, AbsoluteX(adr.into()), 0),
+		CmosInst::StzZeroPage(adr) => store(state, ZeroPage(adr), 0),
+		CmosInst::StzZeroPageX(adr) => store(state, ZeroPageX(adr), 0),
+		CmosInst::TrbAbsolute(adr) => modify(state, Absolute(adr.into()), trb),
+		CmosInst::TrbZeroPage(adr) => modify(state, ZeroPage(adr), trb),
+		CmosInst::TsbAbsolute(adr) => modify(state, Absolute(adr.into()), tsb),
+		CmosInst::TsbZeroPage(adr) => modify(state, ZeroPage(adr), tsb),
+	}
+}
+
+/// TSB and TRB set Z like BIT does, from A AND the old value.
+fn tsb(cpu: &mut Cpu, val: u8) -> u8 {
+	cpu.p.set_z(cpu.a & val == 0);
+	val | cpu.a
+}
+
+fn trb(cpu: &mut Cpu, val: u8) -> u8 {
+	cpu.p.set_z(cpu.a & val == 0);
+	val & !cpu.a
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{cpu::Backend, tests};
+
+	const BACKENDS: &[Backend] = &[
+		#[cfg(feature = "c-core")]
+		Backend::C,
+		Backend::Rust,
+	];
+
+	/// `tests::load` on `backend`.
+	fn load(backend: Backend, program: &[u8]) -> Box<State> {
+		let mut state = tests::load(program);
+		state.backend = backend;
+		state
+	}
+
+	/// Runs `program` from $8000 until it runs off the end.
+	pub fn run(backend: Backend, program: &[u8]) -> Box<State> {
+		let mut state = load(backend, program);
+		let end = 0x8000 + program.len() as u16;
+		for _ in 0..10_000 {
+			if state.cpu.pc == end {
+				return state;
+			}
+			state.next();
+		}
+		panic!("Program didn't finish, stuck at {:04X}", state.cpu.pc);
+	}
+
+	#[test]
+	fn arithmetic() {
+		#[rustfmt::skip]
+		let program = [
+			// $50 + $50 overflows into the sign bit
+			0xA9, 0x50, 0x69, 0x50, 0x85, 0x00, 0x08,
+			// $50 - $F0 borrows
+			0x38, 0xA9, 0x50, 0xE9, 0xF0, 0x85, 0x01, 0x08,
+			// CMP equal sets Z and C
+			0xC9, 0x60, 0x08,
+		];
+		for &backend in BACKENDS {
+			let state = run(backend, &program);
+			assert_eq!(state.ram[0x00], 0xA0, "{backend:?}");
+			assert_eq!(state.ram[0x01], 0x60, "{backend:?}");
+			// N V - B D I Z C, with B and bit 5 set by PHP and I from reset
+			assert_eq!(state.ram[0x1FD], 0b1111_0100, "{backend:?}");
+			assert_eq!(state.ram[0x1FC], 0b0011_0100, "{backend:?}");
+			assert_eq!(state.ram[0x1FB], 0b0011_0111, "{backend:?}");
+		}
+	}
+
+	#[test]
+	fn addressing_modes() {
+		#[rustfmt::skip]
+		let program = [
+			// Pointer at $FF wraps around to $00 for its high byte: ($FF) = $0300
+			0xA9, 0x00, 0x85, 0xFF, 0xA9, 0x03, 0x85, 0x00,
+			0xA0, 0x05, 0xA9, 0x42, 0x91, 0xFF,
+			// Zero page X wraps: $F0,X with X = $20 is $10
+			0xA2, 0x20, 0x95, 0xF0,
+			// Absolute Y crosses into the next page
+			0x99, 0xFF, 0x02,
+		];
+		for &backend in BACKENDS {
+			let state = run(backend, &program);
+			assert_eq!(state.ram[0x305], 0x42, "{backend:?}");
+			assert_eq!(state.ram[0x10], 0x42, "{backend:?}");
+			assert_eq!(state.ram[0x304], 0x42, "{backend:?}");
+		}
+	}
+
+	#[test]
+	fn indirect_modes() {
+		#[rustfmt::skip]
+		let program = [
+			// ($20) = $0300, holding $CD, with $AB at $0305
+			0xA9, 0x00, 0x85, 0x20, 0xA9, 0x03, 0x85, 0x21,
+			0xA9, 0xAB, 0x8D, 0x05, 0x03, 0xA9, 0xCD, 0x8D, 0x00, 0x03,
+			// LDA ($10,X) with X = $10 goes through the pointer at $20 once
+			0xA2, 0x10, 0xA1, 0x10, 0x85, 0x00,
+			// LDA ($20),Y adds Y to the pointer, not to where it is
+			0xA0, 0x05, 0xB1, 0x20, 0x85, 0x01,
+			// ASL $F0,X writes back to $00, where it read from
+			0x16, 0xF0,
+		];
+		for &backend in BACKENDS {
+			let state = run(backend, &program);
+			assert_eq!(state.ram[0x00], 0x9A, "{backend:?}");
+			assert_eq!(state.ram[0x01], 0xAB, "{backend:?}");
+		}
+	}
+
+	#[test]
+	fn subroutines_and_interrupts() {
+		let mut program = vec![0; 0x1010];
+		#[rustfmt::skip]
+		program[..8].copy_from_slice(&[
+			// JSR $8010, BRK and its padding byte, then JMP ($81FF)
+			0x20, 0x10, 0x80, 0x00, 0xEA, 0x6C, 0xFF, 0x81,
+		]);
+		// Increments X and returns
+		program[0x10..0x12].copy_from_slice(&[0xE8, 0x60]);
+		// The pointer's high byte comes from $8100 rather than $8200, so it points at the end
+		program[0x1FF] = 0x10;
+		program[0x100] = 0x90;
+		program[0x200] = 0x80;
+		// The BRK handler at $9000: PHA, LDA #$55, PLA, RTI
+		program[0x1000..0x1005].copy_from_slice(&[0x48, 0xA9, 0x55, 0x68, 0x40]);
+
+		for &backend in BACKENDS {
+			let state = run(backend, &program);
+			assert_eq!(state.cpu.x, 1, "{backend:?}");
+			assert_eq!(state.cpu.s, 0xFD, "{backend:?}");
+			assert_eq!(state.cpu.a, 0, "{backend:?}");
+		}
+	}
+
+	/// A loop running in lockstep on both.
+	#[cfg(feature = "c-core")]
+	#[test]
+	fn backends_agree() {
+		#[rustfmt::skip]
+		let program = [
+			0xA2, 0x05, 0x18, 0xA9, 0x00,
+			// ADC #$33, STA $10,X, DEX, BNE back to the ADC
+			0x69, 0x33, 0x95, 0x10, 0xCA, 0xD0, 0xF9,
+			0xA8, 0xE8,
+		];
+		let rust = run(Backend::Rust, &program);
+		let c = run(Backend::C, &program);
+		assert_eq!(rust.cpu, c.cpu);
+		assert_eq!(rust.ram, c.ram);
+		assert_eq!(rust.cycles, c.cycles);
+	}
+
+	/// Every bus access takes a cycle, including the dummy ones.
+	#[test]
+	fn cycle_counts() {
+		let mut program = vec![0; 0x31];
+		#[rustfmt::skip]
+		program[..0x22].copy_from_slice(&[
+			// LDX #$20, LDY #$01, then INC $10 and INC $10,X
+			0xA2, 0x20, 0xA0, 0x01, 0xE6, 0x10, 0xF6, 0x10,
+			// STA $0200,X always fixes up the address, LDA only when it crosses a page
+			0x9D, 0x00, 0x02, 0xBD, 0x00, 0x02, 0xBD, 0xF0, 0x02,
+			// LDA ($00),Y, ASL A, PHA, PLA
+			0xB1, 0x00, 0x0A, 0x48, 0x68,
+			// LDX #$01 and a BNE that's taken, JMP $801D, JSR $8030, NOP
+			0xA2, 0x01, 0xD0, 0x00, 0x4C, 0x1D, 0x80, 0x20, 0x30, 0x80, 0xEA,
+			// BRK
+			0x00,
+		]);
+		// RTS
+		program[0x30] = 0x60;
+		#[rustfmt::skip]
+		let expected = [
+			2, 2, 5, 6,
+			5, 4, 5,
+			5, 2, 3, 4,
+			2, 3, 3, 6, 6, 2,
+			7,
+		];
+		for &backend in BACKENDS {
+			let mut state = load(backend, &program);
+			for (i, expected) in expected.into_iter().enumerate() {
+				let pc = state.cpu.pc;
+				let before = state.cycles;
+				state.next();
+				assert_eq!(
+					state.cycles - before,
+					expected,
+					"{backend:?} instruction {i} at {pc:04X}"
+				);
+			}
+			assert_eq!(state.cpu.pc, 0x9000, "{backend:?}");
+		}
+	}
+
+	/// Cycles each opcode takes without crossing a page, 0 for the JAMs. Branches count as not
+	/// taken.
+	#[rustfmt::skip]
+	const CYCLES: [u64; 256] = [
+		7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+		2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+		6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+		2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+		6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+		2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+		6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+		2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+		2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+		2, 6, 0, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+		2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+		2, 5, 0, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+		2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+		2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+		2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+		2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+	];
+
+	/// The reads that take a cycle more when indexing crosses a page. Taken branches do too.
+	#[rustfmt::skip]
+	const PAGE_PENALTY: &[u8] = &[
+		// (indirect),Y
+		0x11, 0x31, 0x51, 0x71, 0xB1, 0xB3, 0xD1, 0xF1,
+		// absolute,Y
+		0x19, 0x39, 0x59, 0x79, 0xB9, 0xBB, 0xBE, 0xBF, 0xD9, 0xF9,
+		// absolute,X
+		0x1C, 0x1D, 0x3C, 0x3D, 0x5C, 0x5D, 0x7C, 0x7D, 0xBC, 0xBD, 0xDC, 0xDD, 0xFC, 0xFD,
+	];
+
+	#[test]
+	fn cycle_table() {
+		for &backend in BACKENDS {
+			for opcode in 0..=255u8 {
+				if CYCLES[opcode as usize] == 0 {
+					continue;
+				}
+				// With every flag clear, the branches on a clear flag are taken
+				let branch = opcode & 0x1F == 0x10;
+				let taken = branch && opcode & 0x20 == 0;
+
+				for cross in [false, true] {
+					// Branches go back to $7F82 to cross a page. Everything else gets $80 in the
+					// zero page or $0280, and every pointer in the zero page is $0303. Indexing
+					// any of those by $FF crosses a page, apart from the zero page wrapping.
+					let operand = match (branch, cross) {
+						(true, false) => 0x02,
+						_ => 0x80,
+					};
+					let program = [opcode, operand, 0x02];
+					let mut state = load(backend, &program);
+					state.ram[..0x100].fill(0x03);
+					state.cpu.p = P::from_bits(0);
+					let index = if cross { 0xFF } else { 0 };
+					state.cpu.x = index;
+					state.cpu.y = index;
+
+					let mut expected = CYCLES[opcode as usize];
+					if taken {
+						expected += 1 + cross as u64;
+					} else if cross && PAGE_PENALTY.contains(&opcode) {
+						expected += 1;
+					}
+					let before = state.cycles;
+					state.next();
+					assert_eq!(
+						state.cycles - before,
+						expected,
+						"{backend:?} {:?}, crossing a page: {cross}",
+						Inst::decode(program),
+					);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn decimal_mode() {
+		#[rustfmt::skip]
+		let program = [
+			// SED, then $58 + $46 + 1 = $105, which leaves $05 and carries
+			0xF8, 0x38, 0xA9, 0x58, 0x69, 0x46, 0x85, 0x00, 0x08,
+			// $40 - $13 = $27 with the carry set
+			0x38, 0xA9, 0x40, 0xE9, 0x13, 0x85, 0x01,
+			// $99 + $01 = $00, which only the 65C02 sets Z for
+			0x18, 0xA9, 0x99, 0x69, 0x01, 0x85, 0x02, 0x08,
+			// $80 - $01 = $7F in binary overflows
+			0xD8, 0x38, 0xA9, 0x80, 0xE9, 0x01, 0x08,
+		];
+		for &backend in BACKENDS {
+			// The 2A03 ignores D
+			let state = run(backend, &program);
+			assert_eq!(state.ram[..3], [0x9F, 0x2D, 0x9A], "{backend:?}");
+			assert_eq!(state.ram[0x1FB] & 0x40, 0x40, "{backend:?}");
+
+			for (variant, z) in [(Variant::Nmos6502, 0), (Variant::Cmos65C02, 0x02)] {
+				let mut state = load(backend, &program);
+				state.variant = variant;
+				let end = 0x8000 + program.len() as u16;
+				while state.cpu.pc != end {
+					state.next();
+				}
+				assert_eq!(
+					state.ram[..3],
+					[0x05, 0x27, 0x00],
+					"{backend:?} {variant:?}"
+				);
+				assert_eq!(state.ram[0x1FD] & 0x01, 0x01, "{backend:?} {variant:?}");
+				assert_eq!(state.ram[0x1FC] & 0x03, 0x01 | z, "{backend:?} {variant:?}");
+				assert_eq!(state.ram[0x1FB] & 0x40, 0x40, "{backend:?} {variant:?}");
+			}
+		}
+	}
+
+	#[test]
+	fn cmos_instructions() {
+		let mut program = vec![0; 0x1010];
+		#[rustfmt::skip]
+		program[..0x26].copy_from_slice(&[
+			// LDA #$0F, STA $10, LDA #$3C, TSB $10 leaves $3F, TRB $11
+			0xA9, 0x0F, 0x85, 0x10, 0xA9, 0x3C, 0x04, 0x10, 0x14, 0x11,
+			// LDX #$22, PHX, PLY, STZ $12, INC A
+			0xA2, 0x22, 0xDA, 0x7A, 0x64, 0x12, 0x1A,
+			// Points $20 at $0300, then LDA #$55, STA ($20), LDA #$00, ORA ($20)
+			0xA9, 0x03, 0x85, 0x21, 0x64, 0x20,
+			0xA9, 0x55, 0x92, 0x20, 0xA9, 0x00, 0x12, 0x20,
+			// BRA over a JAM, a single byte NOP, then JMP ($80FF)
+			0x80, 0x01, 0x02, 0xFB, 0x6C, 0xFF, 0x80,
+		]);
+		// The pointer's high byte comes from $8100 now, so it's $8030
+		program[0xFF] = 0x30;
+		program[0x100] = 0x80;
+		// SED, BRK
+		program[0x30..0x32].copy_from_slice(&[0xF8, 0x00]);
+		// The BRK handler: PHP, PLA, STA $13
+		program[0x1000..0x1004].copy_from_slice(&[0x08, 0x68, 0x85, 0x13]);
+
+		for &backend in BACKENDS {
+			let mut state = load(backend, &program);
+			state.variant = Variant::Cmos65C02;
+			state.ram[0x11] = 0xF0;
+			state.ram[0x12] = 0xAA;
+			for _ in 0..100 {
+				if state.cpu.pc == 0x9004 {
+					break;
+				}
+				state.next();
+			}
+			assert_eq!(state.cpu.pc, 0x9004, "{backend:?}");
+			assert_eq!(state.ram[0x10..0x13], [0x3F, 0xC0, 0x00], "{backend:?}");
+			assert_eq!(state.ram[0x300], 0x55, "{backend:?}");
+			assert_eq!((state.cpu.x, state.cpu.y), (0x22, 0x22), "{backend:?}");
+			// B and bit 5 from PHP and I from BRK, which cleared D
+			assert_eq!(state.ram[0x13] & 0x3C, 0x34, "{backend:?}");
+		}
+	}
+
+	/// Every opcode the 65C02 changed runs the same on both cores.
+	#[cfg(feature = "c-core")]
+	#[test]
+	fn cmos_backends_agree() {
+		for opcode in 0..=255u8 {
+			let program = [opcode, 0x80, 0x02];
+			let Some(inst) = CmosInst::decode(program) else {
+				continue;
+			};
+			let [rust, c] = [Backend::Rust, Backend::C].map(|backend| {
+				let mut state = load(backend, &program);
+				state.variant = Variant::Cmos65C02;
+				state.ram[..0x100].fill(0x03);
+				(state.cpu.x, state.cpu.y, state.cpu.a) = (0xFF, 0xFF, 0x5A);
+				state.next();
+				state
+			});
+			assert_eq!(rust.cpu, c.cpu, "{inst:?}");
+			assert_eq!(rust.ram, c.ram, "{inst:?}");
+			assert_eq!(rust.cycles, c.cycles, "{inst:?}");
+			if !matches!(
+				inst,
+				CmosInst::Bra(..)
+					| CmosInst::Brk | CmosInst::JmpIndirect(..)
+					| CmosInst::JmpIndirectX(..)
+			) {
+				assert_eq!(rust.cpu.pc, 0x8000 + inst.len() as u16, "{inst:?}");
+			}
+		}
+	}
+
+	#[test]
+	fn jam_halts_until_reset() {
+		for &backend in BACKENDS {
+			// LDA #$05, JAM, then an INX that never happens
+			let mut state = load(backend, &[0xA9, 0x05, 0x12, 0xE8]);
+			for _ in 0..100 {
+				state.next();
+			}
+			assert!(state.cpu.jammed, "{backend:?}");
+			assert_eq!(state.cpu.pc, 0x8002);
+			assert_eq!((state.cpu.a, state.cpu.x), (0x05, 0));
+			// The clock keeps going
+			assert!(state.cycles > 100);
+		}
+	}
+
+	#[test]
+	fn unofficial_instructions() {
+		#[rustfmt::skip]
+		let program = [
+			// LAX $20 loads $5A into A and X, SAX $21 stores $F0 & $5A
+			0xA9, 0x5A, 0x85, 0x20, 0xA9, 0x00, 0xA7, 0x20, 0xA9, 0xF0, 0x87, 0x21,
+			// DCP, SLO, SRE, RLA, RRA and ISC $21
+			0xC7, 0x21, 0x07, 0x21, 0x47, 0x21, 0x27, 0x21, 0x67, 0x21, 0xE7, 0x21,
+			// ANC #$80, ALR #$FF, ARR #$FF, then LDX #$30 and AXS #$10
+			0x0B, 0x80, 0x4B, 0xFF, 0x6B, 0xFF, 0xA2, 0x30, 0xCB, 0x10,
+			// SHX $03F0,Y crosses a page, which takes the high byte of $10 & $04 = $00
+			0xA9, 0xFF, 0x85, 0x10, 0xA0, 0x20, 0x9E, 0xF0, 0x03,
+			// SHY $0700,X doesn't, and stores $2C & $08
+			0xA2, 0x10, 0xA0, 0x2C, 0x9C, 0x00, 0x07,
+		];
+		for &backend in BACKENDS {
+			let state = run(backend, &program);
+			let cpu = state.cpu;
+			assert_eq!((cpu.a, cpu.x, cpu.y), (0xFF, 0x10, 0x2C), "{backend:?}");
+			assert_eq!(state.ram[0x20], 0x5A, "{backend:?}");
+			assert_eq!(state.ram[0x21], 0x50, "{backend:?}");
+			assert_eq!(state.ram[0x10], 0x00, "{backend:?}");
+			assert_eq!(state.ram[0x710], 0x08, "{backend:?}");
+			// C from AXS, V from ARR
+			assert!(cpu.p.c() && cpu.p.v(), "{backend:?}");
+		}
+	}
+}
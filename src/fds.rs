@@ -0,0 +1,936 @@
+use anyhow::{Result, bail};
+
+use crate::{
+	apu,
+	nes_file::{Mirroring, SharedMem},
+};
+
+/// Size of one disk side in a `.fds` image, which stores the blocks without gaps or CRCs.
+pub const SIDE_SIZE: usize = 65500;
+
+/// The fwNES header some `.fds` images start with.
+const FWNES_MAGIC: &[u8; 4] = b"FDS\x1A";
+/// Every disk side starts with a disk info block, which starts like this.
+const SIDE_MAGIC: &[u8; 15] = b"\x01*NINTENDO-HVC*";
+
+/// The drive spins up over this many zero bits before the first block.
+const LEADING_GAP_BYTES: usize = 28300 / 8;
+/// And leaves this many between blocks.
+const BLOCK_GAP_BYTES: usize = 976 / 8;
+/// Room on a gapped side, enough for a full side split into a reasonable number of files.
+const GAPPED_SIDE_SIZE: usize = LEADING_GAP_BYTES + SIDE_SIZE + 64 * (BLOCK_GAP_BYTES + 3);
+
+/// The drive moves one byte past the head roughly every 150 CPU cycles (96.4 kbit/s).
+const BYTE_CYCLES: u32 = 150;
+/// After reaching the end of the disk, the head takes a while to get back to the start.
+const REWIND_CYCLES: u32 = 50000;
+/// How long the drive stays empty when switching sides, so the BIOS notices the disk changed.
+const SWAP_CYCLES: u32 = 1_789_773 / 2;
+
+pub fn is_disk_image(buffer: &[u8]) -> bool {
+	buffer.starts_with(FWNES_MAGIC) || buffer.starts_with(SIDE_MAGIC)
+}
+
+/// Splits a `.fds` image, with or without the fwNES header, into its raw sides.
+pub fn parse_image(buffer: &[u8]) -> Result<Vec<u8>> {
+	let data = if buffer.starts_with(FWNES_MAGIC) {
+		let Some(data) = buffer.get(16..) else {
+			bail!("File is too short to be an FDS image, the fwNES header alone is 16 bytes");
+		};
+		if data.len() != buffer[4] as usize * SIDE_SIZE {
+			eprintln!(
+				"fwNES header claims {} disk sides but the file holds {} bytes of them, going by the file size",
+				buffer[4],
+				data.len()
+			);
+		}
+		data
+	} else {
+		buffer
+	};
+
+	if data.is_empty() || data.len() % SIDE_SIZE != 0 {
+		bail!(
+			"FDS image is {} bytes, which isn't a whole number of {SIDE_SIZE} byte disk sides",
+			data.len()
+		);
+	}
+	if let Some(side) = data
+		.chunks(SIDE_SIZE)
+		.position(|side| !side.starts_with(SIDE_MAGIC))
+	{
+		bail!("Disk side {side} doesn't start with a disk info block");
+	}
+
+	Ok(data.to_vec())
+}
+
+/// The length of a block as stored in a `.fds` image, going by its type byte. `file_size` is
+/// taken from the file header block preceding a file data block.
+fn block_len(block_type: u8, file_size: usize) -> Option<usize> {
+	match block_type {
+		1 => Some(56),
+		2 => Some(2),
+		3 => Some(16),
+		4 => Some(1 + file_size),
+		_ => None,
+	}
+}
+
+/// Calls `f` with each block on a raw side.
+fn for_each_block(side: &[u8], mut f: impl FnMut(&[u8])) {
+	let mut pos = 0;
+	let mut file_size = 0;
+	// Games can hide files past the count in the file amount block, so go until the blocks run out
+	while let Some(&block_type) = side.get(pos)
+		&& let Some(len) = block_len(block_type, file_size)
+		&& let Some(block) = side.get(pos..pos + len)
+	{
+		if block_type == 3 {
+			file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+		}
+		f(block);
+		pos += len;
+	}
+}
+
+/// Lays a raw side out like the drive sees it: gaps between the blocks, each of which starts
+/// with a $80 mark and ends with its CRC.
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+	let mut gapped = vec![0; LEADING_GAP_BYTES];
+	for_each_block(side, |block| {
+		let start = gapped.len();
+		gapped.push(0x80);
+		gapped.extend_from_slice(block);
+		let crc = block_crc(&gapped[start..]);
+		gapped.extend(crc.to_le_bytes());
+		gapped.extend([0; BLOCK_GAP_BYTES]);
+	});
+	gapped.resize(GAPPED_SIDE_SIZE.max(gapped.len()), 0);
+	gapped
+}
+
+/// The inverse of `add_gaps`, for writing what games saved back to a `.fds` image.
+fn remove_gaps(gapped: &[u8]) -> Vec<u8> {
+	let mut side = Vec::with_capacity(SIDE_SIZE);
+	let mut pos = 0;
+	let mut file_size = 0;
+	while let Some(mark) = gapped
+		.get(pos..)
+		.and_then(|rest| rest.iter().position(|&b| b != 0))
+	{
+		pos += mark;
+		if gapped[pos] != 0x80 {
+			break;
+		}
+		pos += 1;
+
+		let Some(len) = gapped.get(pos).and_then(|&t| block_len(t, file_size)) else {
+			break;
+		};
+		let Some(block) = gapped.get(pos..pos + len) else {
+			break;
+		};
+		if block[0] == 3 {
+			file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+		}
+		side.extend_from_slice(block);
+		// Skip the CRC
+		pos += len + 2;
+	}
+	side.resize(SIDE_SIZE, 0);
+	side
+}
+
+fn update_crc(crc: u16, val: u8) -> u16 {
+	(0..8).fold(crc, |crc, bit| {
+		let carry = crc & 1 != 0;
+		let mut crc = crc >> 1;
+		if carry {
+			crc ^= 0x8408;
+		}
+		if val & (1 << bit) != 0 {
+			crc ^= 0x8000;
+		}
+		crc
+	})
+}
+
+/// The CRC the drive writes after a block, $80 mark included.
+fn block_crc(block: &[u8]) -> u16 {
+	let crc = block.iter().fold(0, |crc, &b| update_crc(crc, b));
+	update_crc(update_crc(crc, 0), 0)
+}
+
+/// What the modulation table's 3 bit entries do to the modulation counter, `None` resetting it.
+const MOD_STEPS: [Option<i8>; 8] = [
+	Some(0),
+	Some(1),
+	Some(2),
+	Some(4),
+	None,
+	Some(-4),
+	Some(-2),
+	Some(-1),
+];
+
+/// The volume or modulation envelope: a gain that either stays where it's put or ramps up or
+/// down by one at a rate set by the envelope and the master speed at $408A.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Envelope {
+	/// $4080/$4084 bit 7 clear.
+	enabled: bool,
+	increase: bool,
+	speed: u8,
+	gain: u8,
+	counter: u32,
+}
+
+impl Envelope {
+	fn write(&mut self, val: u8, master_speed: u8) {
+		self.enabled = val & 0x80 == 0;
+		self.increase = val & 0x40 != 0;
+		self.speed = val & 0x3F;
+		if !self.enabled {
+			self.gain = self.speed;
+		}
+		self.reload(master_speed);
+	}
+
+	fn reload(&mut self, master_speed: u8) {
+		self.counter = 8 * (self.speed as u32 + 1) * master_speed as u32;
+	}
+
+	fn tick(&mut self, master_speed: u8) {
+		if !self.enabled || master_speed == 0 {
+			return;
+		}
+		if self.counter > 0 {
+			self.counter -= 1;
+			return;
+		}
+		self.reload(master_speed);
+		if self.increase && self.gain < 32 {
+			self.gain += 1;
+		} else if !self.increase && self.gain > 0 {
+			self.gain -= 1;
+		}
+	}
+}
+
+/// The disk system's sound: one channel playing a 64 step wave of 6 bit samples, with its
+/// pitch bent by a second table of modulation steps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FdsAudio {
+	/// $4040-$407F
+	wave_ram: [u8; 64],
+	/// $4089 bit 7, which also holds the output where it is.
+	wave_writable: bool,
+	/// $4089 bits 0-1.
+	master_volume: u8,
+	/// $408A, how fast both envelopes run, 0 stopping them.
+	master_speed: u8,
+
+	volume: Envelope,
+	/// The volume as of the start of the wave, which is when changes to it take effect.
+	latched_volume: u8,
+	pitch: u16,
+	wave_halted: bool,
+	envelopes_halted: bool,
+	/// The wave's position is the top 6 bits.
+	wave_accumulator: u32,
+
+	modulation: Envelope,
+	mod_table: [u8; 64],
+	/// Where `$4088` writes go, two entries at a time.
+	mod_write: usize,
+	mod_position: usize,
+	mod_pitch: u16,
+	mod_halted: bool,
+	mod_accumulator: u32,
+	/// 7 bit signed.
+	mod_counter: i8,
+
+	output: u8,
+}
+
+impl Default for FdsAudio {
+	fn default() -> Self {
+		Self {
+			wave_ram: [0; _],
+			wave_writable: false,
+			master_volume: 0,
+			// What the BIOS sets it to
+			master_speed: 0xE8,
+			volume: Envelope::default(),
+			latched_volume: 0,
+			pitch: 0,
+			wave_halted: true,
+			envelopes_halted: true,
+			wave_accumulator: 0,
+			modulation: Envelope::default(),
+			mod_table: [0; _],
+			mod_write: 0,
+			mod_position: 0,
+			mod_pitch: 0,
+			mod_halted: true,
+			mod_accumulator: 0,
+			mod_counter: 0,
+			output: 0,
+		}
+	}
+}
+
+impl FdsAudio {
+	pub fn read(&self, adr: u16) -> Option<u8> {
+		match adr {
+			0x4040..=0x407F => Some(self.wave_ram[adr as usize - 0x4040]),
+			0x4090 => Some(0x40 | self.volume.gain),
+			0x4092 => Some(0x40 | self.modulation.gain),
+			_ => None,
+		}
+	}
+
+	pub fn write(&mut self, adr: u16, val: u8) {
+		match adr {
+			0x4040..=0x407F if self.wave_writable => {
+				self.wave_ram[adr as usize - 0x4040] = val & 0x3F
+			}
+			0x4080 => self.volume.write(val, self.master_speed),
+			0x4082 => self.pitch = self.pitch & 0xF00 | val as u16,
+			0x4083 => {
+				self.pitch = self.pitch & 0x0FF | ((val & 0x0F) as u16) << 8;
+				self.wave_halted = val & 0x80 != 0;
+				self.envelopes_halted = val & 0x40 != 0;
+				if self.wave_halted {
+					self.wave_accumulator = 0;
+				}
+				if self.envelopes_halted {
+					self.volume.reload(self.master_speed);
+					self.modulation.reload(self.master_speed);
+				}
+			}
+			0x4084 => self.modulation.write(val, self.master_speed),
+			0x4085 => self.mod_counter = ((val << 1) as i8) >> 1,
+			0x4086 => self.mod_pitch = self.mod_pitch & 0xF00 | val as u16,
+			0x4087 => {
+				self.mod_pitch = self.mod_pitch & 0x0FF | ((val & 0x0F) as u16) << 8;
+				self.mod_halted = val & 0x80 != 0;
+				if self.mod_halted {
+					self.mod_accumulator &= 0xFFFF_F000;
+				}
+			}
+			// The table can only be filled while it's halted, and each entry is written twice
+			0x4088 if self.mod_halted => {
+				self.mod_table[self.mod_write] = val & 0b111;
+				self.mod_table[self.mod_write + 1] = val & 0b111;
+				self.mod_write = (self.mod_write + 2) % 64;
+				self.mod_position = self.mod_write;
+			}
+			0x4089 => {
+				self.wave_writable = val & 0x80 != 0;
+				self.master_volume = val & 0b11;
+			}
+			0x408A => self.master_speed = val,
+			_ => {}
+		}
+	}
+
+	pub fn tick(&mut self) {
+		if !self.envelopes_halted && !self.wave_halted {
+			self.volume.tick(self.master_speed);
+			self.modulation.tick(self.master_speed);
+		}
+
+		if !self.mod_halted && self.mod_pitch > 0 {
+			let before = self.mod_accumulator;
+			self.mod_accumulator = (self.mod_accumulator + self.mod_pitch as u32) & 0x3F_FFFF;
+			if before >> 16 != self.mod_accumulator >> 16 {
+				self.step_modulation();
+			}
+		}
+
+		if self.wave_halted {
+			return;
+		}
+		let before = self.wave_accumulator;
+		self.wave_accumulator = (self.wave_accumulator + self.wave_pitch()) & 0x3F_FFFF;
+		let position = self.wave_accumulator >> 16;
+		if position < before >> 16 {
+			self.latched_volume = self.volume.gain.min(32);
+		}
+		if !self.wave_writable {
+			self.output = self.wave_ram[position as usize];
+		}
+	}
+
+	fn step_modulation(&mut self) {
+		let step = MOD_STEPS[self.mod_table[self.mod_position] as usize];
+		self.mod_position = (self.mod_position + 1) % 64;
+		self.mod_counter = match step {
+			// Wrapping at 7 bits
+			Some(step) => (self.mod_counter.wrapping_add(step) << 1) >> 1,
+			None => 0,
+		};
+	}
+
+	/// The wave's pitch, bent by the modulation counter times its gain. This is the integer
+	/// arithmetic the hardware does, rounding and all.
+	fn wave_pitch(&self) -> u32 {
+		let counter = self.mod_counter as i32;
+		let mut bend = counter * self.modulation.gain as i32;
+		let remainder = bend & 0x0F;
+		bend >>= 4;
+		if remainder > 0 && bend & 0x80 == 0 {
+			bend += if counter < 0 { -1 } else { 2 };
+		}
+		if bend >= 192 {
+			bend -= 256;
+		} else if bend < -64 {
+			bend += 256;
+		}
+		let mut bend = self.pitch as i32 * bend;
+		let remainder = bend & 0x3F;
+		bend >>= 6;
+		if remainder >= 32 {
+			bend += 1;
+		}
+		(self.pitch as i32 + bend).max(0) as u32
+	}
+
+	/// A full scale wave at full volume swings about 2.4 times as far as a 2A03 pulse at full
+	/// volume, with the master volume taking that down to 2/3, 2/4 or 2/5.
+	pub fn output(&self) -> f32 {
+		let level = self.output as f32 * self.latched_volume as f32 / (63.0 * 32.0);
+		let master = 2.0 / (self.master_volume + 2) as f32;
+		level * master * 2.4 * apu::PULSE_LEVEL
+	}
+}
+
+/// The Famicom Disk System: the RAM adapter in the cartridge slot, and the drive behind it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fds {
+	pub bios: SharedMem,
+	pub prg_ram: [u8; 32 * 1024],
+	pub chr_ram: [u8; 8 * 1024],
+	pub mirroring: Mirroring,
+
+	/// The raw sides as loaded, what saves are diffed against.
+	original: SharedMem,
+	/// The sides as the drive sees them, see `add_gaps`.
+	sides: Vec<SharedMem>,
+	side: Option<usize>,
+	next_side: usize,
+	swap_delay: u32,
+
+	disk_io_enabled: bool,
+	sound_io_enabled: bool,
+
+	timer_reload: u16,
+	timer_counter: u16,
+	timer_repeat: bool,
+	timer_enabled: bool,
+	timer_irq: bool,
+
+	motor_on: bool,
+	reset_transfer: bool,
+	read_mode: bool,
+	crc_control: bool,
+	disk_ready: bool,
+	disk_irq_enabled: bool,
+	disk_irq: bool,
+
+	position: usize,
+	delay: u32,
+	scanning: bool,
+	end_of_head: bool,
+	gap_ended: bool,
+	transfer_complete: bool,
+	previous_crc_control: bool,
+	crc: u16,
+	read_data: u8,
+	write_data: u8,
+	ext_output: u8,
+
+	pub audio: FdsAudio,
+}
+
+impl Fds {
+	/// `disk` is the raw sides back to back, as returned by `parse_image`.
+	pub fn new(bios: &[u8], disk: Vec<u8>) -> Result<Self> {
+		if bios.len() != 8 * 1024 {
+			bail!(
+				"The disk system BIOS should be 8192 bytes, not {}",
+				bios.len()
+			);
+		}
+
+		let mut fds = Self {
+			bios: bios.into(),
+			prg_ram: [0; _],
+			chr_ram: [0; _],
+			mirroring: Mirroring::Horizontal,
+			original: disk.into(),
+			sides: Vec::new(),
+			side: Some(0),
+			next_side: 0,
+			swap_delay: 0,
+			disk_io_enabled: false,
+			sound_io_enabled: false,
+			timer_reload: 0,
+			timer_counter: 0,
+			timer_repeat: false,
+			timer_enabled: false,
+			timer_irq: false,
+			motor_on: false,
+			reset_transfer: false,
+			read_mode: false,
+			crc_control: false,
+			disk_ready: false,
+			disk_irq_enabled: false,
+			disk_irq: false,
+			position: 0,
+			delay: 0,
+			scanning: false,
+			end_of_head: true,
+			gap_ended: false,
+			transfer_complete: false,
+			previous_crc_control: false,
+			crc: 0,
+			read_data: 0,
+			write_data: 0,
+			ext_output: 0,
+			audio: FdsAudio::default(),
+		};
+		fds.set_disk_data(&fds.original.clone())?;
+		Ok(fds)
+	}
+
+	/// The disk as it was loaded.
+	pub fn original_disk_data(&self) -> &[u8] {
+		&self.original
+	}
+
+	/// The disk with everything games have written to it, in the same layout as
+	/// `original_disk_data`.
+	pub fn disk_data(&self) -> Vec<u8> {
+		self.sides
+			.iter()
+			.flat_map(|side| remove_gaps(side))
+			.collect()
+	}
+
+	/// Replaces the contents of all sides, e.g. with a patched copy of `original_disk_data`.
+	pub fn set_disk_data(&mut self, disk: &[u8]) -> Result<()> {
+		if disk.len() != self.original.len() {
+			bail!(
+				"Disk data is {} bytes but the disk is {} bytes",
+				disk.len(),
+				self.original.len()
+			);
+		}
+		self.sides = disk
+			.chunks(SIDE_SIZE)
+			.map(|side| add_gaps(side).into())
+			.collect();
+		Ok(())
+	}
+
+	/// Ejects the disk and, after a delay long enough for the BIOS to notice, inserts the next
+	/// side. Returns the side that will be inserted.
+	pub fn next_side(&mut self) -> usize {
+		let current = self.side.unwrap_or(self.next_side);
+		self.next_side = (current + 1) % self.sides.len();
+		self.side = None;
+		self.swap_delay = SWAP_CYCLES;
+		self.next_side
+	}
+
+	pub fn irq(&self) -> bool {
+		self.timer_irq || self.disk_irq
+	}
+
+	pub fn get_cpu(&self, adr: u16) -> Option<u8> {
+		match adr {
+			0x4030..=0x4033 if !self.disk_io_enabled => None,
+			0x4030 => Some(
+				self.timer_irq as u8
+					| (self.transfer_complete as u8) << 1
+					| (self.crc_error() as u8) << 4
+					| (self.end_of_head as u8) << 6,
+			),
+			0x4031 => Some(self.read_data),
+			0x4032 => {
+				let inserted = self.side.is_some();
+				Some(
+					!inserted as u8
+						| ((!inserted || !self.scanning) as u8) << 1
+						// Write protect, which only an empty drive has
+						| (!inserted as u8) << 2,
+				)
+			}
+			// Bit 7 is the battery check, which always passes
+			0x4033 => Some(0x80 | self.ext_output & 0x7F),
+			0x4040..=0x4092 => self.audio.read(adr),
+			0x6000..=0xDFFF => Some(self.prg_ram[adr as usize - 0x6000]),
+			0xE000..=0xFFFF => Some(self.bios[adr as usize - 0xE000]),
+			_ => None,
+		}
+	}
+
+	/// Like `get_cpu`, but with the side effects of reading the status and data registers.
+	pub fn read_cpu(&mut self, adr: u16) -> Option<u8> {
+		let val = self.get_cpu(adr);
+		if self.disk_io_enabled {
+			match adr {
+				0x4030 => {
+					self.transfer_complete = false;
+					self.timer_irq = false;
+					self.disk_irq = false;
+				}
+				0x4031 => {
+					self.transfer_complete = false;
+					self.disk_irq = false;
+				}
+				_ => {}
+			}
+		}
+		val
+	}
+
+	pub fn set_cpu(&mut self, adr: u16, val: u8) {
+		match adr {
+			0x4023 => {
+				self.disk_io_enabled = val & 1 != 0;
+				self.sound_io_enabled = val & 2 != 0;
+				if !self.disk_io_enabled {
+					self.timer_enabled = false;
+					self.timer_irq = false;
+					self.disk_irq = false;
+				}
+			}
+			0x4020..=0x4026 if !self.disk_io_enabled => {}
+			0x4020 => self.timer_reload = self.timer_reload & 0xFF00 | val as u16,
+			0x4021 => self.timer_reload = self.timer_reload & 0x00FF | (val as u16) << 8,
+			0x4022 => {
+				self.timer_repeat = val & 1 != 0;
+				self.timer_enabled = val & 2 != 0;
+				if self.timer_enabled {
+					self.timer_counter = self.timer_reload;
+				} else {
+					self.timer_irq = false;
+				}
+			}
+			0x4024 => {
+				self.write_data = val;
+				self.transfer_complete = false;
+				self.disk_irq = false;
+			}
+			0x4025 => {
+				self.motor_on = val & 0x01 != 0;
+				self.reset_transfer = val & 0x02 != 0;
+				self.read_mode = val & 0x04 != 0;
+				self.mirroring = if val & 0x08 != 0 {
+					Mirroring::Horizontal
+				} else {
+					Mirroring::Vertical
+				};
+				self.crc_control = val & 0x10 != 0;
+				self.disk_ready = val & 0x40 != 0;
+				self.disk_irq_enabled = val & 0x80 != 0;
+				self.disk_irq = false;
+			}
+			0x4026 => self.ext_output = val,
+			0x4040..=0x408A if !self.sound_io_enabled => {}
+			0x4040..=0x408A => self.audio.write(adr, val),
+			0x6000..=0xDFFF => self.prg_ram[adr as usize - 0x6000] = val,
+			_ => {}
+		}
+	}
+
+	fn crc_error(&self) -> bool {
+		self.read_mode && self.crc_control && self.crc != 0
+	}
+
+	/// Advances the IRQ timer, the drive and the sound by one CPU cycle.
+	pub fn tick(&mut self) {
+		self.tick_timer();
+		self.tick_drive();
+		self.audio.tick();
+	}
+
+	fn tick_timer(&mut self) {
+		if !self.timer_enabled {
+			return;
+		}
+		if self.timer_counter == 0 {
+			self.timer_irq = true;
+			self.timer_counter = self.timer_reload;
+			self.timer_enabled = self.timer_repeat;
+		} else {
+			self.timer_counter -= 1;
+		}
+	}
+
+	fn tick_drive(&mut self) {
+		if self.swap_delay > 0 {
+			self.swap_delay -= 1;
+			if self.swap_delay == 0 {
+				self.side = Some(self.next_side);
+			}
+			return;
+		}
+		let Some(side) = self.side else {
+			return;
+		};
+
+		if !self.motor_on {
+			self.end_of_head = true;
+			self.scanning = false;
+			return;
+		}
+		if self.reset_transfer && !self.scanning {
+			return;
+		}
+		if self.end_of_head {
+			self.delay = REWIND_CYCLES;
+			self.end_of_head = false;
+			self.position = 0;
+			self.gap_ended = false;
+			return;
+		}
+		if self.delay > 0 {
+			self.delay -= 1;
+			return;
+		}
+
+		self.scanning = true;
+		let disk = &mut self.sides[side];
+		let mut irq = self.disk_irq_enabled;
+
+		if self.read_mode {
+			let val = disk[self.position];
+			if !self.previous_crc_control {
+				self.crc = update_crc(self.crc, val);
+			}
+			if !self.disk_ready {
+				self.gap_ended = false;
+				self.crc = 0;
+			} else if val != 0 && !self.gap_ended {
+				// The $80 mark at the end of a gap isn't data, so it doesn't get an IRQ
+				self.gap_ended = true;
+				irq = false;
+			}
+			if self.gap_ended {
+				self.transfer_complete = true;
+				self.read_data = val;
+				self.disk_irq |= irq;
+			}
+		} else {
+			let mut val = 0;
+			if !self.crc_control {
+				self.transfer_complete = true;
+				self.disk_irq |= irq;
+				val = self.write_data;
+			}
+			if !self.disk_ready {
+				val = 0;
+			}
+			if !self.crc_control {
+				self.crc = update_crc(self.crc, val);
+			} else {
+				if !self.previous_crc_control {
+					self.crc = update_crc(update_crc(self.crc, 0), 0);
+				}
+				val = self.crc as u8;
+				self.crc >>= 8;
+			}
+			disk[self.position] = val;
+			self.gap_ended = false;
+		}
+
+		self.previous_crc_control = self.crc_control;
+		self.position += 1;
+		if self.position >= disk.len() {
+			self.motor_on = false;
+		} else {
+			self.delay = BYTE_CYCLES;
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// A side with one 4 byte file on it.
+	fn side() -> Vec<u8> {
+		let mut side = SIDE_MAGIC.to_vec();
+		side.resize(56, 0x20);
+		side.extend([2, 1]);
+		side.extend([
+			3, 0, 0, b'F', b'I', b'L', b'E', b' ', b' ', b' ', b' ', 0, 0, 4, 0, 0,
+		]);
+		side.extend([4, 0xDE, 0xAD, 0xBE, 0xEF]);
+		side.resize(SIDE_SIZE, 0);
+		side
+	}
+
+	#[test]
+	fn images() {
+		let raw = side();
+		assert_eq!(parse_image(&raw).unwrap(), raw);
+
+		let mut fwnes = vec![b'F', b'D', b'S', 0x1A, 2];
+		fwnes.resize(16, 0);
+		fwnes.extend(&raw);
+		fwnes.extend(&raw);
+		assert_eq!(parse_image(&fwnes).unwrap().len(), 2 * SIDE_SIZE);
+
+		assert!(parse_image(&raw[..1000]).is_err());
+		assert!(parse_image(&[0; SIDE_SIZE]).is_err());
+	}
+
+	#[test]
+	fn gaps_round_trip() {
+		let raw = side();
+		let gapped = add_gaps(&raw);
+		assert_eq!(gapped[LEADING_GAP_BYTES], 0x80);
+		assert_eq!(gapped[LEADING_GAP_BYTES + 1], 1);
+		assert_eq!(remove_gaps(&gapped), raw);
+	}
+
+	#[test]
+	fn crc_of_block_and_crc_is_zero() {
+		let raw = side();
+		let mut block = vec![0x80];
+		block.extend_from_slice(&raw[..56]);
+		let crc = block_crc(&block);
+		block.extend(crc.to_le_bytes());
+		assert_eq!(block.iter().fold(0, |crc, &b| update_crc(crc, b)), 0);
+	}
+
+	#[test]
+	fn timer_irq() {
+		let mut fds = Fds::new(&[0; 8 * 1024], side()).unwrap();
+		fds.set_cpu(0x4023, 0x01);
+		fds.set_cpu(0x4020, 10);
+		fds.set_cpu(0x4021, 0);
+		fds.set_cpu(0x4022, 0x02);
+
+		for _ in 0..10 {
+			fds.tick();
+		}
+		assert!(!fds.irq());
+		fds.tick();
+		assert!(fds.irq());
+
+		fds.read_cpu(0x4030);
+		assert!(!fds.irq());
+		// Not repeating, so it stays quiet
+		for _ in 0..100 {
+			fds.tick();
+		}
+		assert!(!fds.irq());
+	}
+
+	#[test]
+	fn wave_playback() {
+		let mut fds = Fds::new(&[0; 8 * 1024], side()).unwrap();
+		fds.set_cpu(0x4023, 0x03);
+		// A ramp from 0 to 63, only writable while the wave is held
+		fds.set_cpu(0x4089, 0x80);
+		for i in 0..64 {
+			fds.set_cpu(0x4040 + i, i as u8);
+		}
+		fds.set_cpu(0x4089, 0x00);
+		fds.set_cpu(0x4040, 0x3F);
+		assert_eq!(fds.get_cpu(0x4040), Some(0));
+
+		// Full volume, and a pitch that moves a step every 64 cycles
+		fds.set_cpu(0x4080, 0xA0);
+		assert_eq!(fds.get_cpu(0x4090), Some(0x60));
+		fds.set_cpu(0x4082, 0x00);
+		fds.set_cpu(0x4083, 0x04);
+		let levels = (0..64 * 64 * 2)
+			.map(|_| {
+				fds.tick();
+				fds.audio.output()
+			})
+			.collect::<Vec<_>>();
+		// Silent until the wave comes round and picks up the new volume
+		assert_eq!(levels[64 * 32], 0.0);
+		let peak = levels.iter().fold(0.0f32, |max, &l| max.max(l));
+		assert!((peak - 2.4 * apu::PULSE_LEVEL).abs() < 1e-6, "{peak}");
+		assert!(levels[64 * 64 + 64 * 32] < peak * 0.55);
+
+		// Master volume takes it down to 2/5
+		fds.set_cpu(0x4089, 0x03);
+		assert!((fds.audio.output() * 5.0 / 2.0 - levels[64 * 64 * 2 - 1]).abs() < 1e-6);
+	}
+
+	#[test]
+	fn modulation_bends_pitch() {
+		let mut audio = FdsAudio::default();
+		audio.write(0x4082, 0x00);
+		audio.write(0x4083, 0x01);
+		for (counter, gain, pitch) in [
+			(0x00, 0x20, 0x100),
+			(0x10, 0x20, 0x180),
+			(0x70, 0x20, 0x080),
+			// Rounding up from the bits dropped
+			(0x01, 0x01, 0x108),
+		] {
+			audio.write(0x4084, 0x80 | gain);
+			audio.write(0x4085, counter);
+			assert_eq!(audio.wave_pitch(), pitch, "{counter:02X} {gain:02X}");
+		}
+
+		// Each table entry is written twice, and stepping through it moves the counter
+		audio.write(0x4087, 0x80);
+		for step in [1, 1, 4, 7] {
+			audio.write(0x4088, step);
+		}
+		audio.write(0x4085, 0x00);
+		audio.mod_position = 0;
+		let counters = (0..8)
+			.map(|_| {
+				audio.step_modulation();
+				audio.mod_counter
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(counters, [1, 2, 3, 4, 0, 0, -1, -2]);
+	}
+
+	#[test]
+	fn reading_the_disk() {
+		let mut fds = Fds::new(&[0; 8 * 1024], side()).unwrap();
+		fds.set_cpu(0x4023, 0x01);
+		// Motor on, read mode, ready for data
+		fds.set_cpu(0x4025, 0x45);
+
+		let mut bytes = Vec::new();
+		for _ in 0..(REWIND_CYCLES + (LEADING_GAP_BYTES as u32 + 20) * (BYTE_CYCLES + 1)) {
+			fds.tick();
+			if fds.get_cpu(0x4030).unwrap() & 0x02 != 0 {
+				bytes.push(fds.read_cpu(0x4031).unwrap());
+			}
+		}
+		assert_eq!(bytes[..16], *b"\x80\x01*NINTENDO-HVC*");
+	}
+
+	#[test]
+	fn side_switching() {
+		let mut fds = Fds::new(&[0; 8 * 1024], [side(), side()].concat()).unwrap();
+		fds.set_cpu(0x4023, 0x01);
+		assert_eq!(fds.get_cpu(0x4032).unwrap() & 1, 0);
+
+		assert_eq!(fds.next_side(), 1);
+		assert_eq!(fds.get_cpu(0x4032).unwrap() & 1, 1);
+		for _ in 0..SWAP_CYCLES {
+			fds.tick();
+		}
+		assert_eq!(fds.get_cpu(0x4032).unwrap() & 1, 0);
+		assert_eq!(fds.side, Some(1));
+	}
+}
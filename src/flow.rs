@@ -0,0 +1,336 @@
+//! Finds the code in PRG ROM by following it from the interrupt vectors, the first step towards
+//! recompiling it. Only what's mapped in right now is seen, so code in banks that aren't, or
+//! that's reached through a jump table, needs a `Profile` of the game running to be found.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+	inst::Inst,
+	nes_file::{Mapper, PrgMapping},
+	profile::Profile,
+};
+
+/// NMI, reset and IRQ, in the order they sit at the top of memory.
+pub const VECTORS: [u16; 3] = [0xFFFA, 0xFFFC, 0xFFFE];
+
+/// No mapper banks PRG ROM in anything smaller than 4K, so a 4K page is always mapped in whole,
+/// at a 4K boundary. Blocks end where a page does, and so only ever depend on the one page.
+pub const PAGE_SIZE: usize = 0x1000;
+
+/// Whether `a` and `b` are in the same 4K of CPU space, and so in the same page of PRG ROM.
+pub fn same_page(a: u16, b: u16) -> bool {
+	a & 0xF000 == b & 0xF000
+}
+
+/// Instructions that are only ever entered at the top and left at the bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+	/// Where it was found, though it runs the same wherever its page is mapped in.
+	pub start: u16,
+	/// Where `start` is in PRG ROM.
+	pub offset: usize,
+	pub insts: Vec<Inst>,
+	/// Where it can go next, with the instruction after a JSR counting as where it returns to.
+	/// Empty after RTS, RTI, indirect jumps and JAMs.
+	pub successors: Vec<u16>,
+}
+
+impl Block {
+	/// One past the last byte of the last instruction.
+	pub fn end(&self) -> u16 {
+		let len = self.insts.iter().map(|inst| inst.len() as u16).sum::<u16>();
+		self.start.wrapping_add(len)
+	}
+
+	/// Whether the last instruction runs on into the next page, which might not be the page
+	/// after it in ROM when it runs.
+	pub fn leaves_page(&self) -> bool {
+		!same_page(self.start, self.end().wrapping_sub(1))
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct FlowGraph {
+	/// Blocks by the page of PRG ROM they're in, then by offset. ROM mapped in at more than one
+	/// address is only found once.
+	pub pages: BTreeMap<usize, BTreeMap<usize, Block>>,
+	/// Where the `JMP ($xxxx)`s are, as where they go depends on RAM.
+	pub indirect_jumps: BTreeSet<u16>,
+	/// Jumps and branches that leave PRG ROM, usually for code copied to RAM.
+	pub outside_rom: BTreeSet<u16>,
+}
+
+/// PRG ROM as the CPU would see it with the banks in `mapping`.
+#[derive(Clone, Copy)]
+struct View<'a> {
+	mapper: &'a Mapper,
+	mapping: PrgMapping,
+}
+
+impl View<'_> {
+	fn prg_rom_offset(&self, adr: u16) -> Option<usize> {
+		let window = adr.checked_sub(0x6000)? as usize / 0x1000;
+		Some(self.mapping[window]? + (adr as usize & 0x0FFF))
+	}
+
+	fn get(&self, adr: u16) -> Option<u8> {
+		self.mapper.prg_rom_at(self.prg_rom_offset(adr)?)
+	}
+
+	fn decode(&self, pc: u16) -> Inst {
+		// Operands running off the end of ROM read as 0, which decoding never looks at anyway
+		let byte = |offset| self.get(pc.wrapping_add(offset)).unwrap_or(0);
+		Inst::decode([byte(0), byte(1), byte(2)])
+	}
+}
+
+impl FlowGraph {
+	/// Follows the code from wherever the vectors point with the banks mapped in now, and from
+	/// everywhere `profile` saw the code go with the banks it saw mapped in.
+	pub fn analyse(mapper: &Mapper, profile: &Profile) -> Self {
+		let mut entries = profile.targets.clone();
+		let now = View {
+			mapper,
+			mapping: mapper.prg_mapping(),
+		};
+		let vectors = VECTORS.iter().filter_map(|&vector| {
+			let lo = now.get(vector)?;
+			let hi = now.get(vector.wrapping_add(1))?;
+			Some(u16::from_le_bytes([lo, hi]))
+		});
+		entries.entry(now.mapping).or_default().extend(vectors);
+
+		let mut graph = FlowGraph::default();
+		for (mapping, entries) in entries {
+			graph.merge(Self::from_entries(View { mapper, mapping }, entries));
+		}
+		graph
+	}
+
+	/// Adds what another pass found. Blocks both found are kept as this one has them.
+	fn merge(&mut self, other: FlowGraph) {
+		for (page, blocks) in other.pages {
+			let ours = self.pages.entry(page).or_default();
+			for (offset, block) in blocks {
+				ours.entry(offset).or_insert(block);
+			}
+		}
+		self.indirect_jumps.extend(other.indirect_jumps);
+		self.outside_rom.extend(other.outside_rom);
+	}
+
+	/// Follows the code from `entries`, as far as it can be followed without running it.
+	fn from_entries(view: View, entries: impl IntoIterator<Item = u16>) -> Self {
+		let mut graph = FlowGraph::default();
+		let mut insts = BTreeMap::new();
+		// Addresses something jumps or branches to, where a block has to start
+		let mut leaders = BTreeSet::new();
+		let mut work = entries.into_iter().collect::<Vec<_>>();
+
+		while let Some(start) = work.pop() {
+			if !leaders.insert(start) {
+				continue;
+			}
+			let mut pc = start;
+			// Runs through to the end of the block, or into code that's already been seen
+			loop {
+				if insts.contains_key(&pc) {
+					// Joining code from the middle splits it there
+					leaders.insert(pc);
+					break;
+				}
+				if view.prg_rom_offset(pc).is_none() {
+					graph.outside_rom.insert(pc);
+					break;
+				}
+				let inst = view.decode(pc);
+				insts.insert(pc, inst);
+				if let Inst::JmpIndirect(..) = inst {
+					graph.indirect_jumps.insert(pc);
+				}
+				let next = pc.wrapping_add(inst.len() as u16);
+				if ends_block(inst) {
+					work.extend(targets(pc, next, inst));
+					break;
+				}
+				if !same_page(pc, next) {
+					work.push(next);
+					break;
+				}
+				pc = next;
+			}
+		}
+
+		for &start in &leaders {
+			if !insts.contains_key(&start) {
+				continue;
+			}
+			let offset = view.prg_rom_offset(start).unwrap();
+			let mut block = Block {
+				start,
+				offset,
+				insts: Vec::new(),
+				successors: Vec::new(),
+			};
+			let mut pc = start;
+			while let Some(&inst) = insts.get(&pc) {
+				block.insts.push(inst);
+				let next = pc.wrapping_add(inst.len() as u16);
+				if ends_block(inst) {
+					block.successors = targets(pc, next, inst);
+					break;
+				}
+				if leaders.contains(&next) || !same_page(pc, next) {
+					block.successors = vec![next];
+					break;
+				}
+				pc = next;
+			}
+			graph
+				.pages
+				.entry(offset / PAGE_SIZE)
+				.or_default()
+				.entry(offset)
+				.or_insert(block);
+		}
+		graph
+	}
+
+	pub fn block_count(&self) -> usize {
+		self.pages.values().map(BTreeMap::len).sum()
+	}
+}
+
+/// BRK and the JAMs end blocks too, which `Inst::ends_bb` leaves out as they're not jumps.
+pub fn ends_block(inst: Inst) -> bool {
+	inst.ends_bb() || inst.is_jam() || inst == Inst::Brk
+}
+
+/// Where control goes after `inst` at `pc`, with `next` being the instruction after it.
+fn targets(pc: u16, next: u16, inst: Inst) -> Vec<u16> {
+	if let Some(offset) = inst.branch_offset() {
+		return vec![next.wrapping_add_signed(offset as i16), next];
+	}
+	match inst {
+		Inst::JmpAbsolute(adr) => vec![adr.into()],
+		Inst::Jsr(adr) => vec![adr.into(), next],
+		// The IRQ handler is a root already, and it returns past the padding byte
+		Inst::Brk => vec![pc.wrapping_add(2)],
+		_ if ends_block(inst) => Vec::new(),
+		_ => vec![next],
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::tests::nrom;
+
+	use super::*;
+
+	#[test]
+	fn follows_branches_and_calls() {
+		#[rustfmt::skip]
+		let program = [
+			// $8000, reset
+			0xA2, 0x00,       // LDX #0
+			0xE8,             // loop: INX
+			0x20, 0x0D, 0x80, // JSR $800D
+			0xD0, 0xFA,       // BNE loop
+			0x4C, 0x0C, 0x80, // JMP $800C
+			0x02,             // JAM, never reached
+			0x02,             // $800C: JAM
+			0xA9, 0x01,       // $800D: LDA #1
+			0x60,             // RTS
+			0x40,             // $8010, NMI and IRQ: RTI
+		];
+		let graph =
+			FlowGraph::analyse(&nrom(&program, 0x8010, 0x8000, 0x8010), &Profile::default());
+
+		let blocks = &graph.pages[&0];
+		let block = |adr| &blocks[&(adr as usize - 0x8000)];
+		let starts = blocks.values().map(|block| block.start).collect::<Vec<_>>();
+		assert_eq!(
+			starts,
+			[0x8000, 0x8002, 0x8006, 0x8008, 0x800C, 0x800D, 0x8010]
+		);
+		assert_eq!(block(0x8000).successors, [0x8002]);
+		assert_eq!(block(0x8002).successors, [0x800D, 0x8006]);
+		assert_eq!(block(0x8002).end(), 0x8006);
+		assert_eq!(block(0x8006).successors, [0x8002, 0x8008]);
+		assert_eq!(block(0x8008).successors, [0x800C]);
+		assert!(block(0x800C).successors.is_empty());
+		assert_eq!(block(0x800D).insts.len(), 2);
+		// The byte after the JMP is never decoded
+		assert!(!blocks.contains_key(&0x000B));
+	}
+
+	#[test]
+	fn records_what_it_cannot_follow() {
+		#[rustfmt::skip]
+		let program = [
+			0xF0, 0x03,       // BEQ +3
+			0x6C, 0x00, 0x02, // JMP ($0200)
+			0x4C, 0x00, 0x03, // JMP $0300
+		];
+		// The 16K shows up at both $8000 and $C000, but it's the same code either way
+		let graph =
+			FlowGraph::analyse(&nrom(&program, 0x8000, 0xC000, 0x8000), &Profile::default());
+
+		assert_eq!(graph.block_count(), 3);
+		assert_eq!(graph.pages[&0][&0x0000].start, 0x8000);
+		assert_eq!(graph.indirect_jumps, BTreeSet::from([0x8002, 0xC002]));
+		assert_eq!(graph.outside_rom, BTreeSet::from([0x0300]));
+	}
+
+	#[test]
+	fn starts_from_profiled_targets() {
+		#[rustfmt::skip]
+		let mut program = vec![
+			0x6C, 0x00, 0x02, // JMP ($0200)
+			0xA9, 0x01,       // $8003: LDA #1, only reached through the jump
+			0x02,             // JAM
+		];
+		program.resize(0x10, 0);
+		program.extend([0xA2, 0x02, 0x02]); // $8010: LDX #2, JAM, reached with the page elsewhere
+		let mapper = nrom(&program, 0x8000, 0x8000, 0x8000);
+		assert_eq!(
+			FlowGraph::analyse(&mapper, &Profile::default()).block_count(),
+			1
+		);
+
+		let mut profile = Profile::default();
+		profile.record(mapper.prg_mapping(), 0x8003);
+		// Where the bank it was seen in is mapped now doesn't matter
+		let mut elsewhere = [None; 10];
+		elsewhere[3] = Some(0x0000);
+		profile.record(elsewhere, 0x9010);
+		let graph = FlowGraph::analyse(&mapper, &profile);
+
+		let blocks = &graph.pages[&0];
+		assert_eq!(graph.block_count(), 3);
+		assert_eq!(blocks[&0x0003].insts.len(), 2);
+		assert_eq!(blocks[&0x0010].start, 0x9010);
+	}
+
+	#[test]
+	fn ends_blocks_at_page_boundaries() {
+		let mut program = vec![0; 0x0FFD];
+		#[rustfmt::skip]
+		program.extend([
+			0xA9, 0x01, // $8FFD: LDA #1
+			0xA2, 0x02, // $8FFF: LDX #2, with its operand in the next page
+			0xC8,       // $9001: INY
+			0x02,       // JAM
+		]);
+		let graph =
+			FlowGraph::analyse(&nrom(&program, 0x8FFD, 0x8FFD, 0x8FFD), &Profile::default());
+
+		let first = &graph.pages[&0][&0x0FFD];
+		assert_eq!(first.insts.len(), 2);
+		assert_eq!(first.successors, [0x9001]);
+		assert!(first.leaves_page());
+		let second = &graph.pages[&1][&0x1001];
+		assert_eq!(second.insts, [Inst::Iny, Inst::decode([0x02, 0, 0])]);
+		assert!(!second.leaves_page());
+	}
+}
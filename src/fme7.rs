@@ -0,0 +1,458 @@
+use anyhow::{Result, bail};
+
+use crate::{
+	apu,
+	nes_file::{Mirroring, SharedMem},
+	ppu::Ppu,
+	rom_header::RomHeader,
+	vrc,
+};
+
+/// One of the 5B's square wave generators.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Tone {
+	period: u16,
+	counter: u16,
+	high: bool,
+}
+
+/// Sunsoft 5B audio, a YM2149F (an AY-3-8910 clone) in the mapper: three square waves that can
+/// each mix in the shared noise and envelope generators.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sunsoft5b {
+	address: u8,
+	tones: [Tone; 3],
+	noise_period: u8,
+	noise_counter: u16,
+	/// 17 bit LFSR
+	noise_lfsr: u32,
+	/// $07, with a set bit disabling tone (bits 0-2) or noise (bits 3-5) on a channel.
+	mixer: u8,
+	/// $08-$0A, bit 4 switches the channel to the envelope.
+	volumes: [u8; 3],
+	envelope_period: u16,
+	envelope_counter: u32,
+	envelope_shape: u8,
+	/// 0-31, before `envelope_attack` flips it.
+	envelope_step: u8,
+	envelope_attack: bool,
+	envelope_holding: bool,
+	/// Alternates the 5B's internal clock, which runs at half the CPU's.
+	divider: bool,
+}
+
+impl Default for Sunsoft5b {
+	fn default() -> Self {
+		Self {
+			address: 0,
+			tones: Default::default(),
+			noise_period: 0,
+			noise_counter: 0,
+			noise_lfsr: 1,
+			mixer: 0,
+			volumes: [0; _],
+			envelope_period: 0,
+			envelope_counter: 0,
+			envelope_shape: 0,
+			envelope_step: 0,
+			envelope_attack: false,
+			envelope_holding: false,
+			divider: false,
+		}
+	}
+}
+
+/// Output of a 5 bit level, in steps of 1.5dB with 0 silent.
+fn level_amplitude(level: u8) -> f32 {
+	match level {
+		0 => 0.0,
+		level => 10f32.powf(-((31 - level) as f32 * 1.5) / 20.0),
+	}
+}
+
+impl Sunsoft5b {
+	/// $C000-$DFFF
+	pub fn select(&mut self, val: u8) {
+		self.address = val;
+	}
+
+	/// $E000-$FFFF
+	pub fn write(&mut self, val: u8) {
+		match self.address {
+			0x00..=0x05 => {
+				let tone = &mut self.tones[self.address as usize / 2];
+				tone.period = if self.address.is_multiple_of(2) {
+					(tone.period & 0xF00) | val as u16
+				} else {
+					(tone.period & 0x0FF) | ((val & 0x0F) as u16) << 8
+				};
+			}
+			0x06 => self.noise_period = val & 0x1F,
+			0x07 => self.mixer = val,
+			0x08..=0x0A => self.volumes[self.address as usize - 0x08] = val & 0x1F,
+			0x0B => self.envelope_period = (self.envelope_period & 0xFF00) | val as u16,
+			0x0C => self.envelope_period = (self.envelope_period & 0x00FF) | (val as u16) << 8,
+			0x0D => {
+				self.envelope_shape = val & 0x0F;
+				self.envelope_step = 0;
+				self.envelope_counter = 0;
+				self.envelope_attack = val & 0b0100 != 0;
+				self.envelope_holding = false;
+			}
+			_ => {}
+		}
+	}
+
+	pub fn tick(&mut self) {
+		self.divider = !self.divider;
+		if !self.divider {
+			return;
+		}
+
+		// Tones flip every 8 internal clocks per period
+		for tone in &mut self.tones {
+			tone.counter += 1;
+			if tone.counter >= tone.period.max(1) * 8 {
+				tone.counter = 0;
+				tone.high = !tone.high;
+			}
+		}
+
+		self.noise_counter += 1;
+		if self.noise_counter >= self.noise_period.max(1) as u16 * 16 {
+			self.noise_counter = 0;
+			let feedback = (self.noise_lfsr ^ (self.noise_lfsr >> 3)) & 1;
+			self.noise_lfsr = (self.noise_lfsr >> 1) | feedback << 16;
+		}
+
+		self.envelope_counter += 1;
+		if self.envelope_counter >= self.envelope_period.max(1) as u32 * 8 {
+			self.envelope_counter = 0;
+			self.step_envelope();
+		}
+	}
+
+	fn step_envelope(&mut self) {
+		if self.envelope_holding {
+			return;
+		}
+		if self.envelope_step < 31 {
+			self.envelope_step += 1;
+			return;
+		}
+
+		// End of a cycle: hold, alternate or repeat depending on the shape
+		let [hold, alternate, _, cont] =
+			[0, 1, 2, 3].map(|bit| self.envelope_shape & (1 << bit) != 0);
+		if !cont {
+			self.envelope_attack = false;
+			self.envelope_holding = true;
+			self.envelope_step = 31;
+		} else if hold {
+			if alternate {
+				self.envelope_attack = !self.envelope_attack;
+			}
+			self.envelope_holding = true;
+		} else {
+			if alternate {
+				self.envelope_attack = !self.envelope_attack;
+			}
+			self.envelope_step = 0;
+		}
+	}
+
+	fn envelope_level(&self) -> u8 {
+		match (
+			self.envelope_attack,
+			self.envelope_holding,
+			self.envelope_shape & 0b1000,
+		) {
+			// Shapes without continue drop to silence and stay there
+			(_, true, 0) => 0,
+			(true, _, _) => self.envelope_step,
+			(false, _, _) => 31 - self.envelope_step,
+		}
+	}
+
+	/// The 5B is mixed hotter than the 2A03 on Gimmick!'s board, a channel at full volume is
+	/// about 2.7 times as loud as a 2A03 pulse at full volume.
+	pub fn output(&self) -> f32 {
+		let noise = self.noise_lfsr & 1 != 0;
+		let mut sum = 0.0;
+		for (i, tone) in self.tones.iter().enumerate() {
+			let tone_off = self.mixer & (1 << i) != 0;
+			let noise_off = self.mixer & (8 << i) != 0;
+			if !((tone.high || tone_off) && (noise || noise_off)) {
+				continue;
+			}
+			let level = match self.volumes[i] {
+				volume if volume & 0x10 != 0 => self.envelope_level(),
+				// Plain volumes are four bits, lining up with every other envelope step
+				0 => 0,
+				volume => volume * 2 + 1,
+			};
+			sum += level_amplitude(level);
+		}
+		sum * (2.7 * apu::PULSE_LEVEL)
+	}
+}
+
+/// Sunsoft's FME-7 (mapper 69), and the 5B which is an FME-7 with sound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fme7 {
+	prg_rom: SharedMem,
+	prg_ram: Vec<u8>,
+	chr: SharedMem,
+	chr_is_ram: bool,
+
+	command: u8,
+	chr_banks: [u8; 8],
+	/// Command 8: bit 7 enables RAM, bit 6 picks RAM over ROM at $6000
+	bank_6000: u8,
+	prg_banks: [u8; 3],
+	mirroring: Mirroring,
+	irq_enabled: bool,
+	counter_enabled: bool,
+	irq_counter: u16,
+	irq_pending: bool,
+	pub audio: Sunsoft5b,
+}
+
+impl Fme7 {
+	pub fn new(header: &RomHeader, prg: &[u8], chr: &[u8]) -> Result<Self> {
+		if prg.is_empty() || !prg.len().is_multiple_of(0x2000) {
+			bail!("FME-7 PRG ROM has to be a whole number of 8K banks");
+		}
+		let chr_is_ram = chr.is_empty();
+
+		Ok(Self {
+			prg_rom: prg.into(),
+			prg_ram: vec![0; header.prg_ram_total()],
+			chr: if chr_is_ram {
+				vec![0; header.chr_ram_size.max(8 * 1024)].into()
+			} else {
+				chr.into()
+			},
+			chr_is_ram,
+			command: 0,
+			chr_banks: [0; _],
+			bank_6000: 0,
+			prg_banks: [0; _],
+			mirroring: Mirroring::Vertical,
+			irq_enabled: false,
+			counter_enabled: false,
+			irq_counter: 0,
+			irq_pending: false,
+			audio: Sunsoft5b::default(),
+		})
+	}
+
+	pub fn prg_rom(&self) -> &[u8] {
+		&self.prg_rom
+	}
+
+	pub fn prg_ram(&self) -> Option<&[u8]> {
+		(!self.prg_ram.is_empty()).then_some(&self.prg_ram[..])
+	}
+
+	pub fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+		(!self.prg_ram.is_empty()).then_some(&mut self.prg_ram[..])
+	}
+
+	pub fn irq(&self) -> bool {
+		self.irq_pending
+	}
+
+	/// The 16 bit counter goes down every CPU cycle, and raises the IRQ when it wraps.
+	pub fn tick(&mut self) {
+		if self.counter_enabled {
+			self.irq_counter = self.irq_counter.wrapping_sub(1);
+			if self.irq_counter == 0xFFFF && self.irq_enabled {
+				self.irq_pending = true;
+			}
+		}
+		self.audio.tick();
+	}
+
+	/// Index into PRG RAM when it's mapped in at $6000.
+	fn ram_index(&self, adr: u16) -> Option<usize> {
+		let mapped = self.bank_6000 & 0xC0 == 0xC0 && !self.prg_ram.is_empty();
+		mapped.then(|| vrc::prg_index(&self.prg_ram, (self.bank_6000 & 0x3F) as usize, adr))
+	}
+
+	/// Where in PRG ROM the byte at `adr` comes from, $6000 included when it's ROM.
+	pub fn prg_rom_offset(&self, adr: u16) -> Option<usize> {
+		let bank = match adr {
+			0x6000..=0x7FFF if self.bank_6000 & 0x40 == 0 => self.bank_6000 & 0x3F,
+			0x8000..=0xDFFF => self.prg_banks[(adr as usize - 0x8000) / 0x2000],
+			0xE000..=0xFFFF => (self.prg_rom.len() / 0x2000 - 1) as u8,
+			_ => return None,
+		};
+		Some(vrc::prg_index(&self.prg_rom, bank as usize, adr))
+	}
+
+	pub fn get_cpu(&self, adr: u16) -> Option<u8> {
+		match adr {
+			0x6000..=0x7FFF if self.bank_6000 & 0x40 != 0 => {
+				self.ram_index(adr).map(|i| self.prg_ram[i])
+			}
+			_ => self.prg_rom_offset(adr).map(|i| self.prg_rom[i]),
+		}
+	}
+
+	pub fn set_cpu(&mut self, adr: u16, val: u8) {
+		match adr {
+			0x6000..=0x7FFF => {
+				if let Some(i) = self.ram_index(adr) {
+					self.prg_ram[i] = val;
+				}
+			}
+			0x8000..=0x9FFF => self.command = val & 0x0F,
+			0xA000..=0xBFFF => self.run_command(val),
+			0xC000..=0xDFFF => self.audio.select(val),
+			0xE000..=0xFFFF => self.audio.write(val),
+			_ => {}
+		}
+	}
+
+	fn run_command(&mut self, val: u8) {
+		match self.command {
+			0x0..=0x7 => self.chr_banks[self.command as usize] = val,
+			0x8 => self.bank_6000 = val,
+			0x9..=0xB => self.prg_banks[self.command as usize - 0x9] = val & 0x3F,
+			0xC => {
+				self.mirroring = match val & 0b11 {
+					0 => Mirroring::Vertical,
+					1 => Mirroring::Horizontal,
+					2 => Mirroring::SingleScreenA,
+					_ => Mirroring::SingleScreenB,
+				}
+			}
+			0xD => {
+				self.irq_enabled = val & 0x01 != 0;
+				self.counter_enabled = val & 0x80 != 0;
+				self.irq_pending = false;
+			}
+			0xE => self.irq_counter = (self.irq_counter & 0xFF00) | val as u16,
+			_ => self.irq_counter = (self.irq_counter & 0x00FF) | (val as u16) << 8,
+		}
+	}
+
+	fn chr_index(&self, adr: u16) -> usize {
+		vrc::chr_index(&self.chr, self.chr_banks[adr as usize / 0x400] as u16, adr)
+	}
+
+	pub fn get_ppu(&self, adr: u16, ppu: &Ppu) -> Option<u8> {
+		match adr {
+			0x0000..=0x1FFF => Some(self.chr[self.chr_index(adr)]),
+			0x2000..=0x3EFF => Some(ppu.vram[self.mirroring.vram_index(adr)]),
+			_ => None,
+		}
+	}
+
+	pub fn set_ppu(&mut self, adr: u16, val: u8, ppu: &mut Ppu) -> Option<()> {
+		match adr {
+			0x0000..=0x1FFF if self.chr_is_ram => {
+				let idx = self.chr_index(adr);
+				self.chr[idx] = val;
+			}
+			0x0000..=0x1FFF => {}
+			0x2000..=0x3EFF => ppu.vram[self.mirroring.vram_index(adr)] = val,
+			_ => return None,
+		}
+		Some(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::vrc::test::{banked_rom, header};
+
+	#[test]
+	fn banking() {
+		let (prg, chr) = banked_rom();
+		let ppu = Ppu::default();
+		let mut fme7 = Fme7::new(&header(69, 0), &prg, &chr).unwrap();
+
+		for (command, val) in [(0x9, 1), (0xA, 2), (0xB, 3), (0x8, 4), (0x3, 0x22)] {
+			fme7.set_cpu(0x8000, command);
+			fme7.set_cpu(0xA000, val);
+		}
+		assert_eq!(
+			[0x6000, 0x8000, 0xA000, 0xC000, 0xE000].map(|adr| fme7.get_cpu(adr).unwrap()),
+			[4, 1, 2, 3, 15]
+		);
+		assert_eq!(fme7.get_ppu(0x0C00, &ppu), Some(0x22));
+
+		// RAM at $6000, but only writable once enabled
+		fme7.set_cpu(0x8000, 0x8);
+		fme7.set_cpu(0xA000, 0x40);
+		fme7.set_cpu(0x6000, 0x99);
+		assert_eq!(fme7.get_cpu(0x6000), None);
+		fme7.set_cpu(0xA000, 0xC0);
+		fme7.set_cpu(0x6000, 0x99);
+		assert_eq!(fme7.get_cpu(0x6000), Some(0x99));
+	}
+
+	#[test]
+	fn irq_counter() {
+		let (prg, chr) = banked_rom();
+		let mut fme7 = Fme7::new(&header(69, 0), &prg, &chr).unwrap();
+		for (command, val) in [(0xE, 0x10), (0xF, 0x00), (0xD, 0x81)] {
+			fme7.set_cpu(0x8000, command);
+			fme7.set_cpu(0xA000, val);
+		}
+
+		for _ in 0..0x10 {
+			fme7.tick();
+		}
+		assert!(!fme7.irq());
+		fme7.tick();
+		assert!(fme7.irq());
+
+		// Acknowledged by any write to the control
+		fme7.set_cpu(0x8000, 0xD);
+		fme7.set_cpu(0xA000, 0x80);
+		assert!(!fme7.irq());
+	}
+
+	#[test]
+	fn tone_and_envelope() {
+		let mut audio = Sunsoft5b::default();
+		let mut write = |reg, val| {
+			audio.select(reg);
+			audio.write(val);
+		};
+		// Channel A tone only, period 2, full volume
+		write(0x00, 2);
+		write(0x07, 0b111_110);
+		write(0x08, 0x0F);
+
+		// Square wave flipping every 32 CPU cycles
+		let levels = (0..128)
+			.map(|_| {
+				audio.tick();
+				audio.output() > 0.0
+			})
+			.collect::<Vec<_>>();
+		let flips = (1..levels.len())
+			.filter(|&i| levels[i] != levels[i - 1])
+			.collect::<Vec<_>>();
+		assert!(flips.len() >= 3);
+		assert!(flips.windows(2).all(|w| w[1] - w[0] == 32));
+		assert!((audio.tones[0].high as u8 as f32 * 0.4 - audio.output()).abs() < 0.001);
+
+		// A single decay, \___
+		audio.select(0x0D);
+		audio.write(0x00);
+		assert_eq!(audio.envelope_level(), 31);
+		audio.select(0x0B);
+		audio.write(1);
+		for _ in 0..16 * 32 {
+			audio.tick();
+		}
+		assert!(audio.envelope_holding);
+		assert_eq!(audio.envelope_level(), 0);
+	}
+}
@@ -2,7 +2,12 @@
 
 use std::fmt::{self, Display};
 
-use crate::{cpu::Cpu, evaluate_instruction::*, interpret::State};
+#[cfg(feature = "c-core")]
+use crate::evaluate_instruction::*;
+use crate::{
+	cpu::{Backend, Cpu, Variant},
+	interpret::State,
+};
 
 use anyhow::{Result, bail};
 
@@ -138,6 +143,18 @@ pub enum Inst {
 	IncZeroPageX(u8) = 0xF6,
 	Inx = 0xE8,
 	Iny = 0xC8,
+	Jam = 0x02,
+	Jam2 = 0x12,
+	Jam3 = 0x22,
+	Jam4 = 0x32,
+	Jam5 = 0x42,
+	Jam6 = 0x52,
+	Jam7 = 0x62,
+	Jam8 = 0x72,
+	Jam9 = 0x92,
+	Jam10 = 0xB2,
+	Jam11 = 0xD2,
+	Jam12 = 0xF2,
 	ISCAbsolute(UnalignedU16) = 0xEF,
 	ISCAbsoluteX(UnalignedU16) = 0xFF,
 	ISCAbsoluteY(UnalignedU16) = 0xFB,
@@ -180,26 +197,12 @@ pub enum Inst {
 	LsrZeroPage(u8) = 0x46,
 	LsrZeroPageX(u8) = 0x56,
 	NOP10 = 0x5A,
-	NOP11 = 0x62,
-	NOP12 = 0x72,
 	NOP13 = 0x7A,
-	NOP14 = 0x82,
-	NOP15 = 0x92,
-	NOP16 = 0xB2,
-	NOP17 = 0xC2,
-	NOP18 = 0xD2,
 	NOP19 = 0xDA,
-	Nop2 = 0x02,
-	NOP20 = 0xE2,
-	NOP21 = 0xF2,
 	NOP22 = 0xFA,
-	NOP3 = 0x12,
 	NOP4 = 0x1A,
-	NOP5 = 0x22,
-	NOP6 = 0x32,
 	NOP7 = 0x3A,
-	NOP8 = 0x42,
-	NOP9 = 0x52,
+	Nop = 0xEA,
 	NOPAbsolute(UnalignedU16) = 0x0C,
 	NOPAbsoluteX(UnalignedU16) = 0x1C,
 	NOPAbsoluteX2(UnalignedU16) = 0x3C,
@@ -209,7 +212,9 @@ pub enum Inst {
 	NOPAbsoluteX6(UnalignedU16) = 0xFC,
 	NOPImmediate(u8) = 0x80,
 	NOPImmediate2(u8) = 0x89,
-	NOPImmediate3(u8) = 0xEA,
+	NOPImmediate3(u8) = 0x82,
+	NOPImmediate4(u8) = 0xC2,
+	NOPImmediate5(u8) = 0xE2,
 	NOPZeroPage(u8) = 0x04,
 	NOPZeroPage3(u8) = 0x44,
 	NOPZeroPage4(u8) = 0x64,
@@ -319,14 +324,296 @@ const _: () = {
 
 impl From<[u8; 3]> for Inst {
 	fn from(code: [u8; 3]) -> Self {
-		// This could be a huge match statement, but I checked that LLVM could optimise that to the
-		// same thing and then went with the more readable version:
-		// https://godbolt.org/z/eM74c6EEs
-		unsafe { std::mem::transmute::<[u8; size_of::<Inst>()], Inst>(code) }
+		Inst::decode(code)
 	}
 }
 
 impl Inst {
+	/// Decodes the opcode and the two bytes after it, which may or may not be operands. Every
+	/// opcode means something, even if it's only JAM. LLVM turns this into the same three byte copy
+	/// as a transmute would be (see `decode_speed`), without having to trust the table.
+	pub const fn decode(code: [u8; 3]) -> Self {
+		let [op, lo, hi] = code;
+		match op {
+			0x00 => Inst::Brk,
+			0x01 => Inst::OraIndirectX(lo),
+			0x02 => Inst::Jam,
+			0x03 => Inst::SLOIndirectX(lo),
+			0x04 => Inst::NOPZeroPage(lo),
+			0x05 => Inst::OraZeroPage(lo),
+			0x06 => Inst::AslZeroPage(lo),
+			0x07 => Inst::SLOZeroPage(lo),
+			0x08 => Inst::Php,
+			0x09 => Inst::OraImmediate(lo),
+			0x0A => Inst::AslAccumulator,
+			0x0B => Inst::AncImmediate(lo),
+			0x0C => Inst::NOPAbsolute(UnalignedU16 { lo, hi }),
+			0x0D => Inst::OraAbsolute(UnalignedU16 { lo, hi }),
+			0x0E => Inst::AslAbsolute(UnalignedU16 { lo, hi }),
+			0x0F => Inst::SLOAbsolute(UnalignedU16 { lo, hi }),
+			0x10 => Inst::Bpl(lo as i8),
+			0x11 => Inst::OraIndirectY(lo),
+			0x12 => Inst::Jam2,
+			0x13 => Inst::SLOIndirectY(lo),
+			0x14 => Inst::NOPZeroPageX(lo),
+			0x15 => Inst::OraZeroPageX(lo),
+			0x16 => Inst::AslZeroPageX(lo),
+			0x17 => Inst::SLOZeroPageX(lo),
+			0x18 => Inst::Clc,
+			0x19 => Inst::OraAbsoluteY(UnalignedU16 { lo, hi }),
+			0x1A => Inst::NOP4,
+			0x1B => Inst::SLOAbsoluteY(UnalignedU16 { lo, hi }),
+			0x1C => Inst::NOPAbsoluteX(UnalignedU16 { lo, hi }),
+			0x1D => Inst::OraAbsoluteX(UnalignedU16 { lo, hi }),
+			0x1E => Inst::AslAbsoluteX(UnalignedU16 { lo, hi }),
+			0x1F => Inst::SLOAbsoluteX(UnalignedU16 { lo, hi }),
+			0x20 => Inst::Jsr(UnalignedU16 { lo, hi }),
+			0x21 => Inst::AndIndirectX(lo),
+			0x22 => Inst::Jam3,
+			0x23 => Inst::RLAIndirectX(lo),
+			0x24 => Inst::BitZeroPage(lo),
+			0x25 => Inst::AndZeroPage(lo),
+			0x26 => Inst::RolZeroPage(lo),
+			0x27 => Inst::RLAZeroPage(lo),
+			0x28 => Inst::Plp,
+			0x29 => Inst::AndImmediate(lo),
+			0x2A => Inst::RolAccumulator,
+			0x2B => Inst::AncImmediate2(lo),
+			0x2C => Inst::BitAbsolute(UnalignedU16 { lo, hi }),
+			0x2D => Inst::AndAbsolute(UnalignedU16 { lo, hi }),
+			0x2E => Inst::RolAbsolute(UnalignedU16 { lo, hi }),
+			0x2F => Inst::RLAAbsolute(UnalignedU16 { lo, hi }),
+			0x30 => Inst::Bmi(lo as i8),
+			0x31 => Inst::AndIndirectY(lo),
+			0x32 => Inst::Jam4,
+			0x33 => Inst::RLAIndirectY(lo),
+			0x34 => Inst::NOPZeroPageX2(lo),
+			0x35 => Inst::AndZeroPageX(lo),
+			0x36 => Inst::RolZeroPageX(lo),
+			0x37 => Inst::RLAZeroPageX(lo),
+			0x38 => Inst::Sec,
+			0x39 => Inst::AndAbsoluteY(UnalignedU16 { lo, hi }),
+			0x3A => Inst::NOP7,
+			0x3B => Inst::RLAAbsoluteY(UnalignedU16 { lo, hi }),
+			0x3C => Inst::NOPAbsoluteX2(UnalignedU16 { lo, hi }),
+			0x3D => Inst::AndAbsoluteX(UnalignedU16 { lo, hi }),
+			0x3E => Inst::RolAbsoluteX(UnalignedU16 { lo, hi }),
+			0x3F => Inst::RLAAbsoluteX(UnalignedU16 { lo, hi }),
+			0x40 => Inst::Rti,
+			0x41 => Inst::EorIndirectX(lo),
+			0x42 => Inst::Jam5,
+			0x43 => Inst::SREIndirectX(lo),
+			0x44 => Inst::NOPZeroPage3(lo),
+			0x45 => Inst::EorZeroPage(lo),
+			0x46 => Inst::LsrZeroPage(lo),
+			0x47 => Inst::SREZeroPage(lo),
+			0x48 => Inst::Pha,
+			0x49 => Inst::EorImmediate(lo),
+			0x4A => Inst::LsrAccumulator,
+			0x4B => Inst::AlrImmediate(lo),
+			0x4C => Inst::JmpAbsolute(UnalignedU16 { lo, hi }),
+			0x4D => Inst::EorAbsolute(UnalignedU16 { lo, hi }),
+			0x4E => Inst::LsrAbsolute(UnalignedU16 { lo, hi }),
+			0x4F => Inst::SREAbsolute(UnalignedU16 { lo, hi }),
+			0x50 => Inst::Bvc(lo as i8),
+			0x51 => Inst::EorIndirectY(lo),
+			0x52 => Inst::Jam6,
+			0x53 => Inst::SREIndirectY(lo),
+			0x54 => Inst::NOPZeroPageX3(lo),
+			0x55 => Inst::EorZeroPageX(lo),
+			0x56 => Inst::LsrZeroPageX(lo),
+			0x57 => Inst::SREZeroPageX(lo),
+			0x58 => Inst::Cli,
+			0x59 => Inst::EorAbsoluteY(UnalignedU16 { lo, hi }),
+			0x5A => Inst::NOP10,
+			0x5B => Inst::SREAbsoluteY(UnalignedU16 { lo, hi }),
+			0x5C => Inst::NOPAbsoluteX3(UnalignedU16 { lo, hi }),
+			0x5D => Inst::EorAbsoluteX(UnalignedU16 { lo, hi }),
+			0x5E => Inst::LsrAbsoluteX(UnalignedU16 { lo, hi }),
+			0x5F => Inst::SREAbsoluteX(UnalignedU16 { lo, hi }),
+			0x60 => Inst::Rts,
+			0x61 => Inst::AdcIndirectX(lo),
+			0x62 => Inst::Jam7,
+			0x63 => Inst::RRAIndirectX(lo),
+			0x64 => Inst::NOPZeroPage4(lo),
+			0x65 => Inst::AdcZeroPage(lo),
+			0x66 => Inst::RorZeroPage(lo),
+			0x67 => Inst::RRAZeroPage(lo),
+			0x68 => Inst::Pla,
+			0x69 => Inst::AdcImmediate(lo),
+			0x6A => Inst::RorAccumulator,
+			0x6B => Inst::ArrImmediate(lo),
+			0x6C => Inst::JmpIndirect(UnalignedU16 { lo, hi }),
+			0x6D => Inst::AdcAbsolute(UnalignedU16 { lo, hi }),
+			0x6E => Inst::RorAbsolute(UnalignedU16 { lo, hi }),
+			0x6F => Inst::RRAAbsolute(UnalignedU16 { lo, hi }),
+			0x70 => Inst::Bvs(lo as i8),
+			0x71 => Inst::AdcIndirectY(lo),
+			0x72 => Inst::Jam8,
+			0x73 => Inst::RRAIndirectY(lo),
+			0x74 => Inst::NOPZeroPageX4(lo),
+			0x75 => Inst::AdcZeroPageX(lo),
+			0x76 => Inst::RorZeroPageX(lo),
+			0x77 => Inst::RRAZeroPageX(lo),
+			0x78 => Inst::Sei,
+			0x79 => Inst::AdcAbsoluteY(UnalignedU16 { lo, hi }),
+			0x7A => Inst::NOP13,
+			0x7B => Inst::RRAAbsoluteY(UnalignedU16 { lo, hi }),
+			0x7C => Inst::NOPAbsoluteX4(UnalignedU16 { lo, hi }),
+			0x7D => Inst::AdcAbsoluteX(UnalignedU16 { lo, hi }),
+			0x7E => Inst::RorAbsoluteX(UnalignedU16 { lo, hi }),
+			0x7F => Inst::RRAAbsoluteX(UnalignedU16 { lo, hi }),
+			0x80 => Inst::NOPImmediate(lo),
+			0x81 => Inst::StaIndirectX(lo),
+			0x82 => Inst::NOPImmediate3(lo),
+			0x83 => Inst::SAXIndirectX(lo),
+			0x84 => Inst::StyZeroPage(lo),
+			0x85 => Inst::StaZeroPage(lo),
+			0x86 => Inst::StxZeroPage(lo),
+			0x87 => Inst::SAXZeroPage(lo),
+			0x88 => Inst::Dey,
+			0x89 => Inst::NOPImmediate2(lo),
+			0x8A => Inst::Txa,
+			0x8B => Inst::XAAImmediate(lo),
+			0x8C => Inst::StyAbsolute(UnalignedU16 { lo, hi }),
+			0x8D => Inst::StaAbsolute(UnalignedU16 { lo, hi }),
+			0x8E => Inst::StxAbsolute(UnalignedU16 { lo, hi }),
+			0x8F => Inst::SAXAbsolute(UnalignedU16 { lo, hi }),
+			0x90 => Inst::Bcc(lo as i8),
+			0x91 => Inst::StaIndirectY(lo),
+			0x92 => Inst::Jam9,
+			0x93 => Inst::AhxIndirectY(lo),
+			0x94 => Inst::StyZeroPageX(lo),
+			0x95 => Inst::StaZeroPageX(lo),
+			0x96 => Inst::StxZeroPageY(lo),
+			0x97 => Inst::SAXZeroPageY(lo),
+			0x98 => Inst::Tya,
+			0x99 => Inst::StaAbsoluteY(UnalignedU16 { lo, hi }),
+			0x9A => Inst::Txs,
+			0x9B => Inst::TASAbsoluteY(UnalignedU16 { lo, hi }),
+			0x9C => Inst::SHYAbsoluteX(UnalignedU16 { lo, hi }),
+			0x9D => Inst::StaAbsoluteX(UnalignedU16 { lo, hi }),
+			0x9E => Inst::SHXAbsoluteY(UnalignedU16 { lo, hi }),
+			0x9F => Inst::AhxAbsoluteY(UnalignedU16 { lo, hi }),
+			0xA0 => Inst::LdyImmediate(lo),
+			0xA1 => Inst::LdaIndirectX(lo),
+			0xA2 => Inst::LdxImmediate(lo),
+			0xA3 => Inst::LAXIndirectX(lo),
+			0xA4 => Inst::LdyZeroPage(lo),
+			0xA5 => Inst::LdaZeroPage(lo),
+			0xA6 => Inst::LdxZeroPage(lo),
+			0xA7 => Inst::LAXZeroPage(lo),
+			0xA8 => Inst::Tay,
+			0xA9 => Inst::LdaImmediate(lo),
+			0xAA => Inst::Tax,
+			0xAB => Inst::LAXImmediate(lo),
+			0xAC => Inst::LdyAbsolute(UnalignedU16 { lo, hi }),
+			0xAD => Inst::LdaAbsolute(UnalignedU16 { lo, hi }),
+			0xAE => Inst::LdxAbsolute(UnalignedU16 { lo, hi }),
+			0xAF => Inst::LAXAbsolute(UnalignedU16 { lo, hi }),
+			0xB0 => Inst::Bcs(lo as i8),
+			0xB1 => Inst::LdaIndirectY(lo),
+			0xB2 => Inst::Jam10,
+			0xB3 => Inst::LAXIndirectY(lo),
+			0xB4 => Inst::LdyZeroPageX(lo),
+			0xB5 => Inst::LdaZeroPageX(lo),
+			0xB6 => Inst::LdxZeroPageY(lo),
+			0xB7 => Inst::LAXZeroPageY(lo),
+			0xB8 => Inst::Clv,
+			0xB9 => Inst::LdaAbsoluteY(UnalignedU16 { lo, hi }),
+			0xBA => Inst::Tsx,
+			0xBB => Inst::LASAbsoluteY(UnalignedU16 { lo, hi }),
+			0xBC => Inst::LdyAbsoluteX(UnalignedU16 { lo, hi }),
+			0xBD => Inst::LdaAbsoluteX(UnalignedU16 { lo, hi }),
+			0xBE => Inst::LdxAbsoluteY(UnalignedU16 { lo, hi }),
+			0xBF => Inst::LAXAbsoluteY(UnalignedU16 { lo, hi }),
+			0xC0 => Inst::CpyImmediate(lo),
+			0xC1 => Inst::CmpIndirectX(lo),
+			0xC2 => Inst::NOPImmediate4(lo),
+			0xC3 => Inst::DCPIndirectX(lo),
+			0xC4 => Inst::CpyZeroPage(lo),
+			0xC5 => Inst::CmpZeroPage(lo),
+			0xC6 => Inst::DecZeroPage(lo),
+			0xC7 => Inst::DCPZeroPage(lo),
+			0xC8 => Inst::Iny,
+			0xC9 => Inst::CmpImmediate(lo),
+			0xCA => Inst::Dex,
+			0xCB => Inst::AxsImmediate(lo),
+			0xCC => Inst::CpyAbsolute(UnalignedU16 { lo, hi }),
+			0xCD => Inst::CmpAbsolute(UnalignedU16 { lo, hi }),
+			0xCE => Inst::DecAbsolute(UnalignedU16 { lo, hi }),
+			0xCF => Inst::DCPAbsolute(UnalignedU16 { lo, hi }),
+			0xD0 => Inst::Bne(lo as i8),
+			0xD1 => Inst::CmpIndirectY(lo),
+			0xD2 => Inst::Jam11,
+			0xD3 => Inst::DCPIndirectY(lo),
+			0xD4 => Inst::NOPZeroPageX5(lo),
+			0xD5 => Inst::CmpZeroPageX(lo),
+			0xD6 => Inst::DecZeroPageX(lo),
+			0xD7 => Inst::DCPZeroPageX(lo),
+			0xD8 => Inst::Cld,
+			0xD9 => Inst::CmpAbsoluteY(UnalignedU16 { lo, hi }),
+			0xDA => Inst::NOP19,
+			0xDB => Inst::DCPAbsoluteY(UnalignedU16 { lo, hi }),
+			0xDC => Inst::NOPAbsoluteX5(UnalignedU16 { lo, hi }),
+			0xDD => Inst::CmpAbsoluteX(UnalignedU16 { lo, hi }),
+			0xDE => Inst::DecAbsoluteX(UnalignedU16 { lo, hi }),
+			0xDF => Inst::DCPAbsoluteX(UnalignedU16 { lo, hi }),
+			0xE0 => Inst::CpxImmediate(lo),
+			0xE1 => Inst::SbcIndirectX(lo),
+			0xE2 => Inst::NOPImmediate5(lo),
+			0xE3 => Inst::ISCIndirectX(lo),
+			0xE4 => Inst::CpxZeroPage(lo),
+			0xE5 => Inst::SbcZeroPage(lo),
+			0xE6 => Inst::IncZeroPage(lo),
+			0xE7 => Inst::ISCZeroPage(lo),
+			0xE8 => Inst::Inx,
+			0xE9 => Inst::SbcImmediate(lo),
+			0xEA => Inst::Nop,
+			0xEB => Inst::SbcImmediate2(lo),
+			0xEC => Inst::CpxAbsolute(UnalignedU16 { lo, hi }),
+			0xED => Inst::SbcAbsolute(UnalignedU16 { lo, hi }),
+			0xEE => Inst::IncAbsolute(UnalignedU16 { lo, hi }),
+			0xEF => Inst::ISCAbsolute(UnalignedU16 { lo, hi }),
+			0xF0 => Inst::Beq(lo as i8),
+			0xF1 => Inst::SbcIndirectY(lo),
+			0xF2 => Inst::Jam12,
+			0xF3 => Inst::ISCIndirectY(lo),
+			0xF4 => Inst::NOPZeroPageX6(lo),
+			0xF5 => Inst::SbcZeroPageX(lo),
+			0xF6 => Inst::IncZeroPageX(lo),
+			0xF7 => Inst::ISCZeroPageX(lo),
+			0xF8 => Inst::Sed,
+			0xF9 => Inst::SbcAbsoluteY(UnalignedU16 { lo, hi }),
+			0xFA => Inst::NOP22,
+			0xFB => Inst::ISCAbsoluteY(UnalignedU16 { lo, hi }),
+			0xFC => Inst::NOPAbsoluteX6(UnalignedU16 { lo, hi }),
+			0xFD => Inst::SbcAbsoluteX(UnalignedU16 { lo, hi }),
+			0xFE => Inst::IncAbsoluteX(UnalignedU16 { lo, hi }),
+			0xFF => Inst::ISCAbsoluteX(UnalignedU16 { lo, hi }),
+		}
+	}
+
+	pub const fn opcode(&self) -> u8 {
+		// SAFETY: `#[repr(u8)]` puts the discriminant first
+		unsafe { *(self as *const Self).cast::<u8>() }
+	}
+
+	/// The twelve opcodes that lock the CPU up until it's reset.
+	pub fn is_jam(&self) -> bool {
+		matches!(
+			self,
+			Inst::Jam
+				| Inst::Jam2 | Inst::Jam3
+				| Inst::Jam4 | Inst::Jam5
+				| Inst::Jam6 | Inst::Jam7
+				| Inst::Jam8 | Inst::Jam9
+				| Inst::Jam10
+				| Inst::Jam11
+				| Inst::Jam12
+		)
+	}
+
 	pub fn ends_bb(&self) -> bool {
 		matches!(
 			self,
@@ -345,6 +632,21 @@ impl Inst {
 		)
 	}
 
+	/// How far one of the eight conditional branches goes, from the instruction after it.
+	pub fn branch_offset(&self) -> Option<i8> {
+		match self {
+			Inst::Bcc(offset)
+			| Inst::Bcs(offset)
+			| Inst::Beq(offset)
+			| Inst::Bmi(offset)
+			| Inst::Bne(offset)
+			| Inst::Bpl(offset)
+			| Inst::Bvc(offset)
+			| Inst::Bvs(offset) => Some(*offset),
+			_ => None,
+		}
+	}
+
 	pub fn len(&self) -> u8 {
 		match self {
 			Inst::Brk
@@ -356,27 +658,25 @@ impl Inst {
 			| Inst::Dey
 			| Inst::Inx
 			| Inst::Iny
-			| Inst::Nop2
-			| Inst::NOP3
+			| Inst::Jam
+			| Inst::Jam2
+			| Inst::Jam3
+			| Inst::Jam4
+			| Inst::Jam5
+			| Inst::Jam6
+			| Inst::Jam7
+			| Inst::Jam8
+			| Inst::Jam9
+			| Inst::Jam10
+			| Inst::Jam11
+			| Inst::Jam12
 			| Inst::NOP4
-			| Inst::NOP5
-			| Inst::NOP6
 			| Inst::NOP7
-			| Inst::NOP8
-			| Inst::NOP9
 			| Inst::NOP10
-			| Inst::NOP11
-			| Inst::NOP12
 			| Inst::NOP13
-			| Inst::NOP14
-			| Inst::NOP15
-			| Inst::NOP16
-			| Inst::NOP17
-			| Inst::NOP18
 			| Inst::NOP19
-			| Inst::NOP20
-			| Inst::NOP21
 			| Inst::NOP22
+			| Inst::Nop
 			| Inst::Pha
 			| Inst::Php
 			| Inst::Pla
@@ -509,7 +809,21 @@ impl Inst {
 			| Inst::AhxIndirectY(..)
 			| Inst::NOPImmediate(..)
 			| Inst::NOPImmediate2(..)
-			| Inst::NOPImmediate3(..) => 2,
+			| Inst::NOPImmediate3(..)
+			| Inst::NOPImmediate4(..)
+			| Inst::NOPImmediate5(..)
+			| Inst::LAXImmediate(..)
+			| Inst::NOPZeroPage(..)
+			| Inst::NOPZeroPage3(..)
+			| Inst::NOPZeroPage4(..)
+			| Inst::NOPZeroPageX(..)
+			| Inst::NOPZeroPageX2(..)
+			| Inst::NOPZeroPageX3(..)
+			| Inst::NOPZeroPageX4(..)
+			| Inst::NOPZeroPageX5(..)
+			| Inst::NOPZeroPageX6(..)
+			| Inst::SbcImmediate2(..)
+			| Inst::XAAImmediate(..) => 2,
 			Inst::OraAbsolute(..)
 			| Inst::OraAbsoluteX(..)
 			| Inst::OraAbsoluteY(..)
@@ -591,23 +905,55 @@ impl Inst {
 			| Inst::NOPAbsoluteX4(..)
 			| Inst::NOPAbsoluteX5(..)
 			| Inst::NOPAbsoluteX6(..) => 3,
-
-			Inst::LAXImmediate(_) => todo!(),
-			Inst::NOPZeroPage(_) => todo!(),
-			Inst::NOPZeroPage3(_) => todo!(),
-			Inst::NOPZeroPage4(_) => todo!(),
-			Inst::NOPZeroPageX(_) => todo!(),
-			Inst::NOPZeroPageX2(_) => todo!(),
-			Inst::NOPZeroPageX3(_) => todo!(),
-			Inst::NOPZeroPageX4(_) => todo!(),
-			Inst::NOPZeroPageX5(_) => todo!(),
-			Inst::NOPZeroPageX6(_) => todo!(),
-			Inst::SbcImmediate2(_) => todo!(),
-			Inst::XAAImmediate(_) => todo!(),
 		}
 	}
 
+	/// Runs the instruction on whichever CPU the state asks for.
 	pub fn evaluate(&self, state: &mut State) {
+		// Every instruction starts by reading its opcode and the byte after it, whether it needs
+		// that byte or not. The cores take it from there.
+		state.read(state.cpu.pc);
+		state.read(state.cpu.pc.wrapping_add(1));
+		let decimal =
+			state.variant == Variant::Cmos65C02 && state.cpu.p.d() && self.is_adc_or_sbc();
+
+		match state.backend {
+			#[cfg(feature = "c-core")]
+			Backend::C => self.evaluate_c(state),
+			#[cfg(feature = "rust-core")]
+			Backend::Rust => crate::execute::execute(*self, state),
+		}
+
+		// The 65C02 takes another cycle fixing up N and Z after decimal arithmetic
+		if decimal {
+			state.idle(1);
+		}
+	}
+
+	fn is_adc_or_sbc(&self) -> bool {
+		matches!(
+			self,
+			Inst::AdcImmediate(..)
+				| Inst::AdcZeroPage(..)
+				| Inst::AdcZeroPageX(..)
+				| Inst::AdcAbsolute(..)
+				| Inst::AdcAbsoluteX(..)
+				| Inst::AdcAbsoluteY(..)
+				| Inst::AdcIndirectX(..)
+				| Inst::AdcIndirectY(..)
+				| Inst::SbcImmediate(..)
+				| Inst::SbcZeroPage(..)
+				| Inst::SbcZeroPageX(..)
+				| Inst::SbcAbsolute(..)
+				| Inst::SbcAbsoluteX(..)
+				| Inst::SbcAbsoluteY(..)
+				| Inst::SbcIndirectX(..)
+				| Inst::SbcIndirectY(..)
+		)
+	}
+
+	#[cfg(feature = "c-core")]
+	fn evaluate_c(&self, state: &mut State) {
 		match self {
 			Inst::AdcImmediate(x) => adc_immediate(state, *x),
 			Inst::AdcZeroPage(x) => adc_zero_page(state, *x),
@@ -705,7 +1051,7 @@ impl Inst {
 			Inst::LsrZeroPageX(x) => lsr_zero_page_x(state, *x),
 			Inst::LsrAbsolute(a) => lsr_absolute(state, a.into()),
 			Inst::LsrAbsoluteX(a) => lsr_absolute_x(state, a.into()),
-			Inst::Nop2 => nop(state),
+			Inst::Nop => nop(state),
 			Inst::OraImmediate(x) => ora_immediate(state, *x),
 			Inst::OraZeroPage(x) => ora_zero_page(state, *x),
 			Inst::OraZeroPageX(x) => ora_zero_page_x(state, *x),
@@ -760,70 +1106,178 @@ impl Inst {
 			Inst::Txa => txa(state),
 			Inst::Txs => txs(state),
 			Inst::Tya => tya(state),
-			// Inst::LAX(LAX::ZeroPage(x)) => lax_zero_page(cpu, *x),
-			// Inst::LAX(LAX::ZeroPageY(x)) => lax_zero_page_y(cpu, *x),
-			// Inst::LAX(LAX::Absolute(a)) => lax_absolute(cpu, *a),
-			// Inst::LAX(LAX::AbsoluteY(a)) => lax_absolute_y(cpu, *a),
-			// Inst::LAX(LAX::IndirectX(x)) => lax_indirect_x(cpu, *x),
-			// Inst::LAX(LAX::IndirectY(x)) => lax_indirect_y(cpu, *x),
-			// Inst::SAX(SAX::ZeroPage(x)) => sax_zero_page(cpu, *x),
-			// Inst::SAX(SAX::ZeroPageY(x)) => sax_zero_page_y(cpu, *x),
-			// Inst::SAX(SAX::Absolute(a)) => sax_absolute(cpu, *a),
-			// Inst::SAX(SAX::IndirectX(x)) => sax_indirect_x(cpu, *x),
-			// Inst::DCP(DCP::ZeroPage(x)) => dcp_zero_page(cpu, *x),
-			// Inst::DCP(DCP::ZeroPageX(x)) => dcp_zero_page_x(cpu, *x),
-			// Inst::DCP(DCP::Absolute(a)) => dcp_absolute(cpu, *a),
-			// Inst::DCP(DCP::AbsoluteX(a)) => dcp_absolute_x(cpu, *a),
-			// Inst::DCP(DCP::AbsoluteY(a)) => dcp_absolute_y(cpu, *a),
-			// Inst::DCP(DCP::IndirectX(x)) => dcp_indirect_x(cpu, *x),
-			// Inst::DCP(DCP::IndirectY(x)) => dcp_indirect_y(cpu, *x),
-			// Inst::ISC(ISC::ZeroPage(x)) => isc_zero_page(cpu, *x),
-			// Inst::ISC(ISC::ZeroPageX(x)) => isc_zero_page_x(cpu, *x),
-			// Inst::ISC(ISC::Absolute(a)) => isc_absolute(cpu, *a),
-			// Inst::ISC(ISC::AbsoluteX(a)) => isc_absolute_x(cpu, *a),
-			// Inst::ISC(ISC::AbsoluteY(a)) => isc_absolute_y(cpu, *a),
-			// Inst::ISC(ISC::IndirectX(x)) => isc_indirect_x(cpu, *x),
-			// Inst::ISC(ISC::IndirectY(x)) => isc_indirect_y(cpu, *x),
-			// Inst::RLA(RLA::ZeroPage(x)) => rla_zero_page(cpu, *x),
-			// Inst::RLA(RLA::ZeroPageX(x)) => rla_zero_page_x(cpu, *x),
-			// Inst::RLA(RLA::Absolute(a)) => rla_absolute(cpu, *a),
-			// Inst::RLA(RLA::AbsoluteX(a)) => rla_absolute_x(cpu, *a),
-			// Inst::RLA(RLA::AbsoluteY(a)) => rla_absolute_y(cpu, *a),
-			// Inst::RLA(RLA::IndirectX(x)) => rla_indirect_x(cpu, *x),
-			// Inst::RLA(RLA::IndirectY(x)) => rla_indirect_y(cpu, *x),
-			// Inst::RRA(RRA::ZeroPage(x)) => rra_zero_page(cpu, *x),
-			// Inst::RRA(RRA::ZeroPageX(x)) => rra_zero_page_x(cpu, *x),
-			// Inst::RRA(RRA::Absolute(a)) => rra_absolute(cpu, *a),
-			// Inst::RRA(RRA::AbsoluteX(a)) => rra_absolute_x(cpu, *a),
-			// Inst::RRA(RRA::AbsoluteY(a)) => rra_absolute_y(cpu, *a),
-			// Inst::RRA(RRA::IndirectX(x)) => rra_indirect_x(cpu, *x),
-			// Inst::RRA(RRA::IndirectY(x)) => rra_indirect_y(cpu, *x),
-			// Inst::SLO(SLO::ZeroPage(x)) => slo_zero_page(cpu, *x),
-			// Inst::SLO(SLO::ZeroPageX(x)) => slo_zero_page_x(cpu, *x),
-			// Inst::SLO(SLO::Absolute(a)) => slo_absolute(cpu, *a),
-			// Inst::SLO(SLO::AbsoluteX(a)) => slo_absolute_x(cpu, *a),
-			// Inst::SLO(SLO::AbsoluteY(a)) => slo_absolute_y(cpu, *a),
-			// Inst::SLO(SLO::IndirectX(x)) => slo_indirect_x(cpu, *x),
-			// Inst::SLO(SLO::IndirectY(x)) => slo_indirect_y(cpu, *x),
-			// Inst::SRE(SRE::ZeroPage(x)) => sre_zero_page(cpu, *x),
-			// Inst::SRE(SRE::ZeroPageX(x)) => sre_zero_page_x(cpu, *x),
-			// Inst::SRE(SRE::Absolute(a)) => sre_absolute(cpu, *a),
-			// Inst::SRE(SRE::AbsoluteX(a)) => sre_absolute_x(cpu, *a),
-			// Inst::SRE(SRE::AbsoluteY(a)) => sre_absolute_y(cpu, *a),
-			// Inst::SRE(SRE::IndirectX(x)) => sre_indirect_x(cpu, *x),
-			// Inst::SRE(SRE::IndirectY(x)) => sre_indirect_y(cpu, *x),
-			// Inst::ANC(x) => anc(cpu, *x),
-			// Inst::Alr(x) => alr(cpu, *x),
-			// Inst::ARR(x) => arr(cpu, *x),
-			// Inst::Axs(x) => axs(cpu, *x),
-			// Inst::LAS(x) => las(cpu, *x),
-			// Inst::TAS(x) => tas(cpu, *x),
-			// Inst::SHY(x) => shy(cpu, *x),
-			// Inst::SHX(x) => shx(cpu, *x),
-			// Inst::Ahx(Ahx::AbsoluteY(a)) => ahx_absolute_y(cpu, *a),
-			// Inst::Ahx(Ahx::IndirectY(x)) => ahx_indirect_y(cpu, *x),
-			// Inst::NOPU(..) => {}
-			_ => todo!("No support for unofficial instructions yet"),
+			Inst::LAXImmediate(x) => lax_immediate(state, *x),
+			Inst::LAXZeroPage(x) => lax_zero_page(state, *x),
+			Inst::LAXZeroPageY(x) => lax_zero_page_y(state, *x),
+			Inst::LAXAbsolute(a) => lax_absolute(state, a.into()),
+			Inst::LAXAbsoluteY(a) => lax_absolute_y(state, a.into()),
+			Inst::LAXIndirectX(x) => lax_indirect_x(state, *x),
+			Inst::LAXIndirectY(x) => lax_indirect_y(state, *x),
+			Inst::SAXZeroPage(x) => sax_zero_page(state, *x),
+			Inst::SAXZeroPageY(x) => sax_zero_page_y(state, *x),
+			Inst::SAXAbsolute(a) => sax_absolute(state, a.into()),
+			Inst::SAXIndirectX(x) => sax_indirect_x(state, *x),
+			Inst::DCPZeroPage(x) => dcp_zero_page(state, *x),
+			Inst::DCPZeroPageX(x) => dcp_zero_page_x(state, *x),
+			Inst::DCPAbsolute(a) => dcp_absolute(state, a.into()),
+			Inst::DCPAbsoluteX(a) => dcp_absolute_x(state, a.into()),
+			Inst::DCPAbsoluteY(a) => dcp_absolute_y(state, a.into()),
+			Inst::DCPIndirectX(x) => dcp_indirect_x(state, *x),
+			Inst::DCPIndirectY(x) => dcp_indirect_y(state, *x),
+			Inst::ISCZeroPage(x) => isc_zero_page(state, *x),
+			Inst::ISCZeroPageX(x) => isc_zero_page_x(state, *x),
+			Inst::ISCAbsolute(a) => isc_absolute(state, a.into()),
+			Inst::ISCAbsoluteX(a) => isc_absolute_x(state, a.into()),
+			Inst::ISCAbsoluteY(a) => isc_absolute_y(state, a.into()),
+			Inst::ISCIndirectX(x) => isc_indirect_x(state, *x),
+			Inst::ISCIndirectY(x) => isc_indirect_y(state, *x),
+			Inst::RLAZeroPage(x) => rla_zero_page(state, *x),
+			Inst::RLAZeroPageX(x) => rla_zero_page_x(state, *x),
+			Inst::RLAAbsolute(a) => rla_absolute(state, a.into()),
+			Inst::RLAAbsoluteX(a) => rla_absolute_x(state, a.into()),
+			Inst::RLAAbsoluteY(a) => rla_absolute_y(state, a.into()),
+			Inst::RLAIndirectX(x) => rla_indirect_x(state, *x),
+			Inst::RLAIndirectY(x) => rla_indirect_y(state, *x),
+			Inst::RRAZeroPage(x) => rra_zero_page(state, *x),
+			Inst::RRAZeroPageX(x) => rra_zero_page_x(state, *x),
+			Inst::RRAAbsolute(a) => rra_absolute(state, a.into()),
+			Inst::RRAAbsoluteX(a) => rra_absolute_x(state, a.into()),
+			Inst::RRAAbsoluteY(a) => rra_absolute_y(state, a.into()),
+			Inst::RRAIndirectX(x) => rra_indirect_x(state, *x),
+			Inst::RRAIndirectY(x) => rra_indirect_y(state, *x),
+			Inst::SLOZeroPage(x) => slo_zero_page(state, *x),
+			Inst::SLOZeroPageX(x) => slo_zero_page_x(state, *x),
+			Inst::SLOAbsolute(a) => slo_absolute(state, a.into()),
+			Inst::SLOAbsoluteX(a) => slo_absolute_x(state, a.into()),
+			Inst::SLOAbsoluteY(a) => slo_absolute_y(state, a.into()),
+			Inst::SLOIndirectX(x) => slo_indirect_x(state, *x),
+			Inst::SLOIndirectY(x) => slo_indirect_y(state, *x),
+			Inst::SREZeroPage(x) => sre_zero_page(state, *x),
+			Inst::SREZeroPageX(x) => sre_zero_page_x(state, *x),
+			Inst::SREAbsolute(a) => sre_absolute(state, a.into()),
+			Inst::SREAbsoluteX(a) => sre_absolute_x(state, a.into()),
+			Inst::SREAbsoluteY(a) => sre_absolute_y(state, a.into()),
+			Inst::SREIndirectX(x) => sre_indirect_x(state, *x),
+			Inst::SREIndirectY(x) => sre_indirect_y(state, *x),
+			Inst::AncImmediate(x) => anc_immediate(state, *x),
+			Inst::AncImmediate2(x) => anc_immediate(state, *x),
+			Inst::AlrImmediate(x) => alr_immediate(state, *x),
+			Inst::ArrImmediate(x) => arr_immediate(state, *x),
+			Inst::AxsImmediate(x) => axs_immediate(state, *x),
+			Inst::XAAImmediate(x) => xaa_immediate(state, *x),
+			Inst::SbcImmediate2(x) => sbc_immediate(state, *x),
+			Inst::LASAbsoluteY(a) => las_absolute_y(state, a.into()),
+			Inst::SHYAbsoluteX(a) => shy_absolute_x(state, a.into()),
+			Inst::SHXAbsoluteY(a) => shx_absolute_y(state, a.into()),
+			Inst::AhxAbsoluteY(a) => ahx_absolute_y(state, a.into()),
+			Inst::AhxIndirectY(x) => ahx_indirect_y(state, *x),
+			Inst::TASAbsoluteY(a) => tas_absolute_y(state, a.into()),
+			Inst::NOP4 | Inst::NOP7 | Inst::NOP10 | Inst::NOP13 | Inst::NOP19 | Inst::NOP22 => {
+				nop(state)
+			}
+			Inst::NOPImmediate(x) => nop_immediate(state, *x),
+			Inst::NOPImmediate2(x) => nop_immediate(state, *x),
+			Inst::NOPImmediate3(x) => nop_immediate(state, *x),
+			Inst::NOPImmediate4(x) => nop_immediate(state, *x),
+			Inst::NOPImmediate5(x) => nop_immediate(state, *x),
+			Inst::NOPZeroPage(x) => nop_zero_page(state, *x),
+			Inst::NOPZeroPage3(x) => nop_zero_page(state, *x),
+			Inst::NOPZeroPage4(x) => nop_zero_page(state, *x),
+			Inst::NOPZeroPageX(x) => nop_zero_page_x(state, *x),
+			Inst::NOPZeroPageX2(x) => nop_zero_page_x(state, *x),
+			Inst::NOPZeroPageX3(x) => nop_zero_page_x(state, *x),
+			Inst::NOPZeroPageX4(x) => nop_zero_page_x(state, *x),
+			Inst::NOPZeroPageX5(x) => nop_zero_page_x(state, *x),
+			Inst::NOPZeroPageX6(x) => nop_zero_page_x(state, *x),
+			Inst::NOPAbsolute(a) => nop_absolute(state, a.into()),
+			Inst::NOPAbsoluteX(a) => nop_absolute_x(state, a.into()),
+			Inst::NOPAbsoluteX2(a) => nop_absolute_x(state, a.into()),
+			Inst::NOPAbsoluteX3(a) => nop_absolute_x(state, a.into()),
+			Inst::NOPAbsoluteX4(a) => nop_absolute_x(state, a.into()),
+			Inst::NOPAbsoluteX5(a) => nop_absolute_x(state, a.into()),
+			Inst::NOPAbsoluteX6(a) => nop_absolute_x(state, a.into()),
+			Inst::Jam
+			| Inst::Jam2
+			| Inst::Jam3
+			| Inst::Jam4
+			| Inst::Jam5
+			| Inst::Jam6
+			| Inst::Jam7
+			| Inst::Jam8
+			| Inst::Jam9
+			| Inst::Jam10
+			| Inst::Jam11
+			| Inst::Jam12 => state.jam(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// The bytes after the opcode that `inst` actually has.
+	fn operands(inst: &Inst) -> Vec<u8> {
+		let ptr = (inst as *const Inst).cast::<u8>();
+		// SAFETY: the variant for a `len` byte instruction has `len - 1` bytes of operand
+		(1..inst.len() as usize)
+			.map(|i| unsafe { *ptr.add(i) })
+			.collect()
+	}
+
+	#[test]
+	fn decodes_every_opcode() {
+		for op in 0..=0xFF {
+			let inst = Inst::decode([op, 0x34, 0x12]);
+			assert_eq!(inst.opcode(), op, "{inst:?}");
+			// Operands end up where the length says they are
+			assert_eq!(
+				operands(&inst),
+				[0x34, 0x12][..inst.len() as usize - 1],
+				"{inst:?}"
+			);
+		}
+
+		let jams = (0..=0xFF)
+			.filter(|&op| Inst::decode([op, 0, 0]).is_jam())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			jams,
+			[
+				0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2
+			]
+		);
+	}
+
+	/// Checks the match in `decode` is as cheap as the transmute it replaced. Only means anything
+	/// optimised: `cargo test --release -- --ignored decode_speed`
+	#[test]
+	#[ignore = "benchmark"]
+	fn decode_speed() {
+		use std::{hint::black_box, time::Instant};
+
+		let code = (0..1 << 24)
+			.map(|i: u32| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+			.collect::<Vec<_>>();
+		let time = |decode: fn([u8; 3]) -> Inst| {
+			let start = Instant::now();
+			let mut sum = 0u32;
+			for window in black_box(&code).windows(3) {
+				let inst = decode(black_box([window[0], window[1], window[2]]));
+				sum = sum.wrapping_add(black_box(inst).opcode() as u32);
+			}
+			(start.elapsed(), black_box(sum))
+		};
+
+		// Fine now every byte has a variant, which `decodes_every_opcode` checks
+		let (transmuted, expected) = time(|code| unsafe { std::mem::transmute(code) });
+		let (matched, sum) = time(Inst::decode);
+		assert_eq!(sum, expected);
+		if !cfg!(debug_assertions) {
+			assert!(
+				matched.as_secs_f64() < transmuted.as_secs_f64() * 1.5,
+				"transmute: {transmuted:?}, match: {matched:?}"
+			);
 		}
 	}
 }
@@ -3,16 +3,23 @@
 use std::sync::{Arc, Mutex};
 
 use crate::{
-	cpu::{Cpu, P},
+	apu::Apu,
+	audio::{Mixer, SampleQueue},
+	cmos::CmosInst,
+	cpu::{Backend, Cpu, P, Variant},
+	dispatch::BlockCache,
 	drawing::{self, Bitmap},
 	inst::Inst,
 	nes_file::Mapper,
-	ppu::{Ppu, Sprite},
+	ppu::{Fetch, Ppu, Sprite},
+	profile::Profile,
 };
 
 pub const PPU_STARTUP_TIME: u64 = 2500;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
 
-// REMEMBER TO REFLECT ANY CHANGES IN `cpu.h`
+// Shared with C up to `cycles`, see `inc/interface.h`. Moving anything there means updating
+// `inc/layout.h` too, or the build fails on one side or the other.
 #[repr(C)]
 pub struct State {
 	pub cpu: Cpu,
@@ -23,8 +30,64 @@ pub struct State {
 	pub output_texture: Arc<Mutex<Bitmap>>,
 	pub current_texture: Bitmap,
 	pub cycles: u64,
+	pub apu: Apu,
+	pub mixer: Mixer,
+	pub backend: Backend,
+	pub variant: Variant,
+	pub blocks: BlockCache,
+	/// Where indirect jumps and returns go, when `--profile` is on.
+	pub profile: Option<Profile>,
 }
 
+mod layout {
+	include!(concat!(env!("OUT_DIR"), "/layout.rs"));
+}
+
+// The C side checks the same numbers in `inc/interface.h`
+#[cfg(target_pointer_width = "64")]
+const _: () = {
+	use std::mem::{offset_of, size_of};
+
+	use layout::*;
+
+	assert!(size_of::<Cpu>() == CPU_SIZE);
+	assert!(offset_of!(Cpu, p) == CPU_P);
+	assert!(offset_of!(Cpu, jammed) == CPU_JAMMED);
+	assert!(offset_of!(Cpu, pc) == CPU_PC);
+
+	assert!(size_of::<Ppu>() == PPU_SIZE);
+	assert!(offset_of!(Ppu, ctrl) == PPU_CTRL);
+	assert!(offset_of!(Ppu, mask) == PPU_MASK);
+	assert!(offset_of!(Ppu, status) == PPU_STATUS);
+	assert!(offset_of!(Ppu, oam_adr) == PPU_OAM_ADR);
+	assert!(offset_of!(Ppu, oam_data) == PPU_OAM_DATA);
+	assert!(offset_of!(Ppu, scroll) == PPU_SCROLL);
+	assert!(offset_of!(Ppu, adr) == PPU_ADR);
+	assert!(offset_of!(Ppu, data) == PPU_DATA);
+	assert!(offset_of!(Ppu, scanline) == PPU_SCANLINE);
+	assert!(offset_of!(Ppu, dot) == PPU_DOT);
+	assert!(offset_of!(Ppu, frame) == PPU_FRAME);
+	assert!(offset_of!(Ppu, cycles) == PPU_CYCLES);
+	assert!(offset_of!(Ppu, vram) == PPU_VRAM);
+	assert!(offset_of!(Ppu, oam) == PPU_OAM);
+	assert!(offset_of!(Ppu, palettes) == PPU_PALETTES);
+	assert!(offset_of!(Ppu, next_tile) == PPU_NEXT_TILE);
+	assert!(offset_of!(Ppu, fine_x) == PPU_FINE_X);
+	assert!(offset_of!(Ppu, write_latch) == PPU_WRITE_LATCH);
+
+	assert!(size_of::<drawing::Colour>() == COLOUR_SIZE);
+	assert!(size_of::<Bitmap>() == BITMAP_SIZE);
+
+	assert!(offset_of!(State, cpu) == STATE_CPU);
+	assert!(offset_of!(State, ppu) == STATE_PPU);
+	assert!(offset_of!(State, rom) == STATE_ROM);
+	assert!(offset_of!(State, ram) == STATE_RAM);
+	assert!(offset_of!(State, bus) == STATE_BUS);
+	assert!(offset_of!(State, output_texture) == STATE_OUTPUT_TEXTURE);
+	assert!(offset_of!(State, current_texture) == STATE_CURRENT_TEXTURE);
+	assert!(offset_of!(State, cycles) == STATE_CYCLES);
+};
+
 #[unsafe(no_mangle)]
 pub unsafe fn state_get_mem(ptr: *mut State, adr: u16) -> u8 {
 	let state = unsafe { &mut *ptr };
@@ -37,6 +100,23 @@ pub unsafe fn state_set_mem(ptr: *mut State, adr: u16, val: u8) {
 	state.set_mem(adr, val);
 }
 
+#[unsafe(no_mangle)]
+pub unsafe fn state_read(ptr: *mut State, adr: u16) -> u8 {
+	let state = unsafe { &mut *ptr };
+	state.read(adr)
+}
+
+#[unsafe(no_mangle)]
+pub unsafe fn state_write(ptr: *mut State, adr: u16, val: u8) {
+	let state = unsafe { &mut *ptr };
+	state.write(adr, val);
+}
+
+#[unsafe(no_mangle)]
+pub unsafe fn state_variant(ptr: *const State) -> u8 {
+	unsafe { &*ptr }.variant as u8
+}
+
 #[unsafe(no_mangle)]
 pub unsafe fn state_step_ppu(ptr: *mut State) {
 	unsafe { &mut *ptr }.step_ppu();
@@ -46,6 +126,12 @@ pub unsafe fn state_step_ppu(ptr: *mut State) {
 pub unsafe fn state_step_ppu_many(ptr: *mut State, times: u32) {
 	for _ in 0..times {
 		unsafe { (&mut *ptr) }.cycles += 1;
+		unsafe { (&mut *ptr) }.rom.tick_cpu();
+		let state = unsafe { &mut *ptr };
+		state.tick_apu();
+		state
+			.mixer
+			.add(state.apu.output() + state.rom.audio_output());
 		unsafe {
 			state_step_ppu(ptr);
 			state_step_ppu(ptr);
@@ -55,7 +141,7 @@ pub unsafe fn state_step_ppu_many(ptr: *mut State, times: u32) {
 }
 
 impl State {
-	pub fn new(rom: Box<Mapper>, output_texture: Arc<Mutex<Bitmap>>) -> Self {
+	pub fn new(rom: Box<Mapper>, output_texture: Arc<Mutex<Bitmap>>, samples: SampleQueue) -> Self {
 		let pc = u16::from_le_bytes([
 			rom.get_cpu(0xFFFC).expect("Cannot read reset vector"),
 			rom.get_cpu(0xFFFD).expect("Cannot read reset vector (2)"),
@@ -67,6 +153,7 @@ impl State {
 			y: 0,
 			s: 0xFD,
 			p: P::new(),
+			jammed: false,
 			pc,
 		};
 
@@ -85,28 +172,125 @@ impl State {
 			output_texture,
 			current_texture,
 			cycles,
+			apu: Apu::new(),
+			mixer: Mixer::new(samples),
+			backend: Backend::default(),
+			variant: Variant::default(),
+			blocks: BlockCache::default(),
+			profile: None,
 		}
 	}
 
-	pub fn next_inst(&self) -> Inst {
-		let code = [
+	/// The opcode at PC and the two bytes after it.
+	fn next_code(&self) -> [u8; 3] {
+		[
 			self.mem_pure(self.cpu.pc),
 			self.mem_pure(self.cpu.pc + 1),
 			self.mem_pure(self.cpu.pc + 2),
-		];
-		code.into()
+		]
 	}
 
-	pub fn next_step(mut self) -> Self {
-		let inst = self.next_inst();
-		inst.evaluate(&mut self);
+	pub fn next_inst(&self) -> Inst {
+		self.next_code().into()
+	}
 
+	pub fn next_step(mut self) -> Self {
+		self.next();
 		self
 	}
 
 	pub fn next(&mut self) {
+		// Nothing gets through to a jammed CPU, but the rest of the console carries on
+		if self.cpu.jammed {
+			self.idle(1);
+			return;
+		}
+
+		if self.irq_pending() {
+			self.interrupt(IRQ_VECTOR);
+			return;
+		}
+
+		if self.variant == Variant::Cmos65C02
+			&& let Some(inst) = CmosInst::decode(self.next_code())
+		{
+			inst.evaluate(self);
+			return;
+		}
+
 		let inst = self.next_inst();
 		inst.evaluate(self);
+		if let Inst::JmpIndirect(..) | Inst::Rts | Inst::Rti = inst {
+			self.record_target();
+		}
+	}
+
+	/// Notes where an indirect jump or return went, if it went into ROM.
+	fn record_target(&mut self) {
+		let Some(profile) = &mut self.profile else {
+			return;
+		};
+		if self.rom.prg_rom_offset(self.cpu.pc).is_some() {
+			profile.record(self.rom.prg_mapping(), self.cpu.pc);
+		}
+	}
+
+	pub fn irq_pending(&self) -> bool {
+		(self.rom.irq() || self.apu.irq()) && !self.cpu.p.i()
+	}
+
+	/// One CPU cycle of the APU, and the DMC's sample fetch if it wants one.
+	fn tick_apu(&mut self) {
+		self.apu.tick();
+		if let Some(adr) = self.apu.dmc_wants() {
+			let val = self.mem_pure(adr);
+			self.apu.dmc_fill(val);
+		}
+	}
+
+	/// One CPU cycle reading the bus. The rest of the console catches up to this cycle first, so
+	/// whatever the read sets off happens when it would on hardware.
+	pub fn read(&mut self, adr: u16) -> u8 {
+		self.idle(1);
+		self.mem(adr)
+	}
+
+	/// One CPU cycle writing the bus, see `read`.
+	pub fn write(&mut self, adr: u16, val: u8) {
+		self.idle(1);
+		self.set_mem(adr, val);
+	}
+
+	pub fn push(&mut self, val: u8) {
+		self.write(0x100 + self.cpu.s as u16, val);
+		self.cpu.s = self.cpu.s.wrapping_sub(1);
+	}
+
+	/// Pushes PC and P and jumps through `vector`, the same way for IRQs and NMIs.
+	fn interrupt(&mut self, vector: u16) {
+		// Two cycles fetching the instruction that doesn't run
+		self.read(self.cpu.pc);
+		self.read(self.cpu.pc);
+		let [lo, hi] = self.cpu.pc.to_le_bytes();
+		self.push(hi);
+		self.push(lo);
+		// B only shows up in the copies of P pushed by BRK and PHP
+		self.push((self.cpu.p.into_bits() & !0x10) | 0x20);
+		self.cpu.p.set_i(true);
+		if self.variant == Variant::Cmos65C02 {
+			self.cpu.p.set_d(false);
+		}
+		self.cpu.pc = u16::from_le_bytes([self.read(vector), self.read(vector + 1)]);
+	}
+
+	/// What the JAM opcodes do. PC stays on the opcode so it's obvious where things went wrong.
+	pub fn jam(&mut self) {
+		self.cpu.jammed = true;
+	}
+
+	/// Lets everything but the CPU run for `cycles` CPU cycles.
+	pub fn idle(&mut self, cycles: u32) {
+		unsafe { state_step_ppu_many(self, cycles) };
 	}
 
 	fn read_ppu_pure(&self, adr: u16) -> u8 {
@@ -137,6 +321,7 @@ impl State {
 		match adr % 8 {
 			2 => {
 				self.ppu.status.set_vblank(false);
+				self.ppu.write_latch = false;
 				println!("cleared vblank by reading");
 			}
 			_ => unreachable!(),
@@ -146,13 +331,42 @@ impl State {
 
 	fn write_ppu(&mut self, adr: u16, val: u8) {
 		match adr % 8 {
-			0 => self.ppu.ctrl.set_bits(val),
+			0 => {
+				self.ppu.ctrl.set_bits(val);
+				let scroll = self.ppu.scroll.get();
+				self.ppu
+					.scroll
+					.set(scroll & !0x0C00 | (val as u16 & 0b11) << 10);
+			}
 			1 => self.ppu.mask.set_bits(val),
 			2 => {}
 			3 => self.ppu.oam_adr = val,
 			4 => self.ppu.oam_data = val,
-			5 => todo!(),
-			6 => todo!(),
+			5 => {
+				let scroll = self.ppu.scroll.get();
+				let val = val as u16;
+				if self.ppu.write_latch {
+					let y = (val & 0b111) << 12 | (val >> 3) << 5;
+					self.ppu.scroll.set(scroll & !0x73E0 | y);
+				} else {
+					self.ppu.scroll.set(scroll & !0x001F | val >> 3);
+					self.ppu.fine_x = val as u8 & 0b111;
+				}
+				self.ppu.write_latch = !self.ppu.write_latch;
+			}
+			6 => {
+				let scroll = self.ppu.scroll.get();
+				if self.ppu.write_latch {
+					self.ppu.scroll.set(scroll & 0xFF00 | val as u16);
+					self.ppu.adr = self.ppu.scroll;
+				} else {
+					// The top bit of the 15 is cleared by the first write
+					self.ppu
+						.scroll
+						.set(scroll & 0x00FF | (val as u16 & 0x3F) << 8);
+				}
+				self.ppu.write_latch = !self.ppu.write_latch;
+			}
 			7 => self.ppu.data = val,
 			_ => unreachable!(),
 		}
@@ -163,9 +377,11 @@ impl State {
 			0x0000..0x0800 => self.ram[adr as usize],
 			0x0800..0x2000 => self.ram[(adr % 2048) as usize],
 			0x2000..0x4000 => self.read_ppu_pure(adr),
-			0x4000..0x4018 => todo!(),
-			0x4018..0x4020 => todo!(),
-			0x4020..=0xFFFF => self.rom.get_cpu(adr).expect("Invalid address for ROM"),
+			// Only bit 5 is left over from the bus
+			0x4015 => self.apu.status() | self.bus & 0x20,
+			// No controllers yet
+			0x4000..0x4020 => self.bus,
+			0x4020..=0xFFFF => self.rom.get_cpu(adr).unwrap_or(self.bus),
 		}
 	}
 
@@ -174,29 +390,38 @@ impl State {
 			0x0000..0x0800 => self.ram[adr as usize],
 			0x0800..0x2000 => self.ram[(adr % 2048) as usize],
 			0x2000..0x4000 => self.read_ppu(adr),
-			0x4000..0x4018 => todo!(),
-			0x4018..0x4020 => todo!(),
-			0x4020..=0xFFFF => self.rom.get_cpu(adr).expect("Invalid address for ROM"),
+			0x4015 => self.apu.read_status() | self.bus & 0x20,
+			// No controllers yet
+			0x4000..0x4020 => self.bus,
+			// Nothing drives the bus for unmapped cartridge space
+			0x4020..=0xFFFF => self.rom.read_cpu(adr).unwrap_or(self.bus),
 		};
 		self.bus = res;
 		res
 	}
 
 	pub fn set_mem(&mut self, adr: u16, val: u8) {
+		self.blocks.ram_code.record_write(adr);
 		match adr {
 			0x0000..0x0800 => self.ram[adr as usize] = val,
 			0x0800..0x2000 => self.ram[(adr % 2048) as usize] = val,
 			0x2000..0x4000 => self.write_ppu(adr, val),
-			0x4000..0x4018 => todo!(),
-			0x4018..0x4020 => todo!(),
-			0x4020..=0xFFFF => self.rom.set_cpu(adr, val).expect("Invalid address for ROM"),
+			0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write(adr, val),
+			// No OAM DMA or controllers yet
+			0x4000..0x4020 => {}
+			0x4020..=0xFFFF => {
+				let mapped = self.rom.prg_mapping();
+				self.rom.set_cpu(adr, val).expect("Invalid address for ROM");
+				if self.rom.prg_mapping() != mapped {
+					self.blocks.prg_switched = true;
+				}
+			}
 		}
 		self.bus = val;
 	}
 
 	pub fn set_vblank(&mut self) {
 		println!("vblank!");
-		if self.ppu.ctrl.nmi_enable() {}
 		if self.cycles > 29658 {
 			self.ppu.status.set_vblank(true);
 		}
@@ -235,6 +460,13 @@ impl State {
 			println!("Cleared vblank by waiting");
 			self.ppu.status.set_vblank(false);
 		}
+		if let Some((adr, kind)) = self.ppu.fetch() {
+			let val = self.rom.fetch_ppu(adr, kind, &self.ppu);
+			if kind == Fetch::Nametable {
+				self.ppu.next_tile = val;
+			}
+		}
+		self.ppu.update_adr();
 		self.ppu.dot += 1;
 		self.ppu.scanline += self.ppu.dot / 341;
 		self.ppu.dot %= 341;
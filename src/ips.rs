@@ -0,0 +1,113 @@
+use anyhow::{Result, bail};
+
+const MAGIC: &[u8; 5] = b"PATCH";
+const EOF: &[u8; 3] = b"EOF";
+/// Records can't start here, as the offset would read as the end marker.
+const EOF_OFFSET: usize = 0x454F46;
+const MAX_RECORD: usize = 0xFFFF;
+
+/// Makes an IPS patch that turns `original` into `modified`, which must be the same size.
+pub fn diff(original: &[u8], modified: &[u8]) -> Vec<u8> {
+	assert_eq!(original.len(), modified.len());
+
+	let mut patch = MAGIC.to_vec();
+	let mut pos = 0;
+	while pos < original.len() {
+		if original[pos] == modified[pos] {
+			pos += 1;
+			continue;
+		}
+
+		let mut start = pos;
+		if start == EOF_OFFSET {
+			start -= 1;
+		}
+		while pos < original.len() && original[pos] != modified[pos] && pos - start < MAX_RECORD {
+			pos += 1;
+		}
+
+		let data = &modified[start..pos];
+		patch.extend(&(start as u32).to_be_bytes()[1..]);
+		patch.extend((data.len() as u16).to_be_bytes());
+		patch.extend(data);
+	}
+	patch.extend(EOF);
+	patch
+}
+
+/// Applies an IPS patch to `data` in place, growing it if the patch writes past its end.
+pub fn apply(patch: &[u8], data: &mut Vec<u8>) -> Result<()> {
+	let Some(mut rest) = patch.strip_prefix(MAGIC) else {
+		bail!("Not an IPS patch, it doesn't start with \"PATCH\"");
+	};
+
+	loop {
+		if rest.starts_with(EOF) {
+			return Ok(());
+		}
+		let Some(([o0, o1, o2, s0, s1], after)) = rest.split_first_chunk::<5>() else {
+			bail!("IPS patch is truncated, it ends without \"EOF\"");
+		};
+		let offset = u32::from_be_bytes([0, *o0, *o1, *o2]) as usize;
+		let size = u16::from_be_bytes([*s0, *s1]) as usize;
+
+		// A size of zero means a run of one byte
+		let (len, fill, after) = if size == 0 {
+			let Some(([r0, r1, val], after)) = after.split_first_chunk::<3>() else {
+				bail!("IPS patch is truncated in a run length record");
+			};
+			(u16::from_be_bytes([*r0, *r1]) as usize, Some(*val), after)
+		} else {
+			(size, None, after)
+		};
+
+		if data.len() < offset + len {
+			data.resize(offset + len, 0);
+		}
+		rest = match fill {
+			Some(val) => {
+				data[offset..offset + len].fill(val);
+				after
+			}
+			None => {
+				let Some((src, after)) = after.split_at_checked(len) else {
+					bail!("IPS patch is truncated in a record at {offset:06X}");
+				};
+				data[offset..offset + len].copy_from_slice(src);
+				after
+			}
+		};
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let original = (0..200).map(|i| i as u8).collect::<Vec<_>>();
+		let mut modified = original.clone();
+		modified[3] = 0xFF;
+		modified[100..150].fill(0);
+
+		let patch = diff(&original, &modified);
+		assert!(patch.starts_with(MAGIC) && patch.ends_with(EOF));
+
+		let mut patched = original.clone();
+		apply(&patch, &mut patched).unwrap();
+		assert_eq!(patched, modified);
+
+		assert_eq!(diff(&original, &original), b"PATCHEOF");
+	}
+
+	#[test]
+	fn run_length_records() {
+		let patch = b"PATCH\x00\x00\x02\x00\x00\x00\x04\xAAEOF";
+		let mut data = vec![0; 4];
+		apply(patch, &mut data).unwrap();
+		assert_eq!(data, [0, 0, 0xAA, 0xAA, 0xAA, 0xAA]);
+
+		assert!(apply(b"PATCH\x00\x00\x02\x00\x05\x01", &mut data).is_err());
+	}
+}
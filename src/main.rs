@@ -1,19 +1,172 @@
+mod apu;
+mod audio;
+mod cmos;
 mod cpu;
+mod disasm;
+mod dispatch;
 mod drawing;
+#[cfg(feature = "c-core")]
 mod evaluate_instruction;
+#[cfg(feature = "rust-core")]
+mod execute;
+mod fds;
+mod flow;
+mod fme7;
 mod inst;
 mod interpret;
+mod ips;
+mod mmc5;
+mod n163;
+mod nes20db;
 mod nes_file;
+mod nsf;
 mod ppu;
+mod profile;
+mod recompile;
+mod rom_db;
+mod rom_header;
+mod save;
+mod verify;
+mod vrc;
+mod vrc6;
+mod vrc7;
 
 #[cfg(test)]
 mod tests;
 
-use std::sync::{Arc, Mutex};
+use std::{
+	path::{Path, PathBuf},
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicBool, Ordering},
+		mpsc::{self, Receiver},
+	},
+	time::Instant,
+};
 
-use drawing::Bitmap;
+use anyhow::{Context, bail};
+
+use audio::SampleQueue;
+use cpu::{Backend, Variant};
+use drawing::{Bitmap, Command};
 use interpret::State;
-use nes_file::Mapper;
+use nes_file::{LoadedRom, Mapper};
+use nsf::{NsfCart, NsfFile, Player};
+use profile::Profile;
+use recompile::CompiledPage;
+use save::{SaveFile, SaveKind};
+use verify::Lockstep;
+
+struct Args {
+	rom: PathBuf,
+	save_dir: Option<PathBuf>,
+	fds_bios: Option<PathBuf>,
+	report_rom_db: bool,
+	import_nes20db: Option<PathBuf>,
+	analyse: bool,
+	recompile: Option<PathBuf>,
+	profile: bool,
+	verify: bool,
+	backend: Backend,
+	variant: Variant,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+	let mut rom = None;
+	let mut save_dir = None;
+	let mut fds_bios = None;
+	let mut report_rom_db = false;
+	let mut import_nes20db = None;
+	let mut analyse = false;
+	let mut recompile = None;
+	let mut profile = false;
+	let mut verify = false;
+	let mut backend = Backend::default();
+	let mut variant = Variant::default();
+
+	let mut args = std::env::args_os().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.to_str() {
+			Some("--save-dir") => {
+				let Some(dir) = args.next() else {
+					bail!("--save-dir needs a directory");
+				};
+				save_dir = Some(dir.into());
+			}
+			Some("--fds-bios") => {
+				let Some(bios) = args.next() else {
+					bail!("--fds-bios needs the path to disksys.rom");
+				};
+				fds_bios = Some(bios.into());
+			}
+			Some("--report-rom-db") => report_rom_db = true,
+			Some("--import-nes20db") => {
+				let Some(xml) = args.next() else {
+					bail!("--import-nes20db needs the path to nes20db.xml");
+				};
+				import_nes20db = Some(xml.into());
+			}
+			Some("--analyse") => analyse = true,
+			Some("--recompile") => {
+				let Some(out) = args.next() else {
+					bail!("--recompile needs a file to write the C to");
+				};
+				recompile = Some(out.into());
+			}
+			Some("--profile") => profile = true,
+			Some("--verify") => verify = true,
+			Some("--cpu") => {
+				let Some(name) = args.next() else {
+					bail!("--cpu needs c or rust");
+				};
+				backend = name.to_string_lossy().parse()?;
+			}
+			Some("--variant") => {
+				let Some(name) = args.next() else {
+					bail!("--variant needs 2a03, 6502 or 65c02");
+				};
+				variant = name.to_string_lossy().parse()?;
+			}
+			Some(flag) if flag.starts_with("--") => bail!("Unknown flag {flag}"),
+			_ if rom.is_none() => rom = Some(arg.into()),
+			_ => bail!("Only one ROM can be loaded at a time"),
+		}
+	}
+
+	Ok(Args {
+		rom: rom.unwrap_or_else(|| "../non-free/SMB1.nes".into()),
+		save_dir,
+		fds_bios,
+		report_rom_db,
+		import_nes20db,
+		analyse,
+		recompile,
+		profile,
+		verify,
+		backend,
+		variant,
+	})
+}
+
+/// Loads a disk image with the BIOS from `--fds-bios`, or else `disksys.rom` next to the image
+/// or in the working directory.
+fn load_fds(path: &Path, buffer: &[u8], bios: Option<&Path>) -> anyhow::Result<LoadedRom> {
+	let candidates = match bios {
+		Some(bios) => vec![bios.to_owned()],
+		None => vec![path.with_file_name("disksys.rom"), "disksys.rom".into()],
+	};
+	let Some(bios) = candidates.iter().find_map(|path| std::fs::read(path).ok()) else {
+		bail!(
+			"Famicom Disk System games need the disk system BIOS, which isn't included. Looked for {}, pass --fds-bios <file> to use another one",
+			candidates
+				.iter()
+				.map(|path| path.display().to_string())
+				.collect::<Vec<_>>()
+				.join(" and ")
+		);
+	};
+	Mapper::load_fds(buffer, &bios)
+}
 
 fn display(state: &State) -> String {
 	use std::fmt::Write;
@@ -72,31 +225,302 @@ fn display(state: &State) -> String {
 	out
 }
 
-fn emulation_loop(shared_texture: Arc<Mutex<Bitmap>>) {
-	let path = std::env::args()
-		.nth(1)
-		.unwrap_or_else(|| "../non-free/SMB1.nes".into());
-	dbg!(&path);
-	let buffer = std::fs::read(path).unwrap();
-	let game = Mapper::parse_ines(buffer).unwrap();
-	let mut system_state = State::new(game, shared_texture);
+/// Sums up what `--analyse` found, 4K page by page.
+fn report_flow(graph: &flow::FlowGraph) {
+	for (page, blocks) in &graph.pages {
+		let bytes = blocks
+			.values()
+			.map(|block| block.end().wrapping_sub(block.start) as usize)
+			.sum::<usize>();
+		println!(
+			"PRG ${:05X}: {} blocks, {bytes} bytes of code",
+			page * flow::PAGE_SIZE,
+			blocks.len()
+		);
+	}
+	println!("{} blocks in all", graph.block_count());
+	for adr in &graph.indirect_jumps {
+		println!("Indirect jump at ${adr:04X}");
+	}
+	for adr in &graph.outside_rom {
+		println!("Leaves ROM for ${adr:04X}");
+	}
+}
+
+/// The emulation thread's end of the window.
+struct Frontend {
+	texture: Arc<Mutex<Bitmap>>,
+	samples: SampleQueue,
+	commands: Receiver<Command>,
+	caption: Arc<Mutex<String>>,
+	running: Arc<AtomicBool>,
+}
+
+impl Frontend {
+	fn set_caption(&self, caption: String) {
+		*self
+			.caption
+			.lock()
+			.expect("Mutex poisoned, not dealing with that") = caption;
+	}
+}
+
+/// Runs `emulation` on its own thread and the window on this one, until the window is closed.
+fn run_with_window(
+	caption: String,
+	emulation: impl FnOnce(Frontend) + Send + 'static,
+) -> anyhow::Result<()> {
+	let shared_texture = drawing::new_bitmap();
+	let samples = audio::new_queue();
+	let caption = Arc::new(Mutex::new(caption));
+	let running = Arc::new(AtomicBool::new(true));
+	let (command_sender, commands) = mpsc::channel();
+
+	let frontend = Frontend {
+		texture: shared_texture.clone(),
+		samples: samples.clone(),
+		commands,
+		caption: caption.clone(),
+		running: running.clone(),
+	};
+	let _emulation = std::thread::spawn(|| emulation(frontend));
+	let result = drawing::sdl_thread(shared_texture, samples, command_sender, caption)
+		.map_err(anyhow::Error::msg);
+
+	// Let the emulation thread write its save before we exit
+	running.store(false, Ordering::Relaxed);
+	_emulation.join().unwrap();
+	result
+}
+
+/// What runs the game's code besides the interpreter, and what watches it run.
+struct Blocks {
+	compiled: &'static [CompiledPage],
+	/// From `--profile`, with where to save it.
+	profile: Option<(PathBuf, Profile)>,
+	/// From `--verify`.
+	verify: bool,
+}
+
+fn emulation_loop(
+	game: Box<Mapper>,
+	mut save: Option<SaveFile>,
+	backend: Backend,
+	variant: Variant,
+	blocks: Blocks,
+	frontend: Frontend,
+) {
+	let mut system_state = State::new(game, frontend.texture.clone(), frontend.samples.clone());
+	system_state.backend = backend;
+	system_state.variant = variant;
+	system_state.blocks.set_compiled(blocks.compiled);
+	let (profile_path, profile) = blocks.profile.unzip();
+	system_state.profile = profile;
+	let mut lockstep = blocks.verify.then(|| Lockstep::new(&system_state));
+	let mut last_flush = system_state.ppu.frame;
 
 	// let mut buf = String::new();
-	loop {
-		system_state.next();
+	while frontend.running.load(Ordering::Relaxed) {
+		match &mut lockstep {
+			Some(lockstep) => {
+				if let Err(divergence) = lockstep.step(&mut system_state) {
+					eprint!("{divergence}");
+					break;
+				}
+			}
+			None => system_state.run_block(),
+		}
+
+		for command in frontend.commands.try_iter() {
+			match command {
+				Command::NextDiskSide => {
+					if let Some(side) = system_state.rom.next_disk_side() {
+						eprintln!("Inserting disk side {side}");
+					}
+				}
+				Command::NextTrack | Command::PreviousTrack => {}
+			}
+		}
 
 		print!("{}", display(&system_state));
 		// buf.clear();
 		// std::io::stdin().read_line(&mut buf).unwrap();
+
+		if let Some(save) = &mut save
+			&& system_state.ppu.frame - last_flush >= save::FLUSH_INTERVAL_FRAMES
+		{
+			last_flush = system_state.ppu.frame;
+			if let Err(e) = save.flush(&system_state.rom) {
+				eprintln!("{e:#}");
+			}
+		}
+	}
+
+	if let Some(save) = &mut save
+		&& let Err(e) = save.flush(&system_state.rom)
+	{
+		eprintln!("{e:#}");
+	}
+	eprintln!("{}", system_state.blocks.stats);
+	eprintln!("{}", system_state.blocks.ram_code);
+
+	if let (Some(path), Some(profile)) = (&profile_path, &system_state.profile) {
+		match profile.save(path) {
+			Ok(()) => eprintln!("{} targets profiled", profile.target_count()),
+			Err(e) => eprintln!("{e:#}"),
+		}
 	}
 }
 
-fn main() {
-	let shared_texture = drawing::new_bitmap();
+/// Plays an NSF in real time, restarting whenever the track changes.
+fn nsf_loop(
+	cart: NsfCart,
+	mut player: Player,
+	backend: Backend,
+	variant: Variant,
+	frontend: Frontend,
+) {
+	let mut state = State::new(
+		Box::new(Mapper::Nsf(cart)),
+		frontend.texture.clone(),
+		frontend.samples.clone(),
+	);
+	state.backend = backend;
+	state.variant = variant;
+	let mut change_track = true;
+	let mut next_play = Instant::now();
 
-	let texture_ptr = shared_texture.clone();
-	let _emulation = std::thread::spawn(|| emulation_loop(texture_ptr));
-	drawing::sdl_thread(shared_texture).unwrap();
+	while frontend.running.load(Ordering::Relaxed) {
+		for command in frontend.commands.try_iter() {
+			match command {
+				Command::NextTrack => player.next_track(),
+				Command::PreviousTrack => player.previous_track(),
+				Command::NextDiskSide => continue,
+			}
+			change_track = true;
+		}
 
-	_emulation.join().unwrap();
+		if change_track {
+			change_track = false;
+			player.init(&mut state);
+			frontend.set_caption(player.caption());
+			next_play = Instant::now();
+		}
+
+		player.play(&mut state);
+
+		next_play += player.play_interval();
+		match next_play.checked_duration_since(Instant::now()) {
+			Some(wait) => std::thread::sleep(wait),
+			// Running behind, don't try to catch up
+			None => next_play = Instant::now(),
+		}
+	}
+}
+
+fn main() -> anyhow::Result<()> {
+	let args = parse_args()?;
+	if let Some(xml) = &args.import_nes20db {
+		let xml = std::fs::read_to_string(xml)
+			.with_context(|| format!("Couldn't read {}", xml.display()))?;
+		print!("{}", nes20db::import_nes20db(&xml)?);
+		return Ok(());
+	}
+	let backend = args.backend;
+	let variant = args.variant;
+	let path = &args.rom;
+	dbg!(&path);
+	let buffer =
+		std::fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+
+	if nsf::is_nsf(&buffer) {
+		let nsf = NsfFile::parse(&buffer)
+			.and_then(|nsf| Ok((NsfCart::new(&nsf)?, nsf)))
+			.with_context(|| format!("Couldn't load {}", path.display()));
+		let (cart, nsf) = nsf?;
+		let player = Player::new(nsf);
+		return run_with_window(player.caption(), move |frontend| {
+			nsf_loop(cart, player, backend, variant, frontend)
+		});
+	}
+
+	let rom = if fds::is_disk_image(&buffer) {
+		load_fds(path, &buffer, args.fds_bios.as_deref())
+	} else {
+		Mapper::load(&buffer)
+	}
+	.with_context(|| format!("Couldn't load {}", path.display()))?;
+	if let Some(junk) = rom.header.junk {
+		eprintln!("Ignoring junk in bytes 7-15 of the header: {junk:02X?}");
+	}
+	let mut game = rom.mapper;
+
+	if args.report_rom_db {
+		println!("{}: {}", path.display(), rom.hashes);
+		match rom.db_entry {
+			Some(entry) if rom.corrections.is_empty() => {
+				println!(
+					"Found \"{}\" in the ROM database, header is correct",
+					entry.name
+				)
+			}
+			Some(entry) => {
+				println!("Found \"{}\" in the ROM database, corrected:", entry.name);
+				for correction in &rom.corrections {
+					println!("  {correction}");
+				}
+			}
+			None => println!("Not in the ROM database, trusting the header"),
+		}
+	}
+
+	let profile_path = Profile::path_for(path);
+	if args.analyse {
+		let profile = Profile::load(&profile_path)?;
+		report_flow(&flow::FlowGraph::analyse(&game, &profile));
+		return Ok(());
+	}
+	if let Some(out) = &args.recompile {
+		let profile = Profile::load(&profile_path)?;
+		let c = recompile::recompile(&flow::FlowGraph::analyse(&game, &profile), rom.hashes.crc32);
+		std::fs::write(out, c).with_context(|| format!("Couldn't write {}", out.display()))?;
+		return Ok(());
+	}
+	let compiled = recompile::compiled_pages(rom.hashes.crc32);
+	// Adds to what earlier runs found
+	let profile = if args.profile {
+		Some((profile_path.clone(), Profile::load(&profile_path)?))
+	} else {
+		None
+	};
+	if !compiled.is_empty() {
+		let blocks = compiled.iter().map(|page| page.count).sum::<usize>();
+		println!("Running {blocks} recompiled blocks");
+	}
+
+	let save_kind = if game.fds().is_some() {
+		Some(SaveKind::Disk)
+	} else {
+		rom.header.battery.then_some(SaveKind::PrgRam)
+	};
+	let save = match save_kind {
+		Some(kind) => {
+			let save_path = SaveFile::path_for(path, args.save_dir.as_deref(), kind);
+			Some(SaveFile::load(save_path, kind, &mut game)?)
+		}
+		None => None,
+	};
+
+	let caption = path
+		.file_name()
+		.map(|name| name.to_string_lossy().into_owned())
+		.unwrap_or_default();
+	let blocks = Blocks {
+		compiled,
+		profile,
+		verify: args.verify,
+	};
+	run_with_window(caption, move |frontend| {
+		emulation_loop(game, save, backend, variant, blocks, frontend)
+	})
 }
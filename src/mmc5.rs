@@ -0,0 +1,657 @@
+use anyhow::{Result, bail};
+
+use crate::{
+	nes_file::SharedMem,
+	ppu::{Fetch, Ppu},
+	rom_header::{HeaderFormat, RomHeader},
+};
+
+/// Nintendo's MMC5 (ExROM): bankswitching in every size, 1K of extra RAM, and a scanline counter
+/// driven by watching the PPU's fetches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mmc5 {
+	prg_rom: SharedMem,
+	prg_ram: Vec<u8>,
+	chr: SharedMem,
+	chr_is_ram: bool,
+	pub exram: [u8; 1024],
+
+	/// $5100
+	prg_mode: u8,
+	/// $5101
+	chr_mode: u8,
+	/// $5102 and $5103, both have to hold the magic values for PRG RAM to be writable.
+	prg_ram_protect: [u8; 2],
+	/// $5104
+	exram_mode: u8,
+	/// $5105, two bits per nametable
+	nametables: u8,
+	/// $5106 and $5107
+	fill_tile: u8,
+	fill_attribute: u8,
+	/// $5113
+	prg_ram_bank: u8,
+	/// $5114-$5117
+	prg_banks: [u8; 4],
+	/// $5120-$5127, for sprites in 8x16 mode and everything in 8x8 mode. With the upper bits
+	/// from $5130 already applied.
+	chr_banks_a: [u16; 8],
+	/// $5128-$512B, for the background in 8x16 mode.
+	chr_banks_b: [u16; 4],
+	/// $5130
+	chr_upper: u8,
+	/// Which set $2007 goes through in 8x16 mode: whichever was written last.
+	last_wrote_b: bool,
+
+	/// $5200-$5202
+	split_control: u8,
+	split_scroll: u8,
+	split_bank: u8,
+
+	/// $5203 and $5204
+	irq_compare: u8,
+	irq_enabled: bool,
+	irq_pending: bool,
+	in_frame: bool,
+	scanline: u8,
+
+	/// Scanline detection: three reads in a row from the same nametable address start a line,
+	/// and a few CPU cycles without reads end the frame.
+	last_fetch_adr: u16,
+	repeated_fetches: u8,
+	idle_cycles: u8,
+
+	/// The background tile being fetched, 0 and 1 being the ones fetched at the end of the
+	/// previous line.
+	tile: u8,
+	/// ExRAM byte for the current tile in extended attribute mode.
+	extended_attribute: u8,
+	in_split: bool,
+	split_y: u16,
+
+	/// $5205 and $5206
+	multiplicand: u8,
+	multiplier: u8,
+}
+
+impl Mmc5 {
+	pub fn new(header: &RomHeader, prg: &[u8], chr: &[u8]) -> Result<Self> {
+		if prg.is_empty() || !prg.len().is_multiple_of(8 * 1024) {
+			bail!("MMC5 PRG ROM has to be a whole number of 8K banks");
+		}
+		if !chr.len().is_multiple_of(1024) {
+			bail!("MMC5 CHR ROM has to be a whole number of 1K banks");
+		}
+
+		// Plain iNES can't say how much PRG RAM there is, and the boards came with anything
+		// from none to 64K, so give games all of it
+		let prg_ram_size = match header.format {
+			HeaderFormat::INes => 64 * 1024,
+			_ => header.prg_ram_total(),
+		};
+		let chr_is_ram = chr.is_empty();
+
+		Ok(Self {
+			prg_rom: prg.into(),
+			prg_ram: vec![0; prg_ram_size],
+			chr: if chr_is_ram {
+				vec![0; header.chr_ram_size.max(8 * 1024)].into()
+			} else {
+				chr.into()
+			},
+			chr_is_ram,
+			exram: [0; _],
+			prg_mode: 3,
+			chr_mode: 0,
+			prg_ram_protect: [0; _],
+			exram_mode: 0,
+			nametables: 0,
+			fill_tile: 0,
+			fill_attribute: 0,
+			prg_ram_bank: 0,
+			prg_banks: [0, 0, 0, 0xFF],
+			chr_banks_a: [0; _],
+			chr_banks_b: [0; _],
+			chr_upper: 0,
+			last_wrote_b: false,
+			split_control: 0,
+			split_scroll: 0,
+			split_bank: 0,
+			irq_compare: 0,
+			irq_enabled: false,
+			irq_pending: false,
+			in_frame: false,
+			scanline: 0,
+			last_fetch_adr: 0,
+			repeated_fetches: 0,
+			idle_cycles: 0,
+			tile: 0,
+			extended_attribute: 0,
+			in_split: false,
+			split_y: 0,
+			multiplicand: 0xFF,
+			multiplier: 0xFF,
+		})
+	}
+
+	pub fn prg_rom(&self) -> &[u8] {
+		&self.prg_rom
+	}
+
+	pub fn prg_ram(&self) -> Option<&[u8]> {
+		(!self.prg_ram.is_empty()).then_some(&self.prg_ram[..])
+	}
+
+	pub fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+		(!self.prg_ram.is_empty()).then_some(&mut self.prg_ram[..])
+	}
+
+	pub fn irq(&self) -> bool {
+		self.irq_enabled && self.irq_pending
+	}
+
+	fn prg_ram_writable(&self) -> bool {
+		self.prg_ram_protect[0] & 0b11 == 0b10 && self.prg_ram_protect[1] & 0b11 == 0b01
+	}
+
+	/// The bank register and 8K page for a $8000-$FFFF address. Bit 7 of the register picks
+	/// ROM over RAM, which $5117 always does.
+	fn prg_page(&self, adr: u16) -> (bool, usize) {
+		let slot = (adr as usize - 0x8000) / 0x2000;
+		let (register, page) = match (self.prg_mode, slot) {
+			(0, _) => (3, (self.prg_banks[3] & 0x7C) as usize | slot),
+			(1, 0 | 1) => (1, (self.prg_banks[1] & 0x7E) as usize | slot & 1),
+			(1, _) => (3, (self.prg_banks[3] & 0x7E) as usize | slot & 1),
+			(2, 0 | 1) => (1, (self.prg_banks[1] & 0x7E) as usize | slot & 1),
+			(2, _) => (slot, (self.prg_banks[slot] & 0x7F) as usize),
+			(_, _) => (slot, (self.prg_banks[slot] & 0x7F) as usize),
+		};
+		let rom = register == 3 || self.prg_banks[register] & 0x80 != 0;
+		(rom, page)
+	}
+
+	fn prg_ram_index(&self, page: usize, adr: u16) -> Option<usize> {
+		let pages = self.prg_ram.len() / 0x2000;
+		(pages > 0).then(|| (page % pages) * 0x2000 + (adr as usize & 0x1FFF))
+	}
+
+	fn prg_rom_index(&self, page: usize, adr: u16) -> usize {
+		let pages = self.prg_rom.len() / 0x2000;
+		(page % pages) * 0x2000 + (adr as usize & 0x1FFF)
+	}
+
+	/// Where in PRG ROM the byte at `adr` comes from, if a ROM page is mapped there.
+	pub fn prg_rom_offset(&self, adr: u16) -> Option<usize> {
+		if adr < 0x8000 {
+			return None;
+		}
+		match self.prg_page(adr) {
+			(true, page) => Some(self.prg_rom_index(page, adr)),
+			(false, _) => None,
+		}
+	}
+
+	pub fn get_cpu(&self, adr: u16) -> Option<u8> {
+		match adr {
+			0x5204 => Some((self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6),
+			0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+			0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+			0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[adr as usize - 0x5C00]),
+			0x6000..=0x7FFF => self
+				.prg_ram_index(self.prg_ram_bank as usize & 0x07, adr)
+				.map(|i| self.prg_ram[i]),
+			0x8000..=0xFFFF => match self.prg_page(adr) {
+				(true, page) => Some(self.prg_rom[self.prg_rom_index(page, adr)]),
+				(false, page) => self
+					.prg_ram_index(page & 0x07, adr)
+					.map(|i| self.prg_ram[i]),
+			},
+			_ => None,
+		}
+	}
+
+	/// Like `get_cpu`, but reading the IRQ status acknowledges the IRQ.
+	pub fn read_cpu(&mut self, adr: u16) -> Option<u8> {
+		let val = self.get_cpu(adr);
+		if adr == 0x5204 {
+			self.irq_pending = false;
+		}
+		val
+	}
+
+	pub fn set_cpu(&mut self, adr: u16, val: u8) {
+		match adr {
+			0x5100 => self.prg_mode = val & 0b11,
+			0x5101 => self.chr_mode = val & 0b11,
+			0x5102 | 0x5103 => self.prg_ram_protect[adr as usize - 0x5102] = val,
+			0x5104 => self.exram_mode = val & 0b11,
+			0x5105 => self.nametables = val,
+			0x5106 => self.fill_tile = val,
+			0x5107 => self.fill_attribute = val & 0b11,
+			0x5113 => self.prg_ram_bank = val,
+			0x5114..=0x5117 => self.prg_banks[adr as usize - 0x5114] = val,
+			0x5120..=0x5127 => {
+				self.chr_banks_a[adr as usize - 0x5120] = (self.chr_upper as u16) << 8 | val as u16;
+				self.last_wrote_b = false;
+			}
+			0x5128..=0x512B => {
+				self.chr_banks_b[adr as usize - 0x5128] = (self.chr_upper as u16) << 8 | val as u16;
+				self.last_wrote_b = true;
+			}
+			0x5130 => self.chr_upper = val & 0b11,
+			0x5200 => self.split_control = val,
+			0x5201 => self.split_scroll = val,
+			0x5202 => self.split_bank = val,
+			0x5203 => self.irq_compare = val,
+			0x5204 => self.irq_enabled = val & 0x80 != 0,
+			0x5205 => self.multiplicand = val,
+			0x5206 => self.multiplier = val,
+			0x5C00..=0x5FFF => {
+				let idx = adr as usize - 0x5C00;
+				match self.exram_mode {
+					// Only writable while the PPU is using it, anything else writes 0
+					0 | 1 => self.exram[idx] = if self.in_frame { val } else { 0 },
+					2 => self.exram[idx] = val,
+					_ => {}
+				}
+			}
+			0x6000..=0x7FFF if self.prg_ram_writable() => {
+				if let Some(i) = self.prg_ram_index(self.prg_ram_bank as usize & 0x07, adr) {
+					self.prg_ram[i] = val;
+				}
+			}
+			0x8000..=0xDFFF if self.prg_ram_writable() => {
+				if let (false, page) = self.prg_page(adr)
+					&& let Some(i) = self.prg_ram_index(page & 0x07, adr)
+				{
+					self.prg_ram[i] = val;
+				}
+			}
+			_ => {}
+		}
+	}
+
+	/// Counts CPU cycles without PPU fetches, which is how the end of the frame is noticed.
+	pub fn tick(&mut self) {
+		if self.idle_cycles < 3 {
+			self.idle_cycles += 1;
+			if self.idle_cycles == 3 {
+				self.in_frame = false;
+				self.last_fetch_adr = 0;
+			}
+		}
+	}
+
+	/// Index into CHR for a pattern table address, using bank set B for 8x16 backgrounds.
+	fn chr_index(&self, adr: u16, set_b: bool) -> usize {
+		let adr = adr as usize & 0x1FFF;
+		let (bank, size) = match (self.chr_mode, set_b) {
+			(0, false) => (self.chr_banks_a[7], 0x2000),
+			(0, true) => (self.chr_banks_b[3], 0x2000),
+			(1, false) => (self.chr_banks_a[adr / 0x1000 * 4 + 3], 0x1000),
+			(1, true) => (self.chr_banks_b[3], 0x1000),
+			(2, false) => (self.chr_banks_a[adr / 0x800 * 2 + 1], 0x800),
+			(2, true) => (self.chr_banks_b[adr / 0x800 % 2 * 2 + 1], 0x800),
+			(_, false) => (self.chr_banks_a[adr / 0x400], 0x400),
+			(_, true) => (self.chr_banks_b[adr / 0x400 % 4], 0x400),
+		};
+		(bank as usize * size + adr % size) % self.chr.len()
+	}
+
+	/// Which bank set CPU accesses through $2007 use.
+	fn cpu_chr_set(&self, ppu: &Ppu) -> bool {
+		ppu.ctrl.sprite_size() && self.last_wrote_b
+	}
+
+	fn nametable(&self, adr: u16, ppu: &Ppu) -> u8 {
+		let offset = adr as usize & 0x3FF;
+		match (self.nametables >> (((adr >> 10) & 0b11) * 2)) & 0b11 {
+			0 => ppu.vram[offset],
+			1 => ppu.vram[0x400 + offset],
+			2 if self.exram_mode <= 1 => self.exram[offset],
+			2 => 0,
+			_ if offset < 0x3C0 => self.fill_tile,
+			_ => self.fill_attribute * 0x55,
+		}
+	}
+
+	fn set_nametable(&mut self, adr: u16, val: u8, ppu: &mut Ppu) {
+		let offset = adr as usize & 0x3FF;
+		match (self.nametables >> (((adr >> 10) & 0b11) * 2)) & 0b11 {
+			0 => ppu.vram[offset] = val,
+			1 => ppu.vram[0x400 + offset] = val,
+			2 if self.exram_mode <= 1 => self.exram[offset] = val,
+			_ => {}
+		}
+	}
+
+	pub fn get_ppu(&self, adr: u16, ppu: &Ppu) -> Option<u8> {
+		match adr {
+			0x0000..=0x1FFF => Some(self.chr[self.chr_index(adr, self.cpu_chr_set(ppu))]),
+			0x2000..=0x3EFF => Some(self.nametable(adr, ppu)),
+			_ => None,
+		}
+	}
+
+	pub fn set_ppu(&mut self, adr: u16, val: u8, ppu: &mut Ppu) -> Option<()> {
+		match adr {
+			0x0000..=0x1FFF if self.chr_is_ram => {
+				let idx = self.chr_index(adr, self.cpu_chr_set(ppu));
+				self.chr[idx] = val;
+			}
+			0x0000..=0x1FFF => {}
+			0x2000..=0x3EFF => self.set_nametable(adr, val, ppu),
+			_ => return None,
+		}
+		Some(())
+	}
+
+	fn new_scanline(&mut self) {
+		if self.in_frame {
+			self.scanline = self.scanline.wrapping_add(1);
+			if self.scanline == self.irq_compare {
+				self.irq_pending = true;
+			}
+		} else {
+			self.in_frame = true;
+			self.scanline = 0;
+			self.irq_pending = false;
+		}
+	}
+
+	/// A read made by the PPU while rendering, which is where all the interesting parts are.
+	pub fn fetch(&mut self, adr: u16, kind: Fetch, ppu: &Ppu) -> u8 {
+		self.idle_cycles = 0;
+
+		let mut line_start = false;
+		if (0x2000..=0x2FFF).contains(&adr) && adr == self.last_fetch_adr {
+			self.repeated_fetches += 1;
+			if self.repeated_fetches == 2 {
+				self.new_scanline();
+				line_start = true;
+			}
+		} else {
+			self.repeated_fetches = 0;
+		}
+		self.last_fetch_adr = adr;
+
+		match kind {
+			Fetch::Nametable => {
+				self.tile = if line_start { 2 } else { (self.tile + 1) % 34 };
+				self.update_split();
+				if self.in_split {
+					let offset = (self.split_y / 8) * 32 + (self.tile % 32) as u16;
+					return self.exram[offset as usize];
+				}
+				if self.exram_mode == 1 {
+					self.extended_attribute = self.exram[adr as usize & 0x3FF];
+				}
+				self.nametable(adr, ppu)
+			}
+			Fetch::Attribute if self.in_split => {
+				let tile = (self.tile % 32) as u16;
+				let attribute = self.exram[(0x3C0 + (self.split_y / 32) * 8 + tile / 4) as usize];
+				let shift = (self.split_y / 16 % 2) * 4 + (tile / 2 % 2) * 2;
+				(attribute >> shift & 0b11) * 0x55
+			}
+			Fetch::Attribute if self.exram_mode == 1 => (self.extended_attribute >> 6) * 0x55,
+			Fetch::Attribute | Fetch::Garbage => self.nametable(adr, ppu),
+			Fetch::Background if self.in_split => {
+				let adr = (adr as usize & 0x0FF8) | (self.split_y as usize & 7);
+				self.chr[(self.split_bank as usize * 0x1000 + adr) % self.chr.len()]
+			}
+			Fetch::Background if self.exram_mode == 1 => {
+				let bank =
+					(self.chr_upper as usize) << 6 | (self.extended_attribute & 0x3F) as usize;
+				self.chr[(bank * 0x1000 + (adr as usize & 0x0FFF)) % self.chr.len()]
+			}
+			Fetch::Background => self.chr[self.chr_index(adr, ppu.ctrl.sprite_size())],
+			Fetch::Sprite => self.chr[self.chr_index(adr, false)],
+		}
+	}
+
+	/// Whether the current tile is on the split side of the screen, and which line of ExRAM
+	/// it shows if so.
+	fn update_split(&mut self) {
+		let enabled = self.split_control & 0x80 != 0 && self.exram_mode <= 1;
+		let threshold = self.split_control & 0x1F;
+		let right = self.split_control & 0x40 != 0;
+		let tile = self.tile % 32;
+		self.in_split = enabled && (tile < threshold) != right;
+
+		if self.in_split {
+			// Tiles 0 and 1 belong to the next line, and the ones fetched on the pre-render line
+			// come before the frame has been noticed
+			let line = match self.in_frame {
+				true => self.scanline as u16 + (self.tile < 2) as u16,
+				false => 0,
+			};
+			self.split_y = (self.split_scroll as u16 + line) % 240;
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::rom_header::{ConsoleType, Timing};
+
+	fn header() -> RomHeader {
+		RomHeader {
+			format: HeaderFormat::Nes20,
+			mapper: 5,
+			submapper: 0,
+			prg_rom_size: 128 * 1024,
+			chr_rom_size: 128 * 1024,
+			prg_ram_size: 0,
+			prg_nvram_size: 32 * 1024,
+			chr_ram_size: 0,
+			chr_nvram_size: 0,
+			mirroring: Default::default(),
+			four_screen: false,
+			battery: true,
+			trainer: false,
+			timing: Timing::Ntsc,
+			console_type: ConsoleType::Nes,
+			misc_roms: 0,
+			expansion_device: 0,
+			junk: None,
+		}
+	}
+
+	/// Every 1K of CHR and 8K of PRG filled with its bank number.
+	fn mmc5() -> Mmc5 {
+		let prg = (0..128 * 1024)
+			.map(|i| (i / 0x2000) as u8)
+			.collect::<Vec<_>>();
+		let chr = (0..128 * 1024)
+			.map(|i| (i / 0x400) as u8)
+			.collect::<Vec<_>>();
+		Mmc5::new(&header(), &prg, &chr).unwrap()
+	}
+
+	/// Runs the PPU through a frame, handing its fetches to the MMC5 and ticking it every third
+	/// dot. Returns the scanline the IRQ was raised on, if it was.
+	fn run_frame(mmc5: &mut Mmc5, ppu: &mut Ppu) -> Option<u16> {
+		let mut irq_line = None;
+		for scanline in 0..262 {
+			for dot in 0..341 {
+				ppu.scanline = scanline;
+				ppu.dot = dot;
+				if (scanline as u32 * 341 + dot as u32).is_multiple_of(3) {
+					mmc5.tick();
+				}
+				if let Some((adr, kind)) = ppu.fetch() {
+					let val = mmc5.fetch(adr, kind, ppu);
+					if kind == Fetch::Nametable {
+						ppu.next_tile = val;
+					}
+				}
+				ppu.update_adr();
+				if mmc5.irq() && irq_line.is_none() {
+					irq_line = Some(scanline);
+				}
+			}
+		}
+		irq_line
+	}
+
+	#[test]
+	fn prg_modes() {
+		let mut mmc5 = mmc5();
+		// Reset state is mode 3 with the last bank at $E000
+		assert_eq!(mmc5.get_cpu(0xE000), Some(15));
+
+		mmc5.set_cpu(0x5100, 0);
+		mmc5.set_cpu(0x5117, 0x87);
+		assert_eq!(mmc5.get_cpu(0x8000), Some(4));
+		assert_eq!(mmc5.get_cpu(0xE000), Some(7));
+
+		mmc5.set_cpu(0x5100, 1);
+		mmc5.set_cpu(0x5115, 0x83);
+		assert_eq!(mmc5.get_cpu(0x8000), Some(2));
+		assert_eq!(mmc5.get_cpu(0xA000), Some(3));
+		assert_eq!(mmc5.get_cpu(0xC000), Some(6));
+
+		mmc5.set_cpu(0x5100, 2);
+		mmc5.set_cpu(0x5116, 0x89);
+		assert_eq!(mmc5.get_cpu(0xC000), Some(9));
+		assert_eq!(mmc5.get_cpu(0xE000), Some(7));
+
+		// RAM in a ROM slot, which needs the protect registers unlocked to write
+		mmc5.set_cpu(0x5100, 3);
+		mmc5.set_cpu(0x5114, 0x01);
+		mmc5.set_cpu(0x8000, 0x42);
+		assert_eq!(mmc5.get_cpu(0x8000), Some(0));
+		mmc5.set_cpu(0x5102, 0x02);
+		mmc5.set_cpu(0x5103, 0x01);
+		mmc5.set_cpu(0x8000, 0x42);
+		assert_eq!(mmc5.get_cpu(0x8000), Some(0x42));
+		mmc5.set_cpu(0x5113, 0x01);
+		assert_eq!(mmc5.get_cpu(0x6000), Some(0x42));
+	}
+
+	#[test]
+	fn chr_sets_in_8x16_mode() {
+		let mut mmc5 = mmc5();
+		let mut ppu = Ppu::default();
+		mmc5.set_cpu(0x5101, 3);
+		for i in 0..8 {
+			mmc5.set_cpu(0x5120 + i, 10 + i as u8);
+		}
+		for i in 0..4 {
+			mmc5.set_cpu(0x5128 + i, 20 + i as u8);
+		}
+
+		// 8x8 sprites: set A for everything
+		assert_eq!(mmc5.fetch(0x0400, Fetch::Background, &ppu), 11);
+		assert_eq!(mmc5.get_ppu(0x1C00, &ppu), Some(17));
+
+		ppu.ctrl.set_sprite_size(true);
+		assert_eq!(mmc5.fetch(0x0400, Fetch::Sprite, &ppu), 11);
+		assert_eq!(mmc5.fetch(0x0400, Fetch::Background, &ppu), 21);
+		assert_eq!(mmc5.fetch(0x1400, Fetch::Background, &ppu), 21);
+		// $2007 uses the set written last
+		assert_eq!(mmc5.get_ppu(0x0000, &ppu), Some(20));
+		mmc5.set_cpu(0x5120, 30);
+		assert_eq!(mmc5.get_ppu(0x0000, &ppu), Some(30));
+
+		mmc5.set_cpu(0x5130, 1);
+		mmc5.set_cpu(0x5121, 0);
+		assert_eq!(mmc5.chr_banks_a[1], 0x100);
+	}
+
+	#[test]
+	fn multiplier() {
+		let mut mmc5 = mmc5();
+		mmc5.set_cpu(0x5205, 200);
+		mmc5.set_cpu(0x5206, 123);
+		assert_eq!(mmc5.get_cpu(0x5205), Some((24600 & 0xFF) as u8));
+		assert_eq!(mmc5.get_cpu(0x5206), Some((24600 >> 8) as u8));
+	}
+
+	#[test]
+	fn fill_mode_and_exram() {
+		let mut mmc5 = mmc5();
+		let ppu = Ppu::default();
+		// Nametable 0 from ExRAM, 1 filled
+		mmc5.set_cpu(0x5105, 0b1110);
+		mmc5.set_cpu(0x5106, 0x33);
+		mmc5.set_cpu(0x5107, 2);
+		assert_eq!(mmc5.get_ppu(0x2400, &ppu), Some(0x33));
+		assert_eq!(mmc5.get_ppu(0x27C0, &ppu), Some(0xAA));
+
+		mmc5.exram[5] = 0x77;
+		assert_eq!(mmc5.get_ppu(0x2005, &ppu), Some(0x77));
+
+		// ExRAM as plain CPU RAM
+		assert_eq!(mmc5.get_cpu(0x5C00), None);
+		mmc5.set_cpu(0x5104, 2);
+		mmc5.set_cpu(0x5C00, 0x12);
+		assert_eq!(mmc5.get_cpu(0x5C00), Some(0x12));
+	}
+
+	#[test]
+	fn extended_attributes() {
+		let mut mmc5 = mmc5();
+		let ppu = Ppu::default();
+		mmc5.set_cpu(0x5104, 1);
+		mmc5.exram[0x21] = 0b11_000101;
+
+		mmc5.fetch(0x2021, Fetch::Nametable, &ppu);
+		assert_eq!(mmc5.fetch(0x23C0, Fetch::Attribute, &ppu), 0xFF);
+		// 4K bank 5 is 1K banks 20 to 23
+		assert_eq!(mmc5.fetch(0x0C00, Fetch::Background, &ppu), 23);
+	}
+
+	#[test]
+	fn scanline_irq() {
+		let mut mmc5 = mmc5();
+		let mut ppu = Ppu::default();
+		ppu.mask.set_show_bg(true);
+
+		mmc5.set_cpu(0x5203, 100);
+		// The frame is noticed through the pre-render line, so the first one starts late
+		run_frame(&mut mmc5, &mut ppu);
+		mmc5.read_cpu(0x5204);
+		mmc5.set_cpu(0x5204, 0x80);
+		assert_eq!(run_frame(&mut mmc5, &mut ppu), Some(100));
+		assert_eq!(mmc5.read_cpu(0x5204).unwrap() & 0x80, 0x80);
+		assert!(!mmc5.irq());
+
+		// Out of frame again after vblank
+		assert_eq!(mmc5.get_cpu(0x5204).unwrap() & 0x40, 0);
+
+		// Nothing when rendering is off
+		ppu.mask.set_show_bg(false);
+		assert_eq!(run_frame(&mut mmc5, &mut ppu), None);
+	}
+
+	#[test]
+	fn vertical_split() {
+		let mut mmc5 = mmc5();
+		let mut ppu = Ppu::default();
+		ppu.mask.set_show_bg(true);
+
+		// Left 4 tiles from ExRAM, showing 4K bank 2
+		mmc5.set_cpu(0x5200, 0x80 | 4);
+		mmc5.set_cpu(0x5202, 2);
+		mmc5.exram[0] = 0x10;
+
+		// Tile 0 of line 0 is fetched on the pre-render line, before the frame starts
+		ppu.scanline = 261;
+		ppu.dot = 321;
+		mmc5.scanline = 200;
+		mmc5.tile = 33;
+		let (adr, kind) = ppu.fetch().unwrap();
+		assert_eq!(mmc5.fetch(adr, kind, &ppu), 0x10);
+		assert!(mmc5.in_split);
+		// Tile $10 of 4K bank 2 is 1K bank 8
+		assert_eq!(mmc5.fetch(0x0100, Fetch::Background, &ppu), 8);
+
+		// Tile 4 isn't
+		mmc5.tile = 3;
+		mmc5.fetch(0x2004, Fetch::Nametable, &ppu);
+		assert!(!mmc5.in_split);
+	}
+}
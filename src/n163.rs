@@ -0,0 +1,395 @@
+use anyhow::{Result, bail};
+
+use crate::{apu, nes_file::SharedMem, ppu::Ppu, rom_header::RomHeader, vrc};
+
+/// CPU cycles spent on each channel before moving to the next.
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+/// Namco 163 wavetable audio: up to eight channels playing 4 bit samples out of 128 bytes of
+/// RAM, which are also where the channel registers live. The chip only has one DAC and takes
+/// turns between the enabled channels, so more channels are quieter and have an audible whine,
+/// which this keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct N163Audio {
+	pub ram: [u8; 128],
+	/// $F800, bit 7 increments it after every access
+	address: u8,
+	/// The channel being updated and output, counting down from 7.
+	channel: u8,
+	cycles: u8,
+	output: f32,
+}
+
+impl Default for N163Audio {
+	fn default() -> Self {
+		Self {
+			ram: [0; _],
+			address: 0,
+			channel: 7,
+			cycles: 0,
+			output: 0.0,
+		}
+	}
+}
+
+impl N163Audio {
+	/// $F800-$FFFF
+	pub fn select(&mut self, val: u8) {
+		self.address = val;
+	}
+
+	/// $4800-$4FFF
+	pub fn read(&self) -> u8 {
+		self.ram[(self.address & 0x7F) as usize]
+	}
+
+	pub fn write(&mut self, val: u8) {
+		self.ram[(self.address & 0x7F) as usize] = val;
+		self.increment();
+	}
+
+	/// Reads go through the same auto-increment as writes.
+	pub fn read_increment(&mut self) -> u8 {
+		let val = self.read();
+		self.increment();
+		val
+	}
+
+	fn increment(&mut self) {
+		if self.address & 0x80 != 0 {
+			self.address = 0x80 | (self.address.wrapping_add(1) & 0x7F);
+		}
+	}
+
+	/// How many channels are on, from the top of $7F. They're the highest numbered ones.
+	fn channel_count(&self) -> u8 {
+		((self.ram[0x7F] >> 4) & 0b111) + 1
+	}
+
+	pub fn tick(&mut self) {
+		self.cycles += 1;
+		if self.cycles < CYCLES_PER_CHANNEL {
+			return;
+		}
+		self.cycles = 0;
+
+		self.output = self.update_channel(self.channel);
+		self.channel = if self.channel <= 8 - self.channel_count() {
+			7
+		} else {
+			self.channel - 1
+		};
+	}
+
+	/// Moves a channel on by a step and returns its output.
+	fn update_channel(&mut self, channel: u8) -> f32 {
+		let base = 0x40 + channel as usize * 8;
+		let regs = &mut self.ram[base..base + 8];
+		let frequency = u32::from_le_bytes([regs[0], regs[2], regs[4] & 0b11, 0]);
+		let mut phase = u32::from_le_bytes([regs[1], regs[3], regs[5], 0]);
+		let length = 256 - (regs[4] & 0xFC) as u32;
+		let offset = regs[6] as u32;
+		let volume = regs[7] & 0x0F;
+
+		phase = (phase + frequency) % (length << 16);
+		[regs[1], regs[3], regs[5], _] = phase.to_le_bytes();
+
+		let index = ((phase >> 16) + offset) as usize & 0xFF;
+		let sample = (self.ram[index / 2] >> ((index % 2) * 4)) & 0x0F;
+		(sample as f32 - 8.0) * volume as f32
+	}
+
+	/// One channel at full volume playing a full scale wave swings about twice as far as a 2A03
+	/// pulse at full volume, before being divided between the channels.
+	pub fn output(&self) -> f32 {
+		// 15 * 15 from the lowest sample to the highest
+		self.output * (2.0 * apu::PULSE_LEVEL / 225.0)
+	}
+}
+
+/// Namco's 163 (mapper 19): PRG and CHR banking, CHR banks that can point at the console's
+/// nametable RAM, a 15 bit IRQ counter and the wavetable sound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct N163 {
+	prg_rom: SharedMem,
+	prg_ram: Vec<u8>,
+	chr: SharedMem,
+	chr_is_ram: bool,
+
+	/// $8000-$BFFF for the pattern tables, $C000-$DFFF for the nametables. $E0 and up can be
+	/// CIRAM instead of CHR ROM.
+	chr_banks: [u8; 12],
+	/// $E000-$F7FF, with the top bits of the first two being flags.
+	prg_banks: [u8; 3],
+	/// $F800, as it also holds the PRG RAM write protection.
+	protect: u8,
+	irq_counter: u16,
+	irq_enabled: bool,
+	irq_pending: bool,
+	/// NES 2.0 submapper 2 is the board without the sound chip's output connected.
+	sound_connected: bool,
+	pub audio: N163Audio,
+}
+
+impl N163 {
+	pub fn new(header: &RomHeader, prg: &[u8], chr: &[u8]) -> Result<Self> {
+		if prg.is_empty() || !prg.len().is_multiple_of(0x2000) {
+			bail!("Namco 163 PRG ROM has to be a whole number of 8K banks");
+		}
+		let chr_is_ram = chr.is_empty();
+
+		Ok(Self {
+			prg_rom: prg.into(),
+			prg_ram: vec![0; header.prg_ram_total()],
+			chr: if chr_is_ram {
+				vec![0; header.chr_ram_size.max(8 * 1024)].into()
+			} else {
+				chr.into()
+			},
+			chr_is_ram,
+			chr_banks: [0; _],
+			prg_banks: [0; _],
+			protect: 0,
+			irq_counter: 0,
+			irq_enabled: false,
+			irq_pending: false,
+			sound_connected: header.submapper != 2,
+			audio: N163Audio::default(),
+		})
+	}
+
+	pub fn prg_rom(&self) -> &[u8] {
+		&self.prg_rom
+	}
+
+	pub fn prg_ram(&self) -> Option<&[u8]> {
+		(!self.prg_ram.is_empty()).then_some(&self.prg_ram[..])
+	}
+
+	pub fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+		(!self.prg_ram.is_empty()).then_some(&mut self.prg_ram[..])
+	}
+
+	pub fn irq(&self) -> bool {
+		self.irq_pending
+	}
+
+	/// The IRQ counter counts up to $7FFF and stops there.
+	pub fn tick(&mut self) {
+		if self.irq_enabled && self.irq_counter < 0x7FFF {
+			self.irq_counter += 1;
+			if self.irq_counter == 0x7FFF {
+				self.irq_pending = true;
+			}
+		}
+		self.audio.tick();
+	}
+
+	/// Bit 6 of $E000 turns the sound off.
+	pub fn audio_output(&self) -> f32 {
+		if !self.sound_connected || self.prg_banks[0] & 0x40 != 0 {
+			0.0
+		} else {
+			self.audio.output()
+		}
+	}
+
+	/// Where in PRG ROM the byte at `adr` comes from.
+	pub fn prg_rom_offset(&self, adr: u16) -> Option<usize> {
+		let bank = match adr {
+			0x8000..=0xDFFF => (self.prg_banks[(adr as usize - 0x8000) / 0x2000] & 0x3F) as usize,
+			0xE000..=0xFFFF => self.prg_rom.len() / 0x2000 - 1,
+			_ => return None,
+		};
+		Some(vrc::prg_index(&self.prg_rom, bank, adr))
+	}
+
+	pub fn get_cpu(&self, adr: u16) -> Option<u8> {
+		match adr {
+			0x4800..=0x4FFF => Some(self.audio.read()),
+			0x5000..=0x57FF => Some(self.irq_counter as u8),
+			0x5800..=0x5FFF => Some((self.irq_enabled as u8) << 7 | (self.irq_counter >> 8) as u8),
+			0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+				Some(self.prg_ram[adr as usize % self.prg_ram.len()])
+			}
+			_ => self.prg_rom_offset(adr).map(|i| self.prg_rom[i]),
+		}
+	}
+
+	/// Like `get_cpu`, but reading sound RAM moves the address along.
+	pub fn read_cpu(&mut self, adr: u16) -> Option<u8> {
+		match adr {
+			0x4800..=0x4FFF => Some(self.audio.read_increment()),
+			_ => self.get_cpu(adr),
+		}
+	}
+
+	/// $F800 bits 4-7 have to be 0100 to write at all, then bits 0-3 protect each 2K.
+	fn prg_ram_writable(&self, adr: u16) -> bool {
+		let section = (adr as usize - 0x6000) / 0x800;
+		self.protect & 0xF0 == 0x40 && self.protect & (1 << section) == 0
+	}
+
+	pub fn set_cpu(&mut self, adr: u16, val: u8) {
+		match adr {
+			0x4800..=0x4FFF => self.audio.write(val),
+			0x5000..=0x57FF => {
+				self.irq_counter = (self.irq_counter & 0x7F00) | val as u16;
+				self.irq_pending = false;
+			}
+			0x5800..=0x5FFF => {
+				self.irq_counter = (self.irq_counter & 0x00FF) | ((val & 0x7F) as u16) << 8;
+				self.irq_enabled = val & 0x80 != 0;
+				self.irq_pending = false;
+			}
+			0x6000..=0x7FFF if !self.prg_ram.is_empty() && self.prg_ram_writable(adr) => {
+				let len = self.prg_ram.len();
+				self.prg_ram[adr as usize % len] = val;
+			}
+			0x8000..=0xDFFF => self.chr_banks[(adr as usize - 0x8000) / 0x800] = val,
+			0xE000..=0xF7FF => self.prg_banks[(adr as usize - 0xE000) / 0x800] = val,
+			0xF800..=0xFFFF => {
+				self.protect = val;
+				self.audio.select(val);
+			}
+			_ => {}
+		}
+	}
+
+	/// Where a $0000-$2FFF PPU address goes: CHR, or CIRAM for banks $E0 and up if that half
+	/// of the pattern tables allows it. Nametables always do.
+	fn ppu_target(&self, adr: u16) -> Target {
+		let slot = (adr as usize & 0x2FFF) / 0x400;
+		let bank = self.chr_banks[slot];
+		let ciram_allowed = match slot {
+			0..=3 => self.prg_banks[1] & 0x40 == 0,
+			4..=7 => self.prg_banks[1] & 0x80 == 0,
+			_ => true,
+		};
+		if bank >= 0xE0 && ciram_allowed {
+			Target::Ciram((bank as usize & 1) * 0x400 + (adr as usize & 0x3FF))
+		} else {
+			Target::Chr(vrc::chr_index(&self.chr, bank as u16, adr))
+		}
+	}
+
+	pub fn get_ppu(&self, adr: u16, ppu: &Ppu) -> Option<u8> {
+		match adr {
+			0x0000..=0x3EFF => match self.ppu_target(adr) {
+				Target::Ciram(i) => Some(ppu.vram[i]),
+				Target::Chr(i) => Some(self.chr[i]),
+			},
+			_ => None,
+		}
+	}
+
+	pub fn set_ppu(&mut self, adr: u16, val: u8, ppu: &mut Ppu) -> Option<()> {
+		match adr {
+			0x0000..=0x3EFF => match self.ppu_target(adr) {
+				Target::Ciram(i) => ppu.vram[i] = val,
+				Target::Chr(i) if self.chr_is_ram => self.chr[i] = val,
+				Target::Chr(_) => {}
+			},
+			_ => return None,
+		}
+		Some(())
+	}
+}
+
+enum Target {
+	Ciram(usize),
+	Chr(usize),
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::vrc::test::{banked_rom, header};
+
+	#[test]
+	fn banking() {
+		let (prg, chr) = banked_rom();
+		let mut ppu = Ppu::default();
+		let mut n163 = N163::new(&header(19, 0), &prg, &chr).unwrap();
+
+		n163.set_cpu(0xE000, 0x41);
+		n163.set_cpu(0xE800, 0x02);
+		n163.set_cpu(0xF000, 0x03);
+		assert_eq!(
+			[0x8000, 0xA000, 0xC000, 0xE000].map(|adr| n163.get_cpu(adr).unwrap()),
+			[1, 2, 3, 15]
+		);
+
+		// CHR ROM, then CIRAM for $E0 and up
+		n163.set_cpu(0x8800, 0x05);
+		assert_eq!(n163.get_ppu(0x0400, &ppu), Some(0x05));
+		n163.set_cpu(0x8800, 0xE1);
+		n163.set_ppu(0x0400, 0x77, &mut ppu);
+		assert_eq!(ppu.vram[0x400], 0x77);
+		// Unless it's switched off for that half, when it's CHR bank $E1 of 128
+		n163.set_cpu(0xE800, 0x42);
+		assert_eq!(n163.get_ppu(0x0400, &ppu), Some(0xE1 % 128));
+
+		// Nametables
+		n163.set_cpu(0xC000, 0xE0);
+		n163.set_cpu(0xC800, 0xE1);
+		assert_eq!(n163.get_ppu(0x2400, &ppu), Some(0x77));
+		n163.set_cpu(0xC000, 0x10);
+		assert_eq!(n163.get_ppu(0x2000, &ppu), Some(0x10));
+	}
+
+	#[test]
+	fn irq_counter() {
+		let (prg, chr) = banked_rom();
+		let mut n163 = N163::new(&header(19, 0), &prg, &chr).unwrap();
+		n163.set_cpu(0x5000, 0xFD);
+		n163.set_cpu(0x5800, 0xFF);
+		assert_eq!(n163.get_cpu(0x5800), Some(0xFF));
+
+		n163.tick();
+		assert!(!n163.irq());
+		n163.tick();
+		assert!(n163.irq());
+		// Stays at $7FFF
+		n163.tick();
+		assert_eq!(n163.get_cpu(0x5000), Some(0xFF));
+
+		n163.set_cpu(0x5800, 0x00);
+		assert!(!n163.irq());
+	}
+
+	#[test]
+	fn sound_ram_and_channels() {
+		let mut audio = N163Audio::default();
+		// Auto-incrementing writes, then reads back
+		audio.select(0x80);
+		for val in [0xF0, 0xF0, 0x0F, 0x0F] {
+			audio.write(val);
+		}
+		audio.select(0x81);
+		assert_eq!(audio.read_increment(), 0xF0);
+		assert_eq!(audio.read_increment(), 0x0F);
+
+		// Channel 7 alone, an 8 sample wave at offset 0, stepping one sample per update
+		audio.ram[0x78] = 0x00;
+		audio.ram[0x7A] = 0x00;
+		audio.ram[0x7C] = 0x01 | (256 - 8) as u8;
+		audio.ram[0x7E] = 0x00;
+		audio.ram[0x7F] = 0x0F;
+
+		let samples = (0..8 * CYCLES_PER_CHANNEL as usize)
+			.filter_map(|i| {
+				audio.tick();
+				(i % CYCLES_PER_CHANNEL as usize == CYCLES_PER_CHANNEL as usize - 1)
+					.then_some(audio.output)
+			})
+			.collect::<Vec<_>>();
+		// The wave is 0, 15, 0, 15, 15, 0, 15, 0 and the first update steps past sample 0
+		assert_eq!(
+			samples,
+			[7.0, -8.0, 7.0, 7.0, -8.0, 7.0, -8.0, -8.0]
+				.map(|s: f32| s * 15.0)
+				.to_vec()
+		);
+	}
+}
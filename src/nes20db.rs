@@ -0,0 +1,100 @@
+//! The NES 2.0 XML database, turned into lines for `data/romdb.txt`. The build script includes
+//! this file too, to fold the XML into the embedded database, so it can only use std and anyhow.
+
+use anyhow::{Context, Result};
+
+/// Turns the NES 2.0 XML database into lines for `romdb.txt`. Every field the XML has is listed,
+/// so a header that disagrees with it in any way gets corrected.
+pub fn import_nes20db(xml: &str) -> Result<String> {
+	let mut out = String::new();
+	for (i, game) in xml.split("<game>").skip(1).enumerate() {
+		let game = game.split_once("</game>").map_or(game, |(game, _)| game);
+		let line = import_game(game).with_context(|| format!("Game {} in the XML", i + 1))?;
+		out.extend(line.map(|line| line + "\n"));
+	}
+	Ok(out)
+}
+
+/// One `<game>`, or `None` for games without a hash of their PRG and CHR ROM together.
+fn import_game(game: &str) -> Result<Option<String>> {
+	let Some(rom) = element(game, "rom") else {
+		return Ok(None);
+	};
+	let crc32 = attribute(rom, "crc32").context("<rom> has no crc32")?;
+	let crc32 = u32::from_str_radix(crc32, 16).context("Bad CRC32")?;
+	let sha1 = match attribute(rom, "sha1") {
+		Some(hex) => parse_sha1(hex)
+			.context("Bad SHA-1")?
+			.map(|b| format!("{b:02X}"))
+			.concat(),
+		None => "-".into(),
+	};
+	let pcb = element(game, "pcb").context("No <pcb>")?;
+	let mapper: u16 = attribute(pcb, "mapper")
+		.context("<pcb> has no mapper")?
+		.parse()?;
+	let submapper: u8 = attribute(pcb, "submapper").unwrap_or("0").parse()?;
+	let mut line = format!("{crc32:08X} {sha1} mapper={mapper} submapper={submapper}");
+	// Anything else is the mapper's business, which the header can't say
+	match attribute(pcb, "mirroring") {
+		Some("H") => line += " mirroring=h",
+		Some("V") => line += " mirroring=v",
+		Some("4") => line += " mirroring=4",
+		_ => {}
+	}
+	let battery = attribute(pcb, "battery") == Some("1");
+	line += &format!(" battery={}", battery as u8);
+	for (tag, field) in [
+		("prgram", "prg_ram"),
+		("prgnvram", "prg_nvram"),
+		("chrram", "chr_ram"),
+		("chrnvram", "chr_nvram"),
+	] {
+		let size: usize = match element(game, tag) {
+			Some(e) => attribute(e, "size")
+				.context("RAM without a size")?
+				.parse()?,
+			None => 0,
+		};
+		line += &format!(" {field}={size}");
+	}
+	let timing = match element(game, "console").and_then(|e| attribute(e, "region")) {
+		Some("1") => "pal",
+		Some("2") => "multi",
+		Some("3") => "dendy",
+		_ => "ntsc",
+	};
+	line += &format!(" timing={timing}");
+
+	let name = game
+		.split_once("<!--")
+		.and_then(|(_, rest)| rest.split_once("-->"))
+		.map_or("", |(name, _)| name.trim());
+	line += &format!(" ; {name}");
+	Ok(Some(line))
+}
+
+/// The attributes of the first `<tag .../>` in `xml`.
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+	// Keep the space before the first attribute, `attribute` looks for one
+	let start = xml.find(&format!("<{tag} "))? + tag.len() + 1;
+	let len = xml[start..].find('>')?;
+	Some(xml[start..start + len].trim_end_matches('/'))
+}
+
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+	let start = attributes.find(&format!(" {name}=\""))? + name.len() + 3;
+	let len = attributes[start..].find('"')?;
+	Some(&attributes[start..start + len])
+}
+
+pub fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+	if hex.len() != 40 {
+		return None;
+	}
+	let mut sha1 = [0; 20];
+	for (i, byte) in sha1.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+	}
+	Some(sha1)
+}
@@ -1,18 +1,46 @@
 #![allow(dead_code, unused)]
 
-use anyhow::{Result, bail};
+use std::{
+	fmt,
+	ops::{Deref, DerefMut},
+	sync::Arc,
+};
 
-use crate::ppu::Ppu;
+use anyhow::{Context, Result, bail};
+
+use crate::{
+	fds::{self, Fds},
+	fme7::Fme7,
+	mmc5::Mmc5,
+	n163::N163,
+	nsf::NsfCart,
+	ppu::{Fetch, Ppu},
+	rom_db::{self, Correction, DbEntry, RomHashes},
+	rom_header::{ConsoleType, HeaderFormat, RomHeader, Timing},
+	vrc::Vrc4,
+	vrc6::Vrc6,
+	vrc7::Vrc7,
+};
+
+/// A cartridge fresh out of a ROM file, along with what was learnt about it on the way.
+#[derive(Debug)]
+pub struct LoadedRom {
+	pub header: RomHeader,
+	pub mapper: Box<Mapper>,
+	pub hashes: RomHashes,
+	pub db_entry: Option<&'static DbEntry>,
+	pub corrections: Vec<Correction>,
+}
 
 // Yeah, yeah, it's huge, but this entire thing is expected to be boxed, so it's fine.
-// #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Mapper {
 	MMC3 {
 		prg_banks: [u8; 2],
 		chr_2k_banks: [u8; 2],
 		chr_1k_banks: [u8; 4],
-		prg_roms: [[u8; 8 * 1024]; 32],
+		prg_rom: SharedMem,
 		// chr_roms: [],
 		prg_mode: Mmc3PrgMode,
 		chr_mode: Mmc3ChrMode,
@@ -21,33 +49,156 @@ pub enum Mapper {
 
 	MMC4,
 
+	MMC5(Mmc5),
+
+	/// VRC2 and VRC4, which only differ in the registers VRC4 added.
+	VRC4(Vrc4),
+
+	VRC6(Vrc6),
+
+	VRC7(Vrc7),
+
+	/// Sunsoft FME-7, and the 5B which is the same with sound added.
+	FME7(Fme7),
+
+	N163(N163),
+
 	NROM256 {
-		prg_ram: [u8; 8 * 1024],
-		prg_rom: [u8; 32 * 1024],
-		chr_rom: [u8; 8 * 1024],
+		prg_ram: Option<[u8; 8 * 1024]>,
+		prg_rom: SharedMem,
+		chr: SharedMem,
+		chr_is_ram: bool,
+		mirroring: Mirroring,
 	},
 
 	NROM128 {
-		ram: [u8; 8 * 1024],
-		rom: [u8; 16 * 1024],
+		prg_ram: Option<[u8; 8 * 1024]>,
+		prg_rom: SharedMem,
+		chr: SharedMem,
+		chr_is_ram: bool,
+		mirroring: Mirroring,
 	},
+
+	Fds(Fds),
+
+	Nsf(NsfCart),
+}
+
+/// Where in PRG ROM each 4K of cartridge space from $6000 up comes from, 4K being the smallest
+/// PRG bank there is. `None` where there's RAM or nothing.
+pub type PrgMapping = [Option<usize>; 10];
+
+/// Nametable mirroring, as selected by the solder pads on simpler boards or by the mapper.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Mirroring {
+	#[default]
+	Horizontal,
+	Vertical,
+	/// All four nametables are the first 1K of CIRAM.
+	SingleScreenA,
+	/// All four nametables are the second 1K of CIRAM.
+	SingleScreenB,
+}
+
+impl Mirroring {
+	/// Maps a $2000-$3EFF nametable address onto the 2K of CIRAM in the console.
+	pub fn vram_index(self, adr: u16) -> usize {
+		let adr = adr as usize & 0x0FFF;
+		match self {
+			Mirroring::Horizontal => (adr & 0x03FF) | ((adr & 0x0800) >> 1),
+			Mirroring::Vertical => adr & 0x07FF,
+			Mirroring::SingleScreenA => adr & 0x03FF,
+			Mirroring::SingleScreenB => 0x0400 | (adr & 0x03FF),
+		}
+	}
+}
+
+/// Cartridge memory that clones of a mapper share until one of them writes to it, which for ROM
+/// is never. Copies still sharing compare equal without looking at the bytes, so `--verify` can
+/// compare mappers after every block without going through all of their ROM.
+#[derive(Clone)]
+pub struct SharedMem(Arc<[u8]>);
+
+impl Deref for SharedMem {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		&self.0
+	}
+}
+
+impl DerefMut for SharedMem {
+	/// Copies the memory first if another mapper still shares it.
+	fn deref_mut(&mut self) -> &mut [u8] {
+		Arc::make_mut(&mut self.0)
+	}
+}
+
+impl From<&[u8]> for SharedMem {
+	fn from(mem: &[u8]) -> Self {
+		Self(mem.into())
+	}
+}
+
+impl From<Vec<u8>> for SharedMem {
+	fn from(mem: Vec<u8>) -> Self {
+		Self(mem.into())
+	}
+}
+
+impl PartialEq for SharedMem {
+	fn eq(&self, other: &Self) -> bool {
+		Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+	}
+}
+
+impl fmt::Debug for SharedMem {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+/// The iNES mapper number of a UNIF board name, for the boards that are supported.
+fn unif_board_mapper(board: &str) -> Option<u16> {
+	// The prefix only says who made the board: Nintendo, licensed, bootleg and so on
+	let board = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-"]
+		.iter()
+		.find_map(|prefix| board.strip_prefix(prefix))
+		.unwrap_or(board);
+
+	match board {
+		"NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" => Some(0),
+		"TBROM" | "TEROM" | "TFROM" | "TGROM" | "TKROM" | "TLROM" | "TNROM" | "TR1ROM"
+		| "TSROM" | "TVROM" | "B4" => Some(4),
+		"TKSROM" | "TLSROM" => Some(118),
+		"TQROM" => Some(119),
+		"EKROM" | "ELROM" | "ETROM" | "EWROM" => Some(5),
+		_ => None,
+	}
+}
+
+/// Maps a $3F00-$3FFF palette address onto the 32 bytes of palette RAM.
+fn palette_index(adr: u16) -> usize {
+	let idx = (adr & 0x1F) as usize;
+	// $3F10/$3F14/$3F18/$3F1C are mirrors of the background entries
+	if idx & 0x13 == 0x10 { idx & 0x0F } else { idx }
 }
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
 pub enum Mmc3PrgMode {
 	#[default]
 	Mode0 = 0,
 	Mode1 = 1,
 }
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
 pub enum Mmc3ChrMode {
 	#[default]
 	Mode0 = 0,
 	Mode1 = 1,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 #[allow(non_snake_case)]
 pub struct Mmc3Registers {
 	// Mapping
@@ -62,98 +213,561 @@ pub struct Mmc3Registers {
 	hE001: u8,
 }
 
+impl Mmc3Registers {
+	/// Bit 0 of $A000, which MMC3 boards without four-screen VRAM all wire up.
+	fn mirroring(&self) -> Mirroring {
+		if self.hA000 & 1 == 0 {
+			Mirroring::Vertical
+		} else {
+			Mirroring::Horizontal
+		}
+	}
+}
+
 impl Mapper {
 	pub fn parse_ines(buffer: Vec<u8>) -> Result<Box<Self>> {
-		let [
-			b'N',
-			b'E',
-			b'S',
-			0x1A,
-			prg_size,
-			_chr_size,
-			flags_6,
-			flags_7,
-			_,
-			_,
-			_,
-			_,
-			_,
-			_,
-			_,
-			_,
-		] = &buffer[0..16]
-		else {
-			bail!("Missing header!");
+		Self::load_ines(&buffer).map(|rom| rom.mapper)
+	}
+
+	/// Like `parse_ines`, but also hands back the header for things the mapper itself doesn't
+	/// care about, like whether the PRG RAM is battery-backed. Known bad headers are fixed from
+	/// the ROM database first.
+	pub fn load_ines(buffer: &[u8]) -> Result<LoadedRom> {
+		let Some(header) = buffer.first_chunk::<16>() else {
+			bail!(
+				"File is too short to be an iNES ROM ({} bytes, the header alone is 16)",
+				buffer.len()
+			);
+		};
+		let header = RomHeader::parse(header)?;
+
+		let trainer_size: usize = if header.trainer { 512 } else { 0 };
+		let prg_offset = 16 + trainer_size;
+		// NES 2.0 exponent sizes can be far beyond anything addressable
+		let Some(chr_offset) = prg_offset.checked_add(header.prg_rom_size) else {
+			bail!(
+				"Header declares {} bytes of PRG ROM, which can't be right",
+				header.prg_rom_size
+			);
+		};
+		let Some(end) = chr_offset.checked_add(header.chr_rom_size) else {
+			bail!(
+				"Header declares {} bytes of CHR ROM, which can't be right",
+				header.chr_rom_size
+			);
+		};
+
+		if buffer.len() < end {
+			bail!(
+				"File is truncated: the header declares {} bytes of trainer, PRG and CHR ROM but only {} follow it",
+				end - 16,
+				buffer.len() - 16
+			);
+		}
+		// Some dumps carry a 127 or 128 byte title at the end, anything more means the header
+		// sizes are wrong. NES 2.0 misc ROMs have no size of their own and run to the end.
+		if header.misc_roms == 0 && buffer.len() - end > 128 {
+			bail!(
+				"File is {} bytes larger than the header declares, the ROM sizes in it are probably wrong",
+				buffer.len() - end
+			);
+		}
+
+		let prg = &buffer[prg_offset..chr_offset];
+		let chr = &buffer[chr_offset..end];
+		let trainer = header.trainer.then(|| &buffer[16..prg_offset]);
+
+		Self::load_checked(header, prg, chr, trainer)
+	}
+
+	/// Parses a UNIF file, which names the board instead of giving a mapper number and splits
+	/// the ROM into tagged chunks.
+	pub fn parse_unif(buffer: Vec<u8>) -> Result<Box<Self>> {
+		Self::load_unif(&buffer).map(|rom| rom.mapper)
+	}
+
+	/// Like `parse_unif`, but also hands back a header synthesised from the chunks so the rest
+	/// of the emulator doesn't have to care which format the ROM came in.
+	pub fn load_unif(buffer: &[u8]) -> Result<LoadedRom> {
+		let Some((b"UNIF", _)) = buffer.split_first_chunk::<4>() else {
+			bail!("Not a UNIF file, it doesn't start with \"UNIF\"");
+		};
+		let Some(mut rest) = buffer.get(32..) else {
+			bail!(
+				"File is too short to be a UNIF ROM ({} bytes, the header alone is 32)",
+				buffer.len()
+			);
 		};
 
-		let trainer_present = flags_6 & (1 << 2) != 0;
-		assert!(!trainer_present); // Not really, but please error early when I hit a game with one.
-		let trainer_offset = if trainer_present { 512 } else { 0 };
-		let prg_offset = 16 + trainer_offset;
-		let _chr_offset = prg_offset + (*prg_size as usize * 16 * 1024);
-		let mapper_type = (*flags_7 & 0xF0) | *flags_6 >> 4;
+		let mut board = None;
+		let mut prg_chunks: [Option<&[u8]>; 16] = [None; _];
+		let mut chr_chunks: [Option<&[u8]>; 16] = [None; _];
+		let mut mirroring = None;
+		let mut battery = false;
+		let mut timing = Timing::Ntsc;
+
+		while !rest.is_empty() {
+			let Some((id, after_id)) = rest.split_first_chunk::<4>() else {
+				bail!("Truncated UNIF chunk header at the end of the file");
+			};
+			let Some((len, after_len)) = after_id.split_first_chunk::<4>() else {
+				bail!("Truncated UNIF chunk header at the end of the file");
+			};
+			let len = u32::from_le_bytes(*len) as usize;
+			if after_len.len() < len {
+				bail!(
+					"UNIF chunk {} claims {len} bytes but only {} are left in the file",
+					String::from_utf8_lossy(id),
+					after_len.len()
+				);
+			}
+			let (data, next) = after_len.split_at(len);
+			rest = next;
+
+			match id {
+				b"MAPR" => {
+					let name = data.split(|&b| b == 0).next().unwrap_or_default();
+					board = Some(
+						std::str::from_utf8(name).context("UNIF board name isn't valid UTF-8")?,
+					);
+				}
+				[b'P', b'R', b'G', n] | [b'C', b'H', b'R', n] => {
+					let Some(idx) = (*n as char).to_digit(16) else {
+						bail!("Unknown UNIF chunk {}", String::from_utf8_lossy(id));
+					};
+					let chunks = if id[0] == b'P' {
+						&mut prg_chunks
+					} else {
+						&mut chr_chunks
+					};
+					chunks[idx as usize] = Some(data);
+				}
+				b"MIRR" => mirroring = data.first().copied(),
+				b"BATR" => battery = true,
+				b"TVCI" => {
+					timing = match data.first() {
+						Some(1) => Timing::Pal,
+						Some(2) => Timing::MultiRegion,
+						_ => Timing::Ntsc,
+					}
+				}
+				// Names, dumper info, checksums and the like
+				_ => {}
+			}
+		}
+
+		let Some(board) = board else {
+			bail!("UNIF file has no MAPR chunk, so the board is unknown");
+		};
+		let Some(mapper) = unif_board_mapper(board) else {
+			bail!("Unsupported UNIF board {board}");
+		};
+
+		let prg = prg_chunks
+			.iter()
+			.flatten()
+			.copied()
+			.collect::<Vec<_>>()
+			.concat();
+		let chr = chr_chunks
+			.iter()
+			.flatten()
+			.copied()
+			.collect::<Vec<_>>()
+			.concat();
+		if prg.is_empty() {
+			bail!("UNIF file has no PRG chunks");
+		}
+
+		let (mirroring, four_screen) = match mirroring {
+			Some(0) | None => (Mirroring::Horizontal, false),
+			Some(1) => (Mirroring::Vertical, false),
+			Some(4) => (Mirroring::Horizontal, true),
+			// Controlled by the mapper, which starts out however it likes
+			Some(5) => (Mirroring::Horizontal, false),
+			Some(2) => (Mirroring::SingleScreenA, false),
+			Some(3) => (Mirroring::SingleScreenB, false),
+			Some(other) => bail!("Unknown UNIF mirroring {other}"),
+		};
+
+		// UNIF doesn't say how much RAM there is, so assume the usual 8K like with iNES
+		let header = RomHeader {
+			format: HeaderFormat::Unif,
+			mapper,
+			submapper: 0,
+			prg_rom_size: prg.len(),
+			chr_rom_size: chr.len(),
+			prg_ram_size: if battery { 0 } else { 8 * 1024 },
+			prg_nvram_size: if battery { 8 * 1024 } else { 0 },
+			chr_ram_size: if chr.is_empty() { 8 * 1024 } else { 0 },
+			chr_nvram_size: 0,
+			mirroring,
+			four_screen,
+			battery,
+			trainer: false,
+			timing,
+			console_type: ConsoleType::Nes,
+			misc_roms: 0,
+			expansion_device: 0,
+			junk: None,
+		};
 
-		match mapper_type {
+		Self::load_checked(header, &prg, &chr, None)
+	}
+
+	/// Loads a Famicom Disk System image, with or without the fwNES header. The BIOS isn't
+	/// freely redistributable, so it has to be supplied separately.
+	pub fn load_fds(buffer: &[u8], bios: &[u8]) -> Result<LoadedRom> {
+		let disk = fds::parse_image(buffer)?;
+		let hashes = RomHashes::new(&disk, &[]);
+		let fds = Fds::new(bios, disk)?;
+
+		let header = RomHeader {
+			format: HeaderFormat::Fds,
+			mapper: 20,
+			submapper: 0,
+			prg_rom_size: fds.bios.len(),
+			chr_rom_size: 0,
+			prg_ram_size: fds.prg_ram.len(),
+			prg_nvram_size: 0,
+			chr_ram_size: fds.chr_ram.len(),
+			chr_nvram_size: 0,
+			mirroring: fds.mirroring,
+			four_screen: false,
+			// What games save goes back to the disk, not to battery RAM
+			battery: false,
+			trainer: false,
+			timing: Timing::Ntsc,
+			console_type: ConsoleType::Nes,
+			misc_roms: 0,
+			expansion_device: 0,
+			junk: None,
+		};
+
+		Ok(LoadedRom {
+			header,
+			mapper: Box::new(Mapper::Fds(fds)),
+			hashes,
+			db_entry: None,
+			corrections: Vec::new(),
+		})
+	}
+
+	/// Picks the loader for `buffer` by its magic bytes.
+	pub fn load(buffer: &[u8]) -> Result<LoadedRom> {
+		match buffer.first_chunk::<4>() {
+			Some(b"NES\x1A") => Self::load_ines(buffer),
+			Some(b"UNIF") => Self::load_unif(buffer),
+			_ if fds::is_disk_image(buffer) => {
+				bail!("Famicom Disk System images need the disk system BIOS, see `load_fds`")
+			}
+			_ => bail!("Unknown ROM format, expected an iNES (.nes) or UNIF (.unf) file"),
+		}
+	}
+
+	/// Fixes `header` from the ROM database and builds the mapper, the common tail of all the
+	/// loaders.
+	fn load_checked(
+		mut header: RomHeader,
+		prg: &[u8],
+		chr: &[u8],
+		trainer: Option<&[u8]>,
+	) -> Result<LoadedRom> {
+		let hashes = RomHashes::new(prg, chr);
+		let db_entry = rom_db::lookup(&hashes);
+		let corrections = db_entry
+			.map(|entry| entry.apply(&mut header))
+			.unwrap_or_default();
+
+		let mapper = Self::from_header(&header, prg, chr, trainer)?;
+		Ok(LoadedRom {
+			header,
+			mapper,
+			hashes,
+			db_entry,
+			corrections,
+		})
+	}
+
+	/// Builds the mapper described by `header` out of already split up PRG and CHR ROM. The
+	/// trainer, if any, is loaded into $7000-$71FF.
+	pub fn from_header(
+		header: &RomHeader,
+		prg: &[u8],
+		chr: &[u8],
+		trainer: Option<&[u8]>,
+	) -> Result<Box<Self>> {
+		let mut mapper = Self::from_header_inner(header, prg, chr)?;
+
+		if let Some(trainer) = trainer {
+			let Some(prg_ram) = mapper.prg_ram_mut() else {
+				bail!(
+					"ROM has a trainer but mapper {} has no PRG RAM to load it into",
+					header.mapper
+				);
+			};
+			prg_ram[0x1000..0x1200].copy_from_slice(trainer);
+		}
+
+		Ok(mapper)
+	}
+
+	fn from_header_inner(header: &RomHeader, prg: &[u8], chr: &[u8]) -> Result<Box<Self>> {
+		match header.mapper {
 			4 | 118 | 119 => {
-				if *prg_size != 16 {
+				if prg.len() != 256 * 1024 {
 					bail!("Wrong amount of prg_roms for an MMC3 mapper");
 				}
 
-				let mut mapper = Box::new(Mapper::MMC3 {
+				Ok(Box::new(Mapper::MMC3 {
 					prg_banks: [0; _],
 					chr_2k_banks: [0; _],
 					chr_1k_banks: [0; _],
-					prg_roms: [[0; _]; _],
+					prg_rom: prg.into(),
 					prg_mode: Mmc3PrgMode::Mode0,
 					chr_mode: Mmc3ChrMode::Mode0,
 					registers: Mmc3Registers::default(),
-				});
-
-				let Mapper::MMC3 { prg_roms, .. } = &mut *mapper else {
-					unreachable!()
-				};
-				for (src, dst) in buffer[prg_offset..]
-					.chunks(16 * 1024)
-					.take(*prg_size as _)
-					.flat_map(|slice_16| slice_16.chunks(8 * 1024))
-					.zip(prg_roms.iter_mut())
-				{
-					dst.copy_from_slice(src);
-				}
-
-				Ok(mapper)
+				}))
 			}
+			// Each of these boxes in a closure, as a `Mapper` on this function's stack costs as
+			// much as MMC3's PRG ROM, and debug builds give every arm its own
+			5 => Mmc5::new(header, prg, chr).map(|mmc5| Box::new(Mapper::MMC5(mmc5))),
 			10 => Ok(Box::new(Mapper::MMC4)),
-			0 if *prg_size == 1 => {
-				let mut mapper = Box::new(Mapper::NROM128 {
-					ram: [0; _],
-					rom: [0; _],
-				});
-				let Mapper::NROM128 { rom, .. } = &mut *mapper else {
-					unreachable!()
+			21 | 22 | 23 | 25 => Vrc4::new(header, prg, chr).map(|vrc| Box::new(Mapper::VRC4(vrc))),
+			24 | 26 => Vrc6::new(header, prg, chr).map(|vrc| Box::new(Mapper::VRC6(vrc))),
+			85 => Vrc7::new(header, prg, chr).map(|vrc| Box::new(Mapper::VRC7(vrc))),
+			19 => N163::new(header, prg, chr).map(|n163| Box::new(Mapper::N163(n163))),
+			69 => Fme7::new(header, prg, chr).map(|fme7| Box::new(Mapper::FME7(fme7))),
+			0 => {
+				if !matches!(prg.len(), 0x4000 | 0x8000) {
+					bail!("Wrong amount of prg_roms for an NROM");
+				}
+				if !matches!(chr.len(), 0 | 0x2000) {
+					bail!("Wrong amount of chr_roms for an NROM");
+				}
+
+				// No CHR ROM means the board has 8K of CHR RAM instead
+				let chr_is_ram = chr.is_empty();
+				let chr_mem = if chr_is_ram {
+					vec![0; 8 * 1024].into()
+				} else {
+					SharedMem::from(chr)
 				};
-				rom.copy_from_slice(&buffer[prg_offset..prg_offset + 16 * 1024]);
-				Ok(mapper)
-			}
-			0 if *prg_size == 2 => {
-				let mut mapper = Box::new(Mapper::NROM256 {
-					prg_ram: [0; _],
-					prg_rom: [0; _],
-					chr_rom: [0; _],
-				});
-				let Mapper::NROM256 { prg_rom: rom, .. } = &mut *mapper else {
-					unreachable!()
+
+				// Plain iNES headers always claim PRG RAM, as other emulators always provide it.
+				// Family BASIC needs it.
+				let prg_ram = (header.prg_ram_total() > 0 || header.trainer).then_some([0; _]);
+				let mirroring = header.mirroring;
+
+				let mapper = if prg.len() == 0x4000 {
+					Mapper::NROM128 {
+						prg_ram,
+						prg_rom: prg.into(),
+						chr: chr_mem,
+						chr_is_ram,
+						mirroring,
+					}
+				} else {
+					Mapper::NROM256 {
+						prg_ram,
+						prg_rom: prg.into(),
+						chr: chr_mem,
+						chr_is_ram,
+						mirroring,
+					}
 				};
-				rom.copy_from_slice(&buffer[prg_offset..prg_offset + 32 * 1024]);
-				Ok(mapper)
+				Ok(Box::new(mapper))
 			}
-			0 => bail!("Wrong amount of prg_roms for an NROM"),
-			_ => bail!("Unknown mapper type {mapper_type}"),
+			mapper_type => bail!("Unknown mapper type {mapper_type}"),
 		}
 	}
 }
 
 impl Mapper {
+	/// The cartridge's PRG RAM as mapped from $6000, if it has any.
+	pub fn prg_ram(&self) -> Option<&[u8]> {
+		match self {
+			Mapper::NROM128 { prg_ram, .. } | Mapper::NROM256 { prg_ram, .. } => {
+				prg_ram.as_ref().map(|ram| &ram[..])
+			}
+			Mapper::MMC5(mmc5) => mmc5.prg_ram(),
+			Mapper::VRC4(vrc) => vrc.prg_ram(),
+			Mapper::VRC6(vrc) => vrc.prg_ram(),
+			Mapper::VRC7(vrc) => vrc.prg_ram(),
+			Mapper::FME7(fme7) => fme7.prg_ram(),
+			Mapper::N163(n163) => n163.prg_ram(),
+			Mapper::MMC3 { .. } | Mapper::MMC4 | Mapper::Fds(_) | Mapper::Nsf(_) => None,
+		}
+	}
+
+	pub fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+		match self {
+			Mapper::NROM128 { prg_ram, .. } | Mapper::NROM256 { prg_ram, .. } => {
+				prg_ram.as_mut().map(|ram| &mut ram[..])
+			}
+			Mapper::MMC5(mmc5) => mmc5.prg_ram_mut(),
+			Mapper::VRC4(vrc) => vrc.prg_ram_mut(),
+			Mapper::VRC6(vrc) => vrc.prg_ram_mut(),
+			Mapper::VRC7(vrc) => vrc.prg_ram_mut(),
+			Mapper::FME7(fme7) => fme7.prg_ram_mut(),
+			Mapper::N163(n163) => n163.prg_ram_mut(),
+			Mapper::MMC3 { .. } | Mapper::MMC4 | Mapper::Fds(_) | Mapper::Nsf(_) => None,
+		}
+	}
+
+	pub fn nsf_mut(&mut self) -> Option<&mut NsfCart> {
+		match self {
+			Mapper::Nsf(cart) => Some(cart),
+			_ => None,
+		}
+	}
+
+	pub fn fds(&self) -> Option<&Fds> {
+		match self {
+			Mapper::Fds(fds) => Some(fds),
+			_ => None,
+		}
+	}
+
+	pub fn fds_mut(&mut self) -> Option<&mut Fds> {
+		match self {
+			Mapper::Fds(fds) => Some(fds),
+			_ => None,
+		}
+	}
+
+	/// Whether the cartridge is holding the IRQ line low.
+	pub fn irq(&self) -> bool {
+		match self {
+			Mapper::MMC5(mmc5) => mmc5.irq(),
+			Mapper::VRC4(vrc) => vrc.irq(),
+			Mapper::VRC6(vrc) => vrc.irq(),
+			Mapper::VRC7(vrc) => vrc.irq(),
+			Mapper::FME7(fme7) => fme7.irq(),
+			Mapper::N163(n163) => n163.irq(),
+			Mapper::Fds(fds) => fds.irq(),
+			_ => false,
+		}
+	}
+
+	/// Called once per CPU cycle, for mappers with timers or other things of their own going on.
+	pub fn tick_cpu(&mut self) {
+		match self {
+			Mapper::MMC5(mmc5) => mmc5.tick(),
+			Mapper::VRC4(vrc) => vrc.tick(),
+			Mapper::VRC6(vrc) => vrc.tick(),
+			Mapper::VRC7(vrc) => vrc.tick(),
+			Mapper::FME7(fme7) => fme7.tick(),
+			Mapper::N163(n163) => n163.tick(),
+			Mapper::Fds(fds) => fds.tick(),
+			Mapper::Nsf(cart) => cart.tick(),
+			_ => {}
+		}
+	}
+
+	/// The level of the cartridge's own sound hardware, on the scale where a 2A03 pulse channel
+	/// at full volume is 0.15.
+	pub fn audio_output(&self) -> f32 {
+		match self {
+			Mapper::VRC6(vrc) => vrc.audio.output(),
+			Mapper::VRC7(vrc) => vrc.audio_output(),
+			Mapper::FME7(fme7) => fme7.audio.output(),
+			Mapper::N163(n163) => n163.audio_output(),
+			Mapper::Fds(fds) => fds.audio.output(),
+			Mapper::Nsf(cart) => cart.audio_output(),
+			_ => 0.0,
+		}
+	}
+
+	/// Called for every read the PPU makes while rendering, for mappers that keep track of what
+	/// the PPU is drawing. Returns the byte read.
+	pub fn fetch_ppu(&mut self, adr: u16, kind: Fetch, ppu: &Ppu) -> u8 {
+		match self {
+			Mapper::MMC5(mmc5) => mmc5.fetch(adr, kind, ppu),
+			_ => self.get_ppu(adr, ppu).unwrap_or(adr as u8),
+		}
+	}
+
+	/// Flips to the next disk side on disk based systems, returning the side that's going in.
+	pub fn next_disk_side(&mut self) -> Option<usize> {
+		self.fds_mut().map(Fds::next_side)
+	}
+
+	/// Like `get_cpu`, but for reads the cartridge can see and react to.
+	pub fn read_cpu(&mut self, adr: u16) -> Option<u8> {
+		match self {
+			Mapper::MMC5(mmc5) => mmc5.read_cpu(adr),
+			Mapper::N163(n163) => n163.read_cpu(adr),
+			Mapper::Fds(fds) => fds.read_cpu(adr),
+			Mapper::Nsf(cart) => cart.read_cpu(adr),
+			_ => self.get_cpu(adr),
+		}
+	}
+
+	/// The byte at `offset` in PRG ROM, mapped in or not.
+	pub fn prg_rom_at(&self, offset: usize) -> Option<u8> {
+		let prg: &[u8] = match self {
+			Mapper::MMC3 { prg_rom, .. } => prg_rom,
+			Mapper::NROM128 { prg_rom, .. } => prg_rom,
+			Mapper::NROM256 { prg_rom, .. } => prg_rom,
+			Mapper::MMC5(mmc5) => mmc5.prg_rom(),
+			Mapper::VRC4(vrc) => vrc.prg_rom(),
+			Mapper::VRC6(vrc) => vrc.prg_rom(),
+			Mapper::VRC7(vrc) => vrc.prg_rom(),
+			Mapper::FME7(fme7) => fme7.prg_rom(),
+			Mapper::N163(n163) => n163.prg_rom(),
+			Mapper::Nsf(cart) => cart.prg_rom(),
+			Mapper::MMC4 | Mapper::Fds(_) => return None,
+		};
+		prg.get(offset).copied()
+	}
+
+	/// Which PRG ROM is mapped where right now.
+	pub fn prg_mapping(&self) -> PrgMapping {
+		std::array::from_fn(|i| self.prg_rom_offset(0x6000 + i as u16 * 0x1000))
+	}
+
+	/// Where in PRG ROM the byte the CPU sees at `adr` comes from, `None` for RAM, registers and
+	/// open bus. Two addresses with the same offset hold the same code.
+	pub fn prg_rom_offset(&self, adr: u16) -> Option<usize> {
+		match self {
+			Mapper::MMC3 {
+				prg_banks,
+				prg_mode: Mmc3PrgMode::Mode0,
+				..
+			} => {
+				let bank = match adr {
+					0x8000..=0x9FFF => prg_banks[0] as usize,
+					0xA000..=0xBFFF => prg_banks[1] as usize,
+					0xC000..=0xDFFF => 30,
+					0xE000..=0xFFFF => 31,
+					_ => return None,
+				};
+				Some(bank * 0x2000 + (adr as usize & 0x1FFF))
+			}
+			// Not readable through `get_cpu` yet either
+			Mapper::MMC3 {
+				prg_mode: Mmc3PrgMode::Mode1,
+				..
+			}
+			| Mapper::MMC4
+			| Mapper::Fds(_) => None,
+			Mapper::NROM128 { prg_rom, .. } => {
+				(adr >= 0x8000).then(|| adr as usize % prg_rom.len())
+			}
+			Mapper::NROM256 { prg_rom, .. } => {
+				(adr >= 0x8000).then(|| adr as usize % prg_rom.len())
+			}
+			Mapper::MMC5(mmc5) => mmc5.prg_rom_offset(adr),
+			Mapper::VRC4(vrc) => vrc.prg_rom_offset(adr),
+			Mapper::VRC6(vrc) => vrc.prg_rom_offset(adr),
+			Mapper::VRC7(vrc) => vrc.prg_rom_offset(adr),
+			Mapper::FME7(fme7) => fme7.prg_rom_offset(adr),
+			Mapper::N163(n163) => n163.prg_rom_offset(adr),
+			Mapper::Nsf(cart) => cart.prg_rom_offset(adr),
+		}
+	}
+
 	pub fn get_cpu(&self, adr: u16) -> Option<u8> {
 		if !(0x4020..=0xFFFF).contains(&adr) {
 			return None;
@@ -162,22 +776,23 @@ impl Mapper {
 		match self {
 			Mapper::MMC3 {
 				prg_banks,
-				prg_roms,
+				prg_rom,
 				prg_mode: Mmc3PrgMode::Mode0,
 				..
-			} => match adr {
-				0x8000..=0x9FFF => prg_roms[prg_banks[0] as usize]
-					.get((adr - 0x8000) as usize)
-					.copied(),
-				0xA000..=0xBFFF => prg_roms[prg_banks[1] as usize]
-					.get((adr - 0xA000) as usize)
-					.copied(),
-				0xC000..=0xDFFF => prg_roms[30].get((adr - 0xC000) as usize).copied(),
-				0xE000..=0xFFFF => prg_roms[31].get((adr - 0xE000) as usize).copied(),
-				_ => panic!(
-					"Out of bounds read from mapper (should probably be 0? But compare to existing emulators when this happens)"
-				),
-			},
+			} => {
+				let bank = match adr {
+					0x8000..=0x9FFF => prg_banks[0] as usize,
+					0xA000..=0xBFFF => prg_banks[1] as usize,
+					0xC000..=0xDFFF => 30,
+					0xE000..=0xFFFF => 31,
+					_ => panic!(
+						"Out of bounds read from mapper (should probably be 0? But compare to existing emulators when this happens)"
+					),
+				};
+				prg_rom
+					.get(bank * 0x2000 + (adr as usize & 0x1FFF))
+					.copied()
+			}
 			Mapper::MMC3 {
 				prg_mode: Mmc3PrgMode::Mode1,
 				..
@@ -191,20 +806,28 @@ impl Mapper {
 				),
 			},
 			Mapper::MMC4 => todo!(),
-			Mapper::NROM128 { ram, rom } => match adr {
-				0x6000..=0x7FFF => ram.get(adr as usize % ram.len()).copied(),
-				0x8000..=0xFFFF => rom.get((adr % 0x4000) as usize).copied(),
-				_ => panic!("Out of bounds read from mapper, check against actual emulators"),
+			Mapper::NROM128 {
+				prg_ram, prg_rom, ..
+			} => match adr {
+				0x6000..=0x7FFF => prg_ram.as_ref().map(|ram| ram[adr as usize % ram.len()]),
+				0x8000..=0xFFFF => prg_rom.get(adr as usize % prg_rom.len()).copied(),
+				_ => None,
 			},
 			Mapper::NROM256 {
-				prg_ram: ram,
-				prg_rom: rom,
-				..
+				prg_ram, prg_rom, ..
 			} => match adr {
-				0x6000..=0x7FFF => ram.get(adr as usize % ram.len()).copied(),
-				0x8000..=0xFFFF => rom.get((adr - 0x8000) as usize).copied(),
-				_ => panic!("Out of bounds read from mapper, check against actual emulators"),
+				0x6000..=0x7FFF => prg_ram.as_ref().map(|ram| ram[adr as usize % ram.len()]),
+				0x8000..=0xFFFF => prg_rom.get(adr as usize % prg_rom.len()).copied(),
+				_ => None,
 			},
+			Mapper::MMC5(mmc5) => mmc5.get_cpu(adr),
+			Mapper::VRC4(vrc) => vrc.get_cpu(adr),
+			Mapper::VRC6(vrc) => vrc.get_cpu(adr),
+			Mapper::VRC7(vrc) => vrc.get_cpu(adr),
+			Mapper::FME7(fme7) => fme7.get_cpu(adr),
+			Mapper::N163(n163) => n163.get_cpu(adr),
+			Mapper::Fds(fds) => fds.get_cpu(adr),
+			Mapper::Nsf(cart) => cart.get_cpu(adr),
 		}
 	}
 
@@ -212,22 +835,22 @@ impl Mapper {
 		match self {
 			Mapper::MMC3 { registers, .. } => {
 				match adr {
-					0x8000..=0x9FFF if adr % 2 == 0 => registers.h8000 = val,
+					0x8000..=0x9FFF if adr.is_multiple_of(2) => registers.h8000 = val,
 					0x8000..=0x9FFF if adr % 2 == 1 => {
 						registers.h8001 = val;
 						todo!("Update banks");
 					}
-					0xA000..=0xBFFF if adr % 2 == 0 => registers.hA000 = val,
+					0xA000..=0xBFFF if adr.is_multiple_of(2) => registers.hA000 = val,
 					0xA000..=0xBFFF if adr % 2 == 1 => {
 						registers.hA001 = val;
 						todo!("Update banks");
 					}
-					0xC000..=0xDFFF if adr % 2 == 0 => registers.hC000 = val,
+					0xC000..=0xDFFF if adr.is_multiple_of(2) => registers.hC000 = val,
 					0xC000..=0xDFFF if adr % 2 == 1 => {
 						registers.hC001 = val;
 						todo!("Update banks");
 					}
-					0xE000..=0xFFFF if adr % 2 == 0 => registers.hE000 = val,
+					0xE000..=0xFFFF if adr.is_multiple_of(2) => registers.hE000 = val,
 					0xE000..=0xFFFF if adr % 2 == 1 => {
 						registers.hE001 = val;
 						todo!("Update banks");
@@ -238,21 +861,45 @@ impl Mapper {
 				Some(())
 			}
 			Mapper::MMC4 => todo!(),
-			Mapper::NROM128 { .. } => match adr {
-				_ => todo!(),
-			},
-			Mapper::NROM256 {
-				prg_ram: ram,
-				prg_rom: rom,
-				..
-			} => {
-				match adr {
-					0x6000..=0x7FFF => ram[adr as usize % ram.len()] = val,
-					0x8000..=0xFFFF => todo!(),
-					_ => panic!("Out of bounds read from mapper, check against actual emulators"),
+			Mapper::MMC5(mmc5) => {
+				mmc5.set_cpu(adr, val);
+				Some(())
+			}
+			Mapper::VRC4(vrc) => {
+				vrc.set_cpu(adr, val);
+				Some(())
+			}
+			Mapper::VRC6(vrc) => {
+				vrc.set_cpu(adr, val);
+				Some(())
+			}
+			Mapper::VRC7(vrc) => {
+				vrc.set_cpu(adr, val);
+				Some(())
+			}
+			Mapper::FME7(fme7) => {
+				fme7.set_cpu(adr, val);
+				Some(())
+			}
+			Mapper::N163(n163) => {
+				n163.set_cpu(adr, val);
+				Some(())
+			}
+			Mapper::NROM128 { prg_ram, .. } | Mapper::NROM256 { prg_ram, .. } => {
+				// Writes to ROM, or to PRG RAM that isn't there, go nowhere
+				if let (0x6000..=0x7FFF, Some(ram)) = (adr, prg_ram) {
+					ram[adr as usize % ram.len()] = val;
 				}
 				Some(())
 			}
+			Mapper::Fds(fds) => {
+				fds.set_cpu(adr, val);
+				Some(())
+			}
+			Mapper::Nsf(cart) => {
+				cart.set_cpu(adr, val);
+				Some(())
+			}
 		}
 	}
 
@@ -275,22 +922,152 @@ impl Mapper {
 			//	0x3F00..=0x3FFF => ppu.palettes.get((adr & 0x1F) as usize).copied(),
 			//	_ => None,
 			// },
-			Mapper::NROM256 { chr_rom, .. } => match adr {
-				0x0000..=0x1FFF => chr_rom.get(adr as usize).copied(),
-				0x2000..=0x3EFF => ppu.vram.get(adr as usize & 0x07FF).copied(),
-				0x3F00..=0x3FFF => {
-					let palettes_raw = ppu.raw_palettes();
-					palettes_raw.get((adr & 0x1F) as usize).copied()
+			Mapper::NROM128 { chr, mirroring, .. } | Mapper::NROM256 { chr, mirroring, .. } => {
+				match adr {
+					0x0000..=0x1FFF => chr.get(adr as usize).copied(),
+					0x2000..=0x3EFF => ppu.vram.get(mirroring.vram_index(adr)).copied(),
+					0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
+					_ => None,
 				}
+			}
+			Mapper::Fds(fds) => match adr {
+				0x0000..=0x1FFF => fds.chr_ram.get(adr as usize).copied(),
+				0x2000..=0x3EFF => ppu.vram.get(fds.mirroring.vram_index(adr)).copied(),
+				0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
 				_ => None,
 			},
-
-			_ => None,
+			Mapper::MMC5(mmc5) => match adr {
+				0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
+				_ => mmc5.get_ppu(adr, ppu),
+			},
+			Mapper::VRC4(vrc) => match adr {
+				0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
+				_ => vrc.get_ppu(adr, ppu),
+			},
+			Mapper::VRC6(vrc) => match adr {
+				0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
+				_ => vrc.get_ppu(adr, ppu),
+			},
+			Mapper::VRC7(vrc) => match adr {
+				0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
+				_ => vrc.get_ppu(adr, ppu),
+			},
+			Mapper::FME7(fme7) => match adr {
+				0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
+				_ => fme7.get_ppu(adr, ppu),
+			},
+			Mapper::N163(n163) => match adr {
+				0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
+				_ => n163.get_ppu(adr, ppu),
+			},
+			Mapper::MMC3 { registers, .. } => match adr {
+				0x2000..=0x3EFF => ppu.vram.get(registers.mirroring().vram_index(adr)).copied(),
+				0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
+				_ => None,
+			},
+			Mapper::MMC4 => match adr {
+				0x3F00..=0x3FFF => ppu.raw_palettes().get(palette_index(adr)).copied(),
+				_ => None,
+			},
+			Mapper::Nsf(_) => None,
 		}
 	}
 
-	pub fn set_ppu(&mut self, _adr: u16, _val: u8) -> Option<()> {
-		todo!()
+	pub fn set_ppu(&mut self, adr: u16, val: u8, ppu: &mut Ppu) -> Option<()> {
+		match self {
+			Mapper::NROM128 {
+				chr,
+				chr_is_ram,
+				mirroring,
+				..
+			}
+			| Mapper::NROM256 {
+				chr,
+				chr_is_ram,
+				mirroring,
+				..
+			} => {
+				match adr {
+					0x0000..=0x1FFF if *chr_is_ram => chr[adr as usize] = val,
+					0x0000..=0x1FFF => {} // CHR ROM
+					0x2000..=0x3EFF => ppu.vram[mirroring.vram_index(adr)] = val,
+					0x3F00..=0x3FFF => ppu.set_raw_palette(palette_index(adr), val),
+					_ => return None,
+				}
+				Some(())
+			}
+			Mapper::Fds(fds) => {
+				match adr {
+					0x0000..=0x1FFF => fds.chr_ram[adr as usize] = val,
+					0x2000..=0x3EFF => ppu.vram[fds.mirroring.vram_index(adr)] = val,
+					0x3F00..=0x3FFF => ppu.set_raw_palette(palette_index(adr), val),
+					_ => return None,
+				}
+				Some(())
+			}
+			Mapper::MMC5(mmc5) => match adr {
+				0x3F00..=0x3FFF => {
+					ppu.set_raw_palette(palette_index(adr), val);
+					Some(())
+				}
+				_ => mmc5.set_ppu(adr, val, ppu),
+			},
+			Mapper::VRC4(vrc) => match adr {
+				0x3F00..=0x3FFF => {
+					ppu.set_raw_palette(palette_index(adr), val);
+					Some(())
+				}
+				_ => vrc.set_ppu(adr, val, ppu),
+			},
+			Mapper::VRC6(vrc) => match adr {
+				0x3F00..=0x3FFF => {
+					ppu.set_raw_palette(palette_index(adr), val);
+					Some(())
+				}
+				_ => vrc.set_ppu(adr, val, ppu),
+			},
+			Mapper::VRC7(vrc) => match adr {
+				0x3F00..=0x3FFF => {
+					ppu.set_raw_palette(palette_index(adr), val);
+					Some(())
+				}
+				_ => vrc.set_ppu(adr, val, ppu),
+			},
+			Mapper::FME7(fme7) => match adr {
+				0x3F00..=0x3FFF => {
+					ppu.set_raw_palette(palette_index(adr), val);
+					Some(())
+				}
+				_ => fme7.set_ppu(adr, val, ppu),
+			},
+			Mapper::N163(n163) => match adr {
+				0x3F00..=0x3FFF => {
+					ppu.set_raw_palette(palette_index(adr), val);
+					Some(())
+				}
+				_ => n163.set_ppu(adr, val, ppu),
+			},
+			// Neither keeps its CHR yet, so writes there go nowhere
+			Mapper::MMC3 { registers, .. } => {
+				match adr {
+					0x0000..=0x1FFF => {}
+					0x2000..=0x3EFF => ppu.vram[registers.mirroring().vram_index(adr)] = val,
+					0x3F00..=0x3FFF => ppu.set_raw_palette(palette_index(adr), val),
+					_ => return None,
+				}
+				Some(())
+			}
+			Mapper::MMC4 => {
+				match adr {
+					0x0000..=0x3EFF => {}
+					0x3F00..=0x3FFF => ppu.set_raw_palette(palette_index(adr), val),
+					_ => return None,
+				}
+				Some(())
+			}
+			// There's no picture to draw while playing music
+			Mapper::Nsf(_) => (adr <= 0x3FFF).then_some(()),
+		}
 	}
 }
 
@@ -315,4 +1092,212 @@ mod test {
 		let buffer = std::fs::read("non-free/FE1EN.nes").unwrap();
 		Mapper::parse_ines(buffer).unwrap();
 	}
+
+	fn nrom_image(prg_size: u8, chr_size: u8, flags_6: u8) -> Vec<u8> {
+		let mut buffer = vec![b'N', b'E', b'S', 0x1A, prg_size, chr_size, flags_6];
+		buffer.resize(16, 0);
+		for bank in 0..prg_size {
+			buffer.extend(std::iter::repeat_n(bank, 16 * 1024));
+		}
+		buffer.extend(std::iter::repeat_n(0xC5, chr_size as usize * 8 * 1024));
+		buffer
+	}
+
+	#[test]
+	fn trainer_is_loaded_into_prg_ram() {
+		let mut buffer = nrom_image(1, 1, 1 << 2);
+		let trainer = (0..512).map(|i| i as u8);
+		buffer.splice(16..16, trainer);
+
+		let mapper = Mapper::parse_ines(buffer).unwrap();
+		assert_eq!(mapper.get_cpu(0x6FFF), Some(0));
+		assert_eq!(mapper.get_cpu(0x7000), Some(0));
+		assert_eq!(mapper.get_cpu(0x7005), Some(5));
+		assert_eq!(mapper.get_cpu(0x71FF), Some(0xFF));
+		assert_eq!(mapper.get_cpu(0x8000), Some(0));
+	}
+
+	#[test]
+	fn junk_in_the_header_is_ignored() {
+		let mut disk_dude = nrom_image(1, 1, 0);
+		disk_dude[7..16].copy_from_slice(b"DiskDude!");
+		let rom = Mapper::load(&disk_dude).unwrap();
+		// 'D' in byte 7 would have made this mapper 64
+		assert_eq!(rom.header.mapper, 0);
+		assert_eq!(rom.header.junk, Some(*b"DiskDude!"));
+	}
+
+	#[test]
+	fn malformed_files_are_errors() {
+		assert!(Mapper::parse_ines(b"NES\x1A".to_vec()).is_err());
+
+		let mut truncated = nrom_image(2, 1, 0);
+		truncated.truncate(truncated.len() - 1);
+		assert!(Mapper::parse_ines(truncated).is_err());
+
+		let mut oversized = nrom_image(1, 1, 0);
+		oversized.extend([0; 16 * 1024]);
+		assert!(Mapper::parse_ines(oversized.clone()).is_err());
+		// Unless the rest is a NES 2.0 misc ROM
+		oversized[7] = 0x08;
+		oversized[14] = 1;
+		assert!(Mapper::parse_ines(oversized).is_ok());
+
+		// NES 2.0 with 2^63 bytes each of PRG and CHR ROM, which only overflow together
+		let mut huge = nrom_image(1, 1, 0);
+		huge[4] = 0xFC;
+		huge[5] = 0xFC;
+		huge[7] = 0x08;
+		huge[9] = 0xFF;
+		let err = Mapper::parse_ines(huge).unwrap_err();
+		assert!(err.to_string().contains("CHR ROM"));
+	}
+
+	fn unif_image(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+		let mut buffer = b"UNIF".to_vec();
+		buffer.extend(7u32.to_le_bytes());
+		buffer.resize(32, 0);
+		for (id, data) in chunks {
+			buffer.extend(*id);
+			buffer.extend((data.len() as u32).to_le_bytes());
+			buffer.extend(*data);
+		}
+		buffer
+	}
+
+	#[test]
+	fn unif_nrom() {
+		let prg0 = [0x11; 16 * 1024];
+		let prg1 = [0x22; 16 * 1024];
+		let chr0 = [0xC5; 8 * 1024];
+		// Chunks may come in any order
+		let buffer = unif_image(&[
+			(b"PRG1", &prg1),
+			(b"MAPR", b"NES-NROM-256\0"),
+			(b"NAME", b"Test\0"),
+			(b"CHR0", &chr0),
+			(b"PRG0", &prg0),
+			(b"MIRR", &[1]),
+			(b"BATR", &[1]),
+		]);
+
+		let rom = Mapper::load(&buffer).unwrap();
+		assert_eq!(rom.header.format, HeaderFormat::Unif);
+		assert_eq!(rom.header.mapper, 0);
+		assert!(rom.header.battery);
+
+		let mapper = rom.mapper;
+		let ppu = Ppu::default();
+		assert_eq!(mapper.get_cpu(0x8000), Some(0x11));
+		assert_eq!(mapper.get_cpu(0xC000), Some(0x22));
+		assert_eq!(mapper.get_ppu(0x0000, &ppu), Some(0xC5));
+		assert!(mapper.prg_ram().is_some());
+		assert!(matches!(
+			*mapper,
+			Mapper::NROM256 {
+				mirroring: Mirroring::Vertical,
+				..
+			}
+		));
+	}
+
+	#[test]
+	fn unif_errors() {
+		let prg0 = [0; 16 * 1024];
+		assert!(Mapper::parse_unif(unif_image(&[(b"PRG0", &prg0)])).is_err());
+
+		let unknown = unif_image(&[(b"MAPR", b"UNL-NOT-A-BOARD\0"), (b"PRG0", &prg0)]);
+		let err = Mapper::parse_unif(unknown).unwrap_err();
+		assert!(err.to_string().contains("UNL-NOT-A-BOARD"));
+
+		// MMC4 can't run anything yet, so its boards are turned away at load time
+		let mmc4 = unif_image(&[(b"MAPR", b"NES-FKROM\0"), (b"PRG0", &prg0)]);
+		let err = Mapper::parse_unif(mmc4).unwrap_err();
+		assert!(err.to_string().contains("Unsupported UNIF board NES-FKROM"));
+
+		let mut truncated = unif_image(&[(b"MAPR", b"NES-NROM-128\0"), (b"PRG0", &prg0)]);
+		truncated.pop();
+		assert!(Mapper::parse_unif(truncated).is_err());
+
+		assert!(Mapper::load(b"not a rom at all").is_err());
+	}
+
+	#[test]
+	fn nrom128_mirrors_prg_and_ignores_rom_writes() {
+		let mut mapper = Mapper::parse_ines(nrom_image(1, 1, 0)).unwrap();
+		let ppu = Ppu::default();
+
+		assert_eq!(mapper.get_cpu(0x8000), mapper.get_cpu(0xC000));
+		assert_eq!(mapper.get_ppu(0x1234, &ppu), Some(0xC5));
+
+		mapper.set_cpu(0xC000, 0xFF).unwrap();
+		assert_eq!(mapper.get_cpu(0xC000), Some(0));
+
+		mapper.set_cpu(0x6123, 0x42).unwrap();
+		assert_eq!(mapper.get_cpu(0x6123), Some(0x42));
+	}
+
+	#[test]
+	fn nrom256_chr_ram_and_mirroring() {
+		let mut mapper = Mapper::parse_ines(nrom_image(2, 0, 1)).unwrap();
+		let mut ppu = Ppu::default();
+
+		assert_eq!(mapper.get_cpu(0xC000), Some(1));
+
+		mapper.set_ppu(0x0010, 0x99, &mut ppu).unwrap();
+		assert_eq!(mapper.get_ppu(0x0010, &ppu), Some(0x99));
+
+		// Vertical mirroring: $2000 and $2800 are the same nametable
+		mapper.set_ppu(0x2005, 0x77, &mut ppu).unwrap();
+		assert_eq!(mapper.get_ppu(0x2805, &ppu), Some(0x77));
+		assert_eq!(mapper.get_ppu(0x2405, &ppu), Some(0));
+	}
+
+	#[test]
+	fn clones_share_rom_until_written() {
+		// Where PRG ROM and CHR RAM are
+		fn memory(mapper: &Mapper) -> (*const u8, *const u8) {
+			let Mapper::NROM256 { prg_rom, chr, .. } = mapper else {
+				unreachable!()
+			};
+			(prg_rom.as_ptr(), chr.as_ptr())
+		}
+
+		let mapper = Mapper::parse_ines(nrom_image(2, 0, 1)).unwrap();
+		let mut copy = mapper.clone();
+		let mut ppu = Ppu::default();
+		assert_eq!(memory(&mapper), memory(&copy));
+
+		// Writing CHR RAM gives the copy its own, PRG ROM stays shared
+		copy.set_ppu(0x0010, 0x99, &mut ppu).unwrap();
+		assert_eq!(mapper.get_ppu(0x0010, &ppu), Some(0));
+		assert_eq!(memory(&mapper).0, memory(&copy).0);
+		assert_ne!(memory(&mapper).1, memory(&copy).1);
+		assert_ne!(mapper, copy);
+	}
+
+	#[test]
+	fn palette_reads_back_as_written() {
+		let mut mapper = Mapper::parse_ines(nrom_image(1, 1, 0)).unwrap();
+		let mut ppu = Ppu::default();
+
+		for val in 0..=0xFF {
+			mapper.set_ppu(0x3F01, val, &mut ppu).unwrap();
+			assert_eq!(mapper.get_ppu(0x3F01, &ppu), Some(val & 0x3F));
+		}
+		// $3F10 is the backdrop at $3F00
+		mapper.set_ppu(0x3F10, 0x0D, &mut ppu).unwrap();
+		assert_eq!(mapper.get_ppu(0x3F00, &ppu), Some(0x0D));
+	}
+
+	#[test]
+	fn ppu_accesses_without_chr() {
+		let mut ppu = Ppu::default();
+
+		// Mappers that don't keep CHR still take palette writes
+		let mut mmc4 = Mapper::MMC4;
+		mmc4.set_ppu(0x1000, 0x12, &mut ppu).unwrap();
+		mmc4.set_ppu(0x3F02, 0x3E, &mut ppu).unwrap();
+		assert_eq!(mmc4.get_ppu(0x3F02, &ppu), Some(0x3E));
+	}
 }
@@ -0,0 +1,585 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+
+use crate::{
+	fme7::Sunsoft5b, interpret::State, n163::N163Audio, nes_file::SharedMem, vrc6::Vrc6Audio,
+	vrc7::Opll,
+};
+
+/// CPU cycles per second on an NTSC console.
+const NTSC_CPU_HZ: f64 = 1_789_773.0;
+const PAL_CPU_HZ: f64 = 1_662_607.0;
+
+/// Where INIT and PLAY return to. Nothing is mapped here, so no tune can jump here on its own.
+const RETURN_ADR: u16 = 0x4100;
+
+/// How long INIT gets before we give up on it returning.
+const INIT_TIMEOUT_CYCLES: u64 = 2 * NTSC_CPU_HZ as u64;
+
+/// The header (or NSFe chunks) of an NSF file, and the tune data.
+#[derive(Debug, Clone, Default)]
+pub struct NsfFile {
+	pub title: String,
+	pub artist: String,
+	pub copyright: String,
+	pub songs: u8,
+	/// Zero based, unlike in the NSF header.
+	pub starting_song: u8,
+	pub load_adr: u16,
+	pub init_adr: u16,
+	pub play_adr: u16,
+	/// The initial banks for $8000-$FFFF, all zero if the tune doesn't bankswitch.
+	pub bank_init: [u8; 8],
+	/// Microseconds between PLAY calls.
+	pub play_speed_ntsc: u16,
+	pub play_speed_pal: u16,
+	pub pal: bool,
+	/// Expansion audio the tune expects, as a bitfield of VRC6, VRC7, FDS, MMC5, N163 and 5B.
+	pub extra_chips: u8,
+	/// Per track titles, only NSFe has these.
+	pub track_labels: Vec<String>,
+	pub data: Vec<u8>,
+}
+
+pub fn is_nsf(buffer: &[u8]) -> bool {
+	buffer.starts_with(b"NESM\x1A") || buffer.starts_with(b"NSFE")
+}
+
+/// A string from a fixed size, NUL padded header field or chunk.
+fn c_string(bytes: &[u8]) -> String {
+	let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+	String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+impl NsfFile {
+	pub fn parse(buffer: &[u8]) -> Result<Self> {
+		if buffer.starts_with(b"NESM\x1A") {
+			Self::parse_nsf(buffer)
+		} else if buffer.starts_with(b"NSFE") {
+			Self::parse_nsfe(buffer)
+		} else {
+			bail!("Not an NSF file, it doesn't start with \"NESM\\x1A\" or \"NSFE\"");
+		}
+	}
+
+	fn parse_nsf(buffer: &[u8]) -> Result<Self> {
+		let Some((header, data)) = buffer.split_first_chunk::<0x80>() else {
+			bail!(
+				"File is too short to be an NSF ({} bytes, the header alone is 128)",
+				buffer.len()
+			);
+		};
+		let word = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+
+		// NSF2 can say how long the data is, anything after that is metadata we don't use
+		let data_len = u32::from_le_bytes([header[0x7D], header[0x7E], header[0x7F], 0]) as usize;
+		let data = match data_len {
+			0 => data,
+			len => data
+				.get(..len)
+				.context("NSF data is shorter than the header says")?,
+		};
+
+		Ok(Self {
+			title: c_string(&header[0x0E..0x2E]),
+			artist: c_string(&header[0x2E..0x4E]),
+			copyright: c_string(&header[0x4E..0x6E]),
+			songs: header[0x06],
+			starting_song: header[0x07].saturating_sub(1),
+			load_adr: word(0x08),
+			init_adr: word(0x0A),
+			play_adr: word(0x0C),
+			bank_init: header[0x70..0x78].try_into().unwrap(),
+			play_speed_ntsc: word(0x6E),
+			play_speed_pal: word(0x78),
+			pal: header[0x7A] & 0b11 == 0b01,
+			extra_chips: header[0x7B],
+			track_labels: Vec::new(),
+			data: data.to_vec(),
+		})
+	}
+
+	fn parse_nsfe(buffer: &[u8]) -> Result<Self> {
+		let mut nsf = Self {
+			play_speed_ntsc: 16639,
+			play_speed_pal: 19997,
+			..Default::default()
+		};
+		let mut has_info = false;
+		let mut rest = &buffer[4..];
+
+		loop {
+			let Some((len, after_len)) = rest.split_first_chunk::<4>() else {
+				bail!("NSFe file ends without an NEND chunk");
+			};
+			let Some((id, after_id)) = after_len.split_first_chunk::<4>() else {
+				bail!("NSFe file ends without an NEND chunk");
+			};
+			let len = u32::from_le_bytes(*len) as usize;
+			let Some((chunk, next)) = after_id.split_at_checked(len) else {
+				bail!(
+					"NSFe chunk {} claims {len} bytes but only {} are left in the file",
+					String::from_utf8_lossy(id),
+					after_id.len()
+				);
+			};
+			rest = next;
+			let word = |i: usize| {
+				chunk
+					.get(i..i + 2)
+					.map(|w| u16::from_le_bytes([w[0], w[1]]))
+			};
+
+			match id {
+				b"INFO" => {
+					let (Some(load_adr), Some(init_adr), Some(play_adr)) =
+						(word(0), word(2), word(4))
+					else {
+						bail!("NSFe INFO chunk is too short");
+					};
+					nsf.load_adr = load_adr;
+					nsf.init_adr = init_adr;
+					nsf.play_adr = play_adr;
+					nsf.pal = chunk.get(6).is_some_and(|flags| flags & 0b11 == 0b01);
+					nsf.extra_chips = chunk.get(7).copied().unwrap_or(0);
+					nsf.songs = chunk.get(8).copied().unwrap_or(1);
+					nsf.starting_song = chunk.get(9).copied().unwrap_or(0);
+					has_info = true;
+				}
+				b"DATA" => nsf.data = chunk.to_vec(),
+				b"BANK" => {
+					for (dst, src) in nsf.bank_init.iter_mut().zip(chunk) {
+						*dst = *src;
+					}
+				}
+				b"RATE" => {
+					nsf.play_speed_ntsc = word(0).unwrap_or(nsf.play_speed_ntsc);
+					nsf.play_speed_pal = word(2).unwrap_or(nsf.play_speed_pal);
+				}
+				b"auth" => {
+					let mut strings = chunk.split(|&b| b == 0).map(c_string);
+					nsf.title = strings.next().unwrap_or_default();
+					nsf.artist = strings.next().unwrap_or_default();
+					nsf.copyright = strings.next().unwrap_or_default();
+				}
+				b"tlbl" => {
+					nsf.track_labels = chunk
+						.split(|&b| b == 0)
+						.map(c_string)
+						.take(nsf.songs as usize)
+						.collect();
+				}
+				b"NEND" => break,
+				// Chunks starting with an upper case letter must be understood to play the tune
+				[b'A'..=b'Z', ..] => {
+					bail!("Unsupported NSFe chunk {}", String::from_utf8_lossy(id))
+				}
+				_ => {}
+			}
+		}
+
+		if !has_info || nsf.data.is_empty() {
+			bail!("NSFe file is missing its INFO or DATA chunk");
+		}
+		Ok(nsf)
+	}
+
+	fn bankswitched(&self) -> bool {
+		self.bank_init != [0; 8]
+	}
+
+	/// Microseconds between PLAY calls, with a sensible default for files that leave it out.
+	fn play_period_us(&self) -> u16 {
+		match (self.pal, self.play_speed_ntsc, self.play_speed_pal) {
+			(false, 0, _) => 16639,
+			(false, speed, _) => speed,
+			(true, _, 0) => 19997,
+			(true, _, speed) => speed,
+		}
+	}
+
+	/// The window title for a track: its number, then the title and artist.
+	pub fn caption(&self, track: u8) -> String {
+		let mut caption = format!("{}/{}", track + 1, self.songs);
+		match self.track_labels.get(track as usize) {
+			Some(label) if !label.is_empty() => caption += &format!(" {label} - {}", self.title),
+			_ => caption += &format!(" {}", self.title),
+		}
+		if !self.artist.is_empty() {
+			caption += &format!(" - {}", self.artist);
+		}
+		caption
+	}
+}
+
+/// The synthetic cartridge NSF tunes run on: 8K of RAM at $6000 and the tune in 4K banks
+/// at $8000, switched through $5FF8-$5FFF. Plus whichever expansion sound chips the tune asks
+/// for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NsfCart {
+	prg: SharedMem,
+	banks: [u8; 8],
+	bank_init: [u8; 8],
+	pub prg_ram: [u8; 8 * 1024],
+	vrc6: Option<Vrc6Audio>,
+	vrc7: Option<Opll>,
+	n163: Option<N163Audio>,
+	s5b: Option<Sunsoft5b>,
+}
+
+impl NsfCart {
+	pub fn new(nsf: &NsfFile) -> Result<Self> {
+		// Bankswitched tunes are laid out from the start of a bank, the rest are loaded at their
+		// load address in a flat 32K
+		let (padding, bank_init) = if nsf.bankswitched() {
+			((nsf.load_adr & 0x0FFF) as usize, nsf.bank_init)
+		} else {
+			let Some(padding) = nsf.load_adr.checked_sub(0x8000) else {
+				bail!("NSF load address {:04X} is below $8000", nsf.load_adr);
+			};
+			if padding as usize + nsf.data.len() > 32 * 1024 {
+				bail!("NSF data doesn't fit in 32K and the tune doesn't bankswitch");
+			}
+			(padding as usize, [0, 1, 2, 3, 4, 5, 6, 7])
+		};
+
+		let mut prg = vec![0; padding];
+		prg.extend_from_slice(&nsf.data);
+		prg.resize(prg.len().next_multiple_of(4 * 1024).max(32 * 1024), 0);
+
+		Ok(Self {
+			prg: prg.into(),
+			banks: bank_init,
+			bank_init,
+			prg_ram: [0; _],
+			vrc6: (nsf.extra_chips & 0b01 != 0).then(Vrc6Audio::default),
+			vrc7: (nsf.extra_chips & 0b10 != 0).then(Opll::default),
+			n163: (nsf.extra_chips & 0b1_0000 != 0).then(N163Audio::default),
+			s5b: (nsf.extra_chips & 0b10_0000 != 0).then(Sunsoft5b::default),
+		})
+	}
+
+	/// Back to the state INIT expects: empty RAM and the initial banks.
+	pub fn reset(&mut self) {
+		self.banks = self.bank_init;
+		self.prg_ram = [0; _];
+		if let Some(vrc6) = &mut self.vrc6 {
+			*vrc6 = Vrc6Audio::default();
+		}
+		if let Some(vrc7) = &mut self.vrc7 {
+			*vrc7 = Opll::default();
+		}
+		if let Some(n163) = &mut self.n163 {
+			*n163 = N163Audio::default();
+		}
+		if let Some(s5b) = &mut self.s5b {
+			*s5b = Sunsoft5b::default();
+		}
+	}
+
+	pub fn tick(&mut self) {
+		if let Some(vrc6) = &mut self.vrc6 {
+			vrc6.tick();
+		}
+		if let Some(vrc7) = &mut self.vrc7 {
+			vrc7.tick();
+		}
+		if let Some(n163) = &mut self.n163 {
+			n163.tick();
+		}
+		if let Some(s5b) = &mut self.s5b {
+			s5b.tick();
+		}
+	}
+
+	pub fn audio_output(&self) -> f32 {
+		self.vrc6.as_ref().map_or(0.0, Vrc6Audio::output)
+			+ self.vrc7.as_ref().map_or(0.0, Opll::output)
+			+ self.n163.as_ref().map_or(0.0, N163Audio::output)
+			+ self.s5b.as_ref().map_or(0.0, Sunsoft5b::output)
+	}
+
+	pub fn prg_rom(&self) -> &[u8] {
+		&self.prg
+	}
+
+	/// Where in the tune's data the byte at `adr` comes from.
+	pub fn prg_rom_offset(&self, adr: u16) -> Option<usize> {
+		if adr < 0x8000 {
+			return None;
+		}
+		let bank = self.banks[(adr as usize - 0x8000) / 0x1000] as usize;
+		let bank_count = self.prg.len() / 0x1000;
+		Some((bank % bank_count) * 0x1000 + (adr as usize & 0x0FFF))
+	}
+
+	pub fn get_cpu(&self, adr: u16) -> Option<u8> {
+		match adr {
+			0x4800..=0x4FFF => self.n163.as_ref().map(N163Audio::read),
+			0x6000..=0x7FFF => Some(self.prg_ram[adr as usize - 0x6000]),
+			_ => self.prg_rom_offset(adr).map(|i| self.prg[i]),
+		}
+	}
+
+	/// Like `get_cpu`, but reading the N163's sound RAM moves its address along.
+	pub fn read_cpu(&mut self, adr: u16) -> Option<u8> {
+		match adr {
+			0x4800..=0x4FFF => self.n163.as_mut().map(N163Audio::read_increment),
+			_ => self.get_cpu(adr),
+		}
+	}
+
+	pub fn set_cpu(&mut self, adr: u16, val: u8) {
+		match adr {
+			0x5FF8..=0x5FFF => self.banks[adr as usize - 0x5FF8] = val,
+			0x6000..=0x7FFF => self.prg_ram[adr as usize - 0x6000] = val,
+			// The tune writes straight to the chips, with none of the cartridges' pin swapping
+			0x9010 if let Some(vrc7) = &mut self.vrc7 => vrc7.select(val),
+			0x9030 if let Some(vrc7) = &mut self.vrc7 => vrc7.write(val),
+			0x9000..=0xB002 if let Some(vrc6) = &mut self.vrc6 => vrc6.write(adr, val),
+			0x4800..=0x4FFF if let Some(n163) = &mut self.n163 => n163.write(val),
+			0xC000..=0xDFFF if let Some(s5b) = &mut self.s5b => s5b.select(val),
+			_ => {}
+		}
+
+		// The 5B's data port and the N163's address port overlap, and tunes using both rely
+		// on each chip ignoring whatever is meant for the other
+		if let (0xE000..=0xFFFF, Some(s5b)) = (adr, &mut self.s5b) {
+			s5b.write(val);
+		}
+		if let (0xF800..=0xFFFF, Some(n163)) = (adr, &mut self.n163) {
+			n163.select(val);
+		}
+	}
+}
+
+/// Drives a tune through the CPU: INIT once per track, then PLAY at the tune's rate.
+#[derive(Debug)]
+pub struct Player {
+	pub nsf: NsfFile,
+	pub track: u8,
+	/// CPU cycles between PLAY calls.
+	pub play_period: u32,
+}
+
+impl Player {
+	pub fn new(nsf: NsfFile) -> Self {
+		let cpu_hz = if nsf.pal { PAL_CPU_HZ } else { NTSC_CPU_HZ };
+		let play_period = (nsf.play_period_us() as f64 * cpu_hz / 1_000_000.0) as u32;
+		Self {
+			track: nsf.starting_song.min(nsf.songs.saturating_sub(1)),
+			nsf,
+			play_period,
+		}
+	}
+
+	/// Real time between PLAY calls.
+	pub fn play_interval(&self) -> Duration {
+		Duration::from_micros(self.nsf.play_period_us() as u64)
+	}
+
+	pub fn caption(&self) -> String {
+		self.nsf.caption(self.track)
+	}
+
+	pub fn next_track(&mut self) {
+		self.track = (self.track + 1) % self.nsf.songs.max(1);
+	}
+
+	pub fn previous_track(&mut self) {
+		self.track = self
+			.track
+			.checked_sub(1)
+			.unwrap_or(self.nsf.songs.saturating_sub(1));
+	}
+
+	/// Resets everything and runs INIT for the current track.
+	pub fn init(&self, state: &mut State) {
+		state.ram = [0; _];
+		if let Some(cart) = state.rom.nsf_mut() {
+			cart.reset();
+		}
+		for adr in 0x4000..=0x4013 {
+			state.set_mem(adr, 0);
+		}
+		state.set_mem(0x4015, 0x00);
+		state.set_mem(0x4015, 0x0F);
+		state.set_mem(0x4017, 0x40);
+
+		state.cpu.jammed = false;
+		state.cpu.s = 0xFD;
+		state.cpu.p.set_i(true);
+		state.cpu.a = self.track;
+		state.cpu.x = self.nsf.pal as u8;
+		state.cpu.y = 0;
+		if !call(state, self.nsf.init_adr, INIT_TIMEOUT_CYCLES) {
+			eprintln!(
+				"INIT for track {} didn't return in time, playing anyway",
+				self.track + 1
+			);
+		}
+	}
+
+	/// Runs PLAY, then idles for the rest of the period. Returns the cycles spent.
+	pub fn play(&self, state: &mut State) -> u64 {
+		let start = state.cycles;
+		call(state, self.nsf.play_adr, self.play_period as u64);
+		let spent = state.cycles - start;
+		if let Some(rest) = (self.play_period as u64).checked_sub(spent) {
+			state.idle(rest as u32);
+		}
+		state.cycles - start
+	}
+}
+
+/// JSRs to `adr` and runs until it returns, or `timeout` cycles have passed. Returns whether
+/// it returned. Either way the stack is left as it was, so routines that never return don't
+/// use it up a call at a time.
+fn call(state: &mut State, adr: u16, timeout: u64) -> bool {
+	let s = state.cpu.s;
+	let [lo, hi] = (RETURN_ADR - 1).to_le_bytes();
+	state.push(hi);
+	state.push(lo);
+	state.cpu.pc = adr;
+
+	let start = state.cycles;
+	while state.cpu.pc != RETURN_ADR {
+		if state.cycles - start >= timeout {
+			state.cpu.s = s;
+			return false;
+		}
+		state.next();
+	}
+	true
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{audio, drawing, nes_file::Mapper};
+
+	/// A tune at $8000 whose INIT stores the track number at $0200 and whose PLAY counts calls
+	/// at $6000.
+	fn nsf_image() -> Vec<u8> {
+		let mut buffer = b"NESM\x1A\x01\x03\x02".to_vec();
+		buffer.extend([0x00, 0x80, 0x00, 0x80, 0x10, 0x80]);
+		buffer.extend(b"Test Tune");
+		buffer.resize(0x2E, 0);
+		buffer.extend(b"Somebody");
+		buffer.resize(0x6E, 0);
+		buffer.extend(16639u16.to_le_bytes());
+		buffer.resize(0x80, 0);
+
+		#[rustfmt::skip]
+		let code = [
+			// INIT: STA $0200, RTS
+			0x8D, 0x00, 0x02, 0x60, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+			// PLAY: LDA $6000, CLC, ADC #1, STA $6000, RTS
+			0xAD, 0x00, 0x60, 0x18, 0x69, 0x01, 0x8D, 0x00, 0x60, 0x60,
+		];
+		buffer.extend(code);
+		buffer
+	}
+
+	#[test]
+	fn parse_nsf() {
+		let nsf = NsfFile::parse(&nsf_image()).unwrap();
+		assert_eq!(nsf.title, "Test Tune");
+		assert_eq!(nsf.artist, "Somebody");
+		assert_eq!(nsf.songs, 3);
+		assert_eq!(nsf.starting_song, 1);
+		assert_eq!(nsf.init_adr, 0x8000);
+		assert_eq!(nsf.play_adr, 0x8010);
+		assert!(!nsf.bankswitched());
+		assert_eq!(nsf.caption(1), "2/3 Test Tune - Somebody");
+	}
+
+	#[test]
+	fn parse_nsfe() {
+		let mut buffer = b"NSFE".to_vec();
+		let mut chunk = |id: &[u8; 4], data: &[u8]| {
+			buffer.extend((data.len() as u32).to_le_bytes());
+			buffer.extend(id);
+			buffer.extend(data);
+		};
+		chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x10, 0x80, 0, 0, 2, 0]);
+		chunk(b"DATA", &[0x60; 32]);
+		chunk(b"BANK", &[0, 1]);
+		chunk(b"auth", b"Tune\0Artist\0(C)\0Ripper\0");
+		chunk(b"tlbl", b"Intro\0Boss\0");
+		chunk(b"text", b"Ignored, it's lower case");
+		chunk(b"NEND", &[]);
+
+		let nsf = NsfFile::parse(&buffer).unwrap();
+		assert_eq!(nsf.songs, 2);
+		assert_eq!(nsf.bank_init, [0, 1, 0, 0, 0, 0, 0, 0]);
+		assert_eq!(nsf.caption(1), "2/2 Boss - Tune - Artist");
+
+		let mut unknown = buffer.clone();
+		unknown.splice(4..4, [0, 0, 0, 0, b'X', b'Y', b'Z', b'W']);
+		assert!(NsfFile::parse(&unknown).is_err());
+	}
+
+	#[test]
+	fn bankswitching() {
+		let nsf = NsfFile {
+			load_adr: 0x8010,
+			bank_init: [0, 1, 2, 3, 4, 5, 6, 7],
+			data: (0..0x2000).map(|i| (i / 0x1000) as u8 + 1).collect(),
+			..Default::default()
+		};
+		let mut cart = NsfCart::new(&nsf).unwrap();
+		assert_eq!(cart.get_cpu(0x8010), Some(1));
+		assert_eq!(cart.get_cpu(0x9010), Some(2));
+
+		cart.set_cpu(0x5FF8, 1);
+		assert_eq!(cart.get_cpu(0x8010), Some(2));
+		cart.reset();
+		assert_eq!(cart.get_cpu(0x8010), Some(1));
+	}
+
+	#[test]
+	fn init_and_play() {
+		let nsf = NsfFile::parse(&nsf_image()).unwrap();
+		let cart = NsfCart::new(&nsf).unwrap();
+		let mut state = State::new(
+			Box::new(Mapper::Nsf(cart)),
+			drawing::new_bitmap(),
+			audio::new_queue(),
+		);
+		let mut player = Player::new(nsf);
+
+		player.next_track();
+		player.init(&mut state);
+		assert_eq!(state.ram[0x200], 2);
+
+		for _ in 0..3 {
+			assert_eq!(player.play(&mut state), player.play_period as u64);
+		}
+		assert_eq!(state.rom.get_cpu(0x6000), Some(3));
+	}
+
+	#[test]
+	fn play_that_never_returns() {
+		// PLAY at $801A is JMP $801A
+		let mut image = nsf_image();
+		image[0x0C..0x0E].copy_from_slice(&0x801Au16.to_le_bytes());
+		image.extend([0x4C, 0x1A, 0x80]);
+		let nsf = NsfFile::parse(&image).unwrap();
+		let cart = NsfCart::new(&nsf).unwrap();
+		let mut state = State::new(
+			Box::new(Mapper::Nsf(cart)),
+			drawing::new_bitmap(),
+			audio::new_queue(),
+		);
+		let player = Player::new(nsf);
+
+		player.init(&mut state);
+		let s = state.cpu.s;
+		for _ in 0..3 {
+			player.play(&mut state);
+			assert_eq!(state.cpu.s, s);
+		}
+	}
+}
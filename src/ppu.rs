@@ -1,7 +1,7 @@
 #![allow(dead_code, unused)]
 
 use bitfields::bitfield;
-use bytemuck::{Pod, Zeroable};
+use bytemuck::{CheckedBitPattern, Pod, Zeroable};
 use derive_more::derive::Into;
 
 use crate::drawing::Colour;
@@ -14,8 +14,12 @@ pub struct Ppu {
 	pub status: Status,
 	pub oam_adr: u8,
 	pub oam_data: u8,
-	pub scroll: Scroll,
-	pub adr: Adr,
+	/// Where drawing starts from, set through $2000, $2005 and $2006 and copied into `adr` as each
+	/// line and frame begin. Called `t` on the nesdev wiki.
+	pub scroll: VramAdr,
+	/// Where the PPU is fetching from, which it walks across and down the screen while rendering.
+	/// Called `v` on the nesdev wiki.
+	pub adr: VramAdr,
 	pub data: u8,
 
 	pub scanline: u16,
@@ -26,6 +30,12 @@ pub struct Ppu {
 	pub oam: Oam,
 
 	pub palettes: Palettes,
+	/// The nametable byte of the background tile being fetched.
+	pub next_tile: u8,
+	/// The pixel within the first tile that a line starts drawing from.
+	pub fine_x: u8,
+	/// Whether the next $2005 or $2006 write is the second of its pair.
+	pub write_latch: bool,
 }
 
 impl Default for Ppu {
@@ -46,11 +56,125 @@ impl Default for Ppu {
 			vram: [0; _],
 			oam: Oam::zeroed(),
 			palettes: [Palette([NesColour::DarkGrey; 4]); 8],
+			next_tile: 0,
+			fine_x: 0,
+			write_latch: false,
 		}
 	}
 }
 
+/// What the PPU is reading from the cartridge, for mappers that watch its fetches.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fetch {
+	Nametable,
+	Attribute,
+	Background,
+	/// The nametable reads made in between sprite fetches, which go unused.
+	Garbage,
+	Sprite,
+}
+
 impl Ppu {
+	pub fn rendering(&self) -> bool {
+		self.mask.show_bg() || self.mask.show_spr()
+	}
+
+	/// The cartridge read the PPU makes on the current dot, if any. Every fetch takes two dots,
+	/// this puts it on the first.
+	pub fn fetch(&self) -> Option<(u16, Fetch)> {
+		if !self.rendering() || !(self.scanline < 240 || self.scanline == 261) {
+			return None;
+		}
+
+		let phase = (self.dot.wrapping_sub(1)) % 8;
+		match self.dot {
+			// Tiles 0 and 1 of a line are fetched at the end of the line before
+			1..=256 | 321..=336 => match phase {
+				0 => Some((self.nametable_adr(), Fetch::Nametable)),
+				2 => Some((self.attribute_adr(), Fetch::Attribute)),
+				4 | 6 => {
+					let table = self.ctrl.background_table() as u16 * 0x1000;
+					let plane = if phase == 6 { 8 } else { 0 };
+					let fine_y = self.adr.get() >> 12;
+					let adr = table | ((self.next_tile as u16) << 4) | plane | fine_y;
+					Some((adr, Fetch::Background))
+				}
+				_ => None,
+			},
+			257..=320 => match phase {
+				0 | 2 => Some((self.nametable_adr(), Fetch::Garbage)),
+				4 | 6 => {
+					let slot = (self.dot - 257) / 8;
+					let plane = if phase == 6 { 8 } else { 0 };
+					Some((self.sprite_pattern_adr(slot) | plane, Fetch::Sprite))
+				}
+				_ => None,
+			},
+			// Two more reads of the next tile, which MMC5 uses to spot new scanlines
+			337 | 339 => Some((self.nametable_adr(), Fetch::Nametable)),
+			_ => None,
+		}
+	}
+
+	/// Moves `adr` on once the current dot's fetch is done: a tile right after every background
+	/// tile, a line down at the end of the visible part of the line, and back to `scroll`
+	/// horizontally after that and vertically on the pre-render line.
+	pub fn update_adr(&mut self) {
+		if !self.rendering() || !(self.scanline < 240 || self.scanline == 261) {
+			return;
+		}
+
+		let adr = self.adr.get();
+		let scroll = self.scroll.get();
+		match self.dot {
+			256 => self.adr.set(increment_y(increment_x(adr))),
+			257 => self.adr.set(adr & !0x041F | scroll & 0x041F),
+			280..=304 if self.scanline == 261 => self.adr.set(adr & !0x7BE0 | scroll & 0x7BE0),
+			8..=255 | 328 | 336 if self.dot.is_multiple_of(8) => self.adr.set(increment_x(adr)),
+			_ => {}
+		}
+	}
+
+	/// Where the nametable byte of the tile at `adr` is.
+	fn nametable_adr(&self) -> u16 {
+		0x2000 | (self.adr.get() & 0x0FFF)
+	}
+
+	/// Where the attribute byte covering the tile at `adr` is.
+	fn attribute_adr(&self) -> u16 {
+		let adr = self.adr.get();
+		0x23C0 | (adr & 0x0C00) | ((adr >> 4) & 0x38) | ((adr >> 2) & 0x07)
+	}
+
+	/// The low plane of the `slot`th sprite on the next line, or of tile $FF for empty slots.
+	fn sprite_pattern_adr(&self, slot: u16) -> u16 {
+		let height = self.sprite_width();
+		let sprite = (self.scanline < 240)
+			.then(|| {
+				self.oam
+					.iter()
+					.filter(|sprite| self.scanline.wrapping_sub(sprite.y as u16) < height)
+					.nth(slot as usize)
+			})
+			.flatten();
+
+		let (tile, mut row) = match sprite {
+			Some(sprite) => (sprite.tile as u16, self.scanline - sprite.y as u16),
+			None => (0xFF, 0),
+		};
+		if sprite.is_some_and(|sprite| sprite.attr.flip_v()) {
+			row = height - 1 - row;
+		}
+
+		if self.ctrl.sprite_size() {
+			let table = (tile & 1) * 0x1000;
+			table | (((tile & 0xFE) + row / 8) << 4) | (row % 8)
+		} else {
+			let table = self.ctrl.sprite_table() as u16 * 0x1000;
+			table | tile << 4 | row
+		}
+	}
+
 	pub fn sprite_is_visible_x(&self, sprite: &Sprite) -> bool {
 		self.dot <= sprite.x as u16 && (sprite.x as u16) < self.dot + self.sprite_width()
 	}
@@ -71,8 +195,14 @@ impl Ppu {
 		NesColour::White.into()
 	}
 
-	pub fn raw_palettes(&self) -> &[u8; 64] {
-		unsafe { std::mem::transmute::<&[Palette; 8], &[u8; 64]>(&self.palettes) }
+	pub fn raw_palettes(&self) -> &[u8; 32] {
+		unsafe { std::mem::transmute::<&[Palette; 8], &[u8; 32]>(&self.palettes) }
+	}
+
+	/// Writes a palette RAM entry. Palette RAM is 6 bits wide, so it reads back as written minus
+	/// the top two bits.
+	pub fn set_raw_palette(&mut self, idx: usize, val: u8) {
+		self.palettes[idx / 4].0[idx % 4] = bytemuck::checked::cast::<u8, NesColour>(val & 0x3F);
 	}
 }
 
@@ -86,10 +216,10 @@ pub struct Ctrl {
 	#[bits(1)]
 	nametable_1: bool,
 	#[bits(1)]
-	_unused: bool,
-	#[bits(1)]
 	vram_increment: bool,
 	#[bits(1)]
+	sprite_table: bool,
+	#[bits(1)]
 	background_table: bool,
 	#[bits(1)]
 	sprite_size: bool,
@@ -133,18 +263,46 @@ pub struct Status {
 	vblank: bool,
 }
 
+/// A 15 bit VRAM address laid out the way the PPU scrolls: coarse X in bits 0-4, coarse Y in
+/// 5-9, the nametable in 10-11 and fine Y in 12-14. Split into bytes so `Ppu` has no padding.
 #[repr(C)]
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
-pub struct Scroll {
-	pub x: u8,
-	pub y: u8,
+pub struct VramAdr {
+	low: u8,
+	high: u8,
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
-pub struct Adr {
-	high: u8,
-	low: u8,
+impl VramAdr {
+	pub fn get(self) -> u16 {
+		u16::from_le_bytes([self.low, self.high])
+	}
+
+	pub fn set(&mut self, adr: u16) {
+		[self.low, self.high] = (adr & 0x7FFF).to_le_bytes();
+	}
+}
+
+/// Moves a VRAM address one tile right, into the next nametable across after the 32nd.
+fn increment_x(adr: u16) -> u16 {
+	if adr & 0x001F == 31 {
+		(adr & !0x001F) ^ 0x0400
+	} else {
+		adr + 1
+	}
+}
+
+/// Moves a VRAM address one pixel down, into the next nametable down after row 29. Rows 30 and
+/// 31 hold attributes, and wrap around to row 0 without switching nametable.
+fn increment_y(adr: u16) -> u16 {
+	if adr & 0x7000 != 0x7000 {
+		return adr + 0x1000;
+	}
+	let adr = adr & !0x7000;
+	match (adr & 0x03E0) >> 5 {
+		29 => (adr & !0x03E0) ^ 0x0800,
+		31 => adr & !0x03E0,
+		_ => adr + 0x20,
+	}
 }
 
 #[repr(C)]
@@ -185,7 +343,7 @@ const _: () = {
 pub struct Palette([NesColour; 4]);
 
 #[repr(u8)]
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Zeroable)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Zeroable, CheckedBitPattern)]
 pub enum NesColour {
 	Black = 0x0F,
 	DarkGrey = 0x00,
@@ -201,6 +359,9 @@ pub enum NesColour {
 	GreenDark = 0x0A,
 	SpringDark = 0x0B,
 	CyanDark = 0x0C,
+	/// Darker than black, enough to upset some TVs.
+	BlackerThanBlack = 0x0D,
+	Black0E = 0x0E,
 	LightGrey = 0x10,
 	AzureMed = 0x11,
 	BlueMed = 0x12,
@@ -214,6 +375,9 @@ pub enum NesColour {
 	GreenMed = 0x1A,
 	SpringMed = 0x1B,
 	CyanMed = 0x1C,
+	Black1D = 0x1D,
+	Black1E = 0x1E,
+	Black1F = 0x1F,
 	White = 0x20,
 	AzureLight = 0x21,
 	BlueLight = 0x22,
@@ -227,6 +391,10 @@ pub enum NesColour {
 	GreenLight = 0x2A,
 	SpringLight = 0x2B,
 	CyanLight = 0x2C,
+	MedGrey = 0x2D,
+	Black2E = 0x2E,
+	Black2F = 0x2F,
+	White30 = 0x30,
 	AzurePale = 0x31,
 	BluePale = 0x32,
 	VioletPale = 0x33,
@@ -239,6 +407,9 @@ pub enum NesColour {
 	GreenPale = 0x3A,
 	SpringPale = 0x3B,
 	CyanPale = 0x3C,
+	PaleGrey = 0x3D,
+	Black3E = 0x3E,
+	Black3F = 0x3F,
 }
 
 // These colours are entirely untrusted and probably just hallucinated.
@@ -558,6 +729,82 @@ impl From<NesColour> for Colour {
 				blue: 180,
 				alpha: 255,
 			},
+			MedGrey => Colour {
+				red: 60,
+				green: 60,
+				blue: 60,
+				alpha: 255,
+			},
+			PaleGrey => Colour {
+				red: 160,
+				green: 162,
+				blue: 160,
+				alpha: 255,
+			},
+			White30 => White.into(),
+			BlackerThanBlack | Black0E | Black1D | Black1E | Black1F | Black2E | Black2F
+			| Black3E | Black3F => Black.into(),
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Runs `ppu` from the pre-render line up to `dot` of line 0, moving `adr` as it goes.
+	fn run_to(ppu: &mut Ppu, dot: u16) {
+		for (scanline, dots) in [(261, 0..341), (0, 0..dot)] {
+			for d in dots {
+				ppu.scanline = scanline;
+				ppu.dot = d;
+				ppu.update_adr();
+			}
+		}
+		ppu.scanline = 0;
+		ppu.dot = dot;
+	}
+
+	#[test]
+	fn adr_follows_scroll() {
+		let mut ppu = Ppu::default();
+		ppu.mask.set_show_bg(true);
+		// Nametable 1, coarse X 30, coarse Y 3, fine Y 2
+		ppu.scroll.set(0x2000 | 0x0400 | 3 << 5 | 30);
+
+		// Tiles 30 and 31 are fetched on the pre-render line, so the line starts on tile 0 of
+		// nametable 0
+		run_to(&mut ppu, 1);
+		assert_eq!(ppu.adr.get(), 0x2000 | 3 << 5);
+
+		// The next line down starts back on tile 30 of nametable 1
+		run_to(&mut ppu, 321);
+		assert_eq!(ppu.adr.get(), 0x3000 | 0x0400 | 3 << 5 | 30);
+	}
+
+	#[test]
+	fn fetches_follow_scroll() {
+		let mut ppu = Ppu::default();
+		ppu.mask.set_show_bg(true);
+		ppu.scroll.set(0x2000 | 0x0400 | 3 << 5 | 30);
+
+		run_to(&mut ppu, 1);
+		assert_eq!(ppu.fetch(), Some((0x2000 | 3 << 5, Fetch::Nametable)));
+		ppu.dot = 3;
+		assert_eq!(ppu.fetch(), Some((0x23C0, Fetch::Attribute)));
+		// Fine Y picks the row of the pattern
+		ppu.dot = 5;
+		ppu.next_tile = 0x42;
+		assert_eq!(ppu.fetch(), Some((0x0422, Fetch::Background)));
+	}
+
+	#[test]
+	fn scroll_wraps_between_nametables() {
+		assert_eq!(increment_x(31), 0x0400);
+		assert_eq!(increment_x(0x0400 | 31), 0);
+		// Off the bottom of row 29 into the nametable below, rows 30 and 31 wrap in place
+		assert_eq!(increment_y(0x7000 | 29 << 5), 0x0800);
+		assert_eq!(increment_y(0x7000 | 31 << 5), 0);
+		assert_eq!(increment_y(0x6000 | 4 << 5), 0x7000 | 4 << 5);
+	}
+}
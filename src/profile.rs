@@ -0,0 +1,143 @@
+//! Where the code went when it ran, for what the analyser can't work out on its own: jump tables
+//! through `JMP ($xxxx)`, and the trick of pushing an address and returning to it with RTS.
+//! `--profile` records them to a file next to the ROM, and the next `--analyse` or `--recompile`
+//! starts from them as well as from the vectors.
+
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fmt, fs, io,
+	path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{nes_file::PrgMapping, save};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+	/// Where indirect jumps, RTS and RTI went to, by the PRG ROM that was mapped in at the time.
+	pub targets: BTreeMap<PrgMapping, BTreeSet<u16>>,
+}
+
+impl Profile {
+	/// `game.profile` next to `game.nes`.
+	pub fn path_for(rom: &Path) -> PathBuf {
+		rom.with_extension("profile")
+	}
+
+	/// The profile at `path`, or an empty one if nothing's been profiled yet.
+	pub fn load(path: &Path) -> Result<Self> {
+		match fs::read_to_string(path) {
+			Ok(text) => {
+				Self::parse(&text).with_context(|| format!("Couldn't parse {}", path.display()))
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+			Err(e) => Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+		}
+	}
+
+	pub fn save(&self, path: &Path) -> Result<()> {
+		save::write_atomic(path, self.to_string().as_bytes())
+			.with_context(|| format!("Couldn't write {}", path.display()))
+	}
+
+	pub fn record(&mut self, mapping: PrgMapping, target: u16) {
+		self.targets.entry(mapping).or_default().insert(target);
+	}
+
+	pub fn target_count(&self) -> usize {
+		self.targets.values().map(BTreeSet::len).sum()
+	}
+
+	fn parse(text: &str) -> Result<Self> {
+		let mut profile = Profile::default();
+		for (i, line) in text.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let parse_line = || -> Result<(PrgMapping, Vec<u16>)> {
+				let Some((mapping, targets)) = line.split_once(':') else {
+					bail!("no ':' between the mapping and the targets");
+				};
+				let windows = mapping
+					.split(',')
+					.map(|window| match window.trim() {
+						"-" => Ok(None),
+						offset => usize::from_str_radix(offset, 16).map(Some),
+					})
+					.collect::<Result<Vec<_>, _>>()?;
+				let Ok(mapping) = PrgMapping::try_from(windows) else {
+					bail!("the mapping needs an entry for each 4K from $6000 up");
+				};
+				let targets = targets
+					.split_whitespace()
+					.map(|target| u16::from_str_radix(target, 16))
+					.collect::<Result<_, _>>()?;
+				Ok((mapping, targets))
+			};
+			let (mapping, targets) = parse_line().with_context(|| format!("Line {}", i + 1))?;
+			for target in targets {
+				profile.record(mapping, target);
+			}
+		}
+		Ok(profile)
+	}
+}
+
+impl fmt::Display for Profile {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(
+			f,
+			"# Where PRG ROM was mapped from $6000 up in 4K steps, then where the code went with it"
+		)?;
+		for (mapping, targets) in &self.targets {
+			let windows = mapping
+				.iter()
+				.map(|window| window.map_or("-".to_string(), |offset| format!("{offset:05X}")))
+				.collect::<Vec<_>>();
+			write!(f, "{}:", windows.join(","))?;
+			for target in targets {
+				write!(f, " {target:04X}")?;
+			}
+			writeln!(f)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let mut profile = Profile::default();
+		let mapping = [
+			None,
+			None,
+			Some(0x0000),
+			Some(0x1000),
+			Some(0x2000),
+			Some(0x3000),
+			Some(0x1E000),
+			Some(0x1F000),
+			Some(0x1E000),
+			Some(0x1F000),
+		];
+		profile.record(mapping, 0x8123);
+		profile.record(mapping, 0xC000);
+		profile.record([Some(0); 10], 0x6000);
+
+		let text = profile.to_string();
+		assert!(text.contains("-,-,00000,01000,02000,03000,1E000,1F000,1E000,1F000: 8123 C000\n"));
+		assert_eq!(Profile::parse(&text).unwrap(), profile);
+		assert_eq!(profile.target_count(), 3);
+
+		let error = Profile::parse("-,-: 8000").unwrap_err();
+		assert_eq!(
+			format!("{error:#}"),
+			"Line 1: the mapping needs an entry for each 4K from $6000 up"
+		);
+	}
+}
@@ -0,0 +1,309 @@
+//! Turns the basic blocks `flow` finds into C, one function per block calling the same
+//! instruction functions the C core interprets with. `NES_RECOMPILED=<file> cargo build` compiles
+//! the output in, and `State::run_block` runs a block whenever PC lands on the start of one.
+//!
+//! Blocks are filed under the page of PRG ROM they're in and found by their offset in it, never by
+//! address, so the same code runs wherever a mapper puts it and switching banks only changes which
+//! page's table the next block comes from.
+
+use std::{collections::BTreeSet, fmt::Write};
+
+use crate::{
+	flow::{Block, FlowGraph, PAGE_SIZE},
+	inst::Inst,
+	interpret::State,
+};
+
+/// The Rust side of `CompiledBlock` in `inc/interface.h`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CompiledBlock {
+	pub offset: usize,
+	pub run: unsafe extern "C" fn(*mut State) -> u16,
+}
+
+/// The Rust side of `CompiledPage` in `inc/interface.h`, the blocks in one page of PRG ROM.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CompiledPage {
+	pub page: usize,
+	pub blocks: *const CompiledBlock,
+	pub count: usize,
+}
+
+// SAFETY: the blocks are a constant table, never written to
+unsafe impl Sync for CompiledPage {}
+
+impl CompiledPage {
+	/// Sorted by offset.
+	pub fn blocks(&self) -> &'static [CompiledBlock] {
+		// SAFETY: the table has `count` blocks, and lives as long as the program
+		unsafe { std::slice::from_raw_parts(self.blocks, self.count) }
+	}
+}
+
+#[cfg(recompiled)]
+mod built_in {
+	use super::CompiledPage;
+
+	unsafe extern "C" {
+		pub static recompiled_crc32: u32;
+		pub static recompiled_page_count: usize;
+		pub static recompiled_pages: CompiledPage;
+	}
+}
+
+/// The pages built in with `NES_RECOMPILED`, if they were recompiled from the ROM with this
+/// CRC32. Running another game's would go badly.
+pub fn compiled_pages(crc32: u32) -> &'static [CompiledPage] {
+	#[cfg(recompiled)]
+	// SAFETY: the recompiled C defines the table with that many pages
+	unsafe {
+		use built_in::*;
+		if recompiled_crc32 == crc32 {
+			return std::slice::from_raw_parts(&raw const recompiled_pages, recompiled_page_count);
+		}
+	}
+	let _ = crc32;
+	&[]
+}
+
+/// C for every block in `graph`, along with the tables `compiled_pages` reads. `crc32` is the
+/// ROM's, see `RomHashes`.
+pub fn recompile(graph: &FlowGraph, crc32: u32) -> String {
+	// Blocks running on into the next page would need to know which page that is, so they're left
+	// to the interpreter
+	let pages = graph
+		.pages
+		.iter()
+		.map(|(&page, blocks)| {
+			let blocks = blocks
+				.values()
+				.filter(|block| !block.leaves_page())
+				.collect::<Vec<_>>();
+			(page, blocks)
+		})
+		.filter(|(_, blocks)| !blocks.is_empty())
+		.collect::<Vec<_>>();
+	let blocks = pages
+		.iter()
+		.flat_map(|(_, blocks)| blocks)
+		.collect::<Vec<_>>();
+	let functions = blocks
+		.iter()
+		.flat_map(|block| &block.insts)
+		.filter(|inst| !inst.is_jam())
+		.map(|&inst| declaration(inst))
+		.collect::<BTreeSet<_>>();
+
+	let mut out = String::new();
+	writeln!(
+		out,
+		"// Recompiled by `nes-emu --recompile`, build with NES_RECOMPILED=<this file>"
+	)
+	.unwrap();
+	writeln!(out, "#include \"interface.h\"\n").unwrap();
+	for function in functions {
+		writeln!(out, "{function}").unwrap();
+	}
+	writeln!(out, "\nconst uint32_t recompiled_crc32 = 0x{crc32:08X};").unwrap();
+
+	for block in &blocks {
+		writeln!(out).unwrap();
+		write_block(&mut out, block);
+	}
+
+	// Both sorted by offset already, for `State::compiled_block`'s lookups
+	for (page, blocks) in &pages {
+		writeln!(out, "\nstatic const CompiledBlock page_{page:02X}[] = {{").unwrap();
+		for block in blocks {
+			let offset = block.offset;
+			writeln!(out, "\t{{0x{offset:05X}, {}}},", block_name(offset)).unwrap();
+		}
+		writeln!(out, "}};").unwrap();
+	}
+
+	writeln!(
+		out,
+		"\nconst size_t recompiled_page_count = {};",
+		pages.len()
+	)
+	.unwrap();
+	if pages.is_empty() {
+		// C has no empty arrays
+		writeln!(out, "const CompiledPage recompiled_pages[1] = {{0}};").unwrap();
+		return out;
+	}
+	writeln!(out, "const CompiledPage recompiled_pages[] = {{").unwrap();
+	for (page, blocks) in &pages {
+		let count = blocks.len();
+		let offset = page * PAGE_SIZE;
+		writeln!(
+			out,
+			"\t{{0x{page:02X}, page_{page:02X}, {count}}}, // ${offset:05X}"
+		)
+		.unwrap();
+	}
+	writeln!(out, "}};").unwrap();
+	out
+}
+
+fn block_name(offset: usize) -> String {
+	format!("block_{offset:05X}")
+}
+
+/// Like `Inst::evaluate` for each instruction in turn, checking in between for IRQs and bank
+/// switches like `State::run_block` does. Nothing depends on where the page is mapped in, which is
+/// why the opcode fetches go by PC.
+fn write_block(out: &mut String, block: &Block) {
+	let name = block_name(block.offset);
+	writeln!(out, "static uint16_t {name}(State *state) {{").unwrap();
+	let mut pc = block.start;
+	for (i, &inst) in block.insts.iter().enumerate() {
+		if i > 0 {
+			writeln!(out, "\tif (state_leave_block(state)) return state->cpu.pc;").unwrap();
+		}
+		writeln!(out, "\t// ${pc:04X}: {inst:?}").unwrap();
+		writeln!(out, "\tstate_read(state, state->cpu.pc);").unwrap();
+		writeln!(out, "\tstate_read(state, (uint16_t) (state->cpu.pc + 1));").unwrap();
+		writeln!(out, "\t{}", call(inst)).unwrap();
+		pc = pc.wrapping_add(inst.len() as u16);
+	}
+	writeln!(out, "\treturn state->cpu.pc;").unwrap();
+	writeln!(out, "}}").unwrap();
+}
+
+/// The C function running `inst`. They're named after the variants, snake cased, without the
+/// numbers that tell the copies of an unofficial opcode apart.
+fn function(inst: Inst) -> String {
+	let debug = format!("{inst:?}");
+	let variant = debug.split('(').next().unwrap();
+	let variant = variant.trim_end_matches(|c: char| c.is_ascii_digit());
+	// Mnemonics are all three letters, with the unofficial ones upper case
+	let (mnemonic, mode) = variant.split_at(3);
+	let mut name = mnemonic.to_ascii_lowercase();
+	for c in mode.chars() {
+		if c.is_ascii_uppercase() {
+			name.push('_');
+		}
+		name.push(c.to_ascii_lowercase());
+	}
+	name
+}
+
+/// The C type of the operand, if `inst` has one.
+fn operand_type(inst: Inst) -> Option<&'static str> {
+	match inst.len() {
+		1 => None,
+		2 if inst.branch_offset().is_some() => Some("int8_t"),
+		2 => Some("uint8_t"),
+		_ => Some("uint16_t"),
+	}
+}
+
+fn declaration(inst: Inst) -> String {
+	let operand = operand_type(inst).map_or(String::new(), |ty| format!(", {ty} val"));
+	format!("void {}(State *state{operand});", function(inst))
+}
+
+fn call(inst: Inst) -> String {
+	if inst.is_jam() {
+		return "state->cpu.jammed = true;".to_string();
+	}
+	// The operand is printed in decimal by `Debug`, sign and all, which C reads the same way
+	let debug = format!("{inst:?}");
+	match debug.split_once('(') {
+		Some((_, operand)) => format!(
+			"{}(state, {});",
+			function(inst),
+			&operand[..operand.len() - 1]
+		),
+		None => format!("{}(state);", function(inst)),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn names_the_c_functions() {
+		let names = [
+			(Inst::LdaImmediate(1), "lda_immediate"),
+			(Inst::AdcAbsoluteX(0x1234.into()), "adc_absolute_x"),
+			(Inst::LAXIndirectY(0), "lax_indirect_y"),
+			(Inst::NOPZeroPageX3(0), "nop_zero_page_x"),
+			(Inst::NOP4, "nop"),
+			(Inst::Rts, "rts"),
+		];
+		for (inst, name) in names {
+			assert_eq!(function(inst), name);
+		}
+
+		assert_eq!(call(Inst::Bne(-6)), "bne(state, -6);");
+		assert_eq!(
+			call(Inst::StaAbsolute(0x2007.into())),
+			"sta_absolute(state, 8199);"
+		);
+		assert_eq!(
+			declaration(Inst::Bpl(0)),
+			"void bpl(State *state, int8_t val);"
+		);
+	}
+
+	#[test]
+	fn files_blocks_by_page() {
+		let block = |start: u16, offset, insts: &[Inst]| Block {
+			start,
+			offset,
+			insts: insts.to_vec(),
+			successors: Vec::new(),
+		};
+		let mut graph = FlowGraph::default();
+		// Running on from $8FFF into $9000, wherever that is in ROM
+		let straddling = block(
+			0x8FFD,
+			0x0FFD,
+			&[Inst::LdaImmediate(1), Inst::LdxImmediate(2)],
+		);
+		graph.pages.entry(0).or_default().insert(0x0FFD, straddling);
+		for (start, offset) in [(0xB001, 0x3001), (0xB000, 0x3000)] {
+			let blocks = graph.pages.entry(3).or_default();
+			blocks.insert(offset, block(start, offset, &[Inst::Iny, Inst::Rts]));
+		}
+
+		let c = recompile(&graph, 0x12345678);
+		assert!(!c.contains("block_00FFD"));
+		assert!(c.contains(
+			"static const CompiledBlock page_03[] = {\n\t{0x03000, block_03000},\n\t{0x03001, block_03001},\n};"
+		));
+		assert!(c.contains("const size_t recompiled_page_count = 1;"));
+		assert!(c.contains("\t{0x03, page_03, 2}, // $03000\n"));
+	}
+
+	/// Every instruction's function is one the C core has, so the recompiled C links.
+	#[test]
+	fn calls_functions_that_exist() {
+		let externs = include_str!("evaluate_instruction.rs");
+		for op in 0..=0xFF {
+			let inst = Inst::decode([op, 0, 0]);
+			if inst.is_jam() {
+				continue;
+			}
+			let prefix = format!("pub safe fn {}(state: &mut State", function(inst));
+			let Some(line) = externs
+				.lines()
+				.find(|line| line.trim().starts_with(&prefix))
+			else {
+				panic!("{inst:?}: no {}", function(inst));
+			};
+			let operand = match operand_type(inst) {
+				None => "State);",
+				Some("int8_t") => ": i8);",
+				Some("uint8_t") => ": u8);",
+				Some(_) => ": u16);",
+			};
+			assert!(line.ends_with(operand), "{inst:?}: {line}");
+		}
+	}
+}
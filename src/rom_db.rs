@@ -0,0 +1,355 @@
+use std::{fmt, sync::LazyLock};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{
+	nes_file::Mirroring,
+	nes20db::parse_sha1,
+	rom_header::{RomHeader, Timing},
+};
+
+/// `data/romdb.txt`, followed by the NES 2.0 XML database when the build was given one, see
+/// `generate_rom_db` in the build script.
+static DATABASE: LazyLock<Vec<DbEntry>> = LazyLock::new(|| {
+	parse_db(include_str!(concat!(env!("OUT_DIR"), "/romdb.txt")))
+		.expect("Embedded ROM database is malformed")
+});
+
+/// Hashes of PRG ROM followed by CHR ROM, the key of the database.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RomHashes {
+	pub crc32: u32,
+	pub sha1: [u8; 20],
+}
+
+impl RomHashes {
+	pub fn new(prg: &[u8], chr: &[u8]) -> Self {
+		let mut crc32 = crc32fast::Hasher::new();
+		crc32.update(prg);
+		crc32.update(chr);
+
+		let mut sha1 = sha1_smol::Sha1::new();
+		sha1.update(prg);
+		sha1.update(chr);
+
+		Self {
+			crc32: crc32.finalize(),
+			sha1: sha1.digest().bytes(),
+		}
+	}
+}
+
+impl fmt::Display for RomHashes {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "crc32 {:08X} sha1 ", self.crc32)?;
+		self.sha1.iter().try_for_each(|b| write!(f, "{b:02X}"))
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbEntry {
+	pub crc32: u32,
+	pub sha1: Option<[u8; 20]>,
+	pub name: &'static str,
+	pub mapper: Option<u16>,
+	pub submapper: Option<u8>,
+	/// `None` inside means four-screen.
+	pub mirroring: Option<Option<Mirroring>>,
+	pub battery: Option<bool>,
+	pub prg_ram_size: Option<usize>,
+	pub prg_nvram_size: Option<usize>,
+	pub chr_ram_size: Option<usize>,
+	pub chr_nvram_size: Option<usize>,
+	pub timing: Option<Timing>,
+}
+
+/// A header field that the database disagreed with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Correction {
+	pub field: &'static str,
+	pub from: String,
+	pub to: String,
+}
+
+impl fmt::Display for Correction {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {} -> {}", self.field, self.from, self.to)
+	}
+}
+
+pub fn lookup(hashes: &RomHashes) -> Option<&'static DbEntry> {
+	DATABASE.iter().find(|entry| entry.matches(hashes))
+}
+
+impl DbEntry {
+	pub fn matches(&self, hashes: &RomHashes) -> bool {
+		self.crc32 == hashes.crc32 && self.sha1.is_none_or(|sha1| sha1 == hashes.sha1)
+	}
+
+	/// Overwrites the fields of `header` the database knows better, returning what changed.
+	pub fn apply(&self, header: &mut RomHeader) -> Vec<Correction> {
+		fn fix<T: PartialEq + fmt::Debug>(
+			corrections: &mut Vec<Correction>,
+			field: &'static str,
+			current: &mut T,
+			correct: Option<T>,
+		) {
+			if let Some(correct) = correct
+				&& *current != correct
+			{
+				corrections.push(Correction {
+					field,
+					from: format!("{current:?}"),
+					to: format!("{correct:?}"),
+				});
+				*current = correct;
+			}
+		}
+
+		let mut corrections = Vec::new();
+		fix(&mut corrections, "mapper", &mut header.mapper, self.mapper);
+		fix(
+			&mut corrections,
+			"submapper",
+			&mut header.submapper,
+			self.submapper,
+		);
+		match self.mirroring {
+			Some(Some(mirroring)) => {
+				fix(
+					&mut corrections,
+					"four_screen",
+					&mut header.four_screen,
+					Some(false),
+				);
+				fix(
+					&mut corrections,
+					"mirroring",
+					&mut header.mirroring,
+					Some(mirroring),
+				);
+			}
+			Some(None) => fix(
+				&mut corrections,
+				"four_screen",
+				&mut header.four_screen,
+				Some(true),
+			),
+			None => {}
+		}
+		fix(
+			&mut corrections,
+			"battery",
+			&mut header.battery,
+			self.battery,
+		);
+		fix(
+			&mut corrections,
+			"prg_ram_size",
+			&mut header.prg_ram_size,
+			self.prg_ram_size,
+		);
+		fix(
+			&mut corrections,
+			"prg_nvram_size",
+			&mut header.prg_nvram_size,
+			self.prg_nvram_size,
+		);
+		fix(
+			&mut corrections,
+			"chr_ram_size",
+			&mut header.chr_ram_size,
+			self.chr_ram_size,
+		);
+		fix(
+			&mut corrections,
+			"chr_nvram_size",
+			&mut header.chr_nvram_size,
+			self.chr_nvram_size,
+		);
+		fix(&mut corrections, "timing", &mut header.timing, self.timing);
+		corrections
+	}
+}
+
+fn parse_db(text: &'static str) -> Result<Vec<DbEntry>> {
+	text.lines()
+		.enumerate()
+		.filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+		.map(|(i, line)| parse_entry(line).with_context(|| format!("ROM database line {}", i + 1)))
+		.collect()
+}
+
+fn parse_entry(line: &'static str) -> Result<DbEntry> {
+	let (fields, name) = line.split_once(';').unwrap_or((line, ""));
+	let mut fields = fields.split_whitespace();
+
+	let crc32 = fields.next().context("Missing CRC32")?;
+	let sha1 = fields.next().context("Missing SHA-1")?;
+	let mut entry = DbEntry {
+		crc32: u32::from_str_radix(crc32, 16).context("Bad CRC32")?,
+		sha1: match sha1 {
+			"-" => None,
+			hex => Some(parse_sha1(hex).context("Bad SHA-1")?),
+		},
+		name: name.trim(),
+		..Default::default()
+	};
+
+	for field in fields {
+		let Some((key, value)) = field.split_once('=') else {
+			bail!("Expected <field>=<value>, got {field}");
+		};
+		match key {
+			"mapper" => entry.mapper = Some(value.parse()?),
+			"submapper" => entry.submapper = Some(value.parse()?),
+			"mirroring" => {
+				entry.mirroring = Some(match value {
+					"h" => Some(Mirroring::Horizontal),
+					"v" => Some(Mirroring::Vertical),
+					"4" => None,
+					_ => bail!("Unknown mirroring {value}"),
+				})
+			}
+			"battery" => entry.battery = Some(value == "1"),
+			"prg_ram" => entry.prg_ram_size = Some(value.parse()?),
+			"prg_nvram" => entry.prg_nvram_size = Some(value.parse()?),
+			"chr_ram" => entry.chr_ram_size = Some(value.parse()?),
+			"chr_nvram" => entry.chr_nvram_size = Some(value.parse()?),
+			"timing" => {
+				entry.timing = Some(match value {
+					"ntsc" => Timing::Ntsc,
+					"pal" => Timing::Pal,
+					"multi" => Timing::MultiRegion,
+					"dendy" => Timing::Dendy,
+					_ => bail!("Unknown timing {value}"),
+				})
+			}
+			_ => bail!("Unknown field {key}"),
+		}
+	}
+
+	Ok(entry)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{nes_file::Mapper, nes20db::import_nes20db, ppu::Ppu};
+
+	#[test]
+	fn embedded_database_parses() {
+		LazyLock::force(&DATABASE);
+	}
+
+	#[test]
+	fn hand_written_entries_come_first() {
+		let hand_written = parse_db(include_str!("../data/romdb.txt")).unwrap();
+		assert_eq!(DATABASE[..hand_written.len()], hand_written[..]);
+	}
+
+	#[test]
+	fn corrections() {
+		let entries = parse_db(
+			"# comment\n\
+			 12345678 - mapper=1 mirroring=v battery=1 prg_nvram=8192 ; Some Game (U)\n",
+		)
+		.unwrap();
+		let [entry] = &entries[..] else {
+			panic!("{entries:?}")
+		};
+		assert_eq!(entry.name, "Some Game (U)");
+
+		let mut header = RomHeader::parse(&[
+			b'N', b'E', b'S', 0x1A, 8, 0, 0x40, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+		])
+		.unwrap();
+		let hashes = RomHashes {
+			crc32: 0x12345678,
+			sha1: [0; 20],
+		};
+		assert!(entry.matches(&hashes));
+
+		let corrections = entry.apply(&mut header);
+		let fields = corrections.iter().map(|c| c.field).collect::<Vec<_>>();
+		assert_eq!(fields, ["mapper", "mirroring", "battery", "prg_nvram_size"]);
+		assert_eq!(header.mapper, 1);
+		assert_eq!(header.mirroring, Mirroring::Vertical);
+		assert!(header.battery);
+		assert!(entry.apply(&mut header).is_empty());
+	}
+
+	#[test]
+	fn nes20db_import() {
+		let prg = [0xEA; 16 * 1024];
+		let chr = [0x55; 8 * 1024];
+		let hashes = RomHashes::new(&prg, &chr);
+		let sha1 = hashes.sha1.map(|b| format!("{b:02x}")).concat();
+		let xml = format!(
+			r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+	<game>
+		<!-- Some Game (USA).nes -->
+		<prgrom size="16384" crc32="00000000" sha1="0000000000000000000000000000000000000000" sum16="0000"/>
+		<chrrom size="8192" crc32="00000000" sha1="0000000000000000000000000000000000000000" sum16="0000"/>
+		<rom size="24576" crc32="{:08x}" sha1="{sha1}" sum16="0000"/>
+		<prgnvram size="8192"/>
+		<pcb mapper="0" submapper="0" mirroring="V" battery="1"/>
+		<console type="0" region="1"/>
+		<expansion type="1"/>
+	</game>
+	<game>
+		<!-- No Combined Hash.nes -->
+		<prgrom size="16384" crc32="00000000" sha1="0000000000000000000000000000000000000000" sum16="0000"/>
+		<pcb mapper="0" submapper="0" mirroring="H" battery="0"/>
+	</game>
+</nes20db>
+"#,
+			hashes.crc32
+		);
+		let text = import_nes20db(&xml).unwrap();
+		assert_eq!(
+			text,
+			format!(
+				"{:08X} {} mapper=0 submapper=0 mirroring=v battery=1 prg_ram=0 prg_nvram=8192 chr_ram=0 chr_nvram=0 timing=pal ; Some Game (USA).nes\n",
+				hashes.crc32,
+				sha1.to_uppercase()
+			)
+		);
+
+		// Now through to the cartridge, from a header that has it all wrong
+		let entries = parse_db(text.leak()).unwrap();
+		assert!(entries[0].matches(&hashes));
+		let mut header = RomHeader::parse(&[
+			b'N', b'E', b'S', 0x1A, 1, 1, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+		])
+		.unwrap();
+		let corrections = entries[0].apply(&mut header);
+		let fields = corrections.iter().map(|c| c.field).collect::<Vec<_>>();
+		assert_eq!(
+			fields,
+			[
+				"mirroring",
+				"battery",
+				"prg_ram_size",
+				"prg_nvram_size",
+				"timing"
+			]
+		);
+
+		let mut mapper = Mapper::from_header(&header, &prg, &chr, None).unwrap();
+		let mut ppu = Ppu::default();
+		mapper.set_ppu(0x2005, 0x77, &mut ppu).unwrap();
+		assert_eq!(mapper.get_ppu(0x2805, &ppu), Some(0x77));
+	}
+
+	#[test]
+	fn hashes() {
+		let hashes = RomHashes::new(b"The quick brown fox ", b"jumps over the lazy dog");
+		assert_eq!(hashes.crc32, 0x414FA339);
+		assert_eq!(
+			hashes.to_string(),
+			"crc32 414FA339 sha1 2FD4E1C67A2D28FCED849EE1BB76E7391B93EB12"
+		);
+	}
+}
@@ -0,0 +1,287 @@
+use anyhow::{Context, Result, bail};
+
+use crate::nes_file::Mirroring;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeaderFormat {
+	INes,
+	Nes20,
+	/// Not a header at all, but pieced together from the chunks of a UNIF file.
+	Unif,
+	/// A Famicom Disk System image, which has no cartridge to describe. Mapper 20 is the number
+	/// emulators use for the disk system.
+	Fds,
+}
+
+/// CPU/PPU timing, byte 12 of an NES 2.0 header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Timing {
+	#[default]
+	Ntsc,
+	Pal,
+	MultiRegion,
+	Dendy,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ConsoleType {
+	#[default]
+	Nes,
+	/// `ppu` and `hardware` are the raw nibbles of byte 13.
+	VsSystem {
+		ppu: u8,
+		hardware: u8,
+	},
+	Playchoice10,
+	/// The extended console type from the low nibble of byte 13.
+	Extended(u8),
+}
+
+/// Everything the 16 byte iNES/NES 2.0 header says about the cartridge. All sizes are in bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomHeader {
+	pub format: HeaderFormat,
+	pub mapper: u16,
+	pub submapper: u8,
+	pub prg_rom_size: usize,
+	pub chr_rom_size: usize,
+	pub prg_ram_size: usize,
+	pub prg_nvram_size: usize,
+	pub chr_ram_size: usize,
+	pub chr_nvram_size: usize,
+	pub mirroring: Mirroring,
+	pub four_screen: bool,
+	pub battery: bool,
+	pub trainer: bool,
+	pub timing: Timing,
+	pub console_type: ConsoleType,
+	pub misc_roms: u8,
+	pub expansion_device: u8,
+	/// Bytes 7-15 as they were in the file, when they were junk and so ignored.
+	pub junk: Option<[u8; 9]>,
+}
+
+impl RomHeader {
+	pub fn parse(header: &[u8; 16]) -> Result<Self> {
+		let [
+			b'N',
+			b'E',
+			b'S',
+			0x1A,
+			prg_lsb,
+			chr_lsb,
+			flags_6,
+			flags_7,
+			flags_8,
+			flags_9,
+			flags_10,
+			flags_11,
+			flags_12,
+			flags_13,
+			flags_14,
+			flags_15,
+		] = *header
+		else {
+			bail!("Not an iNES file, it doesn't start with \"NES\\x1A\"");
+		};
+
+		// Old ROM tools wrote their name over bytes 7-15, "DiskDude!" being the best known. Plain
+		// iNES leaves 12-15 zero, so anything there means none of 7-15 can be trusted.
+		if flags_7 & 0b1100 != 0b1000 && header[12..16] != [0; 4] {
+			let mut clean = *header;
+			clean[7..16].fill(0);
+			return Ok(Self {
+				junk: Some([
+					flags_7, flags_8, flags_9, flags_10, flags_11, flags_12, flags_13, flags_14,
+					flags_15,
+				]),
+				..Self::parse(&clean)?
+			});
+		}
+
+		let mirroring = if flags_6 & 1 != 0 {
+			Mirroring::Vertical
+		} else {
+			Mirroring::Horizontal
+		};
+		let battery = flags_6 & (1 << 1) != 0;
+		let trainer = flags_6 & (1 << 2) != 0;
+		let four_screen = flags_6 & (1 << 3) != 0;
+		let mapper_lo = (flags_7 & 0xF0) as u16 | (flags_6 >> 4) as u16;
+
+		let console_type = match flags_7 & 0b11 {
+			0 => ConsoleType::Nes,
+			1 => ConsoleType::VsSystem {
+				ppu: flags_13 & 0x0F,
+				hardware: flags_13 >> 4,
+			},
+			2 => ConsoleType::Playchoice10,
+			3 => ConsoleType::Extended(flags_13 & 0x0F),
+			_ => unreachable!(),
+		};
+
+		if flags_7 & 0b1100 != 0b1000 {
+			// Plain iNES: byte 8 is PRG RAM in 8K units (with 0 meaning 8K), byte 9 the TV system
+			let prg_ram_size = flags_8.max(1) as usize * 8 * 1024;
+			let chr_rom_size = chr_lsb as usize * 8 * 1024;
+			return Ok(Self {
+				format: HeaderFormat::INes,
+				mapper: mapper_lo,
+				submapper: 0,
+				prg_rom_size: prg_lsb as usize * 16 * 1024,
+				chr_rom_size,
+				prg_ram_size: if battery { 0 } else { prg_ram_size },
+				prg_nvram_size: if battery { prg_ram_size } else { 0 },
+				chr_ram_size: if chr_rom_size == 0 { 8 * 1024 } else { 0 },
+				chr_nvram_size: 0,
+				mirroring,
+				four_screen,
+				battery,
+				trainer,
+				timing: if flags_9 & 1 != 0 {
+					Timing::Pal
+				} else {
+					Timing::Ntsc
+				},
+				// The extended console types only exist in NES 2.0
+				console_type: match console_type {
+					ConsoleType::VsSystem { .. } => ConsoleType::VsSystem {
+						ppu: 0,
+						hardware: 0,
+					},
+					ConsoleType::Extended(_) => ConsoleType::Nes,
+					other => other,
+				},
+				misc_roms: 0,
+				expansion_device: 0,
+				junk: None,
+			});
+		}
+
+		let prg_rom_size = rom_size(prg_lsb, flags_9 & 0x0F, 16 * 1024)
+			.context("PRG ROM size in header is too large")?;
+		let chr_rom_size = rom_size(chr_lsb, flags_9 >> 4, 8 * 1024)
+			.context("CHR ROM size in header is too large")?;
+
+		Ok(Self {
+			format: HeaderFormat::Nes20,
+			mapper: ((flags_8 & 0x0F) as u16) << 8 | mapper_lo,
+			submapper: flags_8 >> 4,
+			prg_rom_size,
+			chr_rom_size,
+			prg_ram_size: ram_size(flags_10 & 0x0F),
+			prg_nvram_size: ram_size(flags_10 >> 4),
+			chr_ram_size: ram_size(flags_11 & 0x0F),
+			chr_nvram_size: ram_size(flags_11 >> 4),
+			mirroring,
+			four_screen,
+			battery,
+			trainer,
+			timing: match flags_12 & 0b11 {
+				0 => Timing::Ntsc,
+				1 => Timing::Pal,
+				2 => Timing::MultiRegion,
+				3 => Timing::Dendy,
+				_ => unreachable!(),
+			},
+			console_type,
+			misc_roms: flags_14 & 0b11,
+			expansion_device: flags_15 & 0x3F,
+			junk: None,
+		})
+	}
+
+	pub fn prg_ram_total(&self) -> usize {
+		self.prg_ram_size + self.prg_nvram_size
+	}
+}
+
+/// NES 2.0 ROM sizes are either a 12 bit count of `unit`s, or `2^E * (MM * 2 + 1)` bytes when
+/// the upper nibble is $F.
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+	if msb == 0x0F {
+		let exponent = (lsb >> 2) as u32;
+		let multiplier = (lsb & 0b11) as usize * 2 + 1;
+		1usize.checked_shl(exponent)?.checked_mul(multiplier)
+	} else {
+		Some(((msb as usize) << 8 | lsb as usize) * unit)
+	}
+}
+
+/// RAM sizes are stored as a shift count of 64 bytes, with 0 meaning no RAM at all.
+fn ram_size(shift: u8) -> usize {
+	if shift == 0 { 0 } else { 64 << shift }
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn ines() {
+		let header = RomHeader::parse(&[
+			b'N', b'E', b'S', 0x1A, 2, 1, 0x43, 0x00, 0, 1, 0, 0, 0, 0, 0, 0,
+		])
+		.unwrap();
+
+		assert_eq!(header.format, HeaderFormat::INes);
+		assert_eq!(header.mapper, 4);
+		assert_eq!(header.prg_rom_size, 32 * 1024);
+		assert_eq!(header.chr_rom_size, 8 * 1024);
+		assert_eq!(header.mirroring, Mirroring::Vertical);
+		assert!(header.battery);
+		assert_eq!(header.prg_nvram_size, 8 * 1024);
+		assert_eq!(header.timing, Timing::Pal);
+
+		// Junk in bytes 12-15 means the rest of 7-15 is probably junk too
+		let header = RomHeader::parse(&[
+			b'N', b'E', b'S', 0x1A, 2, 1, 0x41, 0x60, 0, 1, 0, 0, 0, b'N', b'i', b'!',
+		])
+		.unwrap();
+		assert_eq!(header.mapper, 4);
+		assert_eq!(header.mirroring, Mirroring::Vertical);
+		assert_eq!(header.timing, Timing::Ntsc);
+		assert_eq!(header.junk, Some([0x60, 0, 1, 0, 0, 0, b'N', b'i', b'!']));
+		assert_eq!(
+			RomHeader::parse(b"NES\x1A\x01\x01\x00DiskDude!")
+				.unwrap()
+				.junk,
+			Some(*b"DiskDude!")
+		);
+	}
+
+	#[test]
+	fn nes_2_0() {
+		let header = RomHeader::parse(&[
+			b'N', b'E', b'S', 0x1A, 0x09, 0x00, 0x50, 0x19, 0x31, 0x00, 0x70, 0x07, 0x03, 0x21,
+			0x01, 0x05,
+		])
+		.unwrap();
+
+		assert_eq!(header.format, HeaderFormat::Nes20);
+		assert_eq!(header.mapper, 0x115);
+		assert_eq!(header.submapper, 3);
+		assert_eq!(header.prg_rom_size, 16 * 1024 * 9);
+		assert_eq!(header.chr_rom_size, 0);
+		assert_eq!(header.prg_ram_size, 0);
+		assert_eq!(header.prg_nvram_size, 8 * 1024);
+		assert_eq!(header.chr_ram_size, 8 * 1024);
+		assert_eq!(header.timing, Timing::Dendy);
+		assert_eq!(
+			header.console_type,
+			ConsoleType::VsSystem {
+				ppu: 1,
+				hardware: 2
+			}
+		);
+		assert_eq!(header.misc_roms, 1);
+		assert_eq!(header.expansion_device, 5);
+	}
+
+	#[test]
+	fn nes_2_0_exponent_sizes() {
+		assert_eq!(rom_size(0b0000_0101, 0x0F, 16 * 1024), Some(6));
+		assert_eq!(rom_size(0b0101_0000, 0x0F, 16 * 1024), Some(1 << 20));
+		assert_eq!(rom_size(0x00, 0x01, 8 * 1024), Some(256 * 8 * 1024));
+	}
+}
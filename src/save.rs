@@ -0,0 +1,204 @@
+use std::{
+	fs::{self, File},
+	io::{self, Write},
+	path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{ips, nes_file::Mapper};
+
+/// How often, in frames, battery RAM is checked for changes and written back.
+pub const FLUSH_INTERVAL_FRAMES: u64 = 60;
+
+/// What a save file holds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SaveKind {
+	/// Battery-backed PRG RAM, as is, in a `.sav` file.
+	PrgRam,
+	/// Famicom Disk System disk sides, as an IPS patch against the original image in an `.ips`
+	/// file, so the image itself is never touched.
+	Disk,
+}
+
+/// Battery-backed PRG RAM, or a disk that games write to, mirrored to a file.
+#[derive(Debug)]
+pub struct SaveFile {
+	path: PathBuf,
+	kind: SaveKind,
+	last_written: Vec<u8>,
+}
+
+impl SaveFile {
+	/// `game.sav` (or `game.ips`) next to `game.nes`, or in `save_dir` if one is configured.
+	pub fn path_for(rom: &Path, save_dir: Option<&Path>, kind: SaveKind) -> PathBuf {
+		let extension = match kind {
+			SaveKind::PrgRam => "sav",
+			SaveKind::Disk => "ips",
+		};
+		let file_name = rom.with_extension(extension);
+		match (save_dir, file_name.file_name()) {
+			(Some(dir), Some(name)) => dir.join(name),
+			_ => file_name,
+		}
+	}
+
+	/// Loads the save file into the mapper, if there is one yet.
+	pub fn load(path: PathBuf, kind: SaveKind, mapper: &mut Mapper) -> Result<Self> {
+		match kind {
+			SaveKind::PrgRam => Self::load_prg_ram(path, mapper),
+			SaveKind::Disk => Self::load_disk(path, mapper),
+		}
+	}
+
+	fn load_prg_ram(path: PathBuf, mapper: &mut Mapper) -> Result<Self> {
+		let Some(ram) = mapper.prg_ram_mut() else {
+			anyhow::bail!("Cartridge has a battery but no PRG RAM to back up");
+		};
+
+		match fs::read(&path) {
+			Ok(data) => {
+				if data.len() != ram.len() {
+					eprintln!(
+						"{} is {} bytes but the cartridge has {} bytes of PRG RAM, loading what fits",
+						path.display(),
+						data.len(),
+						ram.len()
+					);
+				}
+				let len = data.len().min(ram.len());
+				ram[..len].copy_from_slice(&data[..len]);
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+		}
+
+		Ok(Self {
+			path,
+			kind: SaveKind::PrgRam,
+			last_written: ram.to_vec(),
+		})
+	}
+
+	fn load_disk(path: PathBuf, mapper: &mut Mapper) -> Result<Self> {
+		let Some(fds) = mapper.fds_mut() else {
+			anyhow::bail!("Only disk systems can have their disks saved");
+		};
+
+		match fs::read(&path) {
+			Ok(patch) => {
+				let mut disk = fds.original_disk_data().to_vec();
+				ips::apply(&patch, &mut disk)
+					.and_then(|()| fds.set_disk_data(&disk))
+					.with_context(|| format!("Couldn't apply {}", path.display()))?;
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+		}
+
+		Ok(Self {
+			path,
+			kind: SaveKind::Disk,
+			last_written: fds.disk_data(),
+		})
+	}
+
+	/// Writes the save to disk if it has changed since the last flush.
+	pub fn flush(&mut self, mapper: &Mapper) -> Result<()> {
+		let current = match self.kind {
+			SaveKind::PrgRam => mapper.prg_ram().map(<[u8]>::to_vec),
+			SaveKind::Disk => mapper.fds().map(|fds| fds.disk_data()),
+		};
+		let Some(current) = current else {
+			return Ok(());
+		};
+		if current == self.last_written {
+			return Ok(());
+		}
+
+		let patch;
+		let data = match (self.kind, mapper.fds()) {
+			(SaveKind::Disk, Some(fds)) => {
+				patch = ips::diff(fds.original_disk_data(), &current);
+				&patch
+			}
+			_ => &current,
+		};
+		write_atomic(&self.path, data)
+			.with_context(|| format!("Couldn't write {}", self.path.display()))?;
+		self.last_written = current;
+		Ok(())
+	}
+}
+
+/// Writes to a temporary file and renames it over the real one, so a crash mid-write leaves
+/// either the old or the new save behind, never half of each.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+	if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+		fs::create_dir_all(dir)?;
+	}
+
+	let mut tmp_name = path.as_os_str().to_owned();
+	tmp_name.push(".tmp");
+	let tmp_path = PathBuf::from(tmp_name);
+
+	let mut file = File::create(&tmp_path)?;
+	file.write_all(data)?;
+	file.sync_all()?;
+	drop(file);
+
+	fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn save_path() {
+		assert_eq!(
+			SaveFile::path_for(Path::new("roms/zelda.nes"), None, SaveKind::PrgRam),
+			Path::new("roms/zelda.sav")
+		);
+		assert_eq!(
+			SaveFile::path_for(
+				Path::new("roms/zelda.nes"),
+				Some(Path::new("saves")),
+				SaveKind::PrgRam
+			),
+			Path::new("saves/zelda.sav")
+		);
+		assert_eq!(
+			SaveFile::path_for(Path::new("roms/zelda.fds"), None, SaveKind::Disk),
+			Path::new("roms/zelda.ips")
+		);
+	}
+
+	fn battery_nrom() -> Box<Mapper> {
+		let mut buffer = vec![b'N', b'E', b'S', 0x1A, 1, 1, 1 << 1];
+		buffer.resize(16 + 16 * 1024 + 8 * 1024, 0);
+		Mapper::parse_ines(buffer).unwrap()
+	}
+
+	#[test]
+	fn round_trip() {
+		let dir = std::env::temp_dir().join(format!("nes-emu-save-{}", std::process::id()));
+		let path = dir.join("game.sav");
+
+		let mut mapper = battery_nrom();
+
+		let mut save = SaveFile::load(path.clone(), SaveKind::PrgRam, &mut mapper).unwrap();
+		save.flush(&mapper).unwrap();
+		assert!(!path.exists(), "Unchanged RAM shouldn't be written");
+
+		mapper.set_cpu(0x6010, 0xAB).unwrap();
+		save.flush(&mapper).unwrap();
+		assert_eq!(fs::read(&path).unwrap()[0x10], 0xAB);
+
+		let mut reloaded = battery_nrom();
+		SaveFile::load(path, SaveKind::PrgRam, &mut reloaded).unwrap();
+		assert_eq!(reloaded.get_cpu(0x6010), Some(0xAB));
+
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,246 @@
+//! Checks the recompiled code against the interpreter: `--verify` runs a second `State` next to
+//! the one on screen, one instruction at a time through `Inst::evaluate`, and after every block
+//! the recompiled one runs, compares the two. The first time they disagree, it stops and says
+//! how, along with the block that did it. The mapper's ROM is shared between the two, so only
+//! its registers and RAM are gone through each time.
+
+use std::fmt::{self, Debug};
+
+use crate::{audio, disasm, drawing, interpret::State};
+
+pub struct Lockstep {
+	interpreted: Box<State>,
+}
+
+/// How the two `State`s came to disagree.
+#[derive(Debug)]
+pub struct Divergence {
+	pub pc: u16,
+	/// Where `pc` is in PRG ROM, `None` for code outside ROM.
+	pub offset: Option<usize>,
+	pub compiled: bool,
+	/// The block's instructions with their addresses, as the interpreter sees them.
+	pub disassembly: Vec<String>,
+	pub differences: Vec<String>,
+}
+
+impl Lockstep {
+	/// Starts the interpreter from where `recompiled` is, which should be where it started.
+	pub fn new(recompiled: &State) -> Self {
+		let mut interpreted = Box::new(State::new(
+			Box::new((*recompiled.rom).clone()),
+			drawing::new_bitmap(),
+			audio::new_queue(),
+		));
+		interpreted.backend = recompiled.backend;
+		interpreted.variant = recompiled.variant;
+		Self { interpreted }
+	}
+
+	/// Runs a block of `recompiled`, then the interpreter up to the same cycle.
+	pub fn step(&mut self, recompiled: &mut State) -> Result<(), Divergence> {
+		let pc = recompiled.cpu.pc;
+		let disassembly = self.disassemble();
+		let compiled = recompiled.blocks.stats.compiled;
+
+		recompiled.run_block();
+		let interpreted = &mut self.interpreted;
+		while interpreted.cycles < recompiled.cycles {
+			interpreted.next();
+		}
+
+		let differences = differences(interpreted, recompiled);
+		if differences.is_empty() {
+			return Ok(());
+		}
+		Err(Divergence {
+			pc,
+			offset: recompiled.rom.prg_rom_offset(pc),
+			compiled: recompiled.blocks.stats.compiled != compiled,
+			disassembly,
+			differences,
+		})
+	}
+
+	/// The block about to run as assembly, before running it changes anything, so the values shown
+	/// are the ones it started from.
+	fn disassemble(&self) -> Vec<String> {
+		let state = &self.interpreted;
+		let mut insts = state.decode_block().to_vec();
+		if insts.is_empty() {
+			insts.push(state.next_inst());
+		}
+		let mut pc = state.cpu.pc;
+		insts
+			.into_iter()
+			.map(|inst| {
+				let mut line = format!("${pc:04X}: ");
+				disasm::write_inst(&mut line, state, pc, inst).unwrap();
+				pc = pc.wrapping_add(inst.len() as u16);
+				line
+			})
+			.collect()
+	}
+}
+
+fn differences(interpreted: &State, recompiled: &State) -> Vec<String> {
+	let mut differences = Vec::new();
+	if interpreted.cpu != recompiled.cpu {
+		differences.push(format!(
+			"CPU: interpreted {:?}, recompiled {:?}",
+			interpreted.cpu, recompiled.cpu
+		));
+	}
+	if interpreted.cycles != recompiled.cycles {
+		differences.push(format!(
+			"Cycles: interpreted {}, recompiled {}",
+			interpreted.cycles, recompiled.cycles
+		));
+	}
+	let ram = interpreted.ram.iter().zip(&recompiled.ram);
+	if let Some((adr, (a, b))) = ram.enumerate().find(|(_, (a, b))| a != b) {
+		differences.push(format!(
+			"RAM from ${adr:04X}: interpreted ${a:02X}, recompiled ${b:02X}"
+		));
+	}
+	if interpreted.ppu != recompiled.ppu {
+		differences.push(format!(
+			"PPU {}",
+			first_difference(&interpreted.ppu, &recompiled.ppu)
+		));
+	}
+	if interpreted.apu != recompiled.apu {
+		differences.push(format!(
+			"APU {}",
+			first_difference(&interpreted.apu, &recompiled.apu)
+		));
+	}
+	if interpreted.rom != recompiled.rom {
+		differences.push(format!(
+			"Mapper {}",
+			first_difference(&interpreted.rom, &recompiled.rom)
+		));
+	}
+	differences
+}
+
+/// The first line that differs when both are pretty printed, along with the line that opened
+/// what it's in, which for structs is the field.
+fn first_difference<T: Debug>(interpreted: &T, recompiled: &T) -> String {
+	let interpreted = format!("{interpreted:#?}");
+	let recompiled = format!("{recompiled:#?}");
+	let interpreted = interpreted.lines().collect::<Vec<_>>();
+	let recompiled = recompiled.lines().collect::<Vec<_>>();
+	let Some(i) = (0..interpreted.len().max(recompiled.len()))
+		.find(|&i| interpreted.get(i) != recompiled.get(i))
+	else {
+		return "differs, but not in its Debug output".to_string();
+	};
+	let within = interpreted[..i]
+		.iter()
+		.rev()
+		.find(|line| line.ends_with(['{', '[', '(']))
+		.map_or("", |line| line.trim());
+	let interpreted = interpreted.get(i).map_or("", |line| line.trim());
+	let recompiled = recompiled.get(i).map_or("", |line| line.trim());
+	format!("in `{within}`: interpreted `{interpreted}`, recompiled `{recompiled}`")
+}
+
+impl fmt::Display for Divergence {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let kind = if self.compiled {
+			"recompiled"
+		} else {
+			"interpreted"
+		};
+		write!(f, "The {kind} block at ${:04X}", self.pc)?;
+		if let Some(offset) = self.offset {
+			write!(f, " (PRG ${offset:05X})")?;
+		}
+		writeln!(f, " didn't do what the interpreter did:")?;
+		for difference in &self.differences {
+			writeln!(f, "  {difference}")?;
+		}
+		writeln!(f, "The block:")?;
+		for line in &self.disassembly {
+			writeln!(f, "  {line}")?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::{
+		inst::Inst,
+		recompile::{CompiledBlock, CompiledPage},
+		tests::load,
+	};
+
+	use super::*;
+
+	#[rustfmt::skip]
+	const PROGRAM: [u8; 12] = [
+		0xA2, 0x03,       // LDX #3
+		0xCA,             // $8002, loop: DEX
+		0x8E, 0x00, 0x03, // STX $0300
+		0xD0, 0xFA,       // BNE loop
+		0x4C, 0x0B, 0x80, // JMP $800B
+		0x02,             // JAM
+	];
+
+	/// DEX, STX $0300, BNE, but with the store going to $0301.
+	unsafe extern "C" fn wrong_loop(ptr: *mut State) -> u16 {
+		let state = unsafe { &mut *ptr };
+		for inst in [Inst::Dex, Inst::StxAbsolute(0x0301.into()), Inst::Bne(-6)] {
+			inst.evaluate(state);
+		}
+		state.cpu.pc
+	}
+
+	static PAGE_0: [CompiledBlock; 1] = [CompiledBlock {
+		offset: 0x0002,
+		run: wrong_loop,
+	}];
+	static PAGES: [CompiledPage; 1] = [CompiledPage {
+		page: 0,
+		blocks: &raw const PAGE_0 as *const CompiledBlock,
+		count: 1,
+	}];
+
+	#[test]
+	fn agrees_with_itself() {
+		let mut state = load(&PROGRAM);
+		let mut lockstep = Lockstep::new(&state);
+		while !state.cpu.jammed {
+			lockstep.step(&mut state).unwrap();
+		}
+		assert_eq!(state.cpu.x, 0);
+	}
+
+	#[test]
+	fn finds_the_first_divergence() {
+		let mut state = load(&PROGRAM);
+		state.blocks.set_compiled(&PAGES);
+		let mut lockstep = Lockstep::new(&state);
+		lockstep.step(&mut state).unwrap();
+		let divergence = lockstep.step(&mut state).unwrap_err();
+
+		assert_eq!(divergence.pc, 0x8002);
+		assert!(divergence.compiled);
+		assert_eq!(
+			divergence.differences,
+			["RAM from $0300: interpreted $01, recompiled $02"]
+		);
+		assert_eq!(
+			divergence.to_string(),
+			"The recompiled block at $8002 (PRG $00002) didn't do what the interpreter did:
+  RAM from $0300: interpreted $01, recompiled $02
+The block:
+  $8002: DEX
+  $8003: STX $0300 = #$02
+  $8006: BNE $8002
+"
+		);
+	}
+}
@@ -0,0 +1,443 @@
+use anyhow::{Result, bail};
+
+use crate::{
+	nes_file::{Mirroring, SharedMem},
+	ppu::Ppu,
+	rom_header::RomHeader,
+};
+
+/// Which CPU address lines a Konami board wires to the chip's two register select pins. Boards
+/// of the same chip differ in this, so the masks can have more than one line set when the
+/// header doesn't say which board it is; games only ever write with one of them set.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pins {
+	pub a0: u16,
+	pub a1: u16,
+}
+
+impl Pins {
+	pub const fn new(a0: u16, a1: u16) -> Self {
+		Self { a0, a1 }
+	}
+
+	/// The register a CPU address selects, as $x000-$x003.
+	pub fn register(self, adr: u16) -> u16 {
+		(adr & 0xF000) | (adr & self.a0 != 0) as u16 | ((adr & self.a1 != 0) as u16) << 1
+	}
+}
+
+/// The IRQ counter shared by VRC4, VRC6 and VRC7. It counts CPU cycles, or with a prescaler
+/// dividing them down to roughly scanlines, without looking at the PPU at all.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VrcIrq {
+	latch: u8,
+	counter: u8,
+	prescaler: i16,
+	enabled: bool,
+	enable_after_ack: bool,
+	cycle_mode: bool,
+	pending: bool,
+}
+
+impl VrcIrq {
+	pub fn pending(&self) -> bool {
+		self.pending
+	}
+
+	pub fn set_latch(&mut self, val: u8) {
+		self.latch = val;
+	}
+
+	/// VRC4 takes the latch four bits at a time.
+	pub fn set_latch_low(&mut self, val: u8) {
+		self.latch = (self.latch & 0xF0) | (val & 0x0F);
+	}
+
+	pub fn set_latch_high(&mut self, val: u8) {
+		self.latch = (self.latch & 0x0F) | (val << 4);
+	}
+
+	pub fn set_control(&mut self, val: u8) {
+		self.enable_after_ack = val & 0b001 != 0;
+		self.enabled = val & 0b010 != 0;
+		self.cycle_mode = val & 0b100 != 0;
+		self.pending = false;
+		if self.enabled {
+			self.counter = self.latch;
+			self.prescaler = 341;
+		}
+	}
+
+	pub fn acknowledge(&mut self) {
+		self.pending = false;
+		self.enabled = self.enable_after_ack;
+	}
+
+	/// Called once per CPU cycle.
+	pub fn tick(&mut self) {
+		if !self.enabled {
+			return;
+		}
+
+		if !self.cycle_mode {
+			// Three PPU dots per CPU cycle, 341 dots per line
+			self.prescaler -= 3;
+			if self.prescaler > 0 {
+				return;
+			}
+			self.prescaler += 341;
+		}
+
+		if self.counter == 0xFF {
+			self.counter = self.latch;
+			self.pending = true;
+		} else {
+			self.counter += 1;
+		}
+	}
+}
+
+/// Maps a 1K CHR bank onto however much CHR there is.
+pub fn chr_index(chr: &[u8], bank: u16, adr: u16) -> usize {
+	(bank as usize * 0x400 + (adr as usize & 0x3FF)) % chr.len()
+}
+
+/// Maps an 8K PRG bank onto however much PRG ROM there is.
+pub fn prg_index(prg: &[u8], bank: usize, adr: u16) -> usize {
+	(bank * 0x2000 + (adr as usize & 0x1FFF)) % prg.len()
+}
+
+/// Konami's VRC2 and VRC4 (mappers 21, 22, 23 and 25). VRC4 is VRC2 with a PRG swap mode,
+/// more mirroring options, wider CHR banks and the IRQ counter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vrc4 {
+	vrc2: bool,
+	pins: Pins,
+	/// VRC2a ignores the low bit of its CHR banks.
+	chr_shift: u8,
+
+	prg_rom: SharedMem,
+	prg_ram: Vec<u8>,
+	chr: SharedMem,
+	chr_is_ram: bool,
+
+	prg_banks: [u8; 2],
+	prg_swap: bool,
+	chr_banks: [u16; 8],
+	mirroring: Mirroring,
+	/// VRC2 boards without RAM have a single bit of storage at $6000 instead, which Ganbare
+	/// Goemon Gaiden uses as a copy protection check.
+	latch: u8,
+	irq: VrcIrq,
+}
+
+impl Vrc4 {
+	pub fn new(header: &RomHeader, prg: &[u8], chr: &[u8]) -> Result<Self> {
+		const A0: u16 = 1 << 0;
+		const A1: u16 = 1 << 1;
+		const A2: u16 = 1 << 2;
+		const A3: u16 = 1 << 3;
+		const A6: u16 = 1 << 6;
+		const A7: u16 = 1 << 7;
+
+		// Submapper 0 is for headers that don't say which board, so wire up both
+		let (vrc2, pins) = match (header.mapper, header.submapper) {
+			(21, 1) => (false, Pins::new(A1, A2)),
+			(21, 2) => (false, Pins::new(A6, A7)),
+			(21, _) => (false, Pins::new(A1 | A6, A2 | A7)),
+			(22, _) => (true, Pins::new(A1, A0)),
+			(23, 1) => (false, Pins::new(A0, A1)),
+			(23, 2) => (false, Pins::new(A2, A3)),
+			(23, 3) => (true, Pins::new(A0, A1)),
+			(23, _) => (false, Pins::new(A0 | A2, A1 | A3)),
+			(25, 1) => (false, Pins::new(A1, A0)),
+			(25, 2) => (false, Pins::new(A3, A2)),
+			(25, 3) => (true, Pins::new(A1, A0)),
+			(25, _) => (false, Pins::new(A1 | A3, A0 | A2)),
+			(mapper, _) => bail!("Mapper {mapper} isn't a VRC2 or VRC4"),
+		};
+
+		if prg.is_empty() || !prg.len().is_multiple_of(0x2000) {
+			bail!("VRC2/VRC4 PRG ROM has to be a whole number of 8K banks");
+		}
+		let chr_is_ram = chr.is_empty();
+
+		Ok(Self {
+			vrc2,
+			pins,
+			chr_shift: (header.mapper == 22) as u8,
+			prg_rom: prg.into(),
+			prg_ram: vec![0; header.prg_ram_total()],
+			chr: if chr_is_ram {
+				vec![0; header.chr_ram_size.max(8 * 1024)].into()
+			} else {
+				chr.into()
+			},
+			chr_is_ram,
+			prg_banks: [0; _],
+			prg_swap: false,
+			chr_banks: [0; _],
+			mirroring: header.mirroring,
+			latch: 0,
+			irq: VrcIrq::default(),
+		})
+	}
+
+	pub fn prg_rom(&self) -> &[u8] {
+		&self.prg_rom
+	}
+
+	pub fn prg_ram(&self) -> Option<&[u8]> {
+		(!self.prg_ram.is_empty()).then_some(&self.prg_ram[..])
+	}
+
+	pub fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+		(!self.prg_ram.is_empty()).then_some(&mut self.prg_ram[..])
+	}
+
+	pub fn irq(&self) -> bool {
+		self.irq.pending()
+	}
+
+	pub fn tick(&mut self) {
+		if !self.vrc2 {
+			self.irq.tick();
+		}
+	}
+
+	fn prg_bank(&self, adr: u16) -> usize {
+		// Counted back from the end, so with a single 8K bank both are bank 0
+		let banks = self.prg_rom.len() / 0x2000;
+		let second_last = (2 * banks - 2) % banks;
+		match (adr, self.prg_swap) {
+			(0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.prg_banks[0] as usize,
+			(0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+			(0xA000..=0xBFFF, _) => self.prg_banks[1] as usize,
+			_ => (second_last + 1) % banks,
+		}
+	}
+
+	/// Where in PRG ROM the byte at `adr` comes from.
+	pub fn prg_rom_offset(&self, adr: u16) -> Option<usize> {
+		(adr >= 0x8000).then(|| prg_index(&self.prg_rom, self.prg_bank(adr), adr))
+	}
+
+	pub fn get_cpu(&self, adr: u16) -> Option<u8> {
+		match adr {
+			0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+				Some(self.prg_ram[adr as usize % self.prg_ram.len()])
+			}
+			0x6000..=0x6FFF if self.vrc2 => Some(self.latch),
+			_ => self.prg_rom_offset(adr).map(|i| self.prg_rom[i]),
+		}
+	}
+
+	pub fn set_cpu(&mut self, adr: u16, val: u8) {
+		match adr {
+			0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+				let len = self.prg_ram.len();
+				self.prg_ram[adr as usize % len] = val;
+			}
+			0x6000..=0x6FFF if self.vrc2 => self.latch = val & 1,
+			0x8000..=0xFFFF => self.set_register(self.pins.register(adr), val),
+			_ => {}
+		}
+	}
+
+	fn set_register(&mut self, register: u16, val: u8) {
+		match register {
+			0x8000..=0x8003 => self.prg_banks[0] = val & 0x1F,
+			0xA000..=0xA003 => self.prg_banks[1] = val & 0x1F,
+			0x9000..=0x9003 if self.vrc2 => {
+				self.mirroring = match val & 1 {
+					0 => Mirroring::Vertical,
+					_ => Mirroring::Horizontal,
+				}
+			}
+			0x9000 | 0x9001 => {
+				self.mirroring = match val & 0b11 {
+					0 => Mirroring::Vertical,
+					1 => Mirroring::Horizontal,
+					2 => Mirroring::SingleScreenA,
+					_ => Mirroring::SingleScreenB,
+				}
+			}
+			// Bit 0 is meant to enable PRG RAM, but not every game sets it, so RAM is always on
+			0x9002 => self.prg_swap = val & 0b10 != 0,
+			0xB000..=0xE003 => {
+				let bank = ((register >> 12) - 0xB) as usize * 2 + (register as usize & 0b10) / 2;
+				let old = self.chr_banks[bank];
+				self.chr_banks[bank] = if register & 1 == 0 {
+					(old & 0x1F0) | (val & 0x0F) as u16
+				} else {
+					let mask = if self.vrc2 { 0x0F } else { 0x1F };
+					(old & 0x00F) | ((val & mask) as u16) << 4
+				};
+			}
+			0xF000 if !self.vrc2 => self.irq.set_latch_low(val),
+			0xF001 if !self.vrc2 => self.irq.set_latch_high(val),
+			0xF002 if !self.vrc2 => self.irq.set_control(val),
+			0xF003 if !self.vrc2 => self.irq.acknowledge(),
+			_ => {}
+		}
+	}
+
+	fn chr_index(&self, adr: u16) -> usize {
+		let bank = self.chr_banks[adr as usize / 0x400] >> self.chr_shift;
+		chr_index(&self.chr, bank, adr)
+	}
+
+	pub fn get_ppu(&self, adr: u16, ppu: &Ppu) -> Option<u8> {
+		match adr {
+			0x0000..=0x1FFF => Some(self.chr[self.chr_index(adr)]),
+			0x2000..=0x3EFF => Some(ppu.vram[self.mirroring.vram_index(adr)]),
+			_ => None,
+		}
+	}
+
+	pub fn set_ppu(&mut self, adr: u16, val: u8, ppu: &mut Ppu) -> Option<()> {
+		match adr {
+			0x0000..=0x1FFF if self.chr_is_ram => {
+				let idx = self.chr_index(adr);
+				self.chr[idx] = val;
+			}
+			0x0000..=0x1FFF => {}
+			0x2000..=0x3EFF => ppu.vram[self.mirroring.vram_index(adr)] = val,
+			_ => return None,
+		}
+		Some(())
+	}
+}
+
+#[cfg(test)]
+pub mod test {
+	use super::*;
+	use crate::rom_header::{ConsoleType, HeaderFormat, Timing};
+
+	pub fn header(mapper: u16, submapper: u8) -> RomHeader {
+		RomHeader {
+			format: HeaderFormat::Nes20,
+			mapper,
+			submapper,
+			prg_rom_size: 128 * 1024,
+			chr_rom_size: 128 * 1024,
+			prg_ram_size: 8 * 1024,
+			prg_nvram_size: 0,
+			chr_ram_size: 0,
+			chr_nvram_size: 0,
+			mirroring: Mirroring::Vertical,
+			four_screen: false,
+			battery: false,
+			trainer: false,
+			timing: Timing::Ntsc,
+			console_type: ConsoleType::Nes,
+			misc_roms: 0,
+			expansion_device: 0,
+			junk: None,
+		}
+	}
+
+	/// 128K of PRG and CHR, every 8K PRG and 1K CHR bank filled with its number.
+	pub fn banked_rom() -> (Vec<u8>, Vec<u8>) {
+		let prg = (0..128 * 1024).map(|i| (i / 0x2000) as u8).collect();
+		let chr = (0..128 * 1024).map(|i| (i / 0x400) as u8).collect();
+		(prg, chr)
+	}
+
+	#[test]
+	fn address_wiring() {
+		let (prg, chr) = banked_rom();
+		let ppu = Ppu::default();
+
+		// VRC4a has its second CHR bank's high nibble at $B006, VRC4c at $B0C0
+		for (submapper, adr) in [(1, 0xB006), (2, 0xB0C0), (0, 0xB006), (0, 0xB0C0)] {
+			let mut vrc = Vrc4::new(&header(21, submapper), &prg, &chr).unwrap();
+			vrc.set_cpu(adr, 0x01);
+			assert_eq!(
+				vrc.get_ppu(0x0400, &ppu),
+				Some(0x10),
+				"{submapper} {adr:04X}"
+			);
+		}
+
+		// VRC2a swaps the pins and drops the low bit of CHR banks
+		let mut vrc = Vrc4::new(&header(22, 0), &prg, &chr).unwrap();
+		vrc.set_cpu(0xB001, 0x05);
+		assert_eq!(vrc.get_ppu(0x0400, &ppu), Some(0x02));
+
+		// VRC4b swaps them too
+		let mut vrc = Vrc4::new(&header(25, 1), &prg, &chr).unwrap();
+		vrc.set_cpu(0xB001, 0x03);
+		assert_eq!(vrc.get_ppu(0x0400, &ppu), Some(0x03));
+	}
+
+	#[test]
+	fn prg_swap_mode() {
+		let (prg, chr) = banked_rom();
+		let mut vrc = Vrc4::new(&header(23, 1), &prg, &chr).unwrap();
+		vrc.set_cpu(0x8000, 3);
+		vrc.set_cpu(0xA000, 4);
+		assert_eq!(
+			[0x8000, 0xA000, 0xC000, 0xE000].map(|adr| vrc.get_cpu(adr).unwrap()),
+			[3, 4, 14, 15]
+		);
+		vrc.set_cpu(0x9002, 0b10);
+		assert_eq!(
+			[0x8000, 0xA000, 0xC000, 0xE000].map(|adr| vrc.get_cpu(adr).unwrap()),
+			[14, 4, 3, 15]
+		);
+
+		// VRC2 has no swap mode, and its $9000-$9003 are all mirroring
+		let mut vrc = Vrc4::new(&header(23, 3), &prg, &chr).unwrap();
+		vrc.set_cpu(0x8000, 3);
+		vrc.set_cpu(0x9002, 0b11);
+		assert_eq!(vrc.get_cpu(0x8000), Some(3));
+		assert_eq!(vrc.mirroring, Mirroring::Horizontal);
+
+		// 8K of PRG is everywhere at once
+		let vrc = Vrc4::new(&header(23, 1), &prg[..0x2000], &chr).unwrap();
+		assert_eq!(
+			[0x8000, 0xA000, 0xC000, 0xE000].map(|adr| vrc.get_cpu(adr).unwrap()),
+			[0; 4]
+		);
+	}
+
+	#[test]
+	fn irq_cycle_mode() {
+		let mut irq = VrcIrq::default();
+		irq.set_latch(0xFD);
+		irq.set_control(0b111);
+		irq.tick();
+		irq.tick();
+		assert!(!irq.pending());
+		irq.tick();
+		assert!(irq.pending());
+
+		// Reloaded from the latch, and kept going because of the A bit
+		irq.acknowledge();
+		for _ in 0..3 {
+			irq.tick();
+		}
+		assert!(irq.pending());
+
+		irq.set_control(0b100);
+		assert!(!irq.pending());
+		irq.tick();
+		assert!(!irq.pending());
+	}
+
+	#[test]
+	fn irq_scanline_mode() {
+		let mut irq = VrcIrq::default();
+		irq.set_latch(0xFF - 9);
+		irq.set_control(0b010);
+
+		// Ten lines of 113.67 CPU cycles
+		let mut cycles = 0;
+		while !irq.pending() {
+			irq.tick();
+			cycles += 1;
+		}
+		assert!((1136..=1138).contains(&cycles), "{cycles}");
+	}
+}
@@ -0,0 +1,405 @@
+use anyhow::{Result, bail};
+
+use crate::{
+	apu,
+	nes_file::{Mirroring, SharedMem},
+	ppu::Ppu,
+	rom_header::RomHeader,
+	vrc::{self, Pins, VrcIrq},
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Pulse {
+	volume: u8,
+	duty: u8,
+	/// Ignores the duty cycle and outputs the volume all the time.
+	constant: bool,
+	period: u16,
+	enabled: bool,
+	divider: u16,
+	step: u8,
+}
+
+impl Pulse {
+	fn write(&mut self, register: u16, val: u8) {
+		match register & 0b11 {
+			0 => {
+				self.constant = val & 0x80 != 0;
+				self.duty = (val >> 4) & 0b111;
+				self.volume = val & 0x0F;
+			}
+			1 => self.period = (self.period & 0xF00) | val as u16,
+			2 => {
+				self.period = (self.period & 0x0FF) | ((val & 0x0F) as u16) << 8;
+				self.enabled = val & 0x80 != 0;
+				if !self.enabled {
+					self.step = 15;
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn tick(&mut self, shift: u8) {
+		if !self.enabled {
+			return;
+		}
+		if self.divider == 0 {
+			self.divider = self.period >> shift;
+			self.step = self.step.wrapping_sub(1) & 0x0F;
+		} else {
+			self.divider -= 1;
+		}
+	}
+
+	fn output(&self) -> u8 {
+		if self.enabled && (self.constant || self.step <= self.duty) {
+			self.volume
+		} else {
+			0
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Sawtooth {
+	rate: u8,
+	period: u16,
+	enabled: bool,
+	divider: u16,
+	step: u8,
+	accumulator: u8,
+}
+
+impl Sawtooth {
+	fn write(&mut self, register: u16, val: u8) {
+		match register & 0b11 {
+			0 => self.rate = val & 0x3F,
+			1 => self.period = (self.period & 0xF00) | val as u16,
+			2 => {
+				self.period = (self.period & 0x0FF) | ((val & 0x0F) as u16) << 8;
+				self.enabled = val & 0x80 != 0;
+				if !self.enabled {
+					self.step = 0;
+					self.accumulator = 0;
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn tick(&mut self, shift: u8) {
+		if !self.enabled {
+			return;
+		}
+		if self.divider > 0 {
+			self.divider -= 1;
+			return;
+		}
+		self.divider = self.period >> shift;
+
+		// The rate gets added every other step, and the saw starts over after seven additions
+		self.step += 1;
+		if self.step == 14 {
+			self.step = 0;
+			self.accumulator = 0;
+		} else if self.step.is_multiple_of(2) {
+			self.accumulator = self.accumulator.wrapping_add(self.rate);
+		}
+	}
+
+	fn output(&self) -> u8 {
+		self.accumulator >> 3
+	}
+}
+
+/// VRC6's two pulse channels and sawtooth, which NSFs can use too.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Vrc6Audio {
+	pulses: [Pulse; 2],
+	sawtooth: Sawtooth,
+	/// $9003
+	halt: bool,
+	shift: u8,
+}
+
+impl Vrc6Audio {
+	/// Takes a write to $9000-$9003, $A000-$A002 or $B000-$B002, with the address lines already
+	/// untangled.
+	pub fn write(&mut self, register: u16, val: u8) {
+		match register {
+			0x9003 => {
+				self.halt = val & 1 != 0;
+				self.shift = match val & 0b110 {
+					0 => 0,
+					0b010 => 4,
+					_ => 8,
+				};
+			}
+			0x9000..=0x9002 => self.pulses[0].write(register, val),
+			0xA000..=0xA002 => self.pulses[1].write(register, val),
+			0xB000..=0xB002 => self.sawtooth.write(register, val),
+			_ => {}
+		}
+	}
+
+	pub fn tick(&mut self) {
+		if self.halt {
+			return;
+		}
+		for pulse in &mut self.pulses {
+			pulse.tick(self.shift);
+		}
+		self.sawtooth.tick(self.shift);
+	}
+
+	/// A pulse at full volume is about as loud as a 2A03 pulse at full volume.
+	pub fn output(&self) -> f32 {
+		let sum = self.pulses[0].output() + self.pulses[1].output() + self.sawtooth.output();
+		sum as f32 * (apu::PULSE_LEVEL / 15.0)
+	}
+}
+
+/// Konami's VRC6 (mappers 24 and 26), from Akumajou Densetsu and the Madara and Esper Dream 2
+/// carts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vrc6 {
+	pins: Pins,
+	prg_rom: SharedMem,
+	prg_ram: Vec<u8>,
+	chr: SharedMem,
+	chr_is_ram: bool,
+
+	/// $8000, in 16K banks
+	prg_bank_16k: u8,
+	/// $C000, in 8K banks
+	prg_bank_8k: u8,
+	/// $D000-$E003
+	chr_banks: [u8; 8],
+	/// $B003
+	banking: u8,
+	irq: VrcIrq,
+	pub audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+	pub fn new(header: &RomHeader, prg: &[u8], chr: &[u8]) -> Result<Self> {
+		// VRC6b has the register select pins swapped
+		let pins = match header.mapper {
+			24 => Pins::new(1 << 0, 1 << 1),
+			26 => Pins::new(1 << 1, 1 << 0),
+			mapper => bail!("Mapper {mapper} isn't a VRC6"),
+		};
+		if prg.is_empty() || !prg.len().is_multiple_of(0x4000) {
+			bail!("VRC6 PRG ROM has to be a whole number of 16K banks");
+		}
+		let chr_is_ram = chr.is_empty();
+
+		Ok(Self {
+			pins,
+			prg_rom: prg.into(),
+			prg_ram: vec![0; header.prg_ram_total()],
+			chr: if chr_is_ram {
+				vec![0; header.chr_ram_size.max(8 * 1024)].into()
+			} else {
+				chr.into()
+			},
+			chr_is_ram,
+			prg_bank_16k: 0,
+			prg_bank_8k: 0,
+			chr_banks: [0; _],
+			banking: 0,
+			irq: VrcIrq::default(),
+			audio: Vrc6Audio::default(),
+		})
+	}
+
+	pub fn prg_rom(&self) -> &[u8] {
+		&self.prg_rom
+	}
+
+	pub fn prg_ram(&self) -> Option<&[u8]> {
+		(!self.prg_ram.is_empty()).then_some(&self.prg_ram[..])
+	}
+
+	pub fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+		(!self.prg_ram.is_empty()).then_some(&mut self.prg_ram[..])
+	}
+
+	pub fn irq(&self) -> bool {
+		self.irq.pending()
+	}
+
+	pub fn tick(&mut self) {
+		self.irq.tick();
+		self.audio.tick();
+	}
+
+	fn prg_ram_enabled(&self) -> bool {
+		self.banking & 0x80 != 0 && !self.prg_ram.is_empty()
+	}
+
+	/// Where in PRG ROM the byte at `adr` comes from.
+	pub fn prg_rom_offset(&self, adr: u16) -> Option<usize> {
+		let bank = match adr {
+			0x8000..=0xBFFF => self.prg_bank_16k as usize * 2 + (adr as usize - 0x8000) / 0x2000,
+			0xC000..=0xDFFF => self.prg_bank_8k as usize,
+			0xE000..=0xFFFF => self.prg_rom.len() / 0x2000 - 1,
+			_ => return None,
+		};
+		Some(vrc::prg_index(&self.prg_rom, bank, adr))
+	}
+
+	pub fn get_cpu(&self, adr: u16) -> Option<u8> {
+		match adr {
+			0x6000..=0x7FFF if self.prg_ram_enabled() => {
+				Some(self.prg_ram[adr as usize % self.prg_ram.len()])
+			}
+			_ => self.prg_rom_offset(adr).map(|i| self.prg_rom[i]),
+		}
+	}
+
+	pub fn set_cpu(&mut self, adr: u16, val: u8) {
+		let register = self.pins.register(adr);
+		match register {
+			0x6000..=0x7FFF if self.prg_ram_enabled() => {
+				let len = self.prg_ram.len();
+				self.prg_ram[adr as usize % len] = val;
+			}
+			0x8000..=0x8003 => self.prg_bank_16k = val & 0x0F,
+			0xB003 => self.banking = val,
+			0x9000..=0xB002 => self.audio.write(register, val),
+			0xC000..=0xC003 => self.prg_bank_8k = val & 0x1F,
+			0xD000..=0xE003 => {
+				let bank = ((register >> 12) - 0xD) as usize * 4 + (register as usize & 0b11);
+				self.chr_banks[bank] = val;
+			}
+			0xF000 => self.irq.set_latch(val),
+			0xF001 => self.irq.set_control(val),
+			0xF002 => self.irq.acknowledge(),
+			_ => {}
+		}
+	}
+
+	/// The 1K CHR bank for a pattern table address, going by the layout in $B003.
+	fn chr_bank(&self, adr: u16) -> u16 {
+		let slot = adr as usize / 0x400;
+		// In 2K banks, the low bit comes from the address
+		let in_2k = |register: u8| ((register & 0xFE) | (slot as u8 & 1)) as u16;
+		match (self.banking & 0b11, slot) {
+			(0, _) => self.chr_banks[slot] as u16,
+			(1, _) => in_2k(self.chr_banks[slot / 2]),
+			(_, 0..=3) => self.chr_banks[slot] as u16,
+			(_, _) => in_2k(self.chr_banks[4 + (slot - 4) / 2]),
+		}
+	}
+
+	/// Nametables can come from CHR ROM too, but no game does that, so only the CIRAM
+	/// mirroring options are here.
+	fn mirroring(&self) -> Mirroring {
+		match (self.banking >> 2) & 0b11 {
+			0 => Mirroring::Vertical,
+			1 => Mirroring::Horizontal,
+			2 => Mirroring::SingleScreenA,
+			_ => Mirroring::SingleScreenB,
+		}
+	}
+
+	pub fn get_ppu(&self, adr: u16, ppu: &Ppu) -> Option<u8> {
+		match adr {
+			0x0000..=0x1FFF => Some(self.chr[vrc::chr_index(&self.chr, self.chr_bank(adr), adr)]),
+			0x2000..=0x3EFF => Some(ppu.vram[self.mirroring().vram_index(adr)]),
+			_ => None,
+		}
+	}
+
+	pub fn set_ppu(&mut self, adr: u16, val: u8, ppu: &mut Ppu) -> Option<()> {
+		match adr {
+			0x0000..=0x1FFF if self.chr_is_ram => {
+				let idx = vrc::chr_index(&self.chr, self.chr_bank(adr), adr);
+				self.chr[idx] = val;
+			}
+			0x0000..=0x1FFF => {}
+			0x2000..=0x3EFF => ppu.vram[self.mirroring().vram_index(adr)] = val,
+			_ => return None,
+		}
+		Some(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::vrc::test::{banked_rom, header};
+
+	#[test]
+	fn banking() {
+		let (prg, chr) = banked_rom();
+		let ppu = Ppu::default();
+		let mut vrc = Vrc6::new(&header(26, 0), &prg, &chr).unwrap();
+
+		vrc.set_cpu(0x8000, 2);
+		vrc.set_cpu(0xC000, 9);
+		assert_eq!(
+			[0x8000, 0xA000, 0xC000, 0xE000].map(|adr| vrc.get_cpu(adr).unwrap()),
+			[4, 5, 9, 15]
+		);
+
+		// VRC6b: $D001 is CHR bank 2
+		vrc.set_cpu(0xD001, 0x21);
+		assert_eq!(vrc.get_ppu(0x0800, &ppu), Some(0x21));
+
+		// RAM only once it's enabled
+		vrc.set_cpu(0x6000, 0x55);
+		assert_eq!(vrc.get_cpu(0x6000), None);
+		vrc.set_cpu(0xB003, 0xA4);
+		vrc.set_cpu(0x6000, 0x55);
+		assert_eq!(vrc.get_cpu(0x6000), Some(0x55));
+		assert_eq!(vrc.mirroring(), Mirroring::Horizontal);
+	}
+
+	#[test]
+	fn pulse() {
+		let mut audio = Vrc6Audio::default();
+		// Duty 4 (5/16), volume 15, period 15 so each step takes 16 cycles
+		audio.write(0x9000, 0x4F);
+		audio.write(0x9001, 0x0F);
+		audio.write(0x9002, 0x80);
+
+		let mut high = 0;
+		for _ in 0..16 * 16 {
+			audio.tick();
+			if audio.output() > 0.0 {
+				high += 1;
+			}
+		}
+		assert_eq!(high, 5 * 16);
+
+		// Halted, the output stays put
+		audio.write(0x9003, 0x01);
+		let level = audio.output();
+		for _ in 0..100 {
+			audio.tick();
+			assert_eq!(audio.output(), level);
+		}
+	}
+
+	#[test]
+	fn sawtooth() {
+		let mut audio = Vrc6Audio::default();
+		audio.write(0xB000, 42);
+		audio.write(0xB001, 0);
+		audio.write(0xB002, 0x80);
+
+		let levels = (0..14)
+			.map(|_| {
+				audio.tick();
+				audio.sawtooth.accumulator
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			levels,
+			[0, 42, 42, 84, 84, 126, 126, 168, 168, 210, 210, 252, 252, 0]
+		);
+	}
+}
@@ -0,0 +1,639 @@
+use std::f32::consts::TAU;
+
+use anyhow::{Result, bail};
+
+use crate::{
+	apu,
+	nes_file::{Mirroring, SharedMem},
+	ppu::Ppu,
+	rom_header::RomHeader,
+	vrc::{self, VrcIrq},
+};
+
+/// The 15 built in instruments, as dumped from a VRC7 die. Instrument 0 is the custom one in
+/// registers $00-$07.
+const PATCHES: [[u8; 8]; 15] = [
+	[0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+	[0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+	[0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+	[0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+	[0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+	[0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+	[0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+	[0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+	[0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+	[0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+	[0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+	[0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+	[0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+	[0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+	[0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+const MULTIPLIERS: [f32; 16] = [
+	0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+
+/// Key scale attenuation in dB at octave 7, by the top four bits of the frequency.
+const KEY_SCALE_DB: [f32; 16] = [
+	0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+	42.0,
+];
+
+/// The chip makes a sample every 36 CPU cycles, about 49.7kHz.
+const CYCLES_PER_SAMPLE: u8 = 36;
+const SAMPLE_RATE: f32 = (crate::audio::CPU_RATE / CYCLES_PER_SAMPLE as f64) as f32;
+/// Attenuation at which an envelope counts as finished.
+const SILENT_DB: f32 = 96.0;
+/// How far a modulator at full volume moves the carrier's phase, in cycles.
+const MODULATION_DEPTH: f32 = 4.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum Stage {
+	Attack,
+	Decay,
+	Sustain,
+	Release,
+	#[default]
+	Off,
+}
+
+/// One of the instrument's settings for the modulator (0) or carrier (1).
+#[derive(Debug, Copy, Clone)]
+struct OperatorPatch {
+	tremolo: bool,
+	vibrato: bool,
+	/// Holds at the sustain level while the key is down, instead of fading out.
+	sustained: bool,
+	key_scale_rate: bool,
+	multiplier: f32,
+	key_scale_level: u8,
+	rectified: bool,
+	attack: u8,
+	decay: u8,
+	sustain_level: u8,
+	release: u8,
+}
+
+impl OperatorPatch {
+	fn new(patch: &[u8; 8], op: usize) -> Self {
+		Self {
+			tremolo: patch[op] & 0x80 != 0,
+			vibrato: patch[op] & 0x40 != 0,
+			sustained: patch[op] & 0x20 != 0,
+			key_scale_rate: patch[op] & 0x10 != 0,
+			multiplier: MULTIPLIERS[(patch[op] & 0x0F) as usize],
+			key_scale_level: patch[2 + op] >> 6,
+			rectified: patch[3] & (0x08 << op) != 0,
+			attack: patch[4 + op] >> 4,
+			decay: patch[4 + op] & 0x0F,
+			sustain_level: patch[6 + op] >> 4,
+			release: patch[6 + op] & 0x0F,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Operator {
+	/// In cycles, 0 to 1.
+	phase: f32,
+	stage: Stage,
+	/// Envelope attenuation in dB.
+	envelope: f32,
+}
+
+impl Operator {
+	fn key_on(&mut self) {
+		self.phase = 0.0;
+		self.stage = Stage::Attack;
+	}
+
+	fn key_off(&mut self) {
+		if self.stage != Stage::Off {
+			self.stage = Stage::Release;
+		}
+	}
+
+	/// Moves the envelope on by a sample. `rate` is the 0-15 rate of the current stage, which
+	/// the caller picks as it depends on the channel too.
+	fn step_envelope(&mut self, patch: &OperatorPatch, rate: u8, key_scale: u8) {
+		// Every step of the effective rate is a quarter of an octave faster
+		let effective = match rate {
+			0 => 0,
+			rate => (rate * 4 + key_scale).min(63),
+		};
+		// 96dB takes 2.4ms at rate 15 and doubles every rate down
+		let decay_db = match effective {
+			0 => 0.0,
+			_ => {
+				let seconds = 0.0024 * 2f32.powf((60.0 - effective as f32) / 4.0);
+				SILENT_DB / (seconds * SAMPLE_RATE)
+			}
+		};
+
+		match self.stage {
+			Stage::Attack if effective >= 60 => {
+				self.envelope = 0.0;
+				self.stage = Stage::Decay;
+			}
+			Stage::Attack => {
+				// Attack is exponential, and about 14 times quicker than decaying
+				let fraction = (decay_db * 14.0 / SILENT_DB).min(1.0);
+				self.envelope -= (self.envelope + 1.0) * fraction;
+				if self.envelope <= 0.0 {
+					self.envelope = 0.0;
+					self.stage = Stage::Decay;
+				}
+			}
+			Stage::Decay => {
+				let sustain_db = patch.sustain_level as f32 * 3.0;
+				self.envelope += decay_db;
+				if self.envelope >= sustain_db {
+					self.envelope = sustain_db;
+					self.stage = Stage::Sustain;
+				}
+			}
+			Stage::Sustain | Stage::Release => {
+				self.envelope += decay_db;
+				if self.envelope >= SILENT_DB {
+					self.envelope = SILENT_DB;
+					self.stage = Stage::Off;
+				}
+			}
+			Stage::Off => {}
+		}
+	}
+
+	/// The operator's output for `modulation` (in cycles) and `attenuation` (in dB, on top of the
+	/// envelope), -1 to 1.
+	fn output(&self, patch: &OperatorPatch, modulation: f32, attenuation: f32) -> f32 {
+		if self.stage == Stage::Off {
+			return 0.0;
+		}
+		let mut wave = (TAU * (self.phase + modulation)).sin();
+		if patch.rectified {
+			wave = wave.max(0.0);
+		}
+		wave * 10f32.powf(-(self.envelope + attenuation) / 20.0)
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Channel {
+	fnum: u16,
+	block: u8,
+	key: bool,
+	/// Slows down the release, like a sustain pedal.
+	sustain: bool,
+	instrument: u8,
+	volume: u8,
+	modulator: Operator,
+	carrier: Operator,
+	/// The modulator's last two outputs, which it feeds back into itself.
+	feedback: [f32; 2],
+}
+
+/// VRC7's FM synthesis, a cut down YM2413 (OPLL) with six channels and no rhythm mode. This is
+/// modelled in floating point from the documented behaviour rather than the chip's log/exp
+/// tables, so it sounds right but won't match a recording sample for sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opll {
+	address: u8,
+	custom: [u8; 8],
+	channels: [Channel; 6],
+	divider: u8,
+	/// Tremolo and vibrato LFOs, in cycles.
+	tremolo_phase: f32,
+	vibrato_phase: f32,
+	output: f32,
+}
+
+impl Default for Opll {
+	fn default() -> Self {
+		Self {
+			address: 0,
+			custom: [0; _],
+			channels: Default::default(),
+			divider: 0,
+			tremolo_phase: 0.0,
+			vibrato_phase: 0.0,
+			output: 0.0,
+		}
+	}
+}
+
+impl Opll {
+	/// $9010
+	pub fn select(&mut self, val: u8) {
+		self.address = val;
+	}
+
+	/// $9030
+	pub fn write(&mut self, val: u8) {
+		let channel = (self.address & 0x0F) as usize;
+		match self.address {
+			0x00..=0x07 => self.custom[self.address as usize] = val,
+			0x10..=0x15 => {
+				let channel = &mut self.channels[channel];
+				channel.fnum = (channel.fnum & 0x100) | val as u16;
+			}
+			0x20..=0x25 => {
+				let channel = &mut self.channels[channel];
+				channel.fnum = (channel.fnum & 0x0FF) | ((val & 1) as u16) << 8;
+				channel.block = (val >> 1) & 0b111;
+				channel.sustain = val & 0x20 != 0;
+
+				let key = val & 0x10 != 0;
+				if key && !channel.key {
+					channel.modulator.key_on();
+					channel.carrier.key_on();
+				} else if !key && channel.key {
+					channel.modulator.key_off();
+					channel.carrier.key_off();
+				}
+				channel.key = key;
+			}
+			0x30..=0x35 => {
+				let channel = &mut self.channels[channel];
+				channel.instrument = val >> 4;
+				channel.volume = val & 0x0F;
+			}
+			_ => {}
+		}
+	}
+
+	fn patch(&self, instrument: u8) -> &[u8; 8] {
+		match instrument {
+			0 => &self.custom,
+			n => &PATCHES[n as usize - 1],
+		}
+	}
+
+	pub fn tick(&mut self) {
+		self.divider += 1;
+		if self.divider == CYCLES_PER_SAMPLE {
+			self.divider = 0;
+			self.output = self.sample();
+		}
+	}
+
+	/// Somewhere around the 2A03's pulse channels, one channel at full volume peaking at a 2A03
+	/// pulse at full volume.
+	pub fn output(&self) -> f32 {
+		self.output
+	}
+
+	fn sample(&mut self) -> f32 {
+		// 3.7Hz tremolo of 4.8dB, 6.4Hz vibrato of 14 cents
+		self.tremolo_phase = (self.tremolo_phase + 3.7 / SAMPLE_RATE).fract();
+		self.vibrato_phase = (self.vibrato_phase + 6.4 / SAMPLE_RATE).fract();
+		let tremolo_db = 4.8 * (0.5 - 0.5 * (TAU * self.tremolo_phase).cos());
+		let vibrato = 2f32.powf(14.0 / 1200.0 * (TAU * self.vibrato_phase).sin());
+
+		let mut sum = 0.0;
+		for i in 0..self.channels.len() {
+			let patch = *self.patch(self.channels[i].instrument);
+			sum += Self::channel_sample(&mut self.channels[i], &patch, tremolo_db, vibrato);
+		}
+		sum * apu::PULSE_LEVEL
+	}
+
+	fn channel_sample(
+		channel: &mut Channel,
+		patch: &[u8; 8],
+		tremolo_db: f32,
+		vibrato: f32,
+	) -> f32 {
+		let ops = [OperatorPatch::new(patch, 0), OperatorPatch::new(patch, 1)];
+
+		// Key scaling: higher notes decay faster and can be quieter
+		let key_code = (channel.block << 1) | (channel.fnum >> 8) as u8;
+		let key_scale_db = (KEY_SCALE_DB[(channel.fnum >> 5) as usize]
+			- 6.0 * (7 - channel.block) as f32)
+			.max(0.0);
+		let base_frequency = channel.fnum as f32 * (1 << channel.block) as f32 / (1 << 19) as f32;
+
+		let attenuation = |op: &OperatorPatch, level_db: f32| {
+			let key_scale = match op.key_scale_level {
+				0 => 0.0,
+				1 => 0.5,
+				2 => 1.0,
+				_ => 2.0,
+			};
+			let tremolo = if op.tremolo { tremolo_db } else { 0.0 };
+			level_db + key_scale * key_scale_db + tremolo
+		};
+
+		let feedback = match patch[3] & 0b111 {
+			0 => 0.0,
+			fb => (channel.feedback[0] + channel.feedback[1]) / 2.0 * (1 << (fb - 1)) as f32 / 32.0,
+		};
+		let total_level_db = (patch[2] & 0x3F) as f32 * 0.75;
+		let modulator =
+			channel
+				.modulator
+				.output(&ops[0], feedback, attenuation(&ops[0], total_level_db));
+		channel.feedback = [channel.feedback[1], modulator];
+
+		let volume_db = channel.volume as f32 * 3.0;
+		let out = channel.carrier.output(
+			&ops[1],
+			modulator * MODULATION_DEPTH,
+			attenuation(&ops[1], volume_db),
+		);
+
+		for (op, patch) in [&mut channel.modulator, &mut channel.carrier]
+			.into_iter()
+			.zip(&ops)
+		{
+			let frequency = base_frequency * patch.multiplier;
+			let frequency = if patch.vibrato {
+				frequency * vibrato
+			} else {
+				frequency
+			};
+			op.phase = (op.phase + frequency).fract();
+
+			let key_scale = if patch.key_scale_rate {
+				key_code
+			} else {
+				key_code >> 2
+			};
+			let rate = match op.stage {
+				Stage::Attack => patch.attack,
+				Stage::Decay => patch.decay,
+				// Percussive sounds keep fading while the key is held
+				Stage::Sustain if patch.sustained => 0,
+				Stage::Sustain => patch.release,
+				Stage::Release if channel.sustain => 5,
+				Stage::Release if patch.sustained => patch.release,
+				Stage::Release => 7,
+				Stage::Off => 0,
+			};
+			op.step_envelope(patch, rate, key_scale);
+		}
+
+		out
+	}
+}
+
+/// Konami's VRC7 (mapper 85), a VRC4-like mapper with an FM synthesiser, from Lagrange Point.
+/// Tiny Toon Adventures 2 uses it too, but without the sound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vrc7 {
+	/// The address line that selects the second register at each $x000, A4 on VRC7a and A3
+	/// on VRC7b.
+	select: u16,
+	prg_rom: SharedMem,
+	prg_ram: Vec<u8>,
+	chr: SharedMem,
+	chr_is_ram: bool,
+
+	prg_banks: [u8; 3],
+	chr_banks: [u8; 8],
+	/// $E000
+	control: u8,
+	irq: VrcIrq,
+	pub audio: Opll,
+}
+
+impl Vrc7 {
+	pub fn new(header: &RomHeader, prg: &[u8], chr: &[u8]) -> Result<Self> {
+		let select = match header.submapper {
+			1 => 1 << 3,
+			2 => 1 << 4,
+			_ => (1 << 3) | (1 << 4),
+		};
+		if prg.is_empty() || !prg.len().is_multiple_of(0x2000) {
+			bail!("VRC7 PRG ROM has to be a whole number of 8K banks");
+		}
+		let chr_is_ram = chr.is_empty();
+
+		Ok(Self {
+			select,
+			prg_rom: prg.into(),
+			prg_ram: vec![0; header.prg_ram_total()],
+			chr: if chr_is_ram {
+				vec![0; header.chr_ram_size.max(8 * 1024)].into()
+			} else {
+				chr.into()
+			},
+			chr_is_ram,
+			prg_banks: [0; _],
+			chr_banks: [0; _],
+			control: 0,
+			irq: VrcIrq::default(),
+			audio: Opll::default(),
+		})
+	}
+
+	pub fn prg_rom(&self) -> &[u8] {
+		&self.prg_rom
+	}
+
+	pub fn prg_ram(&self) -> Option<&[u8]> {
+		(!self.prg_ram.is_empty()).then_some(&self.prg_ram[..])
+	}
+
+	pub fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+		(!self.prg_ram.is_empty()).then_some(&mut self.prg_ram[..])
+	}
+
+	pub fn irq(&self) -> bool {
+		self.irq.pending()
+	}
+
+	pub fn tick(&mut self) {
+		self.irq.tick();
+		self.audio.tick();
+	}
+
+	/// Bit 6 of $E000 holds the sound chip in reset.
+	pub fn audio_output(&self) -> f32 {
+		if self.control & 0x40 != 0 {
+			0.0
+		} else {
+			self.audio.output()
+		}
+	}
+
+	fn prg_ram_enabled(&self) -> bool {
+		self.control & 0x80 != 0 && !self.prg_ram.is_empty()
+	}
+
+	/// Where in PRG ROM the byte at `adr` comes from.
+	pub fn prg_rom_offset(&self, adr: u16) -> Option<usize> {
+		let bank = match adr {
+			0x8000..=0xDFFF => self.prg_banks[(adr as usize - 0x8000) / 0x2000] as usize,
+			0xE000..=0xFFFF => self.prg_rom.len() / 0x2000 - 1,
+			_ => return None,
+		};
+		Some(vrc::prg_index(&self.prg_rom, bank, adr))
+	}
+
+	pub fn get_cpu(&self, adr: u16) -> Option<u8> {
+		match adr {
+			0x6000..=0x7FFF if self.prg_ram_enabled() => {
+				Some(self.prg_ram[adr as usize % self.prg_ram.len()])
+			}
+			_ => self.prg_rom_offset(adr).map(|i| self.prg_rom[i]),
+		}
+	}
+
+	pub fn set_cpu(&mut self, adr: u16, val: u8) {
+		// The sound ports also decode A5
+		match adr & 0xF030 {
+			0x9010 => return self.audio.select(val),
+			0x9030 => return self.audio.write(val),
+			_ => {}
+		}
+
+		let register = (adr & 0xF000) | if adr & self.select != 0 { 0x10 } else { 0 };
+		match register {
+			0x6000..=0x7FFF if self.prg_ram_enabled() => {
+				let len = self.prg_ram.len();
+				self.prg_ram[adr as usize % len] = val;
+			}
+			0x8000 => self.prg_banks[0] = val & 0x3F,
+			0x8010 => self.prg_banks[1] = val & 0x3F,
+			0x9000 => self.prg_banks[2] = val & 0x3F,
+			0xA000..=0xD010 => {
+				let bank = ((register >> 12) - 0xA) as usize * 2 + (register as usize >> 4 & 1);
+				self.chr_banks[bank] = val;
+			}
+			0xE000 => {
+				if val & 0x40 != 0 {
+					self.audio = Opll::default();
+				}
+				self.control = val;
+			}
+			0xE010 => self.irq.set_latch(val),
+			0xF000 => self.irq.set_control(val),
+			0xF010 => self.irq.acknowledge(),
+			_ => {}
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring {
+		match self.control & 0b11 {
+			0 => Mirroring::Vertical,
+			1 => Mirroring::Horizontal,
+			2 => Mirroring::SingleScreenA,
+			_ => Mirroring::SingleScreenB,
+		}
+	}
+
+	fn chr_index(&self, adr: u16) -> usize {
+		vrc::chr_index(&self.chr, self.chr_banks[adr as usize / 0x400] as u16, adr)
+	}
+
+	pub fn get_ppu(&self, adr: u16, ppu: &Ppu) -> Option<u8> {
+		match adr {
+			0x0000..=0x1FFF => Some(self.chr[self.chr_index(adr)]),
+			0x2000..=0x3EFF => Some(ppu.vram[self.mirroring().vram_index(adr)]),
+			_ => None,
+		}
+	}
+
+	pub fn set_ppu(&mut self, adr: u16, val: u8, ppu: &mut Ppu) -> Option<()> {
+		match adr {
+			0x0000..=0x1FFF if self.chr_is_ram => {
+				let idx = self.chr_index(adr);
+				self.chr[idx] = val;
+			}
+			0x0000..=0x1FFF => {}
+			0x2000..=0x3EFF => ppu.vram[self.mirroring().vram_index(adr)] = val,
+			_ => return None,
+		}
+		Some(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::vrc::test::{banked_rom, header};
+
+	#[test]
+	fn banking() {
+		let (prg, chr) = banked_rom();
+		let ppu = Ppu::default();
+		let mut vrc = Vrc7::new(&header(85, 2), &prg, &chr).unwrap();
+
+		vrc.set_cpu(0x8000, 1);
+		vrc.set_cpu(0x8010, 2);
+		vrc.set_cpu(0x9000, 3);
+		assert_eq!(
+			[0x8000, 0xA000, 0xC000, 0xE000].map(|adr| vrc.get_cpu(adr).unwrap()),
+			[1, 2, 3, 15]
+		);
+
+		// VRC7a ignores A3
+		vrc.set_cpu(0xB008, 0x30);
+		vrc.set_cpu(0xB010, 0x31);
+		assert_eq!(vrc.get_ppu(0x0800, &ppu), Some(0x30));
+		assert_eq!(vrc.get_ppu(0x0C00, &ppu), Some(0x31));
+
+		vrc.set_cpu(0xE000, 0x81);
+		vrc.set_cpu(0x7FFF, 0x12);
+		assert_eq!(vrc.get_cpu(0x7FFF), Some(0x12));
+		assert_eq!(vrc.mirroring(), Mirroring::Horizontal);
+	}
+
+	/// Plays a note on channel 0 for `samples` samples and returns what came out.
+	fn play(opll: &mut Opll, samples: usize) -> Vec<f32> {
+		(0..samples)
+			.map(|_| {
+				for _ in 0..CYCLES_PER_SAMPLE {
+					opll.tick();
+				}
+				opll.output()
+			})
+			.collect()
+	}
+
+	#[test]
+	fn note_on_and_off() {
+		let mut opll = Opll::default();
+		let mut write = |reg, val| {
+			opll.select(reg);
+			opll.write(val);
+		};
+		// A near silent modulator and a carrier that goes straight to full volume, so a sine
+		for (reg, val) in [0x01, 0x21, 0x3F, 0x00, 0xF0, 0xF0, 0x0F, 0x0F]
+			.into_iter()
+			.enumerate()
+		{
+			write(reg as u8, val);
+		}
+		// A440 on the custom instrument at full volume
+		write(0x30, 0x00);
+		write(0x10, 0x22);
+		write(0x20, 0x19);
+		let opll = &mut opll;
+		assert_eq!(opll.channels[0].fnum, 0x122);
+		assert_eq!(opll.channels[0].block, 4);
+
+		let on = play(opll, 5000);
+		let peak = on.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+		assert!(
+			peak > 0.9 * apu::PULSE_LEVEL && peak <= apu::PULSE_LEVEL,
+			"{peak}"
+		);
+
+		// About 440 zero crossings a second, upwards
+		let crossings = on[2500..]
+			.windows(2)
+			.filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+			.count();
+		let expected = 440.0 * 2500.0 / SAMPLE_RATE;
+		assert!((crossings as f32 - expected).abs() < 4.0, "{crossings}");
+
+		// Fades out after key off
+		opll.select(0x20);
+		opll.write(0x09);
+		let off = play(opll, SAMPLE_RATE as usize * 2);
+		assert!(off[off.len() - 100..].iter().all(|s| s.abs() < 0.001));
+		assert_eq!(opll.channels[0].carrier.stage, Stage::Off);
+	}
+}